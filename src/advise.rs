@@ -0,0 +1,87 @@
+#[cfg(feature = "std")]
+use sys;
+
+#[cfg(feature = "std")]
+use std::io;
+
+/// How a range of pages is expected to be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Advice {
+    /// The pages will be accessed soon, so they can be read ahead.
+    WillNeed,
+
+    /// The pages won't be accessed soon, so the OS can reclaim them.
+    ///
+    /// Their contents are discarded. On Unix, private anonymous pages read as
+    /// zeroes afterwards, elsewhere the contents are undefined until written.
+    DontNeed,
+
+    /// The pages will be accessed in order, so they can be read ahead
+    /// aggressively and dropped soon after they were accessed.
+    Sequential,
+
+    /// The pages will be accessed in no particular order, so reading ahead
+    /// is wasted.
+    Random,
+}
+
+/// Tells the OS how the pages in `ptr..ptr + len` are going to be used.
+///
+/// Advice applies to whole pages, so `ptr` must be aligned to the page size.
+/// Like `protect`, this is meant for memory obtained from the page based
+/// allocation functions or the `mmap` backend. Advice the platform has no
+/// equivalent for, such as `Sequential` and `Random` on Windows, is ignored.
+///
+/// # Safety
+///
+/// The range must be mapped. With `Advice::DontNeed`, the contents of the
+/// range are lost, so no values stored in it may be read afterwards.
+#[cfg(feature = "std")]
+pub unsafe fn advise(ptr: *mut u8, len: usize, advice: Advice) -> io::Result<()> {
+    if sys::advise(ptr, len, advice) {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use {advise, sys, Advice};
+
+    #[test]
+    fn test_advise() {
+        let page = sys::page_size();
+
+        unsafe {
+            let ptr = sys::map_anonymous(page, 0);
+            assert!(!ptr.is_null());
+            *ptr = 1;
+
+            advise(ptr, page, Advice::WillNeed).unwrap();
+            advise(ptr, page, Advice::Sequential).unwrap();
+            advise(ptr, page, Advice::Random).unwrap();
+            assert_eq!(1, *ptr);
+
+            advise(ptr, page, Advice::DontNeed).unwrap();
+
+            #[cfg(unix)]
+            assert_eq!(0, *ptr);
+
+            *ptr = 2;
+            sys::unmap(ptr, page);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_advise_unaligned() {
+        let page = sys::page_size();
+
+        unsafe {
+            let ptr = sys::map_anonymous(page, 0);
+            assert!(advise(ptr.add(1), 1, Advice::WillNeed).is_err());
+            sys::unmap(ptr, page);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Alignments as types.
+//!
+//! Types such as `AlignedBox` take their alignment as a type parameter, using
+//! one of the markers defined here.
+
+/// A type-level alignment.
+pub trait Alignment {
+    /// The alignment in bytes. Always a power of 2.
+    const ALIGN: usize;
+}
+
+macro_rules! alignments {
+    ($($name:ident => $align:expr,)*) => {
+        $(
+            #[doc = concat!("An alignment of ", stringify!($align), " bytes.")]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+            pub struct $name;
+
+            impl Alignment for $name {
+                const ALIGN: usize = $align;
+            }
+        )*
+    }
+}
+
+alignments! {
+    A16 => 16,
+    A32 => 32,
+    A64 => 64,
+    A128 => 128,
+    A256 => 256,
+    A512 => 512,
+    A1024 => 1024,
+    A2048 => 2048,
+    A4096 => 4096,
+}
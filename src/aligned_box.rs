@@ -0,0 +1,192 @@
+use align::Alignment;
+use {AllocError, Unique};
+
+use core::{cmp, fmt, mem, ops, ptr};
+use core::marker::PhantomData;
+
+/// An owned heap value aligned to at least `A::ALIGN` bytes.
+///
+/// This works like `Box<T>`, but over-aligns the value. The alignment is the
+/// larger of `A::ALIGN` and the natural alignment of `T`, so for example
+/// `AlignedBox<Page, A4096>` places a `Page` at a 4 KiB boundary.
+pub struct AlignedBox<T, A: Alignment> {
+    ptr: Unique<T>,
+    _align: PhantomData<A>,
+}
+
+impl<T, A: Alignment> AlignedBox<T, A> {
+    /// Moves `value` to the heap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails or the alignment is not supported.
+    pub fn new(value: T) -> AlignedBox<T, A> {
+        match AlignedBox::try_new(value) {
+            Ok(b) => b,
+            Err(e) => panic!("failed to allocate aligned box: {}", e),
+        }
+    }
+
+    /// Moves `value` to the heap, returning an error on failure.
+    ///
+    /// `value` is dropped on failure.
+    pub fn try_new(value: T) -> Result<AlignedBox<T, A>, AllocError> {
+        let (size, align) = Self::size_align();
+
+        let ptr = if size == 0 {
+            align as *mut T
+        } else {
+            let layout = ::Layout::from_size_align(size, align)?;
+            unsafe { ::try_allocate(layout)?.cast::<T>().as_ptr() }
+        };
+
+        unsafe {
+            ptr::write(ptr, value);
+            Ok(AlignedBox::from_raw(ptr))
+        }
+    }
+
+    /// Takes ownership of a value obtained from `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `AlignedBox::<T, A>::into_raw`.
+    pub unsafe fn from_raw(ptr: *mut T) -> AlignedBox<T, A> {
+        AlignedBox {
+            ptr: Unique::new_unchecked(ptr),
+            _align: PhantomData,
+        }
+    }
+
+    /// Gives up ownership of the value, returning a pointer to it.
+    ///
+    /// The value can be released by passing the pointer back to `from_raw`.
+    pub fn into_raw(b: AlignedBox<T, A>) -> *mut T {
+        let ptr = b.ptr.as_ptr();
+        mem::forget(b);
+        ptr
+    }
+
+    /// Returns a pointer to the value.
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns a mutable pointer to the value.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Moves the value out of the box, releasing the memory.
+    pub fn into_inner(b: AlignedBox<T, A>) -> T {
+        unsafe {
+            let ptr = AlignedBox::into_raw(b);
+            let value = ptr::read(ptr);
+            Self::deallocate(ptr);
+            value
+        }
+    }
+
+    fn size_align() -> (usize, usize) {
+        (mem::size_of::<T>(), cmp::max(A::ALIGN, mem::align_of::<T>()))
+    }
+
+    unsafe fn deallocate(ptr: *mut T) {
+        let (size, align) = Self::size_align();
+
+        if size != 0 {
+            ::deallocate(ptr as *mut u8, size, align)
+        }
+    }
+}
+
+unsafe impl<T: Send, A: Alignment> Send for AlignedBox<T, A> {}
+unsafe impl<T: Sync, A: Alignment> Sync for AlignedBox<T, A> {}
+
+impl<T, A: Alignment> ops::Deref for AlignedBox<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: Alignment> ops::DerefMut for AlignedBox<T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: Clone, A: Alignment> Clone for AlignedBox<T, A> {
+    fn clone(&self) -> AlignedBox<T, A> {
+        AlignedBox::new((**self).clone())
+    }
+}
+
+impl<T: Default, A: Alignment> Default for AlignedBox<T, A> {
+    fn default() -> AlignedBox<T, A> {
+        AlignedBox::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug, A: Alignment> fmt::Debug for AlignedBox<T, A> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(fmt)
+    }
+}
+
+impl<T, A: Alignment> Drop for AlignedBox<T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            Self::deallocate(self.ptr.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use align::{A16, A4096, A64};
+    use AlignedBox;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_aligned_box() {
+        let mut b = AlignedBox::<u8, A4096>::new(7);
+        assert_eq!(0, b.as_ptr() as usize & 4095);
+        *b += 1;
+        assert_eq!(8, *b);
+
+        let b = b.clone();
+        assert_eq!(0, b.as_ptr() as usize & 4095);
+        assert_eq!(8, AlignedBox::into_inner(b));
+    }
+
+    #[test]
+    fn test_aligned_box_drop() {
+        let rc = Rc::new(());
+        let b = AlignedBox::<_, A64>::new(rc.clone());
+        assert_eq!(2, Rc::strong_count(&rc));
+
+        let ptr = AlignedBox::into_raw(b);
+        let b = unsafe { AlignedBox::<_, A64>::from_raw(ptr) };
+        drop(b);
+        assert_eq!(1, Rc::strong_count(&rc));
+    }
+
+    #[test]
+    fn test_aligned_box_zero_sized() {
+        let b = AlignedBox::<(), A16>::new(());
+        assert_eq!(0, b.as_ptr() as usize & 15);
+    }
+
+    #[test]
+    fn test_aligned_box_natural_alignment() {
+        #[repr(align(256))]
+        struct Big(u8);
+
+        let b = AlignedBox::<_, A16>::new(Big(1));
+        assert_eq!(0, b.as_ptr() as usize & 255);
+        assert_eq!(1, b.0);
+    }
+}
@@ -0,0 +1,188 @@
+use {AllocError, Layout};
+
+use core::{fmt, ops, ptr, slice};
+use core::ptr::NonNull;
+
+/// A heap byte buffer with a guaranteed alignment.
+///
+/// The alignment is kept when the buffer is resized, which makes this
+/// suitable for I/O that requires aligned buffers, such as `O_DIRECT` reads
+/// with 512 or 4096 byte alignment. The contents start out zeroed.
+pub struct AlignedBytes {
+    ptr: NonNull<u8>,
+    len: usize,
+    align: usize,
+}
+
+unsafe impl Send for AlignedBytes {}
+unsafe impl Sync for AlignedBytes {}
+
+impl AlignedBytes {
+    /// Creates a zeroed buffer of `len` bytes aligned to `align`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if `align` is not a power of 2 or
+    /// is larger than `MAX_ALIGN`.
+    pub fn new(len: usize, align: usize) -> Result<AlignedBytes, AllocError> {
+        let layout = Layout::from_size_align(len, align)?;
+
+        if align > ::MAX_ALIGN {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let ptr = if len == 0 {
+            ::dangling(align)
+        } else {
+            unsafe { ::try_allocate_zeroed(layout)? }
+        };
+
+        Ok(AlignedBytes { ptr, len, align })
+    }
+
+    /// Returns the length of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the alignment of the buffer.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Resizes the buffer to `new_len` bytes, keeping its alignment.
+    ///
+    /// The contents are preserved up to the lesser of the old and new lengths,
+    /// and bytes added at the end are zeroed. On failure the buffer is left
+    /// intact.
+    pub fn resize(&mut self, new_len: usize) -> Result<(), AllocError> {
+        Layout::from_size_align(new_len, self.align)?;
+
+        if new_len == self.len {
+            return Ok(());
+        }
+
+        unsafe {
+            if new_len == 0 {
+                ::release(self.ptr, self.layout());
+                self.ptr = ::dangling(self.align);
+            } else if self.len == 0 {
+                self.ptr = ::try_allocate_zeroed(Layout::from_size_align_unchecked(new_len, self.align))?;
+            } else {
+                self.ptr = ::try_reallocate(self.ptr, self.layout(), new_len)?;
+
+                if new_len > self.len {
+                    ptr::write_bytes(self.ptr.as_ptr().add(self.len), 0, new_len - self.len);
+                }
+            }
+        }
+
+        self.len = new_len;
+        Ok(())
+    }
+
+    fn layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.len, self.align) }
+    }
+}
+
+impl ops::Deref for AlignedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl ops::DerefMut for AlignedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for AlignedBytes {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl Clone for AlignedBytes {
+    fn clone(&self) -> AlignedBytes {
+        let mut copy = AlignedBytes::new(self.len, self.align).expect("failed to allocate aligned bytes");
+        copy.copy_from_slice(self);
+        copy
+    }
+}
+
+impl fmt::Debug for AlignedBytes {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("AlignedBytes")
+            .field("len", &self.len)
+            .field("align", &self.align)
+            .finish()
+    }
+}
+
+impl Drop for AlignedBytes {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { ::release(self.ptr, self.layout()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AllocError, AlignedBytes};
+
+    #[test]
+    fn test_aligned_bytes() {
+        let mut buf = AlignedBytes::new(512, 512).unwrap();
+        assert_eq!(0, buf.as_ptr() as usize & 511);
+        assert!(buf.iter().all(|&b| b == 0));
+
+        buf[0] = 1;
+        buf[511] = 2;
+
+        buf.resize(8192).unwrap();
+        assert_eq!(0, buf.as_ptr() as usize & 511);
+        assert_eq!(1, buf[0]);
+        assert_eq!(2, buf[511]);
+        assert!(buf[512..].iter().all(|&b| b == 0));
+
+        buf.resize(16).unwrap();
+        assert_eq!(1, buf[0]);
+
+        let copy = buf.clone();
+        assert_eq!(&buf[..], &copy[..]);
+        assert_eq!(512, copy.align());
+    }
+
+    #[test]
+    fn test_aligned_bytes_empty() {
+        let mut buf = AlignedBytes::new(0, 4096).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(0, buf.as_ptr() as usize & 4095);
+
+        buf.resize(10).unwrap();
+        assert_eq!(0, buf.as_ptr() as usize & 4095);
+        assert!(buf.iter().all(|&b| b == 0));
+
+        buf.resize(0).unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_aligned_bytes_invalid() {
+        assert_eq!(AllocError::InvalidLayout, AlignedBytes::new(8, 3).unwrap_err());
+    }
+}
@@ -0,0 +1,300 @@
+use {backend, AllocError, Layout};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use core::mem::ManuallyDrop;
+use core::ptr::NonNull;
+
+/// An owned block of memory, released when dropped.
+///
+/// This guards a block between allocating it and handing it off, so that an
+/// early return or a panic doesn't leak it. `into_raw` gives up ownership
+/// once the block is in place.
+#[derive(Debug)]
+pub struct Allocation {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl Allocation {
+    /// Allocates an uninitialized block fitting `layout`.
+    ///
+    /// Errors are reported the same way as `try_allocate`.
+    pub fn new(layout: Layout) -> Result<Allocation, AllocError> {
+        let ptr = unsafe { ::try_allocate(layout)? };
+        Ok(Allocation { ptr, layout })
+    }
+
+    /// Allocates a zeroed block fitting `layout`.
+    ///
+    /// Errors are reported the same way as `try_allocate`.
+    pub fn zeroed(layout: Layout) -> Result<Allocation, AllocError> {
+        let ptr = unsafe { ::try_allocate_zeroed(layout)? };
+        Ok(Allocation { ptr, layout })
+    }
+
+    /// Takes ownership of a block allocated by this crate.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated with `layout` and must not be released
+    /// by anything else.
+    pub unsafe fn from_raw(ptr: NonNull<u8>, layout: Layout) -> Allocation {
+        Allocation { ptr, layout }
+    }
+
+    /// Gives up ownership of the block, returning its pointer and layout.
+    ///
+    /// The block must be released with `release`.
+    pub fn into_raw(self) -> (NonNull<u8>, Layout) {
+        let raw = (self.ptr, self.layout);
+        mem::forget(self);
+        raw
+    }
+
+    /// Takes ownership of the memory of `b`, without dropping the values.
+    ///
+    /// The block has the layout of the slice. Where the heap shares the
+    /// global allocator, as with the `Vec` and `std-alloc` backends, the
+    /// memory changes hands without copying.
+    ///
+    /// # Panics
+    ///
+    /// Aborts the process if the values need to be copied and the heap is
+    /// exhausted.
+    pub fn from_boxed_slice<T>(b: Box<[T]>) -> Allocation {
+        let layout = Layout::for_value(&*b);
+        let raw = Box::into_raw(b) as *mut [ManuallyDrop<T>];
+
+        unsafe { Allocation::from_global(raw as *mut u8, layout, || drop(Box::from_raw(raw))) }
+    }
+
+    /// Takes ownership of the memory of `b`, without dropping the value.
+    ///
+    /// This works as `from_boxed_slice` does, for a single value.
+    pub fn from_box<T>(b: Box<T>) -> Allocation {
+        let raw = Box::into_raw(b) as *mut ManuallyDrop<T>;
+
+        unsafe { Allocation::from_global(raw as *mut u8, Layout::new::<T>(), || drop(Box::from_raw(raw))) }
+    }
+
+    /// Hands the block off as a boxed slice of `T`, without copying where the
+    /// heap shares the global allocator.
+    ///
+    /// The slice has as many values as fit in the block. Elsewhere, the
+    /// values are moved into a new box and the block is released.
+    ///
+    /// # Safety
+    ///
+    /// The block must hold initialized values of `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is zero-sized, or if the layout isn't that of an array
+    /// of `T`: the alignment must be the alignment of `T` and the size a
+    /// multiple of its size.
+    pub unsafe fn into_boxed_slice<T>(self) -> Box<[T]> {
+        let elem = Layout::new::<T>();
+
+        assert!(elem.size() > 0, "zero-sized element type");
+        let len = self.layout.size() / elem.size();
+
+        assert!(self.layout.align() == elem.align() && len * elem.size() == self.layout.size(),
+                "{:?} is not the layout of an array of {:?}", self.layout, elem);
+
+        match self.into_global() {
+            Ok(ptr) => Box::from_raw(ptr::slice_from_raw_parts_mut(ptr as *mut T, len)),
+            Err(block) => {
+                let mut vec = Vec::<T>::with_capacity(len);
+                ptr::copy_nonoverlapping(block.as_ptr() as *const T, vec.as_mut_ptr(), len);
+                vec.set_len(len);
+                vec.into_boxed_slice()
+            }
+        }
+    }
+
+    /// Hands the block off as a box of `T`, without copying where the heap
+    /// shares the global allocator.
+    ///
+    /// # Safety
+    ///
+    /// The block must hold an initialized value of `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is zero-sized or the layout is not the layout of `T`.
+    pub unsafe fn into_box<T>(self) -> Box<T> {
+        let layout = Layout::new::<T>();
+
+        assert!(layout.size() > 0, "zero-sized type");
+        assert!(self.layout == layout, "{:?} is not the layout of the type {:?}", self.layout, layout);
+
+        match self.into_global() {
+            Ok(ptr) => Box::from_raw(ptr as *mut T),
+            Err(block) => Box::new(ptr::read(block.as_ptr() as *const T)),
+        }
+    }
+
+    // Takes over memory of the global allocator, calling `free` to release
+    // it if the bytes have to be copied
+    unsafe fn from_global<F: FnOnce()>(ptr: *mut u8, layout: Layout, free: F) -> Allocation {
+        if layout.size() == 0 {
+            free();
+            return Allocation { ptr: ::dangling(layout.align()), layout };
+        }
+
+        if backend::from_global(layout.size(), layout.align()) {
+            ::on_allocate(ptr, layout.size(), layout.align());
+            return Allocation { ptr: NonNull::new_unchecked(ptr), layout };
+        }
+
+        let block = match Allocation::new(layout) {
+            Ok(block) => block,
+            Err(_) => ::oom::abort(layout),
+        };
+
+        ptr::copy_nonoverlapping(ptr, block.as_ptr(), layout.size());
+        free();
+
+        block
+    }
+
+    // Returns the pointer if the block belongs to the global allocator, and
+    // the block back otherwise
+    fn into_global(self) -> Result<*mut u8, Allocation> {
+        let (ptr, size, align) = (self.ptr.as_ptr(), self.layout.size(), self.layout.align());
+
+        if size == 0 {
+            mem::forget(self);
+            return Ok(ptr);
+        }
+
+        if !backend::from_global(size, align) {
+            return Err(self);
+        }
+
+        #[cfg(feature = "debug-checks")]
+        ::leaks::check_release(ptr, size, align);
+
+        ::on_deallocate(ptr, size, align);
+        mem::forget(self);
+
+        Ok(ptr)
+    }
+
+    /// Returns a pointer to the block.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the layout the block was allocated with.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Resizes the block to `new_size` bytes.
+    ///
+    /// The contents are preserved up to the lesser of the new and old sizes.
+    /// On failure the block is left intact.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), AllocError> {
+        let layout = Layout::from_size_align(new_size, self.layout.align())?;
+        self.ptr = unsafe { ::try_reallocate(self.ptr, self.layout, new_size)? };
+        self.layout = layout;
+        Ok(())
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        unsafe { ::release(self.ptr, self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AllocError, Allocation, Layout};
+    use std::boxed::Box;
+    use std::ptr;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_allocation() {
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let block = Allocation::zeroed(layout).unwrap();
+
+        assert_eq!(layout, block.layout());
+        assert_eq!(0, block.as_ptr() as usize & 15);
+
+        unsafe {
+            for i in 0..64 {
+                assert_eq!(0, *block.as_ptr().add(i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocation_into_raw() {
+        let block = Allocation::new(Layout::new::<u64>()).unwrap();
+        let (ptr, layout) = block.into_raw();
+
+        unsafe {
+            ptr::write(ptr.as_ptr() as *mut u64, 7);
+            let block = Allocation::from_raw(ptr, layout);
+            assert_eq!(7, *(block.as_ptr() as *const u64));
+        }
+    }
+
+    #[test]
+    fn test_allocation_boxes() {
+        let b: Box<[u32]> = ::std::vec![1, 2, 3].into_boxed_slice();
+        let block = Allocation::from_boxed_slice(b);
+        assert_eq!(Layout::array::<u32>(3).unwrap(), block.layout());
+        assert_eq!(2, unsafe { *(block.as_ptr() as *const u32).add(1) });
+
+        let b = unsafe { block.into_boxed_slice::<u32>() };
+        assert_eq!(&[1, 2, 3][..], &b[..]);
+
+        let block = Allocation::from_box(Box::new(5u64));
+        assert_eq!(5, unsafe { *block.into_box::<u64>() });
+
+        // Empty slices don't allocate
+        let block = Allocation::from_boxed_slice(Box::<[u16]>::from(Vec::new()));
+        assert_eq!(0, block.layout().size());
+        assert_eq!(0, unsafe { block.into_boxed_slice::<u16>() }.len());
+    }
+
+    #[test]
+    fn test_allocation_box_values_not_dropped() {
+        use std::rc::Rc;
+
+        let rc = Rc::new(());
+        let block = Allocation::from_box(Box::new(rc.clone()));
+        assert_eq!(2, Rc::strong_count(&rc));
+
+        drop(unsafe { block.into_box::<Rc<()>>() });
+        assert_eq!(1, Rc::strong_count(&rc));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_allocation_into_box_wrong_layout() {
+        let block = Allocation::new(Layout::from_size_align(6, 2).unwrap()).unwrap();
+        let _ = unsafe { block.into_boxed_slice::<u32>() };
+    }
+
+    #[test]
+    fn test_allocation_resize() {
+        let mut block = Allocation::new(Layout::from_size_align(4, 4).unwrap()).unwrap();
+
+        unsafe { ptr::write(block.as_ptr() as *mut u32, 0xDEAD_BEEF) };
+        block.resize(4096).unwrap();
+
+        assert_eq!(4096, block.layout().size());
+        assert_eq!(0xDEAD_BEEF, unsafe { *(block.as_ptr() as *const u32) });
+        assert_eq!(Err(AllocError::InvalidLayout), block.resize(0));
+    }
+}
@@ -0,0 +1,292 @@
+use {AllocError, Layout};
+
+use alloc::boxed::Box;
+use core::{cmp, mem, ptr};
+use core::ptr::NonNull;
+
+/// A source of memory blocks.
+///
+/// Collections written against `Alloc` can be backed by the heap or by any of
+/// the allocators built on top of it.
+///
+/// The trait is object safe, so an allocator picked at run time can be passed
+/// around as `&mut dyn Alloc` or `Box<dyn Alloc>`, which implement `Alloc`
+/// themselves. The typed helpers are only available on sized allocators,
+/// which includes these references. Allocators that can be shared between
+/// threads implement `SyncAlloc` instead, through `&self`.
+///
+/// # Safety
+///
+/// Implementations must return blocks that fit the requested layout and
+/// remain valid until they are passed back to `dealloc` (or `realloc`).
+pub unsafe trait Alloc {
+    /// Returns a pointer to a block of memory fitting `layout`.
+    ///
+    /// # Safety
+    ///
+    /// The returned memory is uninitialized. It must be released with
+    /// `dealloc` on the same allocator using the same layout.
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Releases the block referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator for `layout`.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Returns a pointer to a block of zeroed memory fitting `layout`.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as `alloc` apply.
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        ptr::write_bytes(ptr.as_ptr(), 0, layout.size());
+        Ok(ptr)
+    }
+
+    /// Resizes the block referenced by `ptr` to `new_size` bytes.
+    ///
+    /// The contents are preserved up to the lesser of the new and old sizes.
+    /// On failure the original block is left intact.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator for `layout`.
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+        let new_ptr = self.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+
+    /// Returns a pointer to an uninitialized value of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be released with `dealloc_one`.
+    unsafe fn alloc_one<T>(&mut self) -> Result<NonNull<T>, AllocError>
+        where Self: Sized
+    {
+        self.alloc(Layout::new::<T>()).map(NonNull::cast)
+    }
+
+    /// Releases a value allocated with `alloc_one`, without dropping it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_one::<T>` on this allocator.
+    unsafe fn dealloc_one<T>(&mut self, ptr: NonNull<T>)
+        where Self: Sized
+    {
+        self.dealloc(ptr.cast(), Layout::new::<T>())
+    }
+
+    /// Returns a pointer to an uninitialized array of `n` values of type `T`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if the size of the array overflows.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be released with `dealloc_array` using the same `n`.
+    unsafe fn alloc_array<T>(&mut self, n: usize) -> Result<NonNull<T>, AllocError>
+        where Self: Sized
+    {
+        self.alloc(Layout::array::<T>(n)?).map(NonNull::cast)
+    }
+
+    /// Releases an array allocated with `alloc_array`, without dropping its
+    /// elements.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_array::<T>(n)` on this
+    /// allocator.
+    unsafe fn dealloc_array<T>(&mut self, ptr: NonNull<T>, n: usize)
+        where Self: Sized
+    {
+        // The layout was valid when the array was allocated
+        let layout = Layout::from_size_align_unchecked(n * mem::size_of::<T>(), mem::align_of::<T>());
+        self.dealloc(ptr.cast(), layout)
+    }
+}
+
+/// The heap, allocating through the crate's allocation functions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Heap;
+
+unsafe impl Alloc for Heap {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        ::try_allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        ::release(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        ::try_allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        ::try_reallocate(ptr, layout, new_size)
+    }
+}
+
+unsafe impl<A: Alloc + ?Sized> Alloc for &mut A {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        (**self).realloc(ptr, layout, new_size)
+    }
+}
+
+unsafe impl<A: Alloc + ?Sized> Alloc for Box<A> {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        (**self).realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, AllocError, Budget, Heap, Layout};
+    use std::boxed::Box;
+    use std::ptr::{self, NonNull};
+
+    /// Only implements the required methods, exercising the defaults.
+    struct Minimal {
+        live: usize,
+    }
+
+    unsafe impl Alloc for Minimal {
+        unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            let ptr = Heap.alloc(layout)?;
+            ptr::write_bytes(ptr.as_ptr(), 0xff, layout.size());
+            self.live += 1;
+            Ok(ptr)
+        }
+
+        unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+            self.live -= 1;
+            Heap.dealloc(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn test_heap() {
+        unsafe {
+            let layout = Layout::from_size_align(32, 8).unwrap();
+
+            let ptr = Heap.alloc_zeroed(layout).unwrap();
+            assert_eq!(0, *ptr.as_ptr().add(31));
+
+            let ptr = Heap.realloc(ptr, layout, 64).unwrap();
+            assert_eq!(0, *ptr.as_ptr().add(31));
+
+            Heap.dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_default_methods() {
+        let mut a = Minimal { live: 0 };
+
+        unsafe {
+            let layout = Layout::new::<[u32; 4]>();
+            let ptr = a.alloc_zeroed(layout).unwrap();
+            assert_eq!(0, *ptr.as_ptr().add(15));
+
+            *ptr.as_ptr() = 7;
+
+            let ptr = a.realloc(ptr, layout, 32).unwrap();
+            assert_eq!(7, *ptr.as_ptr());
+            assert_eq!(1, a.live);
+
+            a.dealloc(ptr, Layout::from_size_align(32, 4).unwrap());
+
+            let one = a.alloc_one::<u64>().unwrap();
+            assert_eq!(0, one.as_ptr() as usize & 7);
+            a.dealloc_one(one);
+
+            let arr = a.alloc_array::<u16>(10).unwrap();
+            a.dealloc_array(arr, 10);
+
+            assert_eq!(Err(AllocError::InvalidLayout), a.alloc_array::<u64>(usize::MAX));
+        }
+
+        assert_eq!(0, a.live);
+    }
+
+    // Generic code taking the allocator by value
+    unsafe fn exercise<A: Alloc>(mut alloc: A) {
+        let ptr = alloc.alloc_one::<u64>().unwrap();
+        ptr::write(ptr.as_ptr(), 7);
+        assert_eq!(7, *ptr.as_ptr());
+        alloc.dealloc_one(ptr);
+
+        let mut budget = Budget::new(alloc, 16);
+        assert_eq!(Err(AllocError::OutOfMemory), budget.alloc_array::<u64>(3));
+    }
+
+    #[test]
+    fn test_dyn_alloc() {
+        let mut minimal = Minimal { live: 0 };
+
+        unsafe {
+            // Picked at run time, then passed down by reference
+            let allocs: [&mut dyn Alloc; 2] = [&mut minimal, &mut Heap];
+
+            for alloc in allocs {
+                exercise(alloc);
+            }
+
+            let mut boxed: Box<dyn Alloc> = Box::new(Minimal { live: 0 });
+            let ptr = boxed.alloc(Layout::new::<[u32; 8]>()).unwrap();
+            let ptr = boxed.realloc(ptr, Layout::new::<[u32; 8]>(), 64).unwrap();
+            boxed.dealloc(ptr, Layout::from_size_align(64, 4).unwrap());
+            exercise(boxed);
+
+            exercise(&Heap);
+        }
+
+        assert_eq!(0, minimal.live);
+    }
+}
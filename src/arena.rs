@@ -0,0 +1,1300 @@
+//! Arena allocation.
+//!
+//! An arena hands out memory by bumping a pointer through large chunks,
+//! and releases everything at once when it is reset or dropped. This makes
+//! allocation very cheap for data that shares a lifetime, such as the nodes
+//! of a syntax tree.
+
+use {asan, valgrind, AllocError, Layout, Mutexed, RawBuf, SyncAlloc};
+
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem, ptr, slice, str};
+use core::cell::{Cell, RefCell};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+const INITIAL_CHUNK_SIZE: usize = 4096;
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A bump allocator backed by chunks allocated from the heap.
+///
+/// Allocating only needs a shared reference, so values allocated from the
+/// same arena can reference each other. Destructors of values placed in the
+/// arena are not run.
+#[derive(Debug)]
+pub struct Arena {
+    // Bump pointer and end of the current chunk
+    ptr: Cell<usize>,
+    end: Cell<usize>,
+
+    // Size of the next chunk to allocate
+    next_size: Cell<usize>,
+
+    // The first `active` chunks have been bumped through, the rest are kept
+    // for reuse by `reset_retain`
+    chunks: RefCell<Vec<Chunk>>,
+    active: Cell<usize>,
+
+    config: ArenaBuilder,
+}
+
+/// How the chunks of an `Arena` grow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Growth {
+    /// Each chunk is twice the size of the previous one, up to the maximum
+    /// chunk size.
+    Doubling,
+
+    /// Every chunk has the initial chunk size.
+    Fixed,
+}
+
+/// Options for building an `Arena`.
+///
+/// By default, the first chunk is 4 KiB and chunks double in size up to
+/// 1 MiB. Allocations larger than a chunk always get a chunk of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaBuilder {
+    initial_chunk_size: usize,
+    max_chunk_size: usize,
+    growth: Growth,
+}
+
+impl ArenaBuilder {
+    /// Returns the default options.
+    pub fn new() -> ArenaBuilder {
+        ArenaBuilder {
+            initial_chunk_size: INITIAL_CHUNK_SIZE,
+            max_chunk_size: MAX_CHUNK_SIZE,
+            growth: Growth::Doubling,
+        }
+    }
+
+    /// Sets the size of the first chunk.
+    ///
+    /// The maximum chunk size is raised to match if needed.
+    pub fn initial_chunk_size(mut self, size: usize) -> ArenaBuilder {
+        self.initial_chunk_size = cmp::max(size, 1);
+        self.max_chunk_size = cmp::max(self.max_chunk_size, self.initial_chunk_size);
+        self
+    }
+
+    /// Sets the size chunks stop growing at.
+    ///
+    /// The initial chunk size is lowered to match if needed.
+    pub fn max_chunk_size(mut self, size: usize) -> ArenaBuilder {
+        self.max_chunk_size = cmp::max(size, 1);
+        self.initial_chunk_size = cmp::min(self.initial_chunk_size, self.max_chunk_size);
+        self
+    }
+
+    /// Sets how chunks grow.
+    pub fn growth(mut self, growth: Growth) -> ArenaBuilder {
+        self.growth = growth;
+        self
+    }
+
+    /// Builds an empty arena with these options.
+    pub fn build(self) -> Arena {
+        Arena {
+            ptr: Cell::new(0),
+            end: Cell::new(0),
+            next_size: Cell::new(self.initial_chunk_size),
+            chunks: RefCell::new(Vec::new()),
+            active: Cell::new(0),
+            config: self,
+        }
+    }
+}
+
+impl Default for ArenaBuilder {
+    fn default() -> ArenaBuilder {
+        ArenaBuilder::new()
+    }
+}
+
+/// A position in an `Arena`, returned by `Arena::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    chunks: usize,
+    ptr: usize,
+    next_size: usize,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Chunk {
+    fn fits(&self, layout: Layout) -> bool {
+        let start = self.ptr.as_ptr() as usize;
+        let padding = start.wrapping_neg() & (layout.align() - 1);
+        padding.saturating_add(layout.size()) <= self.layout.size()
+    }
+}
+
+unsafe impl Send for Arena {}
+
+impl Arena {
+    /// Creates an empty arena. No memory is allocated until the first
+    /// allocation.
+    pub fn new() -> Arena {
+        ArenaBuilder::new().build()
+    }
+
+    /// Creates an arena with a first chunk of at least `capacity` bytes
+    /// already allocated.
+    ///
+    /// Later chunks follow the default growth policy.
+    pub fn with_capacity(capacity: usize) -> Result<Arena, AllocError> {
+        let arena = Arena::new();
+
+        if capacity != 0 {
+            arena.next_size.set(cmp::max(capacity, INITIAL_CHUNK_SIZE));
+            arena.grow(Layout::from_size_align(0, 1)?)?;
+        }
+
+        Ok(arena)
+    }
+
+    /// Returns an `ArenaBuilder` for configuring a new arena.
+    pub fn builder() -> ArenaBuilder {
+        ArenaBuilder::new()
+    }
+
+    /// Returns the total size of the chunks allocated by the arena, in bytes.
+    ///
+    /// This includes space not handed out yet and chunks retained by
+    /// `reset_retain`.
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks.borrow().iter().map(|chunk| chunk.layout.size()).sum()
+    }
+
+    /// Returns the number of chunks allocated by the arena.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+
+    /// Returns a pointer to a block of memory fitting `layout`.
+    ///
+    /// The memory is uninitialized and lives until the arena is reset or
+    /// dropped. Zero-sized layouts don't consume any memory.
+    pub fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+        }
+
+        if let Some(ptr) = self.bump(layout) {
+            return Ok(ptr);
+        }
+
+        self.grow(layout)?;
+        Ok(self.bump(layout).expect("new chunk fits the layout"))
+    }
+
+    /// Moves `value` into the arena, returning a reference to it.
+    ///
+    /// The value is never dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_val<T>(&self, value: T) -> &mut T {
+        let ptr = match self.alloc(Layout::new::<T>()) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let ptr = match self.alloc(Layout::for_value(src)) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            slice::from_raw_parts_mut(ptr, src.len())
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(src.as_bytes());
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Returns a marker for the current position of the arena.
+    ///
+    /// Passing the marker to `rewind` releases everything allocated since.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            chunks: self.active.get(),
+            ptr: self.ptr.get(),
+            next_size: self.next_size.get(),
+        }
+    }
+
+    /// Releases everything allocated since `checkpoint` was taken.
+    ///
+    /// Chunks put to use since are returned to the heap, along with any
+    /// chunks retained by `reset_retain`, and allocation continues from the
+    /// position of the checkpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the checkpoint was not taken from this arena, or if the
+    /// arena was already reset or rewound past it.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        let chunks = self.chunks.get_mut();
+
+        if checkpoint.chunks > self.active.get() {
+            panic!("checkpoint does not belong to this arena");
+        }
+
+        let end = match checkpoint.chunks.checked_sub(1).map(|i| &chunks[i]) {
+            Some(chunk) => {
+                let start = chunk.ptr.as_ptr() as usize;
+                let end = start + chunk.layout.size();
+
+                if checkpoint.ptr < start || checkpoint.ptr > end {
+                    panic!("checkpoint does not belong to this arena");
+                }
+
+                end
+            }
+            None => 0,
+        };
+
+        for chunk in chunks.drain(checkpoint.chunks..) {
+            release_chunk(chunk);
+        }
+
+        if end != 0 {
+            asan::poison(checkpoint.ptr as *const u8, end - checkpoint.ptr);
+            valgrind::make_noaccess(checkpoint.ptr as *const u8, end - checkpoint.ptr);
+        }
+
+        self.ptr.set(checkpoint.ptr);
+        self.end.set(end);
+        self.active.set(checkpoint.chunks);
+        self.next_size.set(checkpoint.next_size);
+    }
+
+    /// Releases all memory allocated from the arena.
+    pub fn reset(&mut self) {
+        self.release_chunks();
+        self.ptr.set(0);
+        self.end.set(0);
+        self.active.set(0);
+        self.next_size.set(self.config.initial_chunk_size);
+    }
+
+    /// Frees everything allocated from the arena, but keeps its chunks to
+    /// serve later allocations.
+    ///
+    /// An arena that is reset this way after each request settles at the
+    /// capacity the largest request needed, without going back to the heap.
+    /// Use `reset` to release the chunks instead.
+    pub fn reset_retain(&mut self) {
+        for chunk in self.chunks.get_mut().iter() {
+            asan::poison(chunk.ptr.as_ptr(), chunk.layout.size());
+            valgrind::make_noaccess(chunk.ptr.as_ptr(), chunk.layout.size());
+        }
+
+        self.ptr.set(0);
+        self.end.set(0);
+        self.active.set(0);
+    }
+
+    /// Returns the chunks retained by `reset_retain` that are not in use
+    /// again to the heap.
+    ///
+    /// Blocks allocated since the last reset stay valid.
+    pub fn trim(&mut self) {
+        let active = self.active.get();
+
+        for chunk in self.chunks.get_mut().drain(active..) {
+            release_chunk(chunk);
+        }
+    }
+
+    fn bump(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let start = self.ptr.get().checked_add(layout.align() - 1)? & !(layout.align() - 1);
+        let end = start.checked_add(layout.size())?;
+
+        if self.ptr.get() == 0 || end > self.end.get() {
+            return None;
+        }
+
+        self.ptr.set(end);
+        asan::unpoison(start as *const u8, layout.size());
+        valgrind::make_undefined(start as *const u8, layout.size());
+
+        NonNull::new(start as *mut u8)
+    }
+
+    fn grow(&self, layout: Layout) -> Result<(), AllocError> {
+        let mut chunks = self.chunks.borrow_mut();
+        let active = self.active.get();
+
+        // Reuse a retained chunk if one fits; their order doesn't matter
+        let i = match chunks[active..].iter().position(|chunk| chunk.fits(layout)) {
+            Some(i) => active + i,
+            None => {
+                chunks.push(self.new_chunk(layout)?);
+                chunks.len() - 1
+            }
+        };
+
+        chunks.swap(active, i);
+
+        let chunk = &chunks[active];
+        self.ptr.set(chunk.ptr.as_ptr() as usize);
+        self.end.set(chunk.ptr.as_ptr() as usize + chunk.layout.size());
+        self.active.set(active + 1);
+
+        Ok(())
+    }
+
+    fn new_chunk(&self, layout: Layout) -> Result<Chunk, AllocError> {
+        // Room for the block even when the chunk start needs padding
+        let min = layout.size().checked_add(layout.align()).ok_or(AllocError::InvalidLayout)?;
+        let size = cmp::max(self.next_size.get(), min);
+        let align = cmp::max(layout.align(), mem::align_of::<usize>());
+
+        let chunk_layout = Layout::from_size_align(size, align)?;
+        let ptr = unsafe { ::try_allocate(chunk_layout)? };
+
+        // Memory is only accessible once handed out
+        asan::poison(ptr.as_ptr(), size);
+        valgrind::make_noaccess(ptr.as_ptr(), size);
+
+        let next = match self.config.growth {
+            Growth::Doubling => self.next_size.get().saturating_mul(2),
+            Growth::Fixed => self.config.initial_chunk_size,
+        };
+
+        self.next_size.set(cmp::min(next, self.config.max_chunk_size));
+
+        Ok(Chunk { ptr, layout: chunk_layout })
+    }
+
+    fn release_chunks(&self) {
+        for chunk in self.chunks.borrow_mut().drain(..) {
+            release_chunk(chunk);
+        }
+    }
+}
+
+fn release_chunk(chunk: Chunk) {
+    // The heap expects to get back memory it can access
+    asan::unpoison(chunk.ptr.as_ptr(), chunk.layout.size());
+    unsafe { ::release(chunk.ptr, chunk.layout) };
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.release_chunks();
+    }
+}
+
+/// An arena that can be allocated from by multiple threads at once.
+///
+/// Each thread bumps the pointer of the current chunk with an atomic
+/// compare-and-swap, and a thread that finds the chunk full installs a new
+/// one the same way, so allocating never takes a lock. Chunks grow like those
+/// of an `Arena` with the default options. Destructors of values placed in
+/// the arena are not run.
+pub struct SyncArena {
+    // Most recent chunk, linked to the older ones
+    current: AtomicPtr<SyncChunk>,
+
+    // Size of the next chunk to allocate
+    next_size: AtomicUsize,
+}
+
+// Header at the start of each chunk of a `SyncArena`
+struct SyncChunk {
+    prev: *mut SyncChunk,
+    layout: Layout,
+
+    // Address of the first free byte and end of the chunk
+    top: AtomicUsize,
+    end: usize,
+}
+
+unsafe impl Send for SyncArena {}
+unsafe impl Sync for SyncArena {}
+
+impl SyncArena {
+    /// Creates an empty arena. No memory is allocated until the first
+    /// allocation.
+    pub fn new() -> SyncArena {
+        SyncArena {
+            current: AtomicPtr::new(ptr::null_mut()),
+            next_size: AtomicUsize::new(INITIAL_CHUNK_SIZE),
+        }
+    }
+
+    /// Returns the total size of the chunks allocated by the arena, in bytes.
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks().map(|chunk| chunk.layout.size()).sum()
+    }
+
+    /// Returns the number of chunks allocated by the arena.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks().count()
+    }
+
+    /// Returns a pointer to a block of memory fitting `layout`.
+    ///
+    /// The memory is uninitialized and lives until the arena is reset or
+    /// dropped. Zero-sized layouts don't consume any memory.
+    pub fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+        }
+
+        loop {
+            let chunk = self.current.load(Ordering::Acquire);
+
+            if !chunk.is_null() {
+                if let Some(ptr) = unsafe { (*chunk).bump(layout) } {
+                    return Ok(ptr);
+                }
+            }
+
+            self.grow(chunk, layout)?;
+        }
+    }
+
+    /// Moves `value` into the arena, returning a reference to it.
+    ///
+    /// The value is never dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_val<T>(&self, value: T) -> &mut T {
+        let ptr = match self.alloc(Layout::new::<T>()) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let ptr = match self.alloc(Layout::for_value(src)) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            slice::from_raw_parts_mut(ptr, src.len())
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(src.as_bytes());
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Releases all memory allocated from the arena.
+    pub fn reset(&mut self) {
+        self.release_chunks();
+        *self.next_size.get_mut() = INITIAL_CHUNK_SIZE;
+    }
+
+    // Installs a new chunk in place of `old`, unless another thread already
+    // replaced it
+    fn grow(&self, old: *mut SyncChunk, layout: Layout) -> Result<(), AllocError> {
+        let header = mem::size_of::<SyncChunk>();
+
+        // Room for the header and the block, even when the block needs padding
+        let min = layout.size()
+            .checked_add(layout.align())
+            .and_then(|n| n.checked_add(header))
+            .ok_or(AllocError::InvalidLayout)?;
+
+        let next_size = self.next_size.load(Ordering::Relaxed);
+        let size = cmp::max(next_size, min);
+        let align = cmp::max(layout.align(), mem::align_of::<SyncChunk>());
+
+        let chunk_layout = Layout::from_size_align(size, align)?;
+        let ptr = unsafe { ::try_allocate(chunk_layout)? };
+        let chunk = ptr.cast::<SyncChunk>().as_ptr();
+
+        unsafe {
+            ptr::write(chunk, SyncChunk {
+                prev: old,
+                layout: chunk_layout,
+                top: AtomicUsize::new(ptr.as_ptr() as usize + header),
+                end: ptr.as_ptr() as usize + size,
+            });
+        }
+
+        match self.current.compare_exchange(old, chunk, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                let next = cmp::min(next_size.saturating_mul(2), MAX_CHUNK_SIZE);
+                self.next_size.store(cmp::max(next, next_size), Ordering::Relaxed);
+            }
+            Err(_) => unsafe { ::release(ptr, chunk_layout) },
+        }
+
+        Ok(())
+    }
+
+    fn chunks(&self) -> SyncChunks<'_> {
+        SyncChunks {
+            chunk: self.current.load(Ordering::Acquire),
+            _marker: ::core::marker::PhantomData,
+        }
+    }
+
+    fn release_chunks(&mut self) {
+        let mut chunk = mem::replace(self.current.get_mut(), ptr::null_mut());
+
+        while !chunk.is_null() {
+            unsafe {
+                let SyncChunk { prev, layout, .. } = ptr::read(chunk);
+                ::release(NonNull::new_unchecked(chunk as *mut u8), layout);
+                chunk = prev;
+            }
+        }
+    }
+}
+
+impl SyncChunk {
+    fn bump(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let mut top = self.top.load(Ordering::Relaxed);
+
+        loop {
+            let start = top.checked_add(layout.align() - 1)? & !(layout.align() - 1);
+            let end = start.checked_add(layout.size())?;
+
+            if end > self.end {
+                return None;
+            }
+
+            match self.top.compare_exchange_weak(top, end, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return NonNull::new(start as *mut u8),
+                Err(actual) => top = actual,
+            }
+        }
+    }
+}
+
+// Walks the chunks of a `SyncArena`, newest first
+struct SyncChunks<'a> {
+    chunk: *mut SyncChunk,
+    _marker: ::core::marker::PhantomData<&'a SyncArena>,
+}
+
+impl<'a> Iterator for SyncChunks<'a> {
+    type Item = &'a SyncChunk;
+
+    fn next(&mut self) -> Option<&'a SyncChunk> {
+        if self.chunk.is_null() {
+            return None;
+        }
+
+        // Chunks are only released through `&mut SyncArena`
+        let chunk = unsafe { &*self.chunk };
+        self.chunk = chunk.prev;
+        Some(chunk)
+    }
+}
+
+impl Default for SyncArena {
+    fn default() -> SyncArena {
+        SyncArena::new()
+    }
+}
+
+impl fmt::Debug for SyncArena {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SyncArena")
+            .field("chunks", &self.chunk_count())
+            .field("allocated_bytes", &self.allocated_bytes())
+            .finish()
+    }
+}
+
+impl Drop for SyncArena {
+    fn drop(&mut self) {
+        self.release_chunks();
+    }
+}
+
+unsafe impl SyncAlloc for SyncArena {
+    unsafe fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        SyncArena::alloc(self, layout)
+    }
+
+    // Blocks are released all at once, when the arena is reset or dropped
+    unsafe fn dealloc(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+/// An `Arena` that can be allocated from by multiple threads, one at a time.
+///
+/// Every allocation takes a spin lock around the arena, see `Mutexed`. This is
+/// slower than `SyncArena` when threads contend, but keeps the options and
+/// the chunk reuse of `Arena`. Destructors of values placed in the arena are
+/// not run.
+#[derive(Debug, Default)]
+pub struct SharedArena {
+    arena: Mutexed<Arena>,
+}
+
+impl SharedArena {
+    /// Creates an empty arena with the default options. No memory is
+    /// allocated until the first allocation.
+    pub fn new() -> SharedArena {
+        SharedArena::from(Arena::new())
+    }
+
+    /// Returns the total size of the chunks allocated by the arena, in bytes.
+    pub fn allocated_bytes(&self) -> usize {
+        self.arena.lock().allocated_bytes()
+    }
+
+    /// Returns the number of chunks allocated by the arena.
+    pub fn chunk_count(&self) -> usize {
+        self.arena.lock().chunk_count()
+    }
+
+    /// Returns a pointer to a block of memory fitting `layout`.
+    ///
+    /// The memory is uninitialized and lives until the arena is reset or
+    /// dropped. Zero-sized layouts don't consume any memory.
+    pub fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.arena.lock().alloc(layout)
+    }
+
+    /// Moves `value` into the arena, returning a reference to it.
+    ///
+    /// The value is never dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_val<T>(&self, value: T) -> &mut T {
+        let ptr = match self.alloc(Layout::new::<T>()) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        // Chunks outlive the lock, they are only released through
+        // `&mut SharedArena`
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let ptr = match self.alloc(Layout::for_value(src)) {
+            Ok(ptr) => ptr.cast::<T>().as_ptr(),
+            Err(e) => panic!("arena allocation failed: {}", e),
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            slice::from_raw_parts_mut(ptr, src.len())
+        }
+    }
+
+    /// Copies `src` into the arena, returning a reference to the copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(src.as_bytes());
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Returns the wrapped arena, to reset or trim it.
+    pub fn get_mut(&mut self) -> &mut Arena {
+        self.arena.get_mut()
+    }
+
+    /// Consumes the wrapper, returning the arena.
+    pub fn into_inner(self) -> Arena {
+        self.arena.into_inner()
+    }
+}
+
+impl From<Arena> for SharedArena {
+    fn from(arena: Arena) -> SharedArena {
+        SharedArena { arena: Mutexed::new(arena) }
+    }
+}
+
+unsafe impl SyncAlloc for SharedArena {
+    unsafe fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        SharedArena::alloc(self, layout)
+    }
+
+    // Blocks are released all at once, when the arena is reset or dropped
+    unsafe fn dealloc(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+/// An arena of values of type `T`.
+///
+/// Unlike `Arena`, a `TypedArena` drops the values placed in it when it is
+/// dropped, and can iterate over them.
+pub struct TypedArena<T> {
+    // Every chunk but the last is full
+    chunks: RefCell<Vec<RawBuf<T>>>,
+
+    // Number of values in the last chunk
+    len: Cell<usize>,
+}
+
+impl<T> TypedArena<T> {
+    /// Creates an empty arena. No memory is allocated until the first
+    /// allocation.
+    pub fn new() -> TypedArena<T> {
+        TypedArena {
+            chunks: RefCell::new(Vec::new()),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Moves `value` into the arena, returning a reference to it.
+    ///
+    /// The value is dropped when the arena is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc(&self, value: T) -> &mut T {
+        let mut chunks = self.chunks.borrow_mut();
+
+        let full = match chunks.last() {
+            Some(chunk) => self.len.get() == chunk.capacity(),
+            None => true,
+        };
+
+        if full {
+            let cap = match chunks.last() {
+                Some(chunk) => cmp::max(chunk.capacity().saturating_mul(2), 1),
+                None => cmp::max(INITIAL_CHUNK_SIZE / cmp::max(mem::size_of::<T>(), 1), 1),
+            };
+
+            // Cap the chunk size, but always fit at least one value
+            let max = cmp::max(MAX_CHUNK_SIZE / cmp::max(mem::size_of::<T>(), 1), 1);
+
+            match RawBuf::with_capacity(cmp::min(cap, max)) {
+                Ok(chunk) => chunks.push(chunk),
+                Err(e) => panic!("arena allocation failed: {}", e),
+            }
+
+            self.len.set(0);
+        }
+
+        unsafe {
+            let ptr = chunks.last().unwrap().ptr().add(self.len.get());
+            ptr::write(ptr, value);
+            self.len.set(self.len.get() + 1);
+            &mut *ptr
+        }
+    }
+
+    /// Returns the number of values in the arena.
+    pub fn len(&self) -> usize {
+        let chunks = self.chunks.borrow();
+
+        match chunks.split_last() {
+            Some((_, full)) => full.iter().map(RawBuf::capacity).sum::<usize>() + self.len.get(),
+            None => 0,
+        }
+    }
+
+    /// Returns `true` if the arena contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the values in the arena, in allocation order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            chunks: self.chunks.get_mut(),
+            last_len: self.len.get(),
+            current: [].iter_mut(),
+        }
+    }
+}
+
+impl<T> Default for TypedArena<T> {
+    fn default() -> TypedArena<T> {
+        TypedArena::new()
+    }
+}
+
+impl<T> fmt::Debug for TypedArena<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TypedArena")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<T> Drop for TypedArena<T> {
+    fn drop(&mut self) {
+        for value in self.iter_mut() {
+            unsafe { ptr::drop_in_place(value) };
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut TypedArena<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+/// An iterator over the values of a `TypedArena`.
+pub struct IterMut<'a, T: 'a> {
+    chunks: &'a [RawBuf<T>],
+    last_len: usize,
+    current: slice::IterMut<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<&'a mut T> {
+        loop {
+            if let Some(value) = self.current.next() {
+                return Some(value);
+            }
+
+            let (chunk, rest) = self.chunks.split_first()?;
+            let len = if rest.is_empty() { self.last_len } else { chunk.capacity() };
+
+            self.chunks = rest;
+            self.current = unsafe { slice::from_raw_parts_mut(chunk.ptr(), len).iter_mut() };
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for IterMut<'a, T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("IterMut").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arena, Growth, SharedArena, SyncArena, TypedArena};
+    use Layout;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_arena_alloc() {
+        let arena = Arena::new();
+
+        let a = arena.alloc_val(1u8);
+        let b = arena.alloc_val(2u64);
+        let c = arena.alloc_val([3u16; 3]);
+
+        assert_eq!(0, b as *mut u64 as usize & 7);
+        assert_eq!((1, 2, [3, 3, 3]), (*a, *b, *c));
+
+        *a = 10;
+        assert_eq!(10, *a);
+        assert_eq!(2, *b);
+    }
+
+    #[test]
+    fn test_arena_alignment() {
+        let arena = Arena::new();
+
+        for &align in &[1, 2, 8, 64, 256] {
+            arena.alloc(Layout::from_size_align(1, 1).unwrap()).unwrap();
+            let ptr = arena.alloc(Layout::from_size_align(3, align).unwrap()).unwrap();
+            assert_eq!(0, ptr.as_ptr() as usize & (align - 1));
+        }
+    }
+
+    #[test]
+    fn test_arena_large_and_many() {
+        let arena = Arena::new();
+
+        // Larger than any chunk
+        let big = arena.alloc(Layout::from_size_align(4 << 20, 16).unwrap()).unwrap();
+        unsafe { *big.as_ptr().add((4 << 20) - 1) = 1 };
+
+        let vals: Vec<&mut usize> = (0..10_000).map(|i| arena.alloc_val(i)).collect();
+
+        for (i, v) in vals.iter().enumerate() {
+            assert_eq!(i, **v);
+        }
+    }
+
+    #[test]
+    fn test_arena_zero_size() {
+        let arena = Arena::new();
+        let ptr = arena.alloc(Layout::from_size_align(0, 8).unwrap()).unwrap();
+        assert_eq!(8, ptr.as_ptr() as usize);
+        arena.alloc_val(());
+    }
+
+    #[test]
+    fn test_arena_alloc_str_and_slice() {
+        let arena = Arena::new();
+
+        let mut names = Vec::new();
+
+        for i in 0..1000 {
+            let name = ::std::format!("ident_{}", i);
+            names.push(&*arena.alloc_str(&name));
+        }
+
+        assert_eq!("ident_0", names[0]);
+        assert_eq!("ident_999", names[999]);
+
+        let slice = arena.alloc_slice_copy(&[1u32, 2, 3]);
+        slice[0] = 10;
+        assert_eq!(&[10, 2, 3], slice);
+
+        assert_eq!("", arena.alloc_str(""));
+        assert!(arena.alloc_slice_copy::<u64>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_arena_rewind() {
+        let mut arena = Arena::new();
+        arena.alloc_val(1u32);
+
+        let checkpoint = arena.checkpoint();
+        let p1 = arena.alloc_val(2u32) as *mut u32;
+
+        // Spills into more chunks
+        for i in 0..10_000 {
+            arena.alloc_val(i as u64);
+        }
+
+        arena.rewind(checkpoint);
+        assert_eq!(checkpoint, arena.checkpoint());
+
+        // Allocation resumes where the checkpoint was taken
+        let p2 = arena.alloc_val(3u32) as *mut u32;
+        assert_eq!(p1, p2);
+
+        let empty = Arena::new().checkpoint();
+        arena.rewind(empty);
+        assert_eq!(4, *arena.alloc_val(4));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_arena_rewind_foreign() {
+        let other = Arena::new();
+        other.alloc_val(0u8);
+        other.alloc_val([0u8; 16 * 1024]);
+
+        let mut arena = Arena::new();
+        arena.rewind(other.checkpoint());
+    }
+
+    #[test]
+    fn test_arena_builder() {
+        let arena = Arena::builder()
+            .initial_chunk_size(128)
+            .growth(Growth::Fixed)
+            .build();
+
+        assert_eq!(0, arena.chunk_count());
+
+        for _ in 0..4 {
+            arena.alloc_val([0u8; 100]);
+        }
+
+        assert_eq!(4, arena.chunk_count());
+        assert_eq!(4 * 128, arena.allocated_bytes());
+
+        // Oversized allocations get a chunk of their own
+        arena.alloc_val([0u8; 1000]);
+        assert!(arena.allocated_bytes() >= 4 * 128 + 1000);
+
+        let arena = Arena::builder()
+            .initial_chunk_size(100)
+            .max_chunk_size(300)
+            .build();
+
+        // Chunks of 100, 200 and 300 bytes hold 1, 2 and 3 values
+        for _ in 0..7 {
+            arena.alloc_val([0u8; 90]);
+        }
+
+        assert_eq!(100 + 200 + 300 + 300, arena.allocated_bytes());
+    }
+
+    #[test]
+    fn test_arena_with_capacity() {
+        let arena = Arena::with_capacity(64 * 1024).unwrap();
+        assert_eq!(1, arena.chunk_count());
+        assert_eq!(64 * 1024, arena.allocated_bytes());
+
+        for _ in 0..1000 {
+            arena.alloc_val(0u64);
+        }
+
+        assert_eq!(1, arena.chunk_count());
+    }
+
+    #[test]
+    fn test_arena_reset_retain() {
+        let mut arena = Arena::builder().initial_chunk_size(1024).build();
+
+        for _ in 0..3 {
+            for i in 0..1000u64 {
+                arena.alloc_val(i);
+            }
+
+            let chunks = arena.chunk_count();
+            let bytes = arena.allocated_bytes();
+            arena.reset_retain();
+
+            // The retained chunks hold the same values again
+            for i in 0..1000u64 {
+                assert_eq!(i, *arena.alloc_val(i));
+            }
+
+            assert_eq!(chunks, arena.chunk_count());
+            assert_eq!(bytes, arena.allocated_bytes());
+            arena.reset_retain();
+        }
+
+        // Too large for any retained chunk
+        arena.alloc_val([0u8; 64 * 1024]);
+        assert_eq!(0, arena.alloc_val([0u8; 64 * 1024])[0]);
+
+        arena.reset();
+        assert_eq!(0, arena.chunk_count());
+    }
+
+    #[test]
+    fn test_arena_trim() {
+        let mut arena = Arena::builder().initial_chunk_size(1024).build();
+
+        for i in 0..1000u64 {
+            arena.alloc_val(i);
+        }
+
+        let chunks = arena.chunk_count();
+        arena.reset_retain();
+
+        let val = arena.alloc_val(7u64) as *mut u64;
+        arena.trim();
+        assert_eq!(1, arena.chunk_count());
+        assert!(chunks > 1);
+        assert_eq!(7, unsafe { *val });
+    }
+
+    #[test]
+    fn test_arena_reset() {
+        let mut arena = Arena::new();
+        arena.alloc_val([0u8; 100]);
+        arena.reset();
+        assert_eq!(5, *arena.alloc_val(5));
+    }
+
+    #[test]
+    fn test_typed_arena() {
+        let mut arena = TypedArena::new();
+        let first = arena.alloc(0u64);
+
+        for i in 1..10_000 {
+            let v = arena.alloc(i);
+            assert_eq!(i, *v);
+        }
+
+        *first = 100;
+        assert_eq!(10_000, arena.len());
+
+        let values: Vec<u64> = arena.iter_mut().map(|v| *v).collect();
+        assert_eq!(100, values[0]);
+        assert_eq!((1..10_000).collect::<Vec<_>>(), &values[1..]);
+    }
+
+    #[test]
+    fn test_typed_arena_drop() {
+        let rc = Rc::new(());
+
+        {
+            let arena = TypedArena::new();
+
+            for _ in 0..1000 {
+                arena.alloc(rc.clone());
+            }
+
+            assert_eq!(1001, Rc::strong_count(&rc));
+        }
+
+        assert_eq!(1, Rc::strong_count(&rc));
+    }
+
+    #[test]
+    fn test_typed_arena_zero_sized() {
+        let mut arena = TypedArena::new();
+
+        for _ in 0..100 {
+            arena.alloc(());
+        }
+
+        assert_eq!(100, arena.len());
+        assert_eq!(100, arena.iter_mut().count());
+    }
+
+    #[test]
+    fn test_sync_arena() {
+        let arena = SyncArena::new();
+
+        let a = arena.alloc_val(1u8);
+        let b = arena.alloc_val(2u64);
+        assert_eq!(0, b as *mut u64 as usize & 7);
+        assert_eq!((1, 2), (*a, *b));
+        assert_eq!("hello", arena.alloc_str("hello"));
+
+        let big = arena.alloc(Layout::from_size_align(4 << 20, 256).unwrap()).unwrap();
+        assert_eq!(0, big.as_ptr() as usize & 255);
+        assert_eq!(2, arena.chunk_count());
+        assert!(arena.allocated_bytes() > 4 << 20);
+    }
+
+    #[test]
+    fn test_sync_arena_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let arena = Arc::new(SyncArena::new());
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let arena = arena.clone();
+
+            thread::spawn(move || {
+                let vals: Vec<usize> = (0..10_000)
+                    .map(|i| arena.alloc_val(t * 10_000 + i) as *mut usize as usize)
+                    .collect();
+
+                for (i, &ptr) in vals.iter().enumerate() {
+                    assert_eq!(t * 10_000 + i, unsafe { *(ptr as *const usize) });
+                }
+
+                vals
+            })
+        }).collect();
+
+        let mut all: Vec<usize> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+        all.sort();
+        all.dedup();
+        assert_eq!(40_000, all.len());
+
+        let mut arena = Arc::try_unwrap(arena).unwrap();
+        arena.reset();
+        assert_eq!(0, arena.chunk_count());
+    }
+
+    #[test]
+    fn test_shared_arena() {
+        use std::sync::Arc;
+        use std::thread;
+        use SyncAlloc;
+
+        let arena = Arc::new(SharedArena::from(Arena::builder().growth(Growth::Fixed).build()));
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let arena = arena.clone();
+
+            thread::spawn(move || {
+                let vals: Vec<&mut usize> = (0..1000).map(|i| arena.alloc_val(t * 1000 + i)).collect();
+
+                for (i, val) in vals.iter().enumerate() {
+                    assert_eq!(t * 1000 + i, **val);
+                }
+
+                assert_eq!("hello", arena.alloc_str("hello"));
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Shared as an allocator, blocks are only released on reset
+        unsafe {
+            let shared: &dyn SyncAlloc = &*arena;
+            let layout = Layout::from_size_align(100, 16).unwrap();
+            let ptr = shared.alloc(layout).unwrap();
+            assert_eq!(0, ptr.as_ptr() as usize & 15);
+            shared.dealloc(ptr, layout);
+        }
+
+        let mut arena = Arc::try_unwrap(arena).unwrap();
+        assert_eq!(arena.allocated_bytes(), arena.chunk_count() * 4096);
+
+        arena.get_mut().reset_retain();
+        assert!(arena.into_inner().chunk_count() > 1);
+    }
+}
@@ -0,0 +1,30 @@
+//! AddressSanitizer annotations.
+//!
+//! Arenas and slabs poison the parts of their chunks that are not handed
+//! out, so ASan reports accesses to slots that were released, overflow into
+//! a neighboring slot or outlive an arena reset.
+//!
+//! The annotations are only made with the `sanitize` feature, which requires
+//! building with `-Zsanitizer=address` so the ASan runtime is linked in.
+
+#![cfg_attr(not(feature = "sanitize"), allow(unused_variables))]
+
+#[cfg(feature = "sanitize")]
+extern "C" {
+    fn __asan_poison_memory_region(addr: *const u8, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+}
+
+/// Marks `len` bytes at `ptr` as off limits.
+#[inline]
+pub fn poison(ptr: *const u8, len: usize) {
+    #[cfg(feature = "sanitize")]
+    unsafe { __asan_poison_memory_region(ptr, len) }
+}
+
+/// Makes `len` bytes at `ptr` accessible again.
+#[inline]
+pub fn unpoison(ptr: *const u8, len: usize) {
+    #[cfg(feature = "sanitize")]
+    unsafe { __asan_unpoison_memory_region(ptr, len) }
+}
@@ -0,0 +1,154 @@
+use Layout;
+
+use alloc::boxed::Box;
+use core::{cmp, ptr};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// A source of memory for the heap allocation functions, installed at run time
+/// with `set_backend`.
+///
+/// The methods are called with layouts that have already been validated: the
+/// size is not 0 and the alignment is no larger than `MAX_ALIGN`. Blocks are
+/// always released or resized with the layout they were allocated with, its
+/// size being any value between the requested and the usable size.
+///
+/// A backend adding tracking or instrumentation usually wraps
+/// `DefaultBackend`, so the blocks allocated before it was installed can still
+/// be released.
+///
+/// # Safety
+///
+/// Implementations must return null or a block of memory fitting the layout,
+/// that stays valid until it is deallocated.
+pub unsafe trait Backend: Sync {
+    /// Return a pointer to a block of memory fitting `layout`, or null on
+    /// failure.
+    ///
+    /// # Safety
+    ///
+    /// `layout` must have a size other than 0 and an alignment no larger
+    /// than `MAX_ALIGN`.
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Return a pointer to a zeroed block of memory fitting `layout`, or null
+    /// on failure.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as `allocate` apply.
+    unsafe fn allocate_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocate(layout);
+
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, 0, layout.size());
+        }
+
+        ptr
+    }
+
+    /// Deallocates the block referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this backend for `layout`.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+
+    /// Resize the block referenced by `ptr` to `size` bytes, or return null
+    /// and leave it intact on failure.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this backend for `layout`, and `size`
+    /// must not be 0.
+    unsafe fn reallocate(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        let new_ptr = self.allocate(Layout::from_size_align_unchecked(size, layout.align()));
+
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), size));
+            self.deallocate(ptr, layout);
+        }
+
+        new_ptr
+    }
+
+    /// Returns the usable size of a block allocated with `size` and `align`.
+    fn usable_size(&self, size: usize, _align: usize) -> usize {
+        size
+    }
+
+    /// Returns `true` if a block allocated with `size` and `align` comes from
+    /// the global allocator, and can be handed over to `Vec` or `Box`.
+    fn is_global(&self, _size: usize, _align: usize) -> bool {
+        false
+    }
+}
+
+/// The backend selected by the crate features, used until `set_backend` is
+/// called.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultBackend;
+
+unsafe impl Backend for DefaultBackend {
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
+        super::builtin::allocate(layout)
+    }
+
+    unsafe fn allocate_zeroed(&self, layout: Layout) -> *mut u8 {
+        super::builtin::allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        super::builtin::deallocate(ptr, layout)
+    }
+
+    unsafe fn reallocate(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        super::builtin::reallocate(ptr, layout, size)
+    }
+
+    fn usable_size(&self, size: usize, align: usize) -> usize {
+        super::builtin::usable_size(size, align)
+    }
+
+    fn is_global(&self, size: usize, align: usize) -> bool {
+        super::builtin::from_global(size, align)
+    }
+}
+
+// The installed backend, or null for `DefaultBackend`
+static BACKEND: AtomicPtr<&'static dyn Backend> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs `backend` as the source of memory for `allocate`, `deallocate`
+/// and the other heap allocation functions, replacing the one selected by the
+/// crate features.
+///
+/// This lets an application pick its allocator at startup, without building
+/// every crate depending on this one with different features. The arenas,
+/// pools and other allocators of the crate get their chunks from the new
+/// backend as well.
+///
+/// # Safety
+///
+/// Blocks are released to the backend installed at the time, so no block
+/// allocated by the previous backend may be live, unless the new backend can
+/// release them too, as when it wraps `DefaultBackend`. In practice, this is
+/// called at the start of `main`, before anything allocates through the
+/// crate.
+pub unsafe fn set_backend(backend: &'static dyn Backend) {
+    // The reference is wide, so it is boxed to be swapped atomically. Boxes
+    // of previous backends are leaked, as other threads may still read them
+    let backend = Box::into_raw(Box::new(backend));
+    BACKEND.store(backend, Ordering::Release);
+}
+
+/// Returns the installed backend, or `None` for `DefaultBackend`.
+#[inline]
+pub fn current() -> Option<&'static dyn Backend> {
+    let backend = BACKEND.load(Ordering::Acquire);
+
+    if backend.is_null() {
+        None
+    } else {
+        // Only ever stored from a leaked box
+        Some(unsafe { *backend })
+    }
+}
@@ -0,0 +1,78 @@
+use {round_up, Layout};
+
+use core::ffi::c_void;
+
+/// `mallocx` accepts any power of 2.
+pub const MAX_ALIGN: usize = isize::MAX as usize + 1;
+
+/// The alignment guaranteed without passing `MALLOCX_ALIGN`.
+#[cfg(target_pointer_width = "64")]
+const MIN_ALIGN: usize = 16;
+
+#[cfg(not(target_pointer_width = "64"))]
+const MIN_ALIGN: usize = 8;
+
+const MALLOCX_ZERO: i32 = 0x40;
+
+#[link(name = "jemalloc")]
+extern "C" {
+    fn mallocx(size: usize, flags: i32) -> *mut c_void;
+    fn rallocx(ptr: *mut c_void, size: usize, flags: i32) -> *mut c_void;
+    fn sdallocx(ptr: *mut c_void, size: usize, flags: i32);
+    fn nallocx(size: usize, flags: i32) -> usize;
+}
+
+/// Returns the `MALLOCX_ALIGN` flag needed for a block, if any.
+#[inline]
+fn flags(size: usize, align: usize) -> i32 {
+    if align <= MIN_ALIGN && align <= size {
+        0
+    } else {
+        // MALLOCX_LG_ALIGN
+        align.trailing_zeros() as i32
+    }
+}
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    let size = round_up(layout.size(), layout.align());
+    mallocx(size, flags(size, layout.align())) as *mut u8
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    let size = round_up(layout.size(), layout.align());
+    mallocx(size, flags(size, layout.align()) | MALLOCX_ZERO) as *mut u8
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    let size = round_up(layout.size(), layout.align());
+    sdallocx(ptr as *mut c_void, size, flags(size, layout.align()))
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    let size = round_up(size, layout.align());
+    rallocx(ptr as *mut c_void, size, flags(size, layout.align())) as *mut u8
+}
+
+/// Returns the real size of the block `mallocx` hands out.
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+
+    let size = round_up(size, align);
+
+    match unsafe { nallocx(size, flags(size, align)) } {
+        // The size is too large to be served
+        0 => size,
+        usable => usable,
+    }
+}
+
+/// Blocks come from `mallocx`, not the global allocator.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    false
+}
@@ -0,0 +1,122 @@
+use {round_up, Layout};
+use sys;
+
+use core::ptr;
+
+#[cfg(unix)]
+use core::{cmp, mem};
+
+/// `posix_memalign` and `_aligned_malloc` accept any power of 2.
+pub const MAX_ALIGN: usize = isize::MAX as usize + 1;
+
+/// The alignment guaranteed by `malloc`.
+#[cfg(unix)]
+#[cfg(target_pointer_width = "64")]
+const MIN_ALIGN: usize = 16;
+
+#[cfg(unix)]
+#[cfg(not(target_pointer_width = "64"))]
+const MIN_ALIGN: usize = 8;
+
+#[cfg(unix)]
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    let size = round_up(layout.size(), layout.align());
+
+    if layout.align() <= MIN_ALIGN {
+        return sys::malloc(size) as *mut u8;
+    }
+
+    let mut out = ptr::null_mut();
+    // `posix_memalign` requires a multiple of the pointer size
+    let align = cmp::max(layout.align(), mem::size_of::<usize>());
+
+    if sys::posix_memalign(&mut out, align, size) != 0 {
+        return ptr::null_mut();
+    }
+
+    out as *mut u8
+}
+
+#[cfg(unix)]
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    if layout.align() <= MIN_ALIGN {
+        return sys::calloc(round_up(layout.size(), layout.align()), 1) as *mut u8;
+    }
+
+    let ptr = allocate(layout);
+
+    if !ptr.is_null() {
+        ptr::write_bytes(ptr, 0, layout.size());
+    }
+
+    ptr
+}
+
+#[cfg(unix)]
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, _layout: Layout) {
+    sys::free(ptr as *mut sys::c_void)
+}
+
+#[cfg(unix)]
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    if layout.align() <= MIN_ALIGN {
+        return sys::realloc(ptr as *mut sys::c_void, round_up(size, layout.align())) as *mut u8;
+    }
+
+    // `realloc` doesn't preserve larger alignments
+    let new_ptr = allocate(Layout::from_size_align_unchecked(size, layout.align()));
+
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), size));
+        deallocate(ptr, layout);
+    }
+
+    new_ptr
+}
+
+#[cfg(windows)]
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    sys::_aligned_malloc(round_up(layout.size(), layout.align()), layout.align()) as *mut u8
+}
+
+#[cfg(windows)]
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    let ptr = allocate(layout);
+
+    if !ptr.is_null() {
+        ptr::write_bytes(ptr, 0, layout.size());
+    }
+
+    ptr
+}
+
+#[cfg(windows)]
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, _layout: Layout) {
+    sys::_aligned_free(ptr as *mut sys::c_void)
+}
+
+#[cfg(windows)]
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    let size = round_up(size, layout.align());
+    sys::_aligned_realloc(ptr as *mut sys::c_void, size, layout.align()) as *mut u8
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+    round_up(size, align)
+}
+
+/// Blocks come from the C runtime, not the global allocator.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    false
+}
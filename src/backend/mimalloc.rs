@@ -0,0 +1,59 @@
+use {round_up, Layout};
+
+use core::ffi::c_void;
+
+/// `mi_malloc_aligned` accepts any power of 2.
+pub const MAX_ALIGN: usize = isize::MAX as usize + 1;
+
+/// The alignment of every mimalloc block.
+const MIN_ALIGN: usize = 16;
+
+#[link(name = "mimalloc")]
+extern "C" {
+    fn mi_malloc_aligned(size: usize, alignment: usize) -> *mut c_void;
+    fn mi_zalloc_aligned(size: usize, alignment: usize) -> *mut c_void;
+    fn mi_realloc_aligned(p: *mut c_void, newsize: usize, alignment: usize) -> *mut c_void;
+    fn mi_free(p: *mut c_void);
+    fn mi_good_size(size: usize) -> usize;
+}
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    mi_malloc_aligned(round_up(layout.size(), layout.align()), layout.align()) as *mut u8
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    mi_zalloc_aligned(round_up(layout.size(), layout.align()), layout.align()) as *mut u8
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, _layout: Layout) {
+    mi_free(ptr as *mut c_void)
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    mi_realloc_aligned(ptr as *mut c_void, round_up(size, layout.align()), layout.align()) as *mut u8
+}
+
+/// Returns the size class mimalloc serves the block from.
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+
+    let size = round_up(size, align);
+
+    // Over-aligned blocks may start past the beginning of their size class
+    if align <= MIN_ALIGN {
+        unsafe { mi_good_size(size) }
+    } else {
+        size
+    }
+}
+
+/// Blocks come from mimalloc, not the global allocator.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    false
+}
@@ -0,0 +1,450 @@
+use {round_up, Layout};
+use super::base;
+use sys;
+
+use core::{cmp, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_ALIGN: usize = base::MAX_ALIGN;
+
+/// Allocations of at least this many bytes are mapped by default.
+const DEFAULT_THRESHOLD: usize = 128 * 1024;
+
+/// Set once the threshold has been used to place an allocation.
+const FROZEN: usize = !(usize::MAX >> 1);
+
+static THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD);
+
+static HUGE_PAGE_THRESHOLD: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Returns the size at and above which allocations are served by `mmap`.
+pub fn mmap_threshold() -> usize {
+    THRESHOLD.load(Ordering::Relaxed) & !FROZEN
+}
+
+/// Returns the threshold to place a block with, freezing it.
+#[inline]
+fn placement_threshold() -> usize {
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+
+    if threshold & FROZEN == 0 {
+        // Deallocations must make the same decision as the allocation they
+        // release, so the threshold can't change once it's been used.
+        THRESHOLD.fetch_or(FROZEN, Ordering::Relaxed) & !FROZEN
+    } else {
+        threshold & !FROZEN
+    }
+}
+
+/// Sets the size at and above which allocations are served by `mmap`.
+///
+/// The threshold can only be changed before the first allocation is made.
+/// Returns `false` if it is too late to change it.
+pub fn set_mmap_threshold(size: usize) -> bool {
+    let size = cmp::min(size, !FROZEN);
+    let mut curr = THRESHOLD.load(Ordering::Relaxed);
+
+    loop {
+        if curr & FROZEN != 0 {
+            return false;
+        }
+
+        match THRESHOLD.compare_exchange(curr, size, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(actual) => curr = actual,
+        }
+    }
+}
+
+/// Returns the size at and above which mapped allocations are advised to be
+/// backed by transparent huge pages.
+///
+/// The threshold is `usize::MAX`, disabling the advice, unless it was set
+/// with `set_huge_page_threshold`.
+pub fn huge_page_threshold() -> usize {
+    HUGE_PAGE_THRESHOLD.load(Ordering::Relaxed)
+}
+
+/// Sets the size at and above which mapped allocations are advised to be
+/// backed by transparent huge pages.
+///
+/// Those mappings are passed to `madvise(MADV_HUGEPAGE)`, which lets the
+/// kernel back them with huge pages when transparent huge pages are enabled
+/// in `madvise` mode, without reserving any in advance. Only allocations
+/// served by `mmap` are affected, so thresholds below `mmap_threshold` act as
+/// `mmap_threshold`. The advice is only given on Linux, and it can be changed
+/// at any time; pass `usize::MAX` to stop advising new mappings.
+pub fn set_huge_page_threshold(size: usize) {
+    HUGE_PAGE_THRESHOLD.store(size, Ordering::Relaxed);
+}
+
+/// Return a pointer to `size` bytes of zeroed memory, mapped at `addr` if
+/// possible.
+///
+/// The address is only a hint: if the range is taken, the memory is mapped
+/// elsewhere. Use `allocate_at_exact` to fail instead. The size is rounded up
+/// to the page size, and the memory is not part of the heap.
+///
+/// On failure, or if `addr` isn't aligned to the page size, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_at` using the same `size`.
+pub unsafe fn allocate_at(addr: *mut u8, size: usize) -> *mut u8 {
+    map_at(addr, size, 0)
+}
+
+/// Return a pointer to `size` bytes of zeroed memory mapped at `addr`.
+///
+/// This is meant for restoring snapshots of pointer-rich data structures at
+/// the base address they were taken at. Existing mappings are never
+/// replaced: if any page of the range is taken, the allocation fails. The
+/// size is rounded up to the page size, and the memory is not part of the
+/// heap.
+///
+/// On failure, or if `addr` isn't aligned to the page size, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_at` using the same `size`.
+pub unsafe fn allocate_at_exact(addr: *mut u8, size: usize) -> *mut u8 {
+    let ptr = map_at(addr, size, NOREPLACE);
+
+    // Kernels without a no-replace flag treat the address as a hint
+    if !ptr.is_null() && ptr != addr {
+        sys::unmap(ptr, map_len(size));
+        return ptr::null_mut();
+    }
+
+    ptr
+}
+
+/// Deallocates memory obtained from `allocate_at` or `allocate_at_exact`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_at` or `allocate_at_exact`
+/// with the same `size`.
+pub unsafe fn deallocate_at(ptr: *mut u8, size: usize) {
+    sys::unmap(ptr, map_len(size))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const NOREPLACE: sys::c_int = sys::MAP_FIXED_NOREPLACE;
+
+#[cfg(target_os = "freebsd")]
+const NOREPLACE: sys::c_int = sys::MAP_FIXED | sys::MAP_EXCL;
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const NOREPLACE: sys::c_int = 0;
+
+unsafe fn map_at(addr: *mut u8, size: usize, flags: sys::c_int) -> *mut u8 {
+    let page = sys::page_size();
+
+    if size == 0 || size > isize::MAX as usize - (page - 1) || addr as usize & (page - 1) != 0 {
+        return ptr::null_mut();
+    }
+
+    let ptr = sys::mmap(
+        addr as *mut sys::c_void,
+        map_len(size),
+        sys::PROT_READ | sys::PROT_WRITE,
+        sys::MAP_PRIVATE | sys::MAP_ANONYMOUS | flags,
+        -1,
+        0);
+
+    if ptr == sys::MAP_FAILED {
+        return ptr::null_mut();
+    }
+
+    ptr as *mut u8
+}
+
+/// Returns true if blocks of `size` bytes aligned to `align` are mapped.
+///
+/// The decision is made on the size rounded up to the alignment. It is the
+/// same for every size between the requested and the usable size, as
+/// `usable_size` stops short of the threshold for blocks that aren't mapped.
+#[inline]
+fn is_mapped(size: usize, align: usize) -> bool {
+    align <= sys::page_size() && round_up(size, align) >= placement_threshold()
+}
+
+#[inline]
+fn map_len(size: usize) -> usize {
+    round_up(size, sys::page_size())
+}
+
+unsafe fn map(size: usize) -> *mut u8 {
+    let ptr = sys::map_anonymous(map_len(size), 0);
+
+    if !ptr.is_null() {
+        advise_huge(ptr, map_len(size));
+    }
+
+    ptr
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn advise_huge(ptr: *mut u8, len: usize) {
+    // Failing is harmless, the kernel may be built without huge pages
+    if len >= huge_page_threshold() {
+        sys::madvise(ptr as *mut sys::c_void, len, sys::MADV_HUGEPAGE);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn advise_huge(_ptr: *mut u8, _len: usize) {
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn remap(ptr: *mut u8, old_size: usize, size: usize) -> *mut u8 {
+    let ptr = sys::mremap(ptr as *mut sys::c_void, map_len(old_size), map_len(size), sys::MREMAP_MAYMOVE);
+
+    if ptr == sys::MAP_FAILED {
+        return ptr::null_mut();
+    }
+
+    // The mapping may have grown past the threshold
+    advise_huge(ptr as *mut u8, map_len(size));
+
+    ptr as *mut u8
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn remap(ptr: *mut u8, old_size: usize, size: usize) -> *mut u8 {
+    let new_ptr = map(size);
+
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(old_size, size));
+        sys::unmap(ptr, map_len(old_size));
+    }
+
+    new_ptr
+}
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    if is_mapped(layout.size(), layout.align()) {
+        map(layout.size())
+    } else {
+        base::allocate(layout)
+    }
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    if is_mapped(layout.size(), layout.align()) {
+        // Anonymous mappings are zero filled
+        map(layout.size())
+    } else {
+        base::allocate_zeroed(layout)
+    }
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    if is_mapped(layout.size(), layout.align()) {
+        sys::unmap(ptr, map_len(layout.size()));
+    } else {
+        base::deallocate(ptr, layout)
+    }
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    let align = layout.align();
+
+    match (is_mapped(layout.size(), align), is_mapped(size, align)) {
+        (true, true) => remap(ptr, layout.size(), size),
+        (false, false) => base::reallocate(ptr, layout, size),
+        _ => {
+            let new_ptr = allocate(Layout::from_size_align_unchecked(size, align));
+
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), size));
+                deallocate(ptr, layout);
+            }
+
+            new_ptr
+        }
+    }
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    if is_mapped(size, align) {
+        map_len(size)
+    } else if align <= sys::page_size() {
+        // The base allocator may round the size up past the threshold, and
+        // releasing the block with that size would unmap it
+        cmp::min(base::usable_size(size, align), (placement_threshold() - 1) & !(align - 1))
+    } else {
+        base::usable_size(size, align)
+    }
+}
+
+#[inline]
+pub fn from_global(size: usize, align: usize) -> bool {
+    !is_mapped(size, align) && base::from_global(size, align)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_at, allocate_at_exact, deallocate_at, is_mapped};
+    use super::{huge_page_threshold, mmap_threshold, set_huge_page_threshold, set_mmap_threshold};
+    use sys;
+
+    #[test]
+    fn test_mapped_allocations() {
+        let threshold = mmap_threshold();
+        let page = sys::page_size();
+
+        unsafe {
+            let ptr = ::allocate_zeroed(threshold, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & (page - 1));
+            assert_eq!(0, *ptr.add(threshold - 1));
+
+            *ptr = 1;
+
+            // Grow the mapping
+            let ptr = ::reallocate(ptr, threshold, threshold * 4, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(1, *ptr);
+
+            // Shrink below the threshold, moving to the base allocator
+            let ptr = ::reallocate(ptr, threshold * 4, 64, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(1, *ptr);
+
+            // And back again
+            let ptr = ::reallocate(ptr, 64, threshold + 1, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(1, *ptr);
+
+            let usable = ::usable_size(threshold + 1, 8);
+            assert_eq!(0, usable % page);
+            ::deallocate(ptr, usable, 8);
+        }
+    }
+
+    #[test]
+    fn test_release_with_usable_size() {
+        let threshold = mmap_threshold();
+
+        for &size in [threshold / 2 + 1, threshold - 4096 - 1, threshold - 17, threshold - 1].iter() {
+            for &align in [1, 8, 64, 4096].iter() {
+                let usable = ::usable_size(size, align);
+                assert!(usable >= size);
+                assert_eq!(is_mapped(size, align), is_mapped(usable, align));
+
+                unsafe {
+                    let ptr = ::allocate(size, align);
+                    assert!(!ptr.is_null());
+                    *ptr.add(usable - 1) = 1;
+
+                    ::deallocate(ptr, usable, align);
+                }
+            }
+        }
+    }
+
+    // Returns the `VmFlags` of the mapping containing `ptr`
+    #[cfg(target_os = "linux")]
+    fn vm_flags(ptr: *mut u8) -> ::std::string::String {
+        let smaps = ::std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let addr = ptr as usize;
+        let mut inside = false;
+
+        for line in smaps.lines() {
+            let range = line.split(' ').next().unwrap();
+
+            if let Some((start, end)) = range.split_once('-') {
+                if let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+                    inside = start <= addr && addr < end;
+                    continue;
+                }
+            }
+
+            if inside && line.starts_with("VmFlags:") {
+                return line.into();
+            }
+        }
+
+        panic!("no mapping contains {:p}", ptr);
+    }
+
+    #[test]
+    fn test_huge_page_threshold() {
+        let size = mmap_threshold() * 4;
+
+        set_huge_page_threshold(size);
+        assert_eq!(size, huge_page_threshold());
+
+        unsafe {
+            let ptr = ::allocate(size, 8);
+            assert!(!ptr.is_null());
+            *ptr.add(size - 1) = 1;
+
+            // The advice is ignored when transparent huge pages are disabled
+            // or not built into the kernel
+            #[cfg(target_os = "linux")]
+            {
+                let enabled = ::std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled");
+
+                if enabled.map(|enabled| !enabled.contains("[never]")).unwrap_or(false) {
+                    assert!(vm_flags(ptr).contains(" hg"));
+                }
+            }
+
+            ::deallocate(ptr, size, 8);
+        }
+
+        set_huge_page_threshold(usize::MAX);
+    }
+
+    #[test]
+    fn test_allocate_at() {
+        let page = sys::page_size();
+
+        unsafe {
+            // Find a free range by mapping it, then give it back
+            let addr = allocate_at(::core::ptr::null_mut(), 2 * page);
+            assert!(!addr.is_null());
+            deallocate_at(addr, 2 * page);
+
+            let ptr = allocate_at_exact(addr, 2 * page);
+            assert_eq!(addr, ptr);
+            assert_eq!(0, *ptr.add(2 * page - 1));
+            *ptr = 1;
+
+            // The range is taken now
+            assert!(allocate_at_exact(addr.add(page), page).is_null());
+            assert_eq!(1, *ptr);
+
+            let other = allocate_at(addr, page);
+            assert!(!other.is_null());
+            assert!(other != addr);
+            deallocate_at(other, page);
+
+            // Unaligned addresses are rejected
+            assert!(allocate_at(addr.add(1), page).is_null());
+
+            deallocate_at(ptr, 2 * page);
+        }
+    }
+
+    #[test]
+    fn test_threshold_is_frozen() {
+        unsafe {
+            ::deallocate(::allocate(16, 8), 16, 8);
+        }
+
+        let threshold = mmap_threshold();
+        assert!(!set_mmap_threshold(threshold * 2));
+        assert_eq!(threshold, mmap_threshold());
+    }
+}
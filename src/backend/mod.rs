@@ -0,0 +1,124 @@
+//! The memory backends.
+//!
+//! Each backend provides the same set of functions. They are called with
+//! layouts that have already been validated: the size is not 0 and the
+//! alignment is no larger than the backend's `MAX_ALIGN`.
+//!
+//! Blocks are sized by rounding up to the alignment, so any size between the
+//! requested and the usable size maps to the same block. `from_global` tells
+//! whether a block was allocated from the global allocator with that rounded
+//! size, so it can be handed over to `Vec` or `Box`.
+//!
+//! When several backend features are enabled, the first one in this order is
+//! used: `jemalloc`, `mimalloc`, `libc`, `windows` (on Windows only),
+//! `std-alloc`, and the `Vec` shim as the default. The `mmap` feature layers
+//! mapped allocations for large blocks on top of it.
+//!
+//! A `Backend` installed with `set_backend` takes over from the selected one at
+//! run time.
+
+#[cfg(feature = "jemalloc")]
+mod jemalloc;
+
+#[cfg(feature = "jemalloc")]
+use self::jemalloc as base;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+mod mimalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+use self::mimalloc as base;
+
+#[cfg(all(feature = "libc", not(any(feature = "jemalloc", feature = "mimalloc"))))]
+mod libc;
+
+#[cfg(all(feature = "libc", not(any(feature = "jemalloc", feature = "mimalloc"))))]
+use self::libc as base;
+
+#[cfg(all(feature = "windows", windows, not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc"))))]
+mod windows;
+
+#[cfg(all(feature = "windows", windows, not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc"))))]
+use self::windows as base;
+
+#[cfg(all(feature = "std-alloc", not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc", all(feature = "windows", windows)))))]
+mod std_alloc;
+
+#[cfg(all(feature = "std-alloc", not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc", all(feature = "windows", windows)))))]
+use self::std_alloc as base;
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc", all(feature = "windows", windows), feature = "std-alloc")))]
+mod vec;
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc", feature = "libc", all(feature = "windows", windows), feature = "std-alloc")))]
+use self::vec as base;
+
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+
+#[cfg(all(feature = "mmap", unix))]
+use self::mmap as builtin;
+
+#[cfg(all(feature = "mmap", unix))]
+pub use self::mmap::{allocate_at, allocate_at_exact, deallocate_at};
+
+#[cfg(all(feature = "mmap", unix))]
+pub use self::mmap::{huge_page_threshold, mmap_threshold, set_huge_page_threshold, set_mmap_threshold};
+
+#[cfg(not(all(feature = "mmap", unix)))]
+use self::base as builtin;
+
+mod custom;
+
+pub use self::builtin::MAX_ALIGN;
+pub use self::custom::{set_backend, Backend, DefaultBackend};
+
+use Layout;
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    match custom::current() {
+        Some(backend) => backend.allocate(layout),
+        None => builtin::allocate(layout),
+    }
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    match custom::current() {
+        Some(backend) => backend.allocate_zeroed(layout),
+        None => builtin::allocate_zeroed(layout),
+    }
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    match custom::current() {
+        Some(backend) => backend.deallocate(ptr, layout),
+        None => builtin::deallocate(ptr, layout),
+    }
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    match custom::current() {
+        Some(backend) => backend.reallocate(ptr, layout, size),
+        None => builtin::reallocate(ptr, layout, size),
+    }
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    match custom::current() {
+        Some(backend) => backend.usable_size(size, align),
+        None => builtin::usable_size(size, align),
+    }
+}
+
+#[inline]
+pub fn from_global(size: usize, align: usize) -> bool {
+    match custom::current() {
+        Some(backend) => backend.is_global(size, align),
+        None => builtin::from_global(size, align),
+    }
+}
@@ -0,0 +1,45 @@
+use {round_up, Layout};
+
+use alloc::alloc;
+
+/// `std::alloc` accepts any power of 2.
+pub const MAX_ALIGN: usize = isize::MAX as usize + 1;
+
+#[inline]
+fn layout(size: usize, align: usize) -> alloc::Layout {
+    // `Layout` guarantees that the rounded size doesn't overflow
+    unsafe { alloc::Layout::from_size_align_unchecked(round_up(size, align), align) }
+}
+
+#[inline]
+pub unsafe fn allocate(l: Layout) -> *mut u8 {
+    alloc::alloc(layout(l.size(), l.align()))
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(l: Layout) -> *mut u8 {
+    alloc::alloc_zeroed(layout(l.size(), l.align()))
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, l: Layout) {
+    alloc::dealloc(ptr, layout(l.size(), l.align()))
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, l: Layout, size: usize) -> *mut u8 {
+    alloc::realloc(ptr, layout(l.size(), l.align()), round_up(size, l.align()))
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+    round_up(size, align)
+}
+
+/// Blocks are allocated from the global allocator with their size rounded up
+/// to the alignment, as `Vec` and `Box` allocate them.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    true
+}
@@ -0,0 +1,135 @@
+use Layout;
+
+use alloc::vec::Vec;
+use core::{cmp, mem, ptr};
+
+/// Invokes `$f::<T>(args)` where `T` is the allocation unit for `$align`.
+///
+/// Memory is obtained from `Vec<T>`, so the alignment of an allocation is the
+/// alignment of the unit type it was created with.
+macro_rules! with_unit {
+    ($align:expr, $f:ident($($arg:expr),*)) => {
+        match $align {
+            1 => $f::<u8>($($arg),*),
+            2 => $f::<u16>($($arg),*),
+            4 => $f::<u32>($($arg),*),
+            8 => $f::<u64>($($arg),*),
+            16 => $f::<Align16>($($arg),*),
+            32 => $f::<Align32>($($arg),*),
+            64 => $f::<Align64>($($arg),*),
+            128 => $f::<Align128>($($arg),*),
+            256 => $f::<Align256>($($arg),*),
+            512 => $f::<Align512>($($arg),*),
+            1024 => $f::<Align1024>($($arg),*),
+            2048 => $f::<Align2048>($($arg),*),
+            4096 => $f::<Align4096>($($arg),*),
+            align => panic!("unsupported alignment {}", align),
+        }
+    }
+}
+
+/// The largest alignment of the allocation units.
+pub const MAX_ALIGN: usize = 4096;
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    with_unit!(layout.align(), do_allocate(layout.size()))
+}
+
+unsafe fn do_allocate<T>(size: usize) -> *mut u8 {
+    let mut vec = Vec::<T>::new();
+
+    if vec.try_reserve_exact(capacity::<T>(size)).is_err() {
+        return ptr::null_mut();
+    }
+
+    let ptr = vec.as_mut_ptr();
+
+    mem::forget(vec);
+
+    ptr as *mut u8
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    with_unit!(layout.align(), do_allocate_zeroed(layout.size()))
+}
+
+unsafe fn do_allocate_zeroed<T>(size: usize) -> *mut u8 {
+    let ptr = do_allocate::<T>(size);
+
+    if !ptr.is_null() {
+        ptr::write_bytes(ptr, 0, do_usable_size::<T>(size));
+    }
+
+    ptr
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    with_unit!(layout.align(), do_deallocate(ptr, layout.size()))
+}
+
+unsafe fn do_deallocate<T>(ptr: *mut u8, old_size: usize) {
+    let _ = Vec::from_raw_parts(ptr as *mut T, 0, capacity::<T>(old_size));
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    let new_ptr = allocate(Layout::from_size_align_unchecked(size, layout.align()));
+
+    if new_ptr.is_null() {
+        return new_ptr;
+    }
+
+    ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), size));
+    deallocate(ptr, layout);
+
+    new_ptr
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    with_unit!(align, do_usable_size(size))
+}
+
+fn do_usable_size<T>(size: usize) -> usize {
+    capacity::<T>(size) * mem::size_of::<T>()
+}
+
+/// Blocks are allocated by `Vec`s of a unit as large as the alignment, which
+/// is the layout of their size rounded up to the alignment.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    true
+}
+
+/// Returns the number of `T` units needed to hold `size` bytes.
+///
+/// Sizes that are not a multiple of the unit are rounded up, so any size in
+/// the same unit maps to the same capacity.
+#[inline]
+fn capacity<T>(size: usize) -> usize {
+    let unit = mem::size_of::<T>();
+    size / unit + (size & (unit - 1) != 0) as usize
+}
+
+macro_rules! aligned_units {
+    ($($name:ident => $align:tt),*) => {$(
+        #[allow(dead_code)]
+        #[repr(C, align($align))]
+        struct $name(u8);
+    )*}
+}
+
+aligned_units! {
+    Align16 => 16,
+    Align32 => 32,
+    Align64 => 64,
+    Align128 => 128,
+    Align256 => 256,
+    Align512 => 512,
+    Align1024 => 1024,
+    Align2048 => 2048,
+    Align4096 => 4096
+}
@@ -0,0 +1,122 @@
+use {round_up, Layout};
+use sys;
+
+use core::{cmp, ptr};
+
+/// Over-aligned `HeapAlloc` blocks accept any power of 2.
+pub const MAX_ALIGN: usize = isize::MAX as usize + 1;
+
+/// The alignment guaranteed by `HeapAlloc`.
+#[cfg(target_pointer_width = "64")]
+const MIN_ALIGN: usize = 16;
+
+#[cfg(not(target_pointer_width = "64"))]
+const MIN_ALIGN: usize = 8;
+
+/// How a block of a given layout is obtained.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Directly from `HeapAlloc`.
+    Heap,
+
+    /// From `HeapAlloc`, over-allocated so it can be aligned. The original
+    /// pointer is stored in the word preceding the block.
+    Aligned,
+
+    /// From `VirtualAlloc`, whose addresses are aligned to the allocation
+    /// granularity.
+    Virtual,
+}
+
+#[inline]
+fn kind(align: usize) -> Kind {
+    if align <= MIN_ALIGN {
+        Kind::Heap
+    } else if align >= sys::page_size() && align <= sys::allocation_granularity() {
+        Kind::Virtual
+    } else {
+        Kind::Aligned
+    }
+}
+
+unsafe fn heap_alloc(flags: sys::DWORD, layout: Layout) -> *mut u8 {
+    let size = round_up(layout.size(), layout.align());
+    let heap = sys::GetProcessHeap();
+
+    match kind(layout.align()) {
+        Kind::Heap => sys::HeapAlloc(heap, flags, size) as *mut u8,
+        Kind::Aligned => {
+            let ptr = sys::HeapAlloc(heap, flags, size + layout.align()) as *mut u8;
+
+            if ptr.is_null() {
+                return ptr;
+            }
+
+            // There is always at least `MIN_ALIGN` bytes of room in front of
+            // the aligned address for the original pointer.
+            let aligned = ptr.add(layout.align() - (ptr as usize & (layout.align() - 1)));
+            *(aligned as *mut *mut u8).offset(-1) = ptr;
+            aligned
+        }
+        Kind::Virtual => {
+            let flags = sys::MEM_COMMIT | sys::MEM_RESERVE;
+            sys::VirtualAlloc(ptr::null_mut(), size, flags, sys::PAGE_READWRITE) as *mut u8
+        }
+    }
+}
+
+#[inline]
+pub unsafe fn allocate(layout: Layout) -> *mut u8 {
+    heap_alloc(0, layout)
+}
+
+#[inline]
+pub unsafe fn allocate_zeroed(layout: Layout) -> *mut u8 {
+    // `VirtualAlloc` memory is always zeroed, the flag is ignored for it
+    heap_alloc(sys::HEAP_ZERO_MEMORY, layout)
+}
+
+#[inline]
+pub unsafe fn deallocate(ptr: *mut u8, layout: Layout) {
+    match kind(layout.align()) {
+        Kind::Heap => {
+            sys::HeapFree(sys::GetProcessHeap(), 0, ptr as sys::LPVOID);
+        }
+        Kind::Aligned => {
+            let original = *(ptr as *mut *mut u8).offset(-1);
+            sys::HeapFree(sys::GetProcessHeap(), 0, original as sys::LPVOID);
+        }
+        Kind::Virtual => {
+            sys::VirtualFree(ptr as sys::LPVOID, 0, sys::MEM_RELEASE);
+        }
+    }
+}
+
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+    if kind(layout.align()) == Kind::Heap {
+        let size = round_up(size, layout.align());
+        return sys::HeapReAlloc(sys::GetProcessHeap(), 0, ptr as sys::LPVOID, size) as *mut u8;
+    }
+
+    let new_ptr = allocate(Layout::from_size_align_unchecked(size, layout.align()));
+
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), size));
+        deallocate(ptr, layout);
+    }
+
+    new_ptr
+}
+
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+    round_up(size, align)
+}
+
+/// Blocks come from the process heap, not the global allocator.
+#[inline]
+pub fn from_global(_size: usize, _align: usize) -> bool {
+    false
+}
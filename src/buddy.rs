@@ -0,0 +1,461 @@
+use {round_up, Alloc, AllocError, Layout};
+
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem, ptr};
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// A buddy allocator managing a fixed region of memory.
+///
+/// The region is divided into blocks whose sizes are powers of two between
+/// `2^min_order` and `2^max_order` bytes. An allocation is served from the
+/// smallest block that fits, splitting larger blocks in halves as needed, and
+/// a released block is merged with its buddy whenever both are free, which
+/// bounds fragmentation at the cost of rounding allocations up to a power of
+/// two.
+///
+/// Blocks are aligned to their size relative to the start of the region, so
+/// alignments up to the alignment of the region itself are supported.
+pub struct Buddy<'a> {
+    base: *mut u8,
+    len: usize,
+
+    min_order: u32,
+    max_order: u32,
+
+    // Free lists, indexed by `order - min_order`
+    free: Vec<*mut Link>,
+
+    // One bit per block of each order, set if the block is free
+    bits: Vec<Vec<u64>>,
+
+    allocated: usize,
+
+    // Set if the region was mapped by `map`
+    mapped: bool,
+
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+struct Link {
+    prev: *mut Link,
+    next: *mut Link,
+}
+
+unsafe impl<'a> Send for Buddy<'a> {}
+
+impl<'a> Buddy<'a> {
+    /// Creates an allocator serving blocks out of `region`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if `min_order` is larger than
+    /// `max_order`, if `max_order` is not below the bit width of `usize`, or
+    /// if `2^min_order` bytes can't hold two pointers.
+    pub fn new(region: &'a mut [u8], min_order: u32, max_order: u32) -> Result<Buddy<'a>, AllocError> {
+        unsafe { Buddy::from_raw_parts(region.as_mut_ptr(), region.len(), min_order, max_order) }
+    }
+
+    /// Creates an allocator serving blocks out of the `len` bytes at `ptr`.
+    ///
+    /// Returns an error under the same conditions as `new`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes, and must not be
+    /// accessed other than through the allocator's blocks, for the lifetime
+    /// `'a`.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize, min_order: u32, max_order: u32) -> Result<Buddy<'a>, AllocError> {
+        if min_order > max_order
+            || max_order >= usize::MAX.count_ones()
+            || (1 << min_order) < mem::size_of::<Link>()
+        {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let min = 1 << min_order;
+        let start = round_up(ptr as usize, min);
+        let len = len.saturating_sub(start - ptr as usize) & !(min - 1);
+
+        let orders = (max_order - min_order + 1) as usize;
+        let mut bits = Vec::with_capacity(orders);
+
+        for order in min_order..=max_order {
+            bits.push(::alloc::vec![0; round_up(len >> order, 64) / 64]);
+        }
+
+        let mut buddy = Buddy {
+            base: start as *mut u8,
+            len,
+            min_order,
+            max_order,
+            free: ::alloc::vec![ptr::null_mut(); orders],
+            bits,
+            allocated: 0,
+            mapped: false,
+            _marker: PhantomData,
+        };
+
+        // Carve the region into the largest blocks that fit
+        let mut offset = 0;
+
+        while offset < len {
+            let mut order = max_order;
+
+            while offset & ((1 << order) - 1) != 0 || len - offset < 1 << order {
+                order -= 1;
+            }
+
+            buddy.push(offset, order);
+            offset += 1 << order;
+        }
+
+        Ok(buddy)
+    }
+
+    /// Returns the number of bytes in the managed region.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes currently allocated, including rounding.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the order of the smallest blocks.
+    pub fn min_order(&self) -> u32 {
+        self.min_order
+    }
+
+    /// Returns the order of the largest blocks.
+    pub fn max_order(&self) -> u32 {
+        self.max_order
+    }
+
+    /// Returns `true` if `ptr` points into the managed region.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        addr >= self.base as usize && addr < self.base as usize + self.len
+    }
+
+    fn order_for(&self, layout: Layout) -> usize {
+        let size = cmp::max(layout.size(), layout.align());
+        let order = size.next_power_of_two().trailing_zeros();
+        cmp::max(order, self.min_order) as usize
+    }
+
+    fn is_free(&self, offset: usize, order: u32) -> bool {
+        let index = offset >> order;
+        let bits = &self.bits[(order - self.min_order) as usize];
+
+        match bits.get(index / 64) {
+            Some(word) => word & (1 << (index % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn toggle(&mut self, offset: usize, order: u32) {
+        let index = offset >> order;
+        self.bits[(order - self.min_order) as usize][index / 64] ^= 1 << (index % 64);
+    }
+
+    fn push(&mut self, offset: usize, order: u32) {
+        let head = &mut self.free[(order - self.min_order) as usize];
+
+        unsafe {
+            let node = self.base.add(offset) as *mut Link;
+            ptr::write(node, Link { prev: ptr::null_mut(), next: *head });
+
+            if !head.is_null() {
+                (**head).prev = node;
+            }
+
+            *head = node;
+        }
+
+        self.toggle(offset, order);
+    }
+
+    fn remove(&mut self, offset: usize, order: u32) {
+        let head = &mut self.free[(order - self.min_order) as usize];
+
+        unsafe {
+            let node = self.base.add(offset) as *mut Link;
+            let Link { prev, next } = ptr::read(node);
+
+            if prev.is_null() {
+                *head = next;
+            } else {
+                (*prev).next = next;
+            }
+
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+
+        self.toggle(offset, order);
+    }
+
+    fn pop(&mut self, order: u32) -> Option<usize> {
+        let head = self.free[(order - self.min_order) as usize];
+
+        if head.is_null() {
+            return None;
+        }
+
+        let offset = head as usize - self.base as usize;
+        self.remove(offset, order);
+
+        Some(offset)
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl Buddy<'static> {
+    /// Creates an allocator serving blocks out of a region of at least `size`
+    /// bytes mapped from the OS.
+    ///
+    /// The region is unmapped when the allocator is dropped. Returns an error
+    /// under the same conditions as `new`.
+    pub fn map(size: usize, min_order: u32, max_order: u32) -> Result<Buddy<'static>, AllocError> {
+        if size == 0 || size > isize::MAX as usize {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let len = round_up(size, ::sys::page_size());
+
+        unsafe {
+            let ptr = ::sys::map_anonymous(len, 0);
+
+            if ptr.is_null() {
+                return Err(AllocError::OutOfMemory);
+            }
+
+            match Buddy::from_raw_parts(ptr, len, min_order, max_order) {
+                Ok(mut buddy) => {
+                    buddy.mapped = true;
+                    Ok(buddy)
+                }
+                Err(e) => {
+                    ::sys::unmap(ptr, len);
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+unsafe impl<'a> Alloc for Buddy<'a> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 || layout.align() > 1 << (self.base as usize).trailing_zeros() {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let order = self.order_for(layout);
+
+        if order > self.max_order as usize {
+            return Err(AllocError::OutOfMemory);
+        }
+
+        let order = order as u32;
+
+        // Take the smallest free block that fits, splitting off the upper
+        // halves until it has the requested order
+        let mut split = order;
+
+        let offset = loop {
+            if let Some(offset) = self.pop(split) {
+                break offset;
+            }
+
+            if split == self.max_order {
+                return Err(AllocError::OutOfMemory);
+            }
+
+            split += 1;
+        };
+
+        while split > order {
+            split -= 1;
+            self.push(offset + (1 << split), split);
+        }
+
+        self.allocated += 1 << order;
+
+        Ok(NonNull::new_unchecked(self.base.add(offset)))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        debug_assert!(self.contains(ptr.as_ptr()));
+
+        let mut order = self.order_for(layout) as u32;
+        let mut offset = ptr.as_ptr() as usize - self.base as usize;
+
+        self.allocated -= 1 << order;
+
+        // Merge with the buddy for as long as it is free
+        while order < self.max_order {
+            let buddy = offset ^ (1 << order);
+
+            if !self.is_free(buddy, order) {
+                break;
+            }
+
+            self.remove(buddy, order);
+            offset = cmp::min(offset, buddy);
+            order += 1;
+        }
+
+        self.push(offset, order);
+    }
+}
+
+impl<'a> fmt::Debug for Buddy<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Buddy")
+            .field("capacity", &self.len)
+            .field("allocated", &self.allocated)
+            .field("min_order", &self.min_order)
+            .field("max_order", &self.max_order)
+            .finish()
+    }
+}
+
+impl<'a> Drop for Buddy<'a> {
+    fn drop(&mut self) {
+        #[cfg(any(unix, windows))]
+        unsafe {
+            if self.mapped {
+                ::sys::unmap(self.base, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, AllocError, Buddy, Layout};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[repr(align(4096))]
+    struct Region([u8; 4096]);
+
+    fn region() -> Box<Region> {
+        Box::new(Region([0; 4096]))
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let mut buf = region();
+        let mut buddy = Buddy::new(&mut buf.0, 5, 12).unwrap();
+        assert_eq!(4096, buddy.capacity());
+
+        unsafe {
+            let small = Layout::from_size_align(20, 4).unwrap();
+            let a = buddy.alloc(small).unwrap();
+            let b = buddy.alloc(small).unwrap();
+
+            // Both come from splitting the same block
+            assert_eq!(32, b.as_ptr() as usize - a.as_ptr() as usize);
+            assert_eq!(64, buddy.allocated());
+
+            // The whole region is split up, so a maximal block doesn't fit
+            let max = Layout::from_size_align(4096, 1).unwrap();
+            assert_eq!(Err(AllocError::OutOfMemory), buddy.alloc(max));
+
+            buddy.dealloc(a, small);
+            buddy.dealloc(b, small);
+            assert_eq!(0, buddy.allocated());
+
+            // Everything merged back together
+            let c = buddy.alloc(max).unwrap();
+            assert_eq!(a, c);
+            buddy.dealloc(c, max);
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut buf = region();
+        let mut buddy = Buddy::new(&mut buf.0, 6, 12).unwrap();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let ptrs: Vec<_> = (0..32).map(|_| buddy.alloc(layout).unwrap()).collect();
+            assert_eq!(4096, buddy.allocated());
+            assert!(buddy.alloc(layout).is_err());
+
+            for (i, ptr) in ptrs.iter().enumerate() {
+                assert_eq!(0, ptr.as_ptr() as usize % 128);
+                *ptr.as_ptr() = i as u8;
+            }
+
+            for ptr in ptrs.into_iter().rev() {
+                buddy.dealloc(ptr, layout);
+            }
+
+            let max = Layout::from_size_align(4096, 4096).unwrap();
+            let ptr = buddy.alloc(max).unwrap();
+            buddy.dealloc(ptr, max);
+        }
+    }
+
+    #[test]
+    fn test_uneven_region() {
+        let mut buf = region();
+        let mut buddy = Buddy::new(&mut buf.0[..3000], 5, 12).unwrap();
+        assert_eq!(2976, buddy.capacity());
+
+        unsafe {
+            // The largest block is 2048 bytes and can't merge past the end
+            let layout = Layout::from_size_align(2048, 1).unwrap();
+            let a = buddy.alloc(layout).unwrap();
+            assert!(buddy.alloc(layout).is_err());
+
+            let b = buddy.alloc(Layout::from_size_align(512, 1).unwrap()).unwrap();
+            buddy.dealloc(b, Layout::from_size_align(512, 1).unwrap());
+            buddy.dealloc(a, layout);
+        }
+
+        assert_eq!(0, buddy.allocated());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut buf = region();
+        assert!(Buddy::new(&mut buf.0, 8, 7).is_err());
+        assert!(Buddy::new(&mut buf.0, 1, 7).is_err());
+        assert!(Buddy::new(&mut buf.0, 8, 64).is_err());
+
+        let mut buddy = Buddy::new(&mut buf.0, 5, 10).unwrap();
+
+        unsafe {
+            assert_eq!(Err(AllocError::OutOfMemory), buddy.alloc(Layout::from_size_align(2048, 1).unwrap()));
+        }
+
+        drop(buddy);
+
+        // The region is only aligned to 32 bytes
+        let mut buddy = Buddy::new(&mut buf.0[32..], 5, 10).unwrap();
+
+        unsafe {
+            assert!(buddy.alloc(Layout::from_size_align(8, 32).unwrap()).is_ok());
+            assert_eq!(Err(AllocError::InvalidLayout), buddy.alloc(Layout::from_size_align(8, 64).unwrap()));
+        }
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_map() {
+        let mut buddy = Buddy::map(1 << 20, 6, 20).unwrap();
+
+        unsafe {
+            let layout = Layout::from_size_align(5000, 4096).unwrap();
+            let ptr = buddy.alloc(layout).unwrap();
+            *ptr.as_ptr().add(4999) = 1;
+            assert_eq!(8192, buddy.allocated());
+            buddy.dealloc(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use {Alloc, AllocError, Layout};
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A byte limit shared by a group of `Budget` allocators.
+///
+/// Usage is the sum of the sizes of the blocks allocated through the group
+/// and not yet released, as requested, without any padding added by the
+/// allocators.
+pub struct Quota {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl Quota {
+    /// Creates a quota of `limit` bytes.
+    pub fn new(limit: usize) -> Quota {
+        Quota {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the limit in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the limit to `limit` bytes.
+    ///
+    /// Lowering it below the current usage only fails new allocations, the
+    /// blocks already handed out stay valid.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Returns the number of bytes currently allocated against the quota.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes that can still be allocated.
+    pub fn remaining(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    // Charges `size` bytes, unless that would exceed the limit
+    fn reserve(&self, size: usize) -> Result<(), AllocError> {
+        let mut used = self.used.load(Ordering::Relaxed);
+
+        loop {
+            let next = match used.checked_add(size) {
+                Some(next) if next <= self.limit() => next,
+                _ => return Err(AllocError::OutOfMemory),
+            };
+
+            match self.used.compare_exchange_weak(used, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return Ok(()),
+                Err(actual) => used = actual,
+            }
+        }
+    }
+
+    fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for Quota {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Quota")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+/// An allocator that fails once its blocks would exceed a byte limit.
+///
+/// Allocations that fit within the `Quota` are passed on to `A`, others
+/// return `AllocError::OutOfMemory` without reaching it. A quota can be
+/// shared by several allocators through `Budget::with_quota`, capping them as
+/// a group, for instance all the allocators serving one tenant.
+#[derive(Debug)]
+pub struct Budget<A> {
+    inner: A,
+    quota: Arc<Quota>,
+}
+
+impl<A> Budget<A> {
+    /// Wraps `inner` with a quota of its own of `limit` bytes.
+    pub fn new(inner: A, limit: usize) -> Budget<A> {
+        Budget::with_quota(inner, Arc::new(Quota::new(limit)))
+    }
+
+    /// Wraps `inner`, charging its blocks to `quota`.
+    pub fn with_quota(inner: A, quota: Arc<Quota>) -> Budget<A> {
+        Budget { inner, quota }
+    }
+
+    /// Returns the quota the blocks are charged to.
+    pub fn quota(&self) -> &Arc<Quota> {
+        &self.quota
+    }
+
+    /// Returns the number of bytes currently allocated against the quota.
+    ///
+    /// This includes the blocks of every allocator sharing the quota.
+    pub fn used(&self) -> usize {
+        self.quota.used()
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+unsafe impl<A: Alloc> Alloc for Budget<A> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.quota.reserve(layout.size())?;
+
+        let res = self.inner.alloc(layout);
+
+        if res.is_err() {
+            self.quota.release(layout.size());
+        }
+
+        res
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.quota.release(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.quota.reserve(layout.size())?;
+
+        let res = self.inner.alloc_zeroed(layout);
+
+        if res.is_err() {
+            self.quota.release(layout.size());
+        }
+
+        res
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let old_size = layout.size();
+
+        // Growth is charged up front, so the limit holds while the block is
+        // being moved
+        if new_size > old_size {
+            self.quota.reserve(new_size - old_size)?;
+        }
+
+        match self.inner.realloc(ptr, layout, new_size) {
+            Ok(new_ptr) => {
+                if new_size < old_size {
+                    self.quota.release(old_size - new_size);
+                }
+
+                Ok(new_ptr)
+            }
+            Err(e) => {
+                if new_size > old_size {
+                    self.quota.release(new_size - old_size);
+                }
+
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, AllocError, Budget, Heap, Layout, Quota};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_budget() {
+        let mut alloc = Budget::new(Heap, 100);
+        let layout = Layout::from_size_align(60, 8).unwrap();
+
+        unsafe {
+            let a = alloc.alloc(layout).unwrap();
+            assert_eq!(60, alloc.used());
+            assert_eq!(Err(AllocError::OutOfMemory), alloc.alloc(layout));
+            assert_eq!(60, alloc.used());
+
+            assert_eq!(Err(AllocError::OutOfMemory), alloc.realloc(a, layout, 101));
+            let a = alloc.realloc(a, layout, 100).unwrap();
+            assert_eq!(0, alloc.quota().remaining());
+
+            let a = alloc.realloc(a, Layout::from_size_align(100, 8).unwrap(), 10).unwrap();
+            assert_eq!(10, alloc.used());
+
+            alloc.dealloc(a, Layout::from_size_align(10, 8).unwrap());
+            assert_eq!(0, alloc.used());
+        }
+    }
+
+    #[test]
+    fn test_shared_quota() {
+        let quota = Arc::new(Quota::new(64));
+        let mut a = Budget::with_quota(Heap, quota.clone());
+        let mut b = Budget::with_quota(Heap, quota.clone());
+        let layout = Layout::from_size_align(40, 8).unwrap();
+
+        unsafe {
+            let ptr = a.alloc(layout).unwrap();
+            assert_eq!(Err(AllocError::OutOfMemory), b.alloc(layout));
+
+            quota.set_limit(80);
+            let other = b.alloc_zeroed(layout).unwrap();
+            assert_eq!(80, quota.used());
+
+            a.dealloc(ptr, layout);
+            b.dealloc(other, layout);
+            assert_eq!(0, quota.used());
+        }
+    }
+}
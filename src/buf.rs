@@ -0,0 +1,226 @@
+use {round_up, sys, AllocError, VirtualRegion};
+
+use core::{cmp, ops, ptr, slice};
+
+/// A growable byte buffer whose contents never move.
+///
+/// The buffer reserves address space for its maximum capacity up front and
+/// commits more of it as it grows, so pointers into the buffer stay valid
+/// for as long as the buffer lives, no matter how much is appended.
+///
+/// Growing past the maximum capacity fails instead of reallocating.
+#[derive(Debug)]
+pub struct StableBuf {
+    region: VirtualRegion,
+    len: usize,
+    committed: usize,
+}
+
+impl StableBuf {
+    /// Creates an empty buffer that can grow up to `max_capacity` bytes.
+    ///
+    /// No memory is committed until the buffer is written to.
+    pub fn with_max_capacity(max_capacity: usize) -> Result<StableBuf, AllocError> {
+        Ok(StableBuf {
+            region: VirtualRegion::reserve(max_capacity)?,
+            len: 0,
+            committed: 0,
+        })
+    }
+
+    /// Returns the number of bytes in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes the buffer can hold without committing
+    /// more memory.
+    pub fn capacity(&self) -> usize {
+        self.committed
+    }
+
+    /// Returns the number of bytes the buffer can grow to.
+    ///
+    /// This is `max_capacity` rounded up to the page size.
+    pub fn max_capacity(&self) -> usize {
+        self.region.len()
+    }
+
+    /// Returns a pointer to the start of the buffer.
+    ///
+    /// The pointer is the same for the whole life of the buffer.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.region.as_ptr()
+    }
+
+    /// Returns a mutable pointer to the start of the buffer.
+    ///
+    /// The pointer is the same for the whole life of the buffer.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.region.as_ptr()
+    }
+
+    /// Ensures the buffer can hold at least `additional` more bytes.
+    ///
+    /// Memory is committed in growing steps to amortize the cost of
+    /// committing. On failure, or if the buffer would exceed its maximum
+    /// capacity, the buffer is left unchanged.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        let needed = match self.len.checked_add(additional) {
+            Some(needed) if needed <= self.max_capacity() => needed,
+            _ => return Err(AllocError::OutOfMemory),
+        };
+
+        if needed <= self.committed {
+            return Ok(());
+        }
+
+        let target = cmp::min(cmp::max(needed, self.committed * 2), self.max_capacity());
+        let target = round_up(target, sys::page_size());
+
+        self.region.commit(self.committed..target)?;
+        self.committed = target;
+
+        Ok(())
+    }
+
+    /// Appends a byte to the buffer.
+    pub fn push(&mut self, byte: u8) -> Result<(), AllocError> {
+        self.extend_from_slice(&[byte])
+    }
+
+    /// Appends all bytes of `src` to the buffer.
+    pub fn extend_from_slice(&mut self, src: &[u8]) -> Result<(), AllocError> {
+        self.reserve(src.len())?;
+
+        unsafe {
+            let dst = self.region.as_ptr().add(self.len);
+            ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        }
+
+        self.len += src.len();
+        Ok(())
+    }
+
+    /// Shortens the buffer to `len` bytes.
+    ///
+    /// Has no effect if `len` is greater than the current length. The
+    /// committed memory is kept; see `shrink_to_fit`.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.len = len;
+        }
+    }
+
+    /// Removes all bytes from the buffer.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Sets the length of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// `len` must not exceed `capacity()`. Bytes between the old and new
+    /// length are whatever was last written there, or zero.
+    pub unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.committed);
+        self.len = len;
+    }
+
+    /// Returns the committed pages past the end of the buffer to the system.
+    pub fn shrink_to_fit(&mut self) {
+        let end = round_up(self.len, sys::page_size());
+
+        if end < self.committed {
+            self.region.decommit(end..self.committed);
+            self.committed = end;
+        }
+    }
+}
+
+impl ops::Deref for StableBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+}
+
+impl ops::DerefMut for StableBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for StableBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for StableBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StableBuf;
+    use sys;
+    use AllocError;
+
+    #[test]
+    fn test_grow_without_moving() {
+        let mut buf = StableBuf::with_max_capacity(1 << 24).unwrap();
+        let ptr = buf.as_ptr();
+
+        for i in 0..100_000 {
+            buf.push(i as u8).unwrap();
+        }
+
+        assert_eq!(ptr, buf.as_ptr());
+        assert_eq!(100_000, buf.len());
+        assert!(buf.capacity() >= 100_000);
+
+        for (i, &b) in buf.iter().enumerate() {
+            assert_eq!(i as u8, b);
+        }
+    }
+
+    #[test]
+    fn test_max_capacity() {
+        let page = sys::page_size();
+        let mut buf = StableBuf::with_max_capacity(page).unwrap();
+
+        buf.extend_from_slice(&[1; 10]).unwrap();
+        assert_eq!(Err(AllocError::OutOfMemory), buf.reserve(page));
+        assert_eq!(10, buf.len());
+
+        buf.push(2).unwrap();
+        assert_eq!(&[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2], &buf[..]);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let page = sys::page_size();
+        let mut buf = StableBuf::with_max_capacity(16 * page).unwrap();
+
+        buf.reserve(8 * page).unwrap();
+        buf.push(1).unwrap();
+        buf.shrink_to_fit();
+        assert_eq!(page, buf.capacity());
+
+        buf.truncate(0);
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(&[3; 4096]).unwrap();
+        assert_eq!(3, buf[4095]);
+    }
+}
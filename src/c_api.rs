@@ -0,0 +1,211 @@
+//! C ABI exports.
+//!
+//! C libraries linked into the same process can allocate through the crate
+//! with these functions, so their blocks show up in the stats and leak
+//! reports, and are served by the same backend as the rest of the program.
+//! Blocks must be released with `stable_heap_free`, never with `free`.
+//!
+//! C callers don't pass the size back when freeing, so each block starts with
+//! a header recording its size and alignment, placed right before the pointer
+//! handed out.
+
+use core::{cmp, mem, ptr};
+use core::ffi::c_void;
+
+// The size and alignment of the block, as requested
+#[repr(C)]
+struct Header {
+    size: usize,
+    align: usize,
+}
+
+// Returns the offset of the payload and the alignment of the block, padding
+// the header so the payload is aligned
+fn offset(align: usize) -> (usize, usize) {
+    let align = cmp::max(align, mem::align_of::<Header>());
+    (cmp::max(align, mem::size_of::<Header>()), align)
+}
+
+unsafe fn header(ptr: *mut c_void) -> *mut Header {
+    (ptr as *mut Header).sub(1)
+}
+
+/// Return a pointer to `size` bytes of memory aligned to `align`.
+///
+/// Return a null pointer if the memory can't be allocated or `align` is not a
+/// power of 2 no larger than `MAX_ALIGN`. A size of 0 still allocates a
+/// unique block, as `malloc` does.
+#[no_mangle]
+pub extern "C" fn stable_heap_malloc(size: usize, align: usize) -> *mut c_void {
+    unsafe { allocate(size, align, false) }
+}
+
+/// Return a pointer to `size` zeroed bytes of memory aligned to `align`.
+///
+/// Failures are reported the same way as `stable_heap_malloc`.
+#[no_mangle]
+pub extern "C" fn stable_heap_calloc(size: usize, align: usize) -> *mut c_void {
+    unsafe { allocate(size, align, true) }
+}
+
+/// Resize the block referenced by `ptr` to `size` bytes, keeping its
+/// alignment.
+///
+/// A null `ptr` allocates a new block aligned to the natural alignment of a
+/// `usize`, and a `size` of 0 frees the block and returns a null pointer, as
+/// `realloc` does. On failure, return a null pointer and leave the block
+/// intact.
+///
+/// # Safety
+///
+/// `ptr` must be null or a block returned by one of these functions that was
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn stable_heap_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return allocate(size, mem::align_of::<usize>(), false);
+    }
+
+    if size == 0 {
+        stable_heap_free(ptr);
+        return ptr::null_mut();
+    }
+
+    let Header { size: old_size, align } = ptr::read(header(ptr));
+    let (offset, block_align) = offset(align);
+
+    let total = match size.checked_add(offset) {
+        Some(total) if ::check_layout(total, block_align).is_ok() => total,
+        _ => return ptr::null_mut(),
+    };
+
+    let base = (ptr as *mut u8).sub(offset);
+    let base = ::reallocate(base, old_size + offset, total, block_align);
+
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+
+    let ptr = base.add(offset) as *mut c_void;
+    ptr::write(header(ptr), Header { size, align });
+    ptr
+}
+
+/// Deallocates the block referenced by `ptr`. A null `ptr` is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be null or a block returned by one of these functions that was
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn stable_heap_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let Header { size, align } = ptr::read(header(ptr));
+    let (offset, block_align) = offset(align);
+
+    ::deallocate((ptr as *mut u8).sub(offset), size + offset, block_align)
+}
+
+/// Returns the number of bytes usable in the block referenced by `ptr`, or 0
+/// if `ptr` is null.
+///
+/// # Safety
+///
+/// `ptr` must be null or a block returned by one of these functions that was
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn stable_heap_usable_size(ptr: *mut c_void) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+
+    let Header { size, align } = ptr::read(header(ptr));
+    let (offset, block_align) = offset(align);
+
+    ::usable_size(size + offset, block_align) - offset
+}
+
+unsafe fn allocate(size: usize, align: usize, zeroed: bool) -> *mut c_void {
+    if !align.is_power_of_two() {
+        return ptr::null_mut();
+    }
+
+    let (offset, block_align) = offset(align);
+
+    let total = match size.checked_add(offset) {
+        Some(total) if ::check_layout(total, block_align).is_ok() => total,
+        _ => return ptr::null_mut(),
+    };
+
+    let base = if zeroed {
+        ::allocate_zeroed(total, block_align)
+    } else {
+        ::allocate(total, block_align)
+    };
+
+    if base.is_null() {
+        return ptr::null_mut();
+    }
+
+    let ptr = base.add(offset) as *mut c_void;
+    ptr::write(header(ptr), Header { size, align });
+    ptr
+}
+
+#[cfg(test)]
+mod test {
+    use {stable_heap_calloc, stable_heap_free, stable_heap_malloc, stable_heap_realloc, stable_heap_usable_size};
+    use std::{ptr, slice};
+
+    #[test]
+    fn test_c_api() {
+        unsafe {
+            let ptr = stable_heap_malloc(100, 64) as *mut u8;
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % 64);
+            assert!(stable_heap_usable_size(ptr as *mut _) >= 100);
+
+            ptr::write_bytes(ptr, 7, 100);
+
+            let ptr = stable_heap_realloc(ptr as *mut _, 5000) as *mut u8;
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % 64);
+            assert!(slice::from_raw_parts(ptr, 100).iter().all(|&b| b == 7));
+
+            let ptr = stable_heap_realloc(ptr as *mut _, 10) as *mut u8;
+            assert!(slice::from_raw_parts(ptr, 10).iter().all(|&b| b == 7));
+            stable_heap_free(ptr as *mut _);
+
+            let ptr = stable_heap_calloc(32, 1) as *mut u8;
+            assert!(slice::from_raw_parts(ptr, 32).iter().all(|&b| b == 0));
+            assert!(stable_heap_realloc(ptr as *mut _, 0).is_null());
+
+            // Like `malloc`, a size of 0 gives a block that can be freed
+            let ptr = stable_heap_malloc(0, 8);
+            assert!(!ptr.is_null());
+            stable_heap_free(ptr);
+
+            let ptr = stable_heap_realloc(ptr::null_mut(), 24);
+            assert_eq!(0, ptr as usize % ::std::mem::align_of::<usize>());
+            stable_heap_free(ptr);
+
+            stable_heap_free(ptr::null_mut());
+            assert_eq!(0, stable_heap_usable_size(ptr::null_mut()));
+        }
+    }
+
+    #[test]
+    fn test_c_api_invalid() {
+        unsafe {
+            assert!(stable_heap_malloc(16, 3).is_null());
+            assert!(stable_heap_malloc(usize::MAX, 8).is_null());
+
+            let ptr = stable_heap_malloc(16, 8);
+            assert!(stable_heap_realloc(ptr, usize::MAX - 4).is_null());
+            stable_heap_free(ptr);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use round_up;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Returns the size of a cache line, in bytes.
+///
+/// The size is queried from the system when possible and falls back to a
+/// per-architecture default otherwise. The result is always a power of two.
+pub fn cache_line_size() -> usize {
+    static CACHE_LINE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    match CACHE_LINE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let size = match detect() {
+                Some(size) if size.is_power_of_two() && size <= ::MAX_ALIGN => size,
+                _ => DEFAULT,
+            };
+
+            CACHE_LINE_SIZE.store(size, Ordering::Relaxed);
+            size
+        }
+        size => size,
+    }
+}
+
+/// Return a pointer to `size` bytes of memory aligned to, and padded out to,
+/// the cache line size.
+///
+/// No other allocation shares a cache line with the block, which avoids
+/// false sharing between values written by different threads.
+///
+/// If `size` is 0, return `dangling(cache_line_size())` without allocating,
+/// which `deallocate_cache_aligned` accepts with a size of 0.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_cache_aligned` using the
+/// same `size`.
+#[inline]
+pub unsafe fn allocate_cache_aligned(size: usize) -> *mut u8 {
+    let line = cache_line_size();
+
+    if size > isize::MAX as usize - (line - 1) {
+        return ::core::ptr::null_mut();
+    }
+
+    ::allocate(round_up(size, line), line)
+}
+
+/// Deallocates memory obtained from `allocate_cache_aligned`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_cache_aligned` with the same
+/// `size`.
+#[inline]
+pub unsafe fn deallocate_cache_aligned(ptr: *mut u8, size: usize) {
+    let line = cache_line_size();
+    ::deallocate(ptr, round_up(size, line), line)
+}
+
+#[cfg(any(target_arch = "powerpc64", all(target_arch = "aarch64", any(target_os = "macos", target_os = "ios"))))]
+const DEFAULT: usize = 128;
+
+#[cfg(not(any(target_arch = "powerpc64", all(target_arch = "aarch64", any(target_os = "macos", target_os = "ios")))))]
+const DEFAULT: usize = 64;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn detect() -> Option<usize> {
+    match unsafe { ::sys::sysconf(::sys::_SC_LEVEL1_DCACHE_LINESIZE) } {
+        size if size > 0 => Some(size as usize),
+        _ => None,
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn detect() -> Option<usize> {
+    let mut size: u64 = 0;
+    let mut len = ::core::mem::size_of::<u64>();
+
+    let res = unsafe {
+        ::sys::sysctlbyname(
+            b"hw.cachelinesize\0".as_ptr() as *const ::sys::c_char,
+            &mut size as *mut u64 as *mut ::sys::c_void,
+            &mut len,
+            ::core::ptr::null_mut(),
+            0)
+    };
+
+    if res != 0 || size == 0 {
+        return None;
+    }
+
+    Some(size as usize)
+}
+
+#[cfg(not(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos", target_os = "ios")))]
+fn detect() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_cache_aligned, cache_line_size, deallocate_cache_aligned};
+
+    #[test]
+    fn test_cache_line_size() {
+        let line = cache_line_size();
+        assert!(line.is_power_of_two());
+        assert!(line >= 16);
+        assert_eq!(line, cache_line_size());
+    }
+
+    #[test]
+    fn test_allocate_cache_aligned() {
+        let line = cache_line_size();
+
+        unsafe {
+            let ptr = allocate_cache_aligned(8);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & (line - 1));
+            assert!(::usable_size(8, line) >= line);
+            deallocate_cache_aligned(ptr, 8);
+
+            let ptr = allocate_cache_aligned(0);
+            assert_eq!(::dangling(line).as_ptr(), ptr);
+            deallocate_cache_aligned(ptr, 0);
+        }
+    }
+}
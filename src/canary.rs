@@ -0,0 +1,193 @@
+use {Alloc, AllocError, Layout};
+
+use core::{cmp, ptr};
+use core::ptr::NonNull;
+
+// Byte written to the redzones around each block
+const PATTERN: u8 = 0xFD;
+
+// Size of the redzone after each block. The one in front is at least this
+// large, and as large as the alignment.
+const REDZONE: usize = 16;
+
+/// An allocator that surrounds each block of `A` with canary bytes.
+///
+/// Redzones filled with a known pattern are placed directly before and after
+/// every block, and are checked when the block is released or resized. An
+/// off-by-one write past either end is reported with a panic naming the
+/// corrupted address, instead of silently damaging a neighboring block.
+///
+/// This is much lighter than guard pages for small blocks, but only catches
+/// the corruption once the block is released, or when `check` is called.
+#[derive(Debug, Clone, Default)]
+pub struct Canary<A> {
+    inner: A,
+}
+
+impl<A> Canary<A> {
+    /// Wraps `inner` with canaries.
+    pub fn new(inner: A) -> Canary<A> {
+        Canary { inner }
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Verifies the canaries around the block referenced by `ptr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a canary byte was overwritten.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator for `layout`.
+    pub unsafe fn check(&self, ptr: NonNull<u8>, layout: Layout) {
+        let front = front_size(layout.align());
+        let base = ptr.as_ptr().sub(front);
+
+        if let Some(addr) = find_corrupted(base, front) {
+            panic!("canary at {:p} overwritten, {} bytes before the block at {:p}",
+                   addr, ptr.as_ptr() as usize - addr as usize, ptr.as_ptr());
+        }
+
+        let end = ptr.as_ptr().add(layout.size());
+
+        if let Some(addr) = find_corrupted(end, REDZONE) {
+            panic!("canary at {:p} overwritten, {} bytes past the end of the block at {:p}",
+                   addr, addr as usize - end as usize, ptr.as_ptr());
+        }
+    }
+}
+
+unsafe impl<A: Alloc> Alloc for Canary<A> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let base = self.inner.alloc(outer_layout(layout)?)?;
+        Ok(fill(base, layout))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.check(ptr, layout);
+
+        let base = ptr.as_ptr().sub(front_size(layout.align()));
+        self.inner.dealloc(NonNull::new_unchecked(base), outer_layout_unchecked(layout))
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        self.check(ptr, layout);
+
+        if new_size == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+
+        let front = front_size(layout.align());
+        let base = NonNull::new_unchecked(ptr.as_ptr().sub(front));
+        let new_base = self.inner.realloc(base, outer_layout_unchecked(layout), outer_layout(new_layout)?.size())?;
+
+        // The front redzone moved along with the contents
+        let ptr = new_base.as_ptr().add(front);
+        ptr::write_bytes(ptr.add(new_size), PATTERN, REDZONE);
+
+        Ok(NonNull::new_unchecked(ptr))
+    }
+}
+
+// The redzone in front is a multiple of the alignment, so the block stays
+// aligned
+fn front_size(align: usize) -> usize {
+    cmp::max(align, REDZONE)
+}
+
+fn outer_layout(layout: Layout) -> Result<Layout, AllocError> {
+    let size = layout.size()
+        .checked_add(front_size(layout.align()) + REDZONE)
+        .ok_or(AllocError::InvalidLayout)?;
+
+    Layout::from_size_align(size, layout.align())
+}
+
+unsafe fn outer_layout_unchecked(layout: Layout) -> Layout {
+    let size = layout.size() + front_size(layout.align()) + REDZONE;
+    Layout::from_size_align_unchecked(size, layout.align())
+}
+
+// Writes both redzones around a fresh outer block and returns the inner block
+unsafe fn fill(base: NonNull<u8>, layout: Layout) -> NonNull<u8> {
+    let front = front_size(layout.align());
+    let ptr = base.as_ptr().add(front);
+
+    ptr::write_bytes(base.as_ptr(), PATTERN, front);
+    ptr::write_bytes(ptr.add(layout.size()), PATTERN, REDZONE);
+
+    NonNull::new_unchecked(ptr)
+}
+
+unsafe fn find_corrupted(ptr: *const u8, len: usize) -> Option<*const u8> {
+    (0..len).map(|i| ptr.add(i)).find(|&p| *p != PATTERN)
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, Canary, Heap, Layout};
+
+    #[test]
+    fn test_canary() {
+        let mut alloc = Canary::new(Heap);
+
+        unsafe {
+            for &align in &[1, 8, 64] {
+                let layout = Layout::from_size_align(24, align).unwrap();
+                let ptr = alloc.alloc(layout).unwrap();
+                assert_eq!(0, ptr.as_ptr() as usize % align);
+
+                ::std::ptr::write_bytes(ptr.as_ptr(), 1, 24);
+                alloc.check(ptr, layout);
+
+                let ptr = alloc.realloc(ptr, layout, 100).unwrap();
+                assert_eq!(1, *ptr.as_ptr().add(23));
+                *ptr.as_ptr().add(99) = 2;
+
+                alloc.dealloc(ptr, Layout::from_size_align(100, align).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "0 bytes past the end")]
+    fn test_overflow() {
+        let mut alloc = Canary::new(Heap);
+        let layout = Layout::from_size_align(10, 1).unwrap();
+
+        unsafe {
+            let ptr = alloc.alloc(layout).unwrap();
+            *ptr.as_ptr().add(10) = 0;
+            alloc.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "1 bytes before")]
+    fn test_underflow() {
+        let mut alloc = Canary::new(Heap);
+        let layout = Layout::from_size_align(10, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc.alloc(layout).unwrap();
+            *ptr.as_ptr().sub(1) = 0;
+            alloc.dealloc(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use {backend, Layout};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr;
+
+/// Returns a `Vec<u8>` owning the `size` bytes of the block at `ptr`.
+///
+/// When the block comes from the global allocator with an alignment of 1, as
+/// it does with the `Vec` and `std-alloc` backends, the vector takes over the
+/// block without copying. Otherwise the bytes are copied into a new vector
+/// and the block is released. Either way, the block must not be used or
+/// released afterwards.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate` with `size` and `align`, and
+/// its `size` bytes must be initialized.
+pub unsafe fn assume_vec(ptr: *mut u8, size: usize, align: usize) -> Vec<u8> {
+    if size == 0 {
+        return Vec::new();
+    }
+
+    if align == 1 && backend::from_global(size, align) {
+        #[cfg(feature = "debug-checks")]
+        ::leaks::check_release(ptr, size, align);
+
+        // The block leaves the heap as far as the instrumentation goes
+        ::on_deallocate(ptr, size, align);
+
+        return Vec::from_raw_parts(ptr, size, size);
+    }
+
+    let mut vec = Vec::with_capacity(size);
+    ptr::copy_nonoverlapping(ptr, vec.as_mut_ptr(), size);
+    vec.set_len(size);
+
+    ::deallocate(ptr, size, align);
+
+    vec
+}
+
+/// Turns `vec` into a block owned by the heap, returning a pointer to it and
+/// its size.
+///
+/// The block holds the elements of `vec` and is released with `deallocate`
+/// using the returned size and an alignment of 1. Spare capacity is given
+/// back first. Where the heap shares the global allocator, as with the `Vec`
+/// and `std-alloc` backends, the elements aren't copied. An empty vector
+/// yields `dangling(1)` and a size of 0.
+///
+/// # Panics
+///
+/// Aborts the process if the elements need to be copied and the heap is
+/// exhausted, as `Vec` does.
+pub fn leak_vec(vec: Vec<u8>) -> (*mut u8, usize) {
+    let size = vec.len();
+
+    if size == 0 {
+        return (::dangling(1).as_ptr(), 0);
+    }
+
+    if backend::from_global(size, 1) {
+        let ptr = Box::into_raw(vec.into_boxed_slice()) as *mut u8;
+        ::on_allocate(ptr, size, 1);
+
+        return (ptr, size);
+    }
+
+    unsafe {
+        let ptr = ::allocate(size, 1);
+
+        if ptr.is_null() {
+            ::oom::abort(Layout::from_size_align_unchecked(size, 1));
+        }
+
+        ptr::copy_nonoverlapping(vec.as_ptr(), ptr, size);
+
+        (ptr, size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {assume_vec, backend, leak_vec};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_vec_round_trip() {
+        let mut vec = Vec::with_capacity(100);
+        vec.extend_from_slice(b"hello world");
+
+        let (ptr, size) = leak_vec(vec);
+        assert_eq!(11, size);
+
+        unsafe {
+            assert_eq!(b'w', *ptr.add(6));
+
+            let ptr = ::reallocate(ptr, size, 12, 1);
+            *ptr.add(11) = b'!';
+
+            let vec = assume_vec(ptr, 12, 1);
+            assert_eq!(&b"hello world!"[..], &vec[..]);
+
+            if backend::from_global(12, 1) {
+                assert_eq!(ptr, vec.as_ptr() as *mut u8);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vec_conversions_copy() {
+        unsafe {
+            let ptr = ::allocate(8, 8);
+            ::std::ptr::write_bytes(ptr, 7, 8);
+
+            // Over-aligned blocks are copied
+            let vec = assume_vec(ptr, 8, 8);
+            assert_eq!(&[7; 8][..], &vec[..]);
+
+            assert!(assume_vec(::allocate(0, 4), 0, 4).is_empty());
+        }
+
+        let (ptr, size) = leak_vec(Vec::new());
+        assert_eq!(0, size);
+        unsafe { ::deallocate(ptr, size, 1) };
+    }
+}
@@ -0,0 +1,28 @@
+use core::fmt;
+
+/// The error type returned by the fallible allocation functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// The allocator could not satisfy the request.
+    OutOfMemory,
+
+    /// The requested size and alignment do not describe a valid allocation.
+    ///
+    /// This is returned for a size of 0, an alignment that is not a power of
+    /// 2 or is larger than `MAX_ALIGN`, or a size that overflows when rounded
+    /// up to the alignment.
+    InvalidLayout,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllocError::OutOfMemory => fmt.write_str("memory allocation failed"),
+            AllocError::InvalidLayout => fmt.write_str("invalid allocation layout"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for AllocError {
+}
@@ -0,0 +1,108 @@
+use {round_up, sys};
+
+#[cfg(feature = "std")]
+use Protection;
+
+use core::ptr;
+
+#[cfg(feature = "std")]
+use std::io;
+
+/// Return a pointer to `size` bytes of zeroed, page aligned memory for
+/// generated code.
+///
+/// The memory starts out readable and writable but not executable. Once the
+/// code is written, call `make_executable` to flip it to read-execute; the
+/// pages are never writable and executable at the same time.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_executable` using the same
+/// `size`.
+pub unsafe fn allocate_executable(size: usize) -> *mut u8 {
+    let page = sys::page_size();
+
+    if size == 0 || size > isize::MAX as usize - (page - 1) {
+        return ptr::null_mut();
+    }
+
+    sys::map_anonymous(round_up(size, page), 0)
+}
+
+/// Deallocates memory obtained from `allocate_executable`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_executable` with the same
+/// `size`.
+pub unsafe fn deallocate_executable(ptr: *mut u8, size: usize) {
+    sys::unmap(ptr, round_up(size, sys::page_size()))
+}
+
+/// Makes the pages in `ptr..ptr + len` read-execute and flushes the
+/// instruction cache for the range.
+///
+/// The pages are no longer writable afterwards. To patch the code, make them
+/// writable again with `protect(ptr, len, Protection::ReadWrite)` and call
+/// this function once done.
+///
+/// # Safety
+///
+/// `ptr` must be page aligned and the range must lie within memory obtained
+/// from `allocate_executable`. Nothing may write to the range while it is
+/// executable.
+#[cfg(feature = "std")]
+pub unsafe fn make_executable(ptr: *mut u8, len: usize) -> io::Result<()> {
+    ::protect(ptr, len, Protection::ReadExecute)?;
+    flush_icache(ptr, len);
+    Ok(())
+}
+
+// x86 keeps the instruction cache coherent with data writes
+#[cfg(all(unix, not(any(target_arch = "arm", target_arch = "aarch64"))))]
+#[cfg(feature = "std")]
+unsafe fn flush_icache(_ptr: *mut u8, _len: usize) {
+}
+
+#[cfg(all(unix, any(target_arch = "arm", target_arch = "aarch64")))]
+#[cfg(feature = "std")]
+unsafe fn flush_icache(ptr: *mut u8, len: usize) {
+    sys::__clear_cache(ptr as *mut sys::c_void, ptr.add(len) as *mut sys::c_void);
+}
+
+#[cfg(windows)]
+#[cfg(feature = "std")]
+unsafe fn flush_icache(ptr: *mut u8, len: usize) {
+    sys::FlushInstructionCache(sys::GetCurrentProcess(), ptr as sys::LPVOID, len);
+}
+
+#[cfg(all(test, feature = "std", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod test {
+    use super::{allocate_executable, deallocate_executable, make_executable};
+    use std::mem;
+
+    #[test]
+    fn test_make_executable() {
+        // Returns 42
+        #[cfg(target_arch = "x86_64")]
+        let code = [0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3];
+
+        #[cfg(target_arch = "aarch64")]
+        let code = [0x40, 0x05, 0x80, 0x52, 0xC0, 0x03, 0x5F, 0xD6];
+
+        unsafe {
+            let ptr = allocate_executable(code.len());
+            assert!(!ptr.is_null());
+
+            ptr.copy_from_nonoverlapping(code.as_ptr(), code.len());
+            make_executable(ptr, code.len()).unwrap();
+
+            let f: extern "C" fn() -> u32 = mem::transmute(ptr);
+            assert_eq!(42, f());
+
+            deallocate_executable(ptr, code.len());
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Allocation failure injection.
+//!
+//! With the `fail-injection` feature, `try_allocate`, `try_allocate_zeroed`
+//! and `try_reallocate` (and everything built on them) can be made to report
+//! `AllocError::OutOfMemory` on a schedule, so code handling exhaustion can be
+//! tested without exhausting memory.
+//!
+//! Injection applies to the current thread only, so tests running in
+//! parallel don't affect each other. It lasts until the returned guard is
+//! dropped, at which point the previous schedule is restored.
+
+use std::cell::Cell;
+
+#[derive(Debug, Clone, Copy)]
+enum Schedule {
+    Off,
+
+    // Allocations left to succeed before every one fails
+    After(usize),
+
+    // Fail when the next random number, scaled to [0, 1), is below the
+    // probability
+    Random { probability: f64, state: u64 },
+}
+
+::std::thread_local! {
+    #[allow(clippy::missing_const_for_thread_local)]
+    static SCHEDULE: Cell<Schedule> = Cell::new(Schedule::Off);
+}
+
+/// Restores the previous failure schedule of the thread when dropped.
+#[derive(Debug)]
+pub struct FailureGuard {
+    prev: Schedule,
+}
+
+impl Drop for FailureGuard {
+    fn drop(&mut self) {
+        let _ = SCHEDULE.try_with(|schedule| schedule.set(self.prev));
+    }
+}
+
+/// Lets the next `n` allocations of the current thread succeed, and fails
+/// every allocation after them.
+pub fn fail_after(n: usize) -> FailureGuard {
+    set(Schedule::After(n))
+}
+
+/// Fails each allocation of the current thread with probability
+/// `probability`.
+///
+/// The failures are drawn from a pseudo-random sequence started from `seed`,
+/// so a run can be reproduced by passing the same seed.
+///
+/// # Panics
+///
+/// Panics if `probability` is not between 0 and 1.
+pub fn fail_with_probability(probability: f64, seed: u64) -> FailureGuard {
+    assert!((0.0..=1.0).contains(&probability), "probability must be between 0 and 1; probability={}", probability);
+
+    // A zero state would stay zero
+    let state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+
+    set(Schedule::Random { probability, state })
+}
+
+/// Returns `true` if the allocation about to be made must fail.
+pub(crate) fn should_fail() -> bool {
+    SCHEDULE.try_with(|schedule| {
+        let (next, fail) = match schedule.get() {
+            Schedule::Off => return false,
+            Schedule::After(0) => (Schedule::After(0), true),
+            Schedule::After(n) => (Schedule::After(n - 1), false),
+            Schedule::Random { probability, state } => {
+                let state = xorshift(state);
+                let sample = (state >> 11) as f64 / (1u64 << 53) as f64;
+                (Schedule::Random { probability, state }, sample < probability)
+            }
+        };
+
+        schedule.set(next);
+        fail
+    }).unwrap_or(false)
+}
+
+fn set(schedule: Schedule) -> FailureGuard {
+    let prev = SCHEDULE.try_with(|current| current.replace(schedule)).unwrap_or(Schedule::Off);
+    FailureGuard { prev }
+}
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fail_after, fail_with_probability};
+    use {AllocError, Layout};
+    use std::vec::Vec;
+
+    fn try_alloc() -> Result<(), AllocError> {
+        unsafe {
+            let layout = Layout::from_size_align(32, 8).unwrap();
+            let ptr = ::try_allocate(layout)?;
+            ::release(ptr, layout);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_fail_after() {
+        {
+            let _guard = fail_after(2);
+            assert!(try_alloc().is_ok());
+            assert!(try_alloc().is_ok());
+            assert_eq!(Err(AllocError::OutOfMemory), try_alloc());
+            assert_eq!(Err(AllocError::OutOfMemory), try_alloc());
+
+            // Nested guards restore the outer schedule
+            {
+                let _inner = fail_after(1);
+                assert!(try_alloc().is_ok());
+            }
+
+            assert_eq!(Err(AllocError::OutOfMemory), try_alloc());
+        }
+
+        assert!(try_alloc().is_ok());
+    }
+
+    #[test]
+    fn test_fail_with_probability() {
+        let run = |seed| {
+            let _guard = fail_with_probability(0.5, seed);
+            (0..64).map(|_| try_alloc().is_err()).collect::<Vec<_>>()
+        };
+
+        let a = run(7);
+        assert_eq!(a, run(7));
+        assert!(a.iter().any(|&failed| failed));
+        assert!(a.iter().any(|&failed| !failed));
+
+        {
+            let _guard = fail_with_probability(0.0, 1);
+            assert!((0..64).all(|_| try_alloc().is_ok()));
+        }
+
+        let _guard = fail_with_probability(1.0, 1);
+        assert!((0..64).all(|_| try_alloc().is_err()));
+    }
+}
@@ -0,0 +1,168 @@
+use {Alloc, AllocError, Buddy, FixedAlloc, FreeList, Layout, StackAlloc};
+
+use core::{cmp, ptr};
+use core::ptr::NonNull;
+
+/// An allocator that can tell whether it handed out a block.
+///
+/// # Safety
+///
+/// `owns` must return `true` for every block allocated and not yet released
+/// by the allocator, and `false` for blocks of any other allocator.
+pub unsafe trait Owns: Alloc {
+    /// Returns `true` if `ptr` was allocated by this allocator.
+    fn owns(&self, ptr: NonNull<u8>) -> bool;
+}
+
+/// An allocator that tries `A` first and falls back to `B`.
+///
+/// `A` must implement `Owns` so that blocks are released by the allocator
+/// they came from. A typical use is a fixed buffer backed up by the heap.
+#[derive(Debug, Clone, Default)]
+pub struct Fallback<A, B> {
+    primary: A,
+    fallback: B,
+}
+
+impl<A, B> Fallback<A, B> {
+    /// Combines `primary` with `fallback`.
+    pub fn new(primary: A, fallback: B) -> Fallback<A, B> {
+        Fallback { primary, fallback }
+    }
+
+    /// Returns a reference to the primary allocator.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Returns a reference to the fallback allocator.
+    pub fn fallback(&self) -> &B {
+        &self.fallback
+    }
+
+    /// Splits the combinator into its two allocators.
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.fallback)
+    }
+}
+
+unsafe impl<A: Owns, B: Alloc> Alloc for Fallback<A, B> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        match self.primary.alloc(layout) {
+            Ok(ptr) => Ok(ptr),
+            Err(_) => self.fallback.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.primary.owns(ptr) {
+            self.primary.dealloc(ptr, layout)
+        } else {
+            self.fallback.dealloc(ptr, layout)
+        }
+    }
+
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        match self.primary.alloc_zeroed(layout) {
+            Ok(ptr) => Ok(ptr),
+            Err(_) => self.fallback.alloc_zeroed(layout),
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        if !self.primary.owns(ptr) {
+            return self.fallback.realloc(ptr, layout, new_size);
+        }
+
+        if let Ok(new_ptr) = self.primary.realloc(ptr, layout, new_size) {
+            return Ok(new_ptr);
+        }
+
+        // Move the block over to the fallback
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+        let new_ptr = self.fallback.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.primary.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+}
+
+unsafe impl<'a> Owns for FixedAlloc<'a> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.contains(ptr.as_ptr())
+    }
+}
+
+unsafe impl<'a> Owns for FreeList<'a> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.contains(ptr.as_ptr())
+    }
+}
+
+unsafe impl<'a> Owns for Buddy<'a> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.contains(ptr.as_ptr())
+    }
+}
+
+unsafe impl Owns for StackAlloc {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.contains(ptr.as_ptr())
+    }
+}
+
+unsafe impl<A: Owns, B: Owns> Owns for Fallback<A, B> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.primary.owns(ptr) || self.fallback.owns(ptr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, Fallback, FixedAlloc, Heap, Layout, Owns, StackAlloc};
+
+    #[test]
+    fn test_fallback() {
+        let mut buf = [0u8; 64];
+        let mut alloc = Fallback::new(FixedAlloc::new(&mut buf), Heap);
+        let layout = Layout::from_size_align(48, 8).unwrap();
+
+        unsafe {
+            let a = alloc.alloc(layout).unwrap();
+            let b = alloc.alloc(layout).unwrap();
+            assert!(alloc.primary().owns(a));
+            assert!(!alloc.primary().owns(b));
+
+            *b.as_ptr() = 1;
+
+            alloc.dealloc(b, layout);
+            alloc.dealloc(a, layout);
+            assert_eq!(0, alloc.primary().used());
+        }
+    }
+
+    #[test]
+    fn test_realloc_moves_to_fallback() {
+        let mut alloc = Fallback::new(StackAlloc::with_capacity(64).unwrap(), Heap);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+
+        unsafe {
+            let a = alloc.alloc(layout).unwrap();
+            *a.as_ptr() = 5;
+
+            // Grows in place
+            let a = alloc.realloc(a, layout, 64).unwrap();
+            assert!(alloc.primary().owns(a));
+
+            // Too large for the stack
+            let big = alloc.realloc(a, Layout::from_size_align(64, 8).unwrap(), 1000).unwrap();
+            assert!(!alloc.primary().owns(big));
+            assert_eq!(5, *big.as_ptr());
+            assert_eq!(0, alloc.primary().used());
+
+            alloc.dealloc(big, Layout::from_size_align(1000, 8).unwrap());
+        }
+    }
+}
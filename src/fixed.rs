@@ -0,0 +1,180 @@
+use {round_up, Alloc, AllocError, Layout};
+
+use core::{cmp, fmt, ptr};
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// A bump allocator over a caller-provided buffer.
+///
+/// Blocks are carved off the front of the buffer in order, and the allocator
+/// never touches the heap or the OS, so it can serve static or stack memory
+/// on targets without either. Releasing the most recently allocated block
+/// returns its memory, and `reset` releases everything at once.
+pub struct FixedAlloc<'a> {
+    ptr: *mut u8,
+    len: usize,
+
+    // Offset of the first free byte
+    top: usize,
+
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+unsafe impl<'a> Send for FixedAlloc<'a> {}
+
+impl<'a> FixedAlloc<'a> {
+    /// Creates an allocator serving blocks out of `buf`.
+    pub fn new(buf: &'a mut [u8]) -> FixedAlloc<'a> {
+        FixedAlloc {
+            ptr: buf.as_mut_ptr(),
+            len: buf.len(),
+            top: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes in use, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.top
+    }
+
+    /// Returns the number of bytes left at the end of the buffer.
+    pub fn remaining(&self) -> usize {
+        self.len - self.top
+    }
+
+    /// Returns `true` if `ptr` points into the buffer.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        addr >= self.ptr as usize && addr < self.ptr as usize + self.len
+    }
+
+    /// Releases every block.
+    ///
+    /// Pointers to the released blocks must not be used afterwards.
+    pub fn reset(&mut self) {
+        self.top = 0;
+    }
+
+    // Returns true if the block is the last one allocated
+    fn is_top(&self, ptr: NonNull<u8>, size: usize) -> bool {
+        ptr.as_ptr() as usize + size == self.ptr as usize + self.top
+    }
+}
+
+unsafe impl<'a> Alloc for FixedAlloc<'a> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let base = self.ptr as usize;
+        let start = round_up(base + self.top, layout.align()) - base;
+
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= self.len => {
+                self.top = end;
+                Ok(NonNull::new_unchecked(self.ptr.add(start)))
+            }
+            _ => Err(AllocError::OutOfMemory),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_top(ptr, layout.size()) {
+            self.top = ptr.as_ptr() as usize - self.ptr as usize;
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let start = ptr.as_ptr() as usize - self.ptr as usize;
+
+        // The last block can be resized in place
+        if self.is_top(ptr, layout.size()) && new_size > 0 {
+            if new_size > self.len - start {
+                return Err(AllocError::OutOfMemory);
+            }
+
+            self.top = start + new_size;
+            return Ok(ptr);
+        }
+
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+        let new_ptr = self.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+}
+
+impl<'a> fmt::Debug for FixedAlloc<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FixedAlloc")
+            .field("capacity", &self.len)
+            .field("used", &self.top)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, AllocError, FixedAlloc, Layout};
+
+    #[test]
+    fn test_fixed_alloc() {
+        let mut buf = [0u8; 256];
+        let mut fixed = FixedAlloc::new(&mut buf);
+
+        unsafe {
+            let a = fixed.alloc_one::<u8>().unwrap();
+            let b = fixed.alloc_one::<u64>().unwrap();
+            assert_eq!(0, b.as_ptr() as usize % 8);
+            assert!(b.as_ptr() as usize > a.as_ptr() as usize);
+
+            // Only the last block is given back
+            fixed.dealloc_one(a);
+            let used = fixed.used();
+            fixed.dealloc_one(b);
+            assert!(fixed.used() < used);
+
+            let n = fixed.remaining();
+            fixed.alloc_array::<u8>(n).unwrap();
+            assert_eq!(0, fixed.remaining());
+            assert_eq!(Err(AllocError::OutOfMemory), fixed.alloc_one::<u8>());
+        }
+
+        fixed.reset();
+        assert_eq!(0, fixed.used());
+        assert_eq!(256, fixed.capacity());
+    }
+
+    #[test]
+    fn test_realloc_in_place() {
+        let mut buf = [0u8; 64];
+        let mut fixed = FixedAlloc::new(&mut buf);
+
+        unsafe {
+            let layout = Layout::from_size_align(8, 1).unwrap();
+            let a = fixed.alloc(layout).unwrap();
+            *a.as_ptr() = 3;
+
+            let grown = fixed.realloc(a, layout, 32).unwrap();
+            assert_eq!(a, grown);
+            assert_eq!(32, fixed.used());
+
+            assert_eq!(Err(AllocError::OutOfMemory), fixed.realloc(a, Layout::from_size_align(32, 1).unwrap(), 65));
+
+            let shrunk = fixed.realloc(a, Layout::from_size_align(32, 1).unwrap(), 4).unwrap();
+            assert_eq!(a, shrunk);
+            assert_eq!(3, *shrunk.as_ptr());
+            assert_eq!(4, fixed.used());
+        }
+    }
+}
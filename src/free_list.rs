@@ -0,0 +1,402 @@
+use {round_up, Alloc, AllocError, Layout};
+
+use core::{cmp, fmt, mem, ptr};
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+// Blocks are carved in multiples of this size, so a free block always has room
+// for its header
+const UNIT: usize = mem::size_of::<FreeBlock>();
+
+/// How a `FreeList` picks the free block to serve an allocation from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    /// Use the first block, by address, large enough for the allocation.
+    First,
+
+    /// Use the smallest block large enough for the allocation.
+    Best,
+}
+
+/// A free-list allocator managing a fixed region of memory.
+///
+/// The free blocks are kept in a list sorted by address, threaded through the
+/// blocks themselves, and adjacent blocks are merged when memory is released.
+/// Allocations are rounded up to a multiple of two words.
+///
+/// The region is either borrowed from the caller or, with `map`, mapped from
+/// the OS and unmapped when the allocator is dropped.
+pub struct FreeList<'a> {
+    base: *mut u8,
+    len: usize,
+
+    // Sorted by address
+    head: *mut FreeBlock,
+
+    fit: Fit,
+    allocated: usize,
+
+    // Set if the region was mapped by `map`
+    mapped: bool,
+
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+unsafe impl<'a> Send for FreeList<'a> {}
+
+impl<'a> FreeList<'a> {
+    /// Creates an allocator serving blocks out of `region`.
+    pub fn new(region: &'a mut [u8], fit: Fit) -> FreeList<'a> {
+        unsafe { FreeList::from_raw_parts(region.as_mut_ptr(), region.len(), fit) }
+    }
+
+    /// Creates an allocator serving blocks out of the `len` bytes at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes, and must not be
+    /// accessed other than through the allocator's blocks, for the lifetime
+    /// `'a`.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize, fit: Fit) -> FreeList<'a> {
+        let start = round_up(ptr as usize, UNIT);
+        let len = len.saturating_sub(start - ptr as usize) & !(UNIT - 1);
+        let base = start as *mut u8;
+
+        let mut list = FreeList {
+            base,
+            len,
+            head: ptr::null_mut(),
+            fit,
+            allocated: 0,
+            mapped: false,
+            _marker: PhantomData,
+        };
+
+        if len > 0 {
+            list.head = base as *mut FreeBlock;
+            ptr::write(list.head, FreeBlock { size: len, next: ptr::null_mut() });
+        }
+
+        list
+    }
+
+    /// Returns the number of bytes in the managed region.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes currently allocated, including rounding.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the size of the largest free block.
+    ///
+    /// An allocation with an alignment of at most two words succeeds if and
+    /// only if its rounded size is no larger than this.
+    pub fn largest_free(&self) -> usize {
+        let mut largest = 0;
+        let mut curr = self.head;
+
+        while !curr.is_null() {
+            unsafe {
+                largest = cmp::max(largest, (*curr).size);
+                curr = (*curr).next;
+            }
+        }
+
+        largest
+    }
+
+    /// Returns `true` if `ptr` points into the managed region.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        addr >= self.base as usize && addr < self.base as usize + self.len
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl FreeList<'static> {
+    /// Creates an allocator serving blocks out of a region of at least `size`
+    /// bytes mapped from the OS.
+    ///
+    /// The region is unmapped when the allocator is dropped.
+    pub fn map(size: usize, fit: Fit) -> Result<FreeList<'static>, AllocError> {
+        if size == 0 || size > isize::MAX as usize {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let len = round_up(size, ::sys::page_size());
+
+        unsafe {
+            let ptr = ::sys::map_anonymous(len, 0);
+
+            if ptr.is_null() {
+                return Err(AllocError::OutOfMemory);
+            }
+
+            let mut list = FreeList::from_raw_parts(ptr, len, fit);
+            list.mapped = true;
+
+            Ok(list)
+        }
+    }
+}
+
+unsafe impl<'a> Alloc for FreeList<'a> {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let size = block_size(layout.size());
+        let align = cmp::max(layout.align(), UNIT);
+
+        // Find the link to the chosen block and the padding needed to align it
+        let mut chosen: Option<(*mut *mut FreeBlock, usize)> = None;
+        let mut link = &mut self.head as *mut *mut FreeBlock;
+
+        while !(*link).is_null() {
+            let block = *link;
+            let pad = round_up(block as usize, align) - block as usize;
+
+            if pad.checked_add(size).map(|n| n <= (*block).size).unwrap_or(false) {
+                match self.fit {
+                    Fit::First => {
+                        chosen = Some((link, pad));
+                        break;
+                    }
+                    Fit::Best => {
+                        let better = match chosen {
+                            Some((best, _)) => (*block).size < (**best).size,
+                            None => true,
+                        };
+
+                        if better {
+                            chosen = Some((link, pad));
+                        }
+                    }
+                }
+            }
+
+            link = &mut (*block).next;
+        }
+
+        let (mut link, pad) = chosen.ok_or(AllocError::OutOfMemory)?;
+        let block = *link;
+        let FreeBlock { size: block_size, next } = ptr::read(block);
+
+        // Unlink the block, putting back the padding before the allocation
+        // and the remainder after it
+        *link = next;
+
+        if pad > 0 {
+            ptr::write(block, FreeBlock { size: pad, next: *link });
+            *link = block;
+            link = &mut (*block).next;
+        }
+
+        let ptr = (block as *mut u8).add(pad);
+        let rest = block_size - pad - size;
+
+        if rest > 0 {
+            let tail = ptr.add(size) as *mut FreeBlock;
+            ptr::write(tail, FreeBlock { size: rest, next: *link });
+            *link = tail;
+        }
+
+        self.allocated += size;
+
+        Ok(NonNull::new_unchecked(ptr))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        debug_assert!(self.contains(ptr.as_ptr()));
+
+        let block = ptr.as_ptr() as *mut FreeBlock;
+        let size = block_size(layout.size());
+        self.allocated -= size;
+
+        // Find the free blocks around the released one
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+
+        while !next.is_null() && (next as usize) < block as usize {
+            prev = next;
+            next = (*next).next;
+        }
+
+        ptr::write(block, FreeBlock { size, next });
+
+        if !next.is_null() && block as usize + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == block as usize {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+impl<'a> fmt::Debug for FreeList<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("FreeList")
+            .field("capacity", &self.len)
+            .field("allocated", &self.allocated)
+            .field("fit", &self.fit)
+            .finish()
+    }
+}
+
+impl<'a> Drop for FreeList<'a> {
+    fn drop(&mut self) {
+        #[cfg(any(unix, windows))]
+        unsafe {
+            if self.mapped {
+                ::sys::unmap(self.base, self.len);
+            }
+        }
+    }
+}
+
+#[inline]
+fn block_size(size: usize) -> usize {
+    round_up(cmp::max(size, UNIT), UNIT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::UNIT;
+    use {Alloc, AllocError, Fit, FreeList, Layout};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[repr(align(4096))]
+    struct Region([u8; 4096]);
+
+    fn region() -> Box<Region> {
+        Box::new(Region([0; 4096]))
+    }
+
+    #[test]
+    fn test_alloc_and_coalesce() {
+        let mut buf = region();
+        let mut list = FreeList::new(&mut buf.0, Fit::First);
+        assert_eq!(4096, list.capacity());
+
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let a = list.alloc(layout).unwrap();
+            let b = list.alloc(layout).unwrap();
+            let c = list.alloc(layout).unwrap();
+            assert!(list.contains(a.as_ptr()) && list.contains(c.as_ptr()));
+            assert_eq!(3 * 112, list.allocated());
+
+            // Releasing out of order merges everything back into one block
+            list.dealloc(b, layout);
+            list.dealloc(a, layout);
+            list.dealloc(c, layout);
+
+            assert_eq!(0, list.allocated());
+            assert_eq!(4096, list.largest_free());
+        }
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut buf = region();
+        let mut list = FreeList::new(&mut buf.0, Fit::First);
+
+        unsafe {
+            let small = Layout::from_size_align(8, 1).unwrap();
+            let a = list.alloc(small).unwrap();
+
+            let aligned = Layout::from_size_align(64, 256).unwrap();
+            let b = list.alloc(aligned).unwrap();
+            assert_eq!(0, b.as_ptr() as usize % 256);
+
+            // The padding before `b` is still usable
+            let c = list.alloc(small).unwrap();
+            assert!((c.as_ptr() as usize) < b.as_ptr() as usize);
+
+            list.dealloc(a, small);
+            list.dealloc(b, aligned);
+            list.dealloc(c, small);
+
+            assert_eq!(4096, list.largest_free());
+        }
+    }
+
+    #[test]
+    fn test_best_fit() {
+        let mut buf = region();
+        let mut list = FreeList::new(&mut buf.0, Fit::Best);
+
+        let big = Layout::from_size_align(256, 8).unwrap();
+        let small = Layout::from_size_align(64, 8).unwrap();
+
+        unsafe {
+            // Leave a 256 and a 64 byte hole, in that order
+            let a = list.alloc(big).unwrap();
+            let _b = list.alloc(small).unwrap();
+            let c = list.alloc(small).unwrap();
+            let _d = list.alloc(small).unwrap();
+
+            list.dealloc(a, big);
+            list.dealloc(c, small);
+
+            assert_eq!(c, list.alloc(small).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut buf = region();
+        let mut list = FreeList::new(&mut buf.0, Fit::First);
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 8).unwrap();
+            let ptrs: Vec<_> = (0..4).map(|_| list.alloc(layout).unwrap()).collect();
+
+            assert_eq!(Err(AllocError::OutOfMemory), list.alloc(Layout::new::<u8>()));
+            assert_eq!(Err(AllocError::InvalidLayout), list.alloc(Layout::new::<()>()));
+
+            for ptr in ptrs {
+                list.dealloc(ptr, layout);
+            }
+        }
+
+        drop(list);
+
+        // Too small to hold a single block once aligned
+        let mut list = FreeList::new(&mut buf.0[1..UNIT + 1], Fit::First);
+        assert_eq!(0, list.capacity());
+        assert!(unsafe { list.alloc(Layout::new::<u8>()) }.is_err());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_map() {
+        let mut list = FreeList::map(10_000, Fit::Best).unwrap();
+        assert!(list.capacity() >= 10_000);
+
+        unsafe {
+            let layout = Layout::from_size_align(5000, 4096).unwrap();
+            let ptr = list.alloc(layout).unwrap();
+            *ptr.as_ptr().add(4999) = 1;
+            list.dealloc(ptr, layout);
+        }
+    }
+}
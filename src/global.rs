@@ -0,0 +1,109 @@
+use {Alloc, Layout, Mutexed};
+
+use core::alloc::{self, GlobalAlloc};
+use core::ptr::{self, NonNull};
+
+/// Adapts an `Alloc` so it can be installed with `#[global_allocator]`.
+///
+/// `GlobalAlloc` is called through a shared reference from any thread, while
+/// `Alloc` takes `&mut self`, so calls are serialized with the spin lock of a
+/// `Mutexed`.
+///
+/// The wrapped allocator must not allocate through the global allocator
+/// itself, as the reentrant call would deadlock. `Heap` does with the `Vec`
+/// and `std-alloc` backends, so it can't be installed directly.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: GlobalAdapter<MyAlloc> = GlobalAdapter::new(MyAlloc::new());
+/// ```
+pub struct GlobalAdapter<A> {
+    inner: Mutexed<A>,
+}
+
+impl<A> GlobalAdapter<A> {
+    /// Wraps `inner`.
+    pub const fn new(inner: A) -> GlobalAdapter<A> {
+        GlobalAdapter {
+            inner: Mutexed::new(inner),
+        }
+    }
+
+    /// Consumes the adapter, returning the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+
+    fn with<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut A) -> R,
+    {
+        f(&mut self.inner.lock())
+    }
+}
+
+unsafe impl<A: Alloc + Send> GlobalAlloc for GlobalAdapter<A> {
+    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+        self.with(|a| a.alloc(layout.into()))
+            .map(NonNull::as_ptr)
+            .unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+        self.with(|a| a.dealloc(NonNull::new_unchecked(ptr), layout.into()))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
+        self.with(|a| a.alloc_zeroed(layout.into()))
+            .map(NonNull::as_ptr)
+            .unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: alloc::Layout, new_size: usize) -> *mut u8 {
+        self.with(|a| a.realloc(NonNull::new_unchecked(ptr), layout.into(), new_size))
+            .map(NonNull::as_ptr)
+            .unwrap_or(ptr::null_mut())
+    }
+}
+
+impl From<alloc::Layout> for Layout {
+    fn from(layout: alloc::Layout) -> Layout {
+        // `alloc::Layout` upholds the same invariants
+        unsafe { Layout::from_size_align_unchecked(layout.size(), layout.align()) }
+    }
+}
+
+impl From<Layout> for alloc::Layout {
+    fn from(layout: Layout) -> alloc::Layout {
+        unsafe { alloc::Layout::from_size_align_unchecked(layout.size(), layout.align()) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GlobalAdapter;
+    use Heap;
+
+    use std::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_global_adapter() {
+        let global = GlobalAdapter::new(Heap);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+
+        unsafe {
+            let ptr = global.alloc_zeroed(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(0, *ptr.add(23));
+
+            *ptr = 3;
+
+            let ptr = global.realloc(ptr, layout, 48);
+            assert!(!ptr.is_null());
+            assert_eq!(3, *ptr);
+
+            global.dealloc(ptr, Layout::from_size_align(48, 8).unwrap());
+        }
+
+        assert!(global.inner.try_lock().is_some());
+    }
+}
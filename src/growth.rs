@@ -0,0 +1,134 @@
+//! Capacity growth for collections.
+//!
+//! Growing a buffer geometrically, rather than by exactly what is needed,
+//! keeps the cost of appending amortized constant. The functions here do the
+//! arithmetic once, checking it for overflow and keeping the resulting
+//! size within what a `Layout` can describe.
+
+use AllocError;
+
+use core::cmp;
+
+/// How the capacity of a collection grows.
+///
+/// By default, the capacity doubles and is at least 4 once allocating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    numerator: usize,
+    denominator: usize,
+    min_capacity: usize,
+}
+
+impl Policy {
+    /// Returns the default policy.
+    pub fn new() -> Policy {
+        Policy {
+            numerator: 2,
+            denominator: 1,
+            min_capacity: 4,
+        }
+    }
+
+    /// Sets the factor the capacity is multiplied by, as
+    /// `numerator / denominator`, e.g. `3, 2` for 1.5.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the factor is not larger than 1.
+    pub fn factor(mut self, numerator: usize, denominator: usize) -> Policy {
+        assert!(denominator > 0 && numerator > denominator, "growth factor must be larger than 1");
+
+        self.numerator = numerator;
+        self.denominator = denominator;
+        self
+    }
+
+    /// Sets the smallest capacity handed out once the collection allocates.
+    pub fn min_capacity(mut self, min_capacity: usize) -> Policy {
+        self.min_capacity = min_capacity;
+        self
+    }
+
+    /// Returns the capacity to grow to, so at least `current + additional`
+    /// values of `elem_size` bytes fit.
+    ///
+    /// The grown capacity is capped so its size doesn't exceed `isize::MAX`
+    /// bytes. Returns `AllocError::InvalidLayout` if `current + additional`
+    /// values don't fit within that limit.
+    pub fn grow(&self, current: usize, additional: usize, elem_size: usize) -> Result<usize, AllocError> {
+        let max = max_capacity(elem_size);
+
+        let required = match current.checked_add(additional) {
+            Some(required) if required <= max => required,
+            _ => return Err(AllocError::InvalidLayout),
+        };
+
+        // The product can't overflow in 128 bits
+        let grown = current as u128 * self.numerator as u128 / self.denominator as u128;
+        let grown = cmp::min(grown, max as u128) as usize;
+
+        let cap = cmp::max(cmp::max(grown, required), self.min_capacity);
+        Ok(cmp::min(cap, max))
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::new()
+    }
+}
+
+/// Returns the capacity to grow to, so at least `current + additional`
+/// values of `elem_size` bytes fit, using the default `Policy`.
+///
+/// Returns `AllocError::InvalidLayout` if `current + additional` values
+/// don't fit in `isize::MAX` bytes.
+pub fn amortized_grow(current: usize, additional: usize, elem_size: usize) -> Result<usize, AllocError> {
+    Policy::new().grow(current, additional, elem_size)
+}
+
+/// Returns the largest number of values of `elem_size` bytes a `Layout` can
+/// describe.
+pub fn max_capacity(elem_size: usize) -> usize {
+    match elem_size {
+        0 => usize::MAX,
+        size => isize::MAX as usize / size,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use AllocError;
+    use super::{amortized_grow, max_capacity, Policy};
+
+    #[test]
+    fn test_amortized_grow() {
+        assert_eq!(Ok(4), amortized_grow(0, 1, 8));
+        assert_eq!(Ok(8), amortized_grow(4, 1, 8));
+        assert_eq!(Ok(104), amortized_grow(4, 100, 8));
+        assert_eq!(Ok(usize::MAX), amortized_grow(usize::MAX / 2 + 1, 1, 0));
+
+        // Growth stops at the largest layout
+        let max = max_capacity(16);
+        assert_eq!(Ok(max), amortized_grow(max - 1, 1, 16));
+        assert_eq!(Ok(max), amortized_grow(max / 2 + 1, 1, 16));
+        assert_eq!(Err(AllocError::InvalidLayout), amortized_grow(max, 1, 16));
+        assert_eq!(Err(AllocError::InvalidLayout), amortized_grow(1, usize::MAX, 1));
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::new().factor(3, 2).min_capacity(16);
+
+        assert_eq!(Ok(16), policy.grow(0, 1, 4));
+        assert_eq!(Ok(24), policy.grow(16, 1, 4));
+        assert_eq!(Ok(100), policy.grow(16, 84, 4));
+        assert_eq!(Policy::new(), Policy::default());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_policy_shrinking_factor() {
+        let _ = Policy::new().factor(1, 2);
+    }
+}
@@ -0,0 +1,163 @@
+use {round_up, sys, Alloc, AllocError, Layout, Protection};
+
+use core::ptr;
+use core::ptr::NonNull;
+
+/// Return a pointer to `size` bytes of zeroed memory aligned to `align`,
+/// placed directly in front of an inaccessible guard page.
+///
+/// Any write past the end of the block faults immediately instead of
+/// corrupting neighboring memory. The block is rounded up to the alignment
+/// only, so just the padding it needs sits between the block and the guard.
+/// Each allocation takes at least two pages, so this is meant for debugging.
+///
+/// On failure, or if `align` is larger than the page size, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_guarded` using the same
+/// `size` and `align`.
+pub unsafe fn allocate_guarded(size: usize, align: usize) -> *mut u8 {
+    let page = sys::page_size();
+
+    if size == 0 || !align.is_power_of_two() || align > page || size > isize::MAX as usize - 2 * page {
+        return ptr::null_mut();
+    }
+
+    let len = round_up(size, page);
+    let base = sys::map_anonymous(len + page, 0);
+
+    if base.is_null() {
+        return base;
+    }
+
+    if !sys::protect(base.add(len), page, Protection::NoAccess) {
+        sys::unmap(base, len + page);
+        return ptr::null_mut();
+    }
+
+    base.add(len - round_up(size, align))
+}
+
+/// Deallocates memory obtained from `allocate_guarded`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_guarded` with the same `size`
+/// and `align`.
+pub unsafe fn deallocate_guarded(ptr: *mut u8, size: usize, align: usize) {
+    let page = sys::page_size();
+    let len = round_up(size, page);
+    let guard = ptr.add(round_up(size, align));
+
+    sys::unmap(guard.sub(len), len + page)
+}
+
+/// A debug allocator placing every block between inaccessible pages.
+///
+/// Each block gets pages of its own, ending right at a guard page, with a
+/// second guard page in front. Released blocks are unmapped rather than
+/// recycled. Overruns, underruns past the front padding and accesses after
+/// release all fault deterministically instead of corrupting memory.
+///
+/// Every block takes at least three pages of address space, so this is meant
+/// for tests. Alignments above the page size are not supported.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageGuardAlloc;
+
+unsafe impl Alloc for PageGuardAlloc {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let page = sys::page_size();
+
+        if layout.size() == 0 || layout.align() > page || layout.size() > isize::MAX as usize - 3 * page {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let len = round_up(layout.size(), page);
+        let base = sys::map_anonymous(len + 2 * page, 0);
+
+        if base.is_null() {
+            return Err(AllocError::OutOfMemory);
+        }
+
+        let guarded = sys::protect(base, page, Protection::NoAccess)
+            && sys::protect(base.add(page + len), page, Protection::NoAccess);
+
+        if !guarded {
+            sys::unmap(base, len + 2 * page);
+            return Err(AllocError::OutOfMemory);
+        }
+
+        Ok(NonNull::new_unchecked(base.add(page + len - round_up(layout.size(), layout.align()))))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let page = sys::page_size();
+        let len = round_up(layout.size(), page);
+        let end = ptr.as_ptr().add(round_up(layout.size(), layout.align()));
+
+        sys::unmap(end.sub(len + page), len + 2 * page)
+    }
+
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        // Fresh mappings are zeroed
+        self.alloc(layout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_guarded, deallocate_guarded};
+    use {Alloc, AllocError, Layout, PageGuardAlloc};
+    use sys;
+
+    #[test]
+    fn test_allocate_guarded() {
+        unsafe {
+            let ptr = allocate_guarded(100, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & 7);
+
+            // The block ends right at the guard page
+            let end = ptr.add(104);
+            assert_eq!(0, end as usize & (sys::page_size() - 1));
+
+            for i in 0..100 {
+                *ptr.add(i) = i as u8;
+            }
+
+            deallocate_guarded(ptr, 100, 8);
+        }
+    }
+
+    #[test]
+    fn test_allocate_guarded_unsupported() {
+        unsafe {
+            assert!(allocate_guarded(0, 8).is_null());
+            assert!(allocate_guarded(8, 3).is_null());
+            assert!(allocate_guarded(8, sys::page_size() * 2).is_null());
+        }
+    }
+
+    #[test]
+    fn test_page_guard_alloc() {
+        let mut alloc = PageGuardAlloc;
+        let page = sys::page_size();
+
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).unwrap();
+            let ptr = alloc.alloc_zeroed(layout).unwrap();
+            assert_eq!(0, (ptr.as_ptr() as usize + 104) % page);
+            assert!((0..100).all(|i| *ptr.as_ptr().add(i) == 0));
+
+            let ptr = alloc.realloc(ptr, layout, 3 * page).unwrap();
+            *ptr.as_ptr().add(3 * page - 1) = 1;
+            assert_eq!(0, ptr.as_ptr() as usize % page);
+
+            alloc.dealloc(ptr, Layout::from_size_align(3 * page, 8).unwrap());
+
+            assert_eq!(Err(AllocError::InvalidLayout), alloc.alloc(Layout::from_size_align(8, 2 * page).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A pool of `T` values addressed by generational `Handle`s.
+///
+/// Every slot carries a generation that is bumped when its value is removed,
+/// so a handle that outlives its value no longer resolves, even once the slot
+/// has been reused.
+pub struct HandlePool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+/// A reference to a value in a `HandlePool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+impl Handle {
+    /// Returns the index of the slot the handle refers to.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the generation of the slot when the handle was created.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl<T> HandlePool<T> {
+    /// Creates an empty pool.
+    pub fn new() -> HandlePool<T> {
+        HandlePool {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Creates an empty pool with room for `capacity` values.
+    pub fn with_capacity(capacity: usize) -> HandlePool<T> {
+        HandlePool {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of values in the pool.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the pool holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stores `value` in the pool and returns its handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool already has `u32::MAX` slots.
+    pub fn insert(&mut self, value: T) -> Handle {
+        self.len += 1;
+
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+
+            return Handle { index, generation: slot.generation };
+        }
+
+        let index = self.slots.len();
+        assert!(index < u32::MAX as usize, "handle pool is full");
+
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+
+        Handle { index: index as u32, generation: 0 }
+    }
+
+    /// Returns `true` if `handle` refers to a value in the pool.
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Returns a reference to the value of `handle`, or `None` if the handle
+    /// is stale.
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        match self.slots.get(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation => slot.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the value of `handle`, or `None` if the
+    /// handle is stale.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Removes the value of `handle` from the pool, invalidating the handle
+    /// and any copies of it.
+    ///
+    /// Returns `None` if the handle is stale.
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let slot = match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation => slot,
+            _ => return None,
+        };
+
+        let value = slot.value.take()?;
+        self.len -= 1;
+
+        // A slot whose generation would wrap is retired, so that old handles
+        // can never match it again
+        if let Some(generation) = slot.generation.checked_add(1) {
+            slot.generation = generation;
+            self.free.push(handle.index);
+        }
+
+        Some(value)
+    }
+
+    /// Removes all values from the pool, invalidating every handle.
+    pub fn clear(&mut self) {
+        for index in 0..self.slots.len() {
+            let handle = Handle {
+                index: index as u32,
+                generation: self.slots[index].generation,
+            };
+
+            drop(self.remove(handle));
+        }
+    }
+
+    /// Returns an iterator over the handles and values in the pool.
+    pub fn iter(&self) -> HandleIter<'_, T> {
+        HandleIter {
+            slots: self.slots.iter().enumerate(),
+        }
+    }
+}
+
+impl<T> Default for HandlePool<T> {
+    fn default() -> HandlePool<T> {
+        HandlePool::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HandlePool<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a HandlePool<T> {
+    type Item = (Handle, &'a T);
+    type IntoIter = HandleIter<'a, T>;
+
+    fn into_iter(self) -> HandleIter<'a, T> {
+        self.iter()
+    }
+}
+
+/// An iterator over the handles and values of a `HandlePool`.
+pub struct HandleIter<'a, T: 'a> {
+    slots: ::core::iter::Enumerate<::core::slice::Iter<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for HandleIter<'a, T> {
+    type Item = (Handle, &'a T);
+
+    fn next(&mut self) -> Option<(Handle, &'a T)> {
+        for (index, slot) in &mut self.slots {
+            if let Some(ref value) = slot.value {
+                let handle = Handle {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+
+                return Some((handle, value));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Handle, HandlePool};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_handle_pool() {
+        let mut pool = HandlePool::new();
+
+        let a = pool.insert("a");
+        let b = pool.insert("b");
+        assert_eq!(2, pool.len());
+        assert_eq!(Some(&"a"), pool.get(a));
+
+        *pool.get_mut(b).unwrap() = "c";
+        assert_eq!(Some("c"), pool.remove(b));
+        assert_eq!(None, pool.remove(b));
+        assert!(!pool.contains(b));
+
+        // The slot is reused, but the stale handle does not resolve
+        let d = pool.insert("d");
+        assert_eq!(b.index(), d.index());
+        assert!(b.generation() != d.generation());
+        assert_eq!(None, pool.get(b));
+        assert_eq!(Some(&"d"), pool.get(d));
+
+        let all: Vec<(Handle, &&str)> = pool.iter().collect();
+        assert_eq!(::alloc::vec![(a, &"a"), (d, &"d")], all);
+
+        pool.clear();
+        assert!(pool.is_empty());
+        assert!(!pool.contains(a));
+        assert!(!pool.contains(d));
+    }
+
+    #[test]
+    fn test_handle_pool_retires_slots() {
+        let mut pool = HandlePool::new();
+
+        let a = pool.insert(1);
+        pool.slots[0].generation = u32::MAX;
+        let a = Handle { generation: u32::MAX, ..a };
+
+        assert_eq!(Some(1), pool.remove(a));
+
+        // The exhausted slot is not reused
+        let b = pool.insert(2);
+        assert_eq!(1, b.index());
+        assert_eq!(None, pool.get(a));
+    }
+}
@@ -0,0 +1,105 @@
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// An operation reported to the allocation hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocEvent {
+    /// A block was allocated.
+    Allocate {
+        ptr: *mut u8,
+        size: usize,
+        align: usize,
+    },
+
+    /// A block is about to be released.
+    Deallocate {
+        ptr: *mut u8,
+        size: usize,
+        align: usize,
+    },
+
+    /// A block was resized, and possibly moved from `old_ptr` to `ptr`.
+    Reallocate {
+        old_ptr: *mut u8,
+        ptr: *mut u8,
+        old_size: usize,
+        size: usize,
+        align: usize,
+    },
+}
+
+// The hook as a data pointer, or null if there is none
+static HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers `hook` to be called on every allocation, deallocation and
+/// reallocation made through the heap allocation functions, replacing any
+/// previous hook.
+///
+/// The hook is called on the thread doing the allocation, after the fact for
+/// allocations and before the fact for deallocations. It must not allocate
+/// through this crate itself, or it recurses.
+pub fn set_alloc_hook(hook: fn(AllocEvent)) {
+    HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+/// Removes the hook registered with `set_alloc_hook`, if any.
+pub fn clear_alloc_hook() {
+    HOOK.store(ptr::null_mut(), Ordering::Release);
+}
+
+#[inline]
+pub fn notify(event: AllocEvent) {
+    let hook = HOOK.load(Ordering::Acquire);
+
+    if !hook.is_null() {
+        // Only ever stored from a `fn(AllocEvent)`
+        let hook: fn(AllocEvent) = unsafe { mem::transmute(hook) };
+        hook(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {clear_alloc_hook, set_alloc_hook, AllocEvent};
+    use std::cell::RefCell;
+    use std::vec::Vec;
+
+    ::std::thread_local! {
+        #[allow(clippy::missing_const_for_thread_local)]
+        static EVENTS: RefCell<Vec<AllocEvent>> = RefCell::new(Vec::new());
+    }
+
+    fn record(event: AllocEvent) {
+        // The hook is global, so only keep events of this test's size
+        let size = match event {
+            AllocEvent::Allocate { size, .. } => size,
+            AllocEvent::Deallocate { size, .. } => size,
+            AllocEvent::Reallocate { old_size, .. } => old_size,
+        };
+
+        if size == 12345 || size == 23456 {
+            let _ = EVENTS.try_with(|events| events.borrow_mut().push(event));
+        }
+    }
+
+    #[test]
+    fn test_hook() {
+        set_alloc_hook(record);
+
+        unsafe {
+            let ptr = ::allocate(12345, 8);
+            let new_ptr = ::reallocate(ptr, 12345, 23456, 8);
+            ::deallocate(new_ptr, 23456, 8);
+
+            let events = EVENTS.with(|events| events.borrow().clone());
+
+            assert_eq!(::alloc::vec![
+                AllocEvent::Allocate { ptr, size: 12345, align: 8 },
+                AllocEvent::Reallocate { old_ptr: ptr, ptr: new_ptr, old_size: 12345, size: 23456, align: 8 },
+                AllocEvent::Deallocate { ptr: new_ptr, size: 23456, align: 8 },
+            ], events);
+        }
+
+        clear_alloc_hook();
+    }
+}
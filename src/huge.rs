@@ -0,0 +1,116 @@
+use {round_up, sys};
+
+use core::ptr;
+
+/// The size of the huge pages backing an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HugePageSize {
+    /// 2 MiB pages.
+    Size2MiB,
+
+    /// 1 GiB pages.
+    Size1GiB,
+}
+
+impl HugePageSize {
+    /// Returns the size of the page in bytes.
+    pub fn bytes(&self) -> usize {
+        match *self {
+            HugePageSize::Size2MiB => 2 * 1024 * 1024,
+            HugePageSize::Size1GiB => 1024 * 1024 * 1024,
+        }
+    }
+
+    fn map_len(&self, size: usize) -> usize {
+        round_up(size, self.bytes())
+    }
+}
+
+/// Return a pointer to `size` bytes of zeroed memory backed by huge pages.
+///
+/// The allocation is rounded up to a multiple of the huge page size. If the
+/// system can't provide huge pages, because none are reserved or the process
+/// lacks the privilege, the memory is backed by normal pages instead. Only
+/// memory backed by huge pages is aligned to the huge page size, the normal
+/// page fallback is aligned to the normal page size.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_huge` using the same `size`
+/// and `page`.
+pub unsafe fn allocate_huge(size: usize, page: HugePageSize) -> *mut u8 {
+    if size == 0 || size > isize::MAX as usize - (page.bytes() - 1) {
+        return ptr::null_mut();
+    }
+
+    let ptr = map_huge(page.map_len(size), page);
+
+    if !ptr.is_null() {
+        return ptr;
+    }
+
+    sys::map_anonymous(page.map_len(size), 0)
+}
+
+/// Deallocates memory obtained from `allocate_huge`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_huge` with the same `size` and
+/// `page`.
+pub unsafe fn deallocate_huge(ptr: *mut u8, size: usize, page: HugePageSize) {
+    sys::unmap(ptr, page.map_len(size))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn map_huge(len: usize, page: HugePageSize) -> *mut u8 {
+    let shift = page.bytes().trailing_zeros() as sys::c_int;
+    sys::map_anonymous(len, sys::MAP_HUGETLB | (shift << sys::MAP_HUGE_SHIFT))
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+unsafe fn map_huge(_len: usize, _page: HugePageSize) -> *mut u8 {
+    ptr::null_mut()
+}
+
+#[cfg(windows)]
+unsafe fn map_huge(len: usize, _page: HugePageSize) -> *mut u8 {
+    // Windows only exposes one large page size, which both sizes are a
+    // multiple of.
+    if sys::GetLargePageMinimum() == 0 {
+        return ptr::null_mut();
+    }
+
+    sys::map_anonymous(len, sys::MEM_LARGE_PAGES)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_huge, deallocate_huge, HugePageSize};
+
+    #[test]
+    fn test_allocate_huge() {
+        let page = HugePageSize::Size2MiB;
+
+        unsafe {
+            let ptr = allocate_huge(100, page);
+            assert!(!ptr.is_null());
+
+            // The whole page is usable
+            assert_eq!(0, *ptr.add(page.bytes() - 1));
+            *ptr.add(page.bytes() - 1) = 1;
+
+            deallocate_huge(ptr, 100, page);
+        }
+    }
+
+    #[test]
+    fn test_allocate_huge_invalid_size() {
+        unsafe {
+            assert!(allocate_huge(0, HugePageSize::Size2MiB).is_null());
+            assert!(allocate_huge(usize::MAX, HugePageSize::Size1GiB).is_null());
+        }
+    }
+}
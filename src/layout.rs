@@ -0,0 +1,219 @@
+use AllocError;
+
+use core::{cmp, mem};
+
+/// The size and alignment of a block of memory.
+///
+/// A `Layout` is passed to the allocation functions that create a block and
+/// to the functions that release it, so the two can't get out of sync.
+///
+/// The alignment is always a power of 2, and the size rounded up to the
+/// alignment never overflows `isize::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Layout {
+    size: usize,
+    align: usize,
+}
+
+impl Layout {
+    /// Returns the layout of a value of type `T`.
+    pub fn new<T>() -> Layout {
+        Layout {
+            size: mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
+        }
+    }
+
+    /// Returns the layout of the value referenced by `val`.
+    pub fn for_value<T: ?Sized>(val: &T) -> Layout {
+        Layout {
+            size: mem::size_of_val(val),
+            align: mem::align_of_val(val),
+        }
+    }
+
+    /// Returns the layout of an array of `n` values of type `T`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if the size of the array overflows.
+    pub fn array<T>(n: usize) -> Result<Layout, AllocError> {
+        let size = mem::size_of::<T>().checked_mul(n).ok_or(AllocError::InvalidLayout)?;
+        Layout::from_size_align(size, mem::align_of::<T>())
+    }
+
+    /// Returns a layout with the given `size` and `align`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if `align` is not a power of 2 or if
+    /// rounding `size` up to `align` overflows `isize::MAX`.
+    pub fn from_size_align(size: usize, align: usize) -> Result<Layout, AllocError> {
+        if !align.is_power_of_two() {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        if size > isize::MAX as usize - (align - 1) {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        Ok(Layout { size, align })
+    }
+
+    /// Returns a layout with the given `size` and `align` without checking
+    /// them.
+    ///
+    /// # Safety
+    ///
+    /// The arguments must satisfy the requirements of `from_size_align`.
+    pub unsafe fn from_size_align_unchecked(size: usize, align: usize) -> Layout {
+        Layout { size, align }
+    }
+
+    /// Returns the size of the block in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment of the block in bytes.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns a layout with the same size and an alignment of at least
+    /// `align`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if `align` is not a power of 2 or
+    /// the resulting layout is invalid.
+    pub fn align_to(&self, align: usize) -> Result<Layout, AllocError> {
+        if !align.is_power_of_two() {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        Layout::from_size_align(self.size, cmp::max(self.align, align))
+    }
+
+    /// Returns the number of padding bytes needed after `self` so that the
+    /// following address is aligned to `align`.
+    ///
+    /// `align` must be a power of 2.
+    pub fn padding_needed_for(&self, align: usize) -> usize {
+        debug_assert!(align.is_power_of_two());
+
+        let rounded = self.size.wrapping_add(align - 1) & !(align - 1);
+        rounded.wrapping_sub(self.size)
+    }
+
+    /// Returns the layout with its size rounded up to a multiple of its
+    /// alignment.
+    ///
+    /// This is the layout of an array element: the stride between
+    /// consecutive values.
+    pub fn pad_to_align(&self) -> Layout {
+        // Can't overflow, `from_size_align` ensures the rounded size fits
+        let size = self.size + self.padding_needed_for(self.align);
+        Layout { size, align: self.align }
+    }
+
+    /// Returns the layout of `n` consecutive values of `self`, each padded to
+    /// its alignment, along with the stride between them.
+    ///
+    /// Returns `AllocError::InvalidLayout` on overflow.
+    pub fn repeat(&self, n: usize) -> Result<(Layout, usize), AllocError> {
+        let stride = self.pad_to_align().size;
+        let size = stride.checked_mul(n).ok_or(AllocError::InvalidLayout)?;
+
+        Ok((Layout::from_size_align(size, self.align)?, stride))
+    }
+
+    /// Returns the layout of `self` followed by `next`, with padding inserted
+    /// so that `next` is properly aligned, along with the offset of `next`.
+    ///
+    /// The resulting layout has the larger of the two alignments. It is not
+    /// padded at the end; use `pad_to_align` when it is used as a `repr(C)`
+    /// struct.
+    ///
+    /// Returns `AllocError::InvalidLayout` on overflow.
+    pub fn extend(&self, next: Layout) -> Result<(Layout, usize), AllocError> {
+        let align = cmp::max(self.align, next.align);
+        let offset = self.size.checked_add(self.padding_needed_for(next.align)).ok_or(AllocError::InvalidLayout)?;
+        let size = offset.checked_add(next.size).ok_or(AllocError::InvalidLayout)?;
+
+        Ok((Layout::from_size_align(size, align)?, offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AllocError, Layout};
+
+    #[test]
+    fn test_new() {
+        let layout = Layout::new::<u64>();
+        assert_eq!(8, layout.size());
+        assert_eq!(8, layout.align());
+
+        let layout = Layout::for_value(&[0u16; 3][..]);
+        assert_eq!(6, layout.size());
+        assert_eq!(2, layout.align());
+    }
+
+    #[test]
+    fn test_array() {
+        let layout = Layout::array::<u32>(10).unwrap();
+        assert_eq!(40, layout.size());
+        assert_eq!(4, layout.align());
+
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::array::<u32>(usize::MAX / 2));
+    }
+
+    #[test]
+    fn test_from_size_align() {
+        assert!(Layout::from_size_align(10, 4).is_ok());
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::from_size_align(10, 0));
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::from_size_align(10, 3));
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::from_size_align(isize::MAX as usize, 2));
+    }
+
+    #[test]
+    fn test_align_to() {
+        let layout = Layout::new::<u16>().align_to(8).unwrap();
+        assert_eq!(2, layout.size());
+        assert_eq!(8, layout.align());
+
+        let layout = Layout::new::<u64>().align_to(2).unwrap();
+        assert_eq!(8, layout.align());
+
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::new::<u8>().align_to(3));
+    }
+
+    #[test]
+    fn test_pad_to_align() {
+        let layout = Layout::from_size_align(5, 4).unwrap();
+        assert_eq!(3, layout.padding_needed_for(4));
+        assert_eq!(0, layout.padding_needed_for(1));
+        assert_eq!(8, layout.pad_to_align().size());
+        assert_eq!(4, layout.pad_to_align().align());
+    }
+
+    #[test]
+    fn test_repeat() {
+        let (layout, stride) = Layout::from_size_align(5, 4).unwrap().repeat(3).unwrap();
+        assert_eq!(8, stride);
+        assert_eq!(24, layout.size());
+        assert_eq!(4, layout.align());
+
+        assert_eq!(Err(AllocError::InvalidLayout), Layout::new::<u64>().repeat(usize::MAX));
+    }
+
+    #[test]
+    fn test_extend() {
+        // struct { a: u8, b: u32, c: u16 }
+        let (layout, b) = Layout::new::<u8>().extend(Layout::new::<u32>()).unwrap();
+        let (layout, c) = layout.extend(Layout::new::<u16>()).unwrap();
+        assert_eq!(4, b);
+        assert_eq!(8, c);
+        assert_eq!(10, layout.size());
+        assert_eq!(4, layout.align());
+        assert_eq!(12, layout.pad_to_align().size());
+
+        let big = Layout::from_size_align(isize::MAX as usize - 8, 1).unwrap();
+        assert_eq!(Err(AllocError::InvalidLayout), big.extend(Layout::new::<u64>()));
+    }
+}
@@ -0,0 +1,352 @@
+//! Leak detection and heap debugging.
+//!
+//! With the `leak-check` feature, every block allocated through the heap
+//! allocation functions is recorded in a registry until it is released, and
+//! `report` lists the blocks still outstanding. Calling `report` at the end
+//! of a test, or registering `dump_at_exit`, points out missing
+//! `deallocate` calls.
+//!
+//! With the `leak-backtrace` feature, the registry also captures a backtrace
+//! when a block is allocated, so a leak can be traced back to its call site.
+//! Capturing is much slower than the allocation itself, so
+//! `set_backtrace_sampling` can limit it to a fraction of the allocations.
+//!
+//! With the `debug-checks` feature, `deallocate` and `reallocate` look the
+//! block up in the registry first, and panic if it isn't live or if the
+//! layout passed in doesn't match the one it was allocated with. Without the
+//! check, either mistake silently corrupts the heap. Blocks are also filled
+//! with `0xCD` when allocated and `0xDD` when released, so reads of
+//! uninitialized or freed memory stand out. The patterns can be changed with
+//! `set_poison_patterns`.
+//!
+//! The registry takes a lock on every allocation, so this is meant for
+//! debugging rather than production builds.
+
+use reentrancy;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "debug-checks")]
+use std::sync::atomic::{self, AtomicU8};
+#[cfg(feature = "debug-checks")]
+use std::ptr;
+use std::vec::Vec;
+
+#[cfg(feature = "leak-backtrace")]
+use std::backtrace::Backtrace;
+#[cfg(feature = "leak-backtrace")]
+use std::sync::Arc;
+#[cfg(feature = "leak-backtrace")]
+use std::sync::atomic::AtomicUsize;
+
+/// A block that was allocated and not released.
+#[derive(Debug, Clone)]
+pub struct Leak {
+    /// The address of the block.
+    pub ptr: *mut u8,
+
+    /// The size the block was allocated or last resized with.
+    pub size: usize,
+
+    /// The alignment the block was allocated with.
+    pub align: usize,
+
+    #[cfg(feature = "leak-backtrace")]
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl Leak {
+    /// Returns the backtrace captured when the block was allocated.
+    ///
+    /// Returns `None` if the allocation was skipped by the sampling rate.
+    #[cfg(feature = "leak-backtrace")]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+struct Block {
+    size: usize,
+    align: usize,
+
+    #[cfg(feature = "leak-backtrace")]
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+// Outstanding blocks by address
+static LIVE: Mutex<Option<HashMap<usize, Block>>> = Mutex::new(None);
+
+// Capture a backtrace for one allocation out of this many, zero disables
+// capturing
+#[cfg(feature = "leak-backtrace")]
+static SAMPLE_EVERY: AtomicUsize = AtomicUsize::new(1);
+
+#[cfg(feature = "leak-backtrace")]
+static SAMPLE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Fill patterns for newly allocated and freed memory
+#[cfg(feature = "debug-checks")]
+static ALLOC_POISON: AtomicU8 = AtomicU8::new(0xCD);
+
+#[cfg(feature = "debug-checks")]
+static FREE_POISON: AtomicU8 = AtomicU8::new(0xDD);
+
+::std::thread_local! {
+    // Set while the thread updates the registry
+    #[allow(clippy::missing_const_for_thread_local)]
+    static BUSY: Cell<bool> = Cell::new(false);
+}
+
+/// Returns the blocks that are allocated and not released, ordered by
+/// address.
+pub fn report() -> Vec<Leak> {
+    let mut leaks = with_registry(|live| {
+        live.iter()
+            .map(|(&ptr, block)| Leak {
+                ptr: ptr as *mut u8,
+                size: block.size,
+                align: block.align,
+                #[cfg(feature = "leak-backtrace")]
+                backtrace: block.backtrace.clone(),
+            })
+            .collect::<Vec<_>>()
+    }).unwrap_or_default();
+
+    leaks.sort_by_key(|leak| leak.ptr as usize);
+    leaks
+}
+
+/// Captures a backtrace for one allocation out of `every`.
+///
+/// The default is to capture one for every allocation. Passing `0` stops
+/// capturing altogether, blocks allocated afterwards are still tracked.
+#[cfg(feature = "leak-backtrace")]
+pub fn set_backtrace_sampling(every: usize) {
+    SAMPLE_EVERY.store(every, Ordering::Relaxed);
+}
+
+/// Sets the bytes written over newly allocated and freed memory.
+///
+/// The defaults are `0xCD` and `0xDD`. Memory from `allocate_zeroed` is
+/// never filled.
+#[cfg(feature = "debug-checks")]
+pub fn set_poison_patterns(alloc: u8, free: u8) {
+    ALLOC_POISON.store(alloc, Ordering::Relaxed);
+    FREE_POISON.store(free, Ordering::Relaxed);
+}
+
+/// Prints the outstanding blocks to stderr when the process exits, if there
+/// are any.
+///
+/// Calling this more than once has no further effect. Exiting through a
+/// signal or `abort` skips the report.
+#[cfg(any(unix, windows))]
+pub fn dump_at_exit() {
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn dump() {
+        let leaks = report();
+
+        if leaks.is_empty() {
+            return;
+        }
+
+        let bytes: usize = leaks.iter().map(|leak| leak.size).sum();
+        ::std::eprintln!("stable-heap: {} blocks ({} bytes) were never released", leaks.len(), bytes);
+
+        for leak in &leaks {
+            ::std::eprintln!("  {:p}: size={} align={}", leak.ptr, leak.size, leak.align);
+
+            #[cfg(feature = "leak-backtrace")]
+            {
+                if let Some(backtrace) = leak.backtrace() {
+                    ::std::eprintln!("{}", backtrace);
+                }
+            }
+        }
+    }
+
+    if !REGISTERED.swap(true, Ordering::SeqCst) {
+        unsafe { ::sys::atexit(dump) };
+    }
+}
+
+pub(crate) fn record_alloc(ptr: *mut u8, size: usize, align: usize) {
+    // The backtrace is captured inside the guard as capturing allocates
+    reentrancy::guarded(&BUSY, || {
+        let block = Block {
+            size,
+            align,
+            #[cfg(feature = "leak-backtrace")]
+            backtrace: capture(),
+        };
+
+        registry(|live| live.insert(ptr as usize, block));
+    });
+}
+
+pub(crate) fn record_dealloc(ptr: *mut u8) {
+    with_registry(|live| {
+        live.remove(&(ptr as usize));
+    });
+}
+
+/// Panics unless `ptr` is live and `size` and `align` describe the block it
+/// was allocated with.
+#[cfg(feature = "debug-checks")]
+pub(crate) fn check_release(ptr: *mut u8, size: usize, align: usize) {
+    let found = with_registry(|live| {
+        live.get(&(ptr as usize)).map(|block| (block.size, block.align))
+    });
+
+    let (alloc_size, alloc_align) = match found {
+        Some(Some(block)) => block,
+        Some(None) => panic!("stable-heap: releasing {:p}, which is not a live allocation (double free?)", ptr),
+        // The registry can't be consulted from here
+        None => return,
+    };
+
+    // Any size that maps to the same block is accepted
+    if align != alloc_align || ::usable_size(size, align) != ::usable_size(alloc_size, alloc_align) {
+        panic!("stable-heap: releasing {:p} with size {} and align {}, but it was allocated \
+                with size {} and align {}", ptr, size, align, alloc_size, alloc_align);
+    }
+}
+
+/// Fills `len` bytes at `ptr` with the allocation pattern.
+#[cfg(feature = "debug-checks")]
+pub(crate) unsafe fn poison_alloc(ptr: *mut u8, len: usize) {
+    ptr::write_bytes(ptr, ALLOC_POISON.load(Ordering::Relaxed), len);
+}
+
+/// Fills `len` bytes at `ptr` with the free pattern.
+///
+/// The writes are volatile, as the memory is released right after.
+#[cfg(feature = "debug-checks")]
+pub(crate) unsafe fn poison_free(ptr: *mut u8, len: usize) {
+    let pattern = FREE_POISON.load(Ordering::Relaxed);
+
+    for i in 0..len {
+        ptr::write_volatile(ptr.add(i), pattern);
+    }
+
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+pub(crate) fn record_realloc(old_ptr: *mut u8, ptr: *mut u8, size: usize, align: usize) {
+    with_registry(|live| {
+        // Keep the backtrace of the original allocation
+        let mut block = live.remove(&(old_ptr as usize)).unwrap_or(Block {
+            size,
+            align,
+            #[cfg(feature = "leak-backtrace")]
+            backtrace: None,
+        });
+
+        block.size = size;
+        block.align = align;
+        live.insert(ptr as usize, block);
+    });
+}
+
+// `is_multiple_of` needs Rust 1.87
+#[cfg(feature = "leak-backtrace")]
+#[allow(clippy::manual_is_multiple_of)]
+fn capture() -> Option<Arc<Backtrace>> {
+    let every = SAMPLE_EVERY.load(Ordering::Relaxed);
+
+    if every == 0 || SAMPLE_COUNT.fetch_add(1, Ordering::Relaxed) % every != 0 {
+        return None;
+    }
+
+    Some(Arc::new(Backtrace::force_capture()))
+}
+
+fn with_registry<F, R>(f: F) -> Option<R>
+    where F: FnOnce(&mut HashMap<usize, Block>) -> R
+{
+    reentrancy::guarded(&BUSY, || registry(f))
+}
+
+fn registry<F, R>(f: F) -> R
+    where F: FnOnce(&mut HashMap<usize, Block>) -> R
+{
+    let mut live = LIVE.lock().unwrap_or_else(|e| e.into_inner());
+    f(live.get_or_insert_with(HashMap::new))
+}
+
+#[cfg(test)]
+mod test {
+    use super::report;
+
+    #[test]
+    fn test_report() {
+        unsafe {
+            let ptr = ::allocate(777, 16);
+            assert!(report().iter().any(|leak| leak.ptr == ptr && leak.size == 777 && leak.align == 16));
+
+            let ptr = ::reallocate(ptr, 777, 7777, 16);
+            assert!(report().iter().any(|leak| leak.ptr == ptr && leak.size == 7777));
+
+            ::deallocate(ptr, 7777, 16);
+            assert!(!report().iter().any(|leak| leak.ptr == ptr && leak.size == 7777));
+        }
+    }
+
+    #[cfg(feature = "leak-backtrace")]
+    #[test]
+    fn test_backtrace() {
+        unsafe {
+            let ptr = ::allocate(555, 8);
+            let ptr = ::reallocate(ptr, 555, 5555, 8);
+
+            let leak = report().into_iter().find(|leak| leak.ptr == ptr).unwrap();
+            let backtrace = ::std::format!("{}", leak.backtrace().unwrap());
+            assert!(backtrace.contains("test_backtrace"));
+
+            ::deallocate(ptr, 5555, 8);
+        }
+    }
+
+    #[cfg(feature = "debug-checks")]
+    #[test]
+    fn test_poison() {
+        unsafe {
+            let ptr = ::allocate(32, 8);
+            assert!((0..32).all(|i| *ptr.add(i) == 0xCD));
+
+            ::std::ptr::write_bytes(ptr, 1, 32);
+            let ptr = ::reallocate(ptr, 32, 64, 8);
+            assert!((0..32).all(|i| *ptr.add(i) == 1));
+            assert!((32..64).all(|i| *ptr.add(i) == 0xCD));
+            ::deallocate(ptr, 64, 8);
+
+            let ptr = ::allocate_zeroed(32, 8);
+            assert!((0..32).all(|i| *ptr.add(i) == 0));
+            ::deallocate(ptr, 32, 8);
+        }
+    }
+
+    #[cfg(feature = "debug-checks")]
+    #[test]
+    #[should_panic(expected = "not a live allocation")]
+    fn test_double_free() {
+        unsafe {
+            let ptr = ::allocate(64, 8);
+            ::deallocate(ptr, 64, 8);
+            ::deallocate(ptr, 64, 8);
+        }
+    }
+
+    #[cfg(feature = "debug-checks")]
+    #[test]
+    #[should_panic(expected = "allocated with size 4096 and align 8")]
+    fn test_layout_mismatch() {
+        unsafe {
+            let ptr = ::allocate(4096, 8);
+            ::deallocate(ptr, 16, 8);
+        }
+    }
+}
@@ -1,65 +1,840 @@
-use std::mem;
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+pub mod align;
+pub mod arena;
+pub mod growth;
+pub mod rc_raw;
+
+mod aligned_box;
+mod aligned_bytes;
+mod allocation;
+mod allocator;
+mod asan;
+mod backend;
+mod buddy;
+mod budget;
+mod cache;
+mod canary;
+mod convert;
+mod error;
+mod fallback;
+mod fixed;
+mod free_list;
+mod handle;
+mod hook;
+mod layout;
+mod oom;
+mod pool;
+mod prefixed;
+mod protect;
+mod raw_buf;
+mod scratch;
+mod shared_bytes;
+mod simd;
+mod size_class;
+mod slab;
+mod stack;
+mod sync_alloc;
+mod sys;
+mod trim;
+mod typed;
+mod unique;
+mod valgrind;
+
+#[cfg(feature = "c-api")]
+mod c_api;
+
+#[cfg(feature = "global-alloc")]
+mod global;
+
+#[cfg(feature = "std")]
+mod magazine;
+
+#[cfg(any(feature = "leak-check", feature = "heap-profile", feature = "trace"))]
+mod reentrancy;
+
+#[cfg(any(unix, windows))]
+mod advise;
+
+#[cfg(any(unix, windows))]
+mod buf;
+
+#[cfg(any(unix, windows))]
+mod exec;
+
+#[cfg(any(unix, windows))]
+mod guard;
+
+#[cfg(any(unix, windows))]
+mod huge;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+mod mapped;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+mod mirror;
+
+#[cfg(any(unix, windows))]
+mod numa;
+
+#[cfg(any(unix, windows))]
+mod region;
+
+#[cfg(any(unix, windows))]
+pub mod secure;
+
+#[cfg(feature = "fail-injection")]
+pub mod failure;
+
+#[cfg(feature = "leak-check")]
+pub mod leaks;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "heap-profile")]
+pub mod profile;
+
+#[cfg(feature = "stats")]
+pub mod stats;
+
+#[cfg(feature = "trace")]
+pub mod trace;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+mod shm;
+
+pub use aligned_box::AlignedBox;
+pub use aligned_bytes::AlignedBytes;
+pub use allocation::Allocation;
+pub use allocator::{Alloc, Heap};
+pub use backend::{set_backend, Backend, DefaultBackend};
+pub use buddy::Buddy;
+pub use budget::{Budget, Quota};
+pub use cache::{allocate_cache_aligned, cache_line_size, deallocate_cache_aligned};
+pub use canary::Canary;
+pub use convert::{assume_vec, leak_vec};
+pub use error::AllocError;
+pub use fallback::{Fallback, Owns};
+pub use fixed::FixedAlloc;
+pub use free_list::{Fit, FreeList};
+pub use handle::{Handle, HandleIter, HandlePool};
+pub use hook::{clear_alloc_hook, set_alloc_hook, AllocEvent};
+pub use layout::Layout;
+pub use oom::{clear_oom_handler, set_oom_handler, OomAction};
+pub use pool::{Pool, PoolBox};
+pub use prefixed::{allocate_prefixed, allocate_tailed, deallocate_prefixed, deallocate_tailed, prefixed_header, prefixed_layout, tailed_layout, tailed_slice};
+pub use protect::Protection;
+pub use raw_buf::RawBuf;
+pub use scratch::with_scratch;
+pub use shared_bytes::SharedBytes;
+pub use simd::{allocate_simd, deallocate_simd, SimdAlign, SimdBuf};
+pub use size_class::{is_size_class, next_size_class, round_up_pow2, SizeClassAlloc};
+pub use slab::Slab;
+pub use stack::StackAlloc;
+pub use sync_alloc::{Mutexed, MutexedGuard, SyncAlloc};
+pub use trim::{register_trim_callback, trim, TrimLevel};
+pub use typed::{allocate_array, allocate_init, allocate_one, allocate_uninit_slice, clone_slice_raw};
+pub use typed::{deallocate_array, deallocate_one, deallocate_uninit_slice, drop_and_deallocate, reallocate_array};
+pub use unique::Unique;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use protect::protect;
+
+#[cfg(feature = "c-api")]
+pub use c_api::{stable_heap_calloc, stable_heap_free, stable_heap_malloc, stable_heap_realloc, stable_heap_usable_size};
+
+#[cfg(feature = "global-alloc")]
+pub use global::GlobalAdapter;
+
+#[cfg(feature = "std")]
+pub use magazine::ThreadCache;
+
+#[cfg(all(feature = "mmap", unix))]
+pub use backend::{allocate_at, allocate_at_exact, deallocate_at};
+
+#[cfg(all(feature = "mmap", unix))]
+pub use backend::{huge_page_threshold, mmap_threshold, set_huge_page_threshold, set_mmap_threshold};
+
+#[cfg(any(unix, windows))]
+pub use advise::Advice;
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use advise::advise;
+
+#[cfg(any(unix, windows))]
+pub use buf::StableBuf;
+
+#[cfg(any(unix, windows))]
+pub use exec::{allocate_executable, deallocate_executable};
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use exec::make_executable;
+
+#[cfg(any(unix, windows))]
+pub use guard::{allocate_guarded, deallocate_guarded, PageGuardAlloc};
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use mapped::{deallocate_mapped, MapMode, MappedFile};
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use mirror::{allocate_mirrored, MirroredMemory};
+
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub use shm::{allocate_shared, RawSharedHandle, SharedMemory};
+
+#[cfg(any(unix, windows))]
+pub use numa::{allocate_on_node, deallocate_numa, interleave};
+
+#[cfg(any(unix, windows))]
+pub use region::VirtualRegion;
+
+#[cfg(any(unix, windows))]
+pub use huge::{allocate_huge, deallocate_huge, HugePageSize};
+
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::sync::atomic;
 
 /// An arbitrary non-null address to represent zero-size allocations.
 ///
 /// This preserves the non-null invariant for types like `Box<T>`. The address
 /// may overlap with non-zero-size memory allocations.
+///
+/// The address is only aligned to 1 byte, so it can't stand in for most
+/// types; `dangling` returns a sentinel aligned as needed.
+#[deprecated(note = "use `dangling`, which respects the alignment")]
+#[allow(clippy::manual_dangling_ptr)]
 pub const EMPTY: *mut () = 0x1 as *mut ();
 
+/// Returns a non-null pointer aligned to `align`, to represent zero-size
+/// allocations.
+///
+/// The pointer must not be dereferenced or released. Like `EMPTY`, the
+/// address may overlap with non-zero-size memory allocations.
+///
+/// # Panics
+///
+/// Panics if `align` is not a power of 2.
+#[inline]
+pub fn dangling(align: usize) -> NonNull<u8> {
+    assert!(align.is_power_of_two(), "unsupported alignment {}", align);
+
+    // A power of two is never 0
+    unsafe { NonNull::new_unchecked(align as *mut u8) }
+}
+
+/// Returns a non-null pointer aligned for `T`, to represent zero-size
+/// allocations of `T`.
+#[inline]
+pub fn dangling_for<T>() -> NonNull<T> {
+    dangling(mem::align_of::<T>()).cast()
+}
+
+/// Rounds `size` up to a multiple of `align`, which must be a power of 2.
+#[inline]
+pub(crate) fn round_up(size: usize, align: usize) -> usize {
+    (size + (align - 1)) & !(align - 1)
+}
+
+/// Checks that `layout` describes an allocation that can be served.
+fn validate(layout: Layout) -> Result<(), AllocError> {
+    if layout.size() == 0 || layout.align() > MAX_ALIGN {
+        return Err(AllocError::InvalidLayout);
+    }
+
+    Ok(())
+}
+
+/// Reports a new block to the instrumentation.
+#[inline]
+fn on_allocate(ptr: *mut u8, size: usize, align: usize) {
+    #[cfg(feature = "stats")]
+    stats::record_alloc(size);
+
+    #[cfg(feature = "leak-check")]
+    leaks::record_alloc(ptr, size, align);
+
+    #[cfg(feature = "heap-profile")]
+    profile::record_alloc(ptr, size);
+
+    #[cfg(feature = "trace")]
+    trace::record_alloc(ptr, size, align);
+
+    hook::notify(AllocEvent::Allocate { ptr, size, align });
+}
+
+/// Reports a block about to be released to the instrumentation.
+#[inline]
+fn on_deallocate(ptr: *mut u8, size: usize, align: usize) {
+    #[cfg(feature = "stats")]
+    stats::record_dealloc(size);
+
+    #[cfg(feature = "leak-check")]
+    leaks::record_dealloc(ptr);
+
+    #[cfg(feature = "heap-profile")]
+    profile::record_dealloc(ptr);
+
+    #[cfg(feature = "trace")]
+    trace::record_dealloc(ptr, size, align);
+
+    hook::notify(AllocEvent::Deallocate { ptr, size, align });
+}
+
+/// Reports a resized block to the instrumentation.
+#[inline]
+fn on_reallocate(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize, align: usize) {
+    #[cfg(feature = "stats")]
+    stats::record_realloc(old_size, size);
+
+    #[cfg(feature = "leak-check")]
+    leaks::record_realloc(old_ptr, ptr, size, align);
+
+    #[cfg(feature = "heap-profile")]
+    profile::record_realloc(old_ptr, ptr, old_size, size);
+
+    #[cfg(feature = "trace")]
+    trace::record_realloc(old_ptr, ptr, old_size, size, align);
+
+    hook::notify(AllocEvent::Reallocate { old_ptr, ptr, old_size, size, align });
+}
+
+/// Overwrites `len` bytes at `ptr` with zeroes in a way the compiler can't
+/// optimize away, even when the memory is freed right after.
+unsafe fn zero_volatile(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        ptr::write_volatile(ptr.add(i), 0);
+    }
+
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+/// Converts the result of a fallible allocation to the raw pointer API.
+///
+/// Exhaustion is reported as a null pointer, while invalid arguments keep
+/// panicking as they always have.
+#[inline]
+fn raw(res: Result<NonNull<u8>, AllocError>, size: usize, align: usize) -> *mut u8 {
+    match res {
+        Ok(ptr) => ptr.as_ptr(),
+        Err(AllocError::OutOfMemory) => ptr::null_mut(),
+        Err(AllocError::InvalidLayout) => invalid_layout(size, align),
+    }
+}
+
+/// Returns the sentinel standing in for a zero-size block aligned to `align`.
+#[inline]
+fn zero_size(align: usize) -> *mut u8 {
+    if !align.is_power_of_two() || align > MAX_ALIGN {
+        invalid_layout(0, align);
+    }
+
+    dangling(align).as_ptr()
+}
+
+#[cold]
+fn invalid_layout(size: usize, align: usize) -> ! {
+    if !align.is_power_of_two() || align > MAX_ALIGN {
+        panic!("unsupported alignment {}", align);
+    }
+
+    panic!("invalid allocate arguments; size={}; align={}", size, align);
+}
+
+/// The largest alignment supported by the allocation functions.
+///
+/// This depends on the backend the crate is built with.
+pub const MAX_ALIGN: usize = backend::MAX_ALIGN;
+
 /// Return a pointer to `size` bytes of memory aligned to `align`.
 ///
-/// On failure, return a null pointer.
+/// If `size` is 0, return `dangling(align)` without allocating. Passing it
+/// back to `deallocate` and `reallocate` with a size of 0 is fine.
+///
+/// On failure, call the handler registered with `set_oom_handler`, if any,
+/// and return a null pointer unless it retries successfully.
 ///
-/// Behavior is undefined if the requested size is 0 or the alignment is not a
-/// power of 2. The alignment must be no larger than the largest supported page
-/// size on the platform.
+/// # Safety
+///
+/// Behavior is undefined if the alignment is not a power of 2. The alignment
+/// must be no larger than `MAX_ALIGN`.
 #[inline]
 pub unsafe fn allocate(size: usize, align: usize) -> *mut u8 {
-    assert!(size & (align - 1) == 0, "invalid allocate arguments; size={}; align={}", size, align);
+    if size == 0 {
+        return zero_size(align);
+    }
+
+    let res = Layout::from_size_align(size, align)
+        .and_then(|layout| oom::retry(layout, || try_allocate(layout)));
+
+    raw(res, size, align)
+}
+
+/// Return a pointer to a block of memory fitting `layout`.
+///
+/// On failure, return `AllocError::OutOfMemory`. If the layout has a size of 0
+/// or an alignment larger than `MAX_ALIGN`, return `AllocError::InvalidLayout`.
+///
+/// # Safety
+///
+/// The returned memory is uninitialized and must be released with `release`
+/// (or `deallocate`) using the same layout.
+#[inline]
+pub unsafe fn try_allocate(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    validate(layout)?;
+
+    #[cfg(feature = "fail-injection")]
+    {
+        if failure::should_fail() {
+            return Err(AllocError::OutOfMemory);
+        }
+    }
+
+    let ptr = NonNull::new(backend::allocate(layout)).ok_or(AllocError::OutOfMemory)?;
+
+    #[cfg(feature = "debug-checks")]
+    leaks::poison_alloc(ptr.as_ptr(), layout.size());
+
+    on_allocate(ptr.as_ptr(), layout.size(), layout.align());
+
+    Ok(ptr)
+}
+
+/// Return a pointer to `size` bytes of zeroed memory aligned to `align`.
+///
+/// When possible, the zeroed memory is requested from the system allocator
+/// directly, which allows large blocks to be backed by fresh pages without an
+/// explicit memset.
+///
+/// Zero-size requests are handled as by `allocate`.
+///
+/// On failure, call the handler registered with `set_oom_handler`, if any,
+/// and return a null pointer unless it retries successfully.
+///
+/// # Safety
+///
+/// Behavior is undefined if the alignment is not a power of 2. The alignment
+/// must be no larger than `MAX_ALIGN`.
+#[inline]
+pub unsafe fn allocate_zeroed(size: usize, align: usize) -> *mut u8 {
+    if size == 0 {
+        return zero_size(align);
+    }
 
-    match align {
-        1 => do_allocate::<u8>(size),
-        2 => do_allocate::<u16>(size >> 1),
-        4 => do_allocate::<u32>(size >> 2),
-        8 => do_allocate::<u64>(size >> 3),
-        _ => panic!("unsupported alignment {}", align),
+    let res = Layout::from_size_align(size, align)
+        .and_then(|layout| oom::retry(layout, || try_allocate_zeroed(layout)));
+
+    raw(res, size, align)
+}
+
+/// Return a pointer to a block of zeroed memory fitting `layout`.
+///
+/// Errors are reported the same way as `try_allocate`.
+///
+/// # Safety
+///
+/// The returned memory must be released with `release` (or `deallocate`)
+/// using the same layout.
+#[inline]
+pub unsafe fn try_allocate_zeroed(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    validate(layout)?;
+
+    #[cfg(feature = "fail-injection")]
+    {
+        if failure::should_fail() {
+            return Err(AllocError::OutOfMemory);
+        }
     }
+
+    let ptr = NonNull::new(backend::allocate_zeroed(layout)).ok_or(AllocError::OutOfMemory)?;
+
+    on_allocate(ptr.as_ptr(), layout.size(), layout.align());
+
+    Ok(ptr)
 }
 
-unsafe fn do_allocate<T>(capacity: usize) -> *mut u8 {
-    let vec = Vec::<T>::with_capacity(capacity);
-    let ptr = vec.as_ptr();
+/// Return a pointer to a new block of `len` bytes aligned to `align`, holding
+/// a copy of the `len` bytes at `src`.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `len` bytes. The same requirements as
+/// `allocate` apply to `len` and `align`, and the block must be released with
+/// `deallocate` using them.
+#[inline]
+pub unsafe fn copy_into_new(src: *const u8, len: usize, align: usize) -> *mut u8 {
+    let ptr = allocate(len, align);
 
-    mem::forget(vec);
+    if !ptr.is_null() {
+        ptr::copy_nonoverlapping(src, ptr, len);
+    }
 
-    ptr as *mut u8
+    ptr
 }
 
 /// Deallocates the memory referenced by `ptr`.
 ///
+/// Deallocating a zero-size block does nothing.
+///
+/// # Safety
+///
 /// The `ptr` parameter must not be null.
 ///
 /// The `old_size` and `align` parameters are the parameters that were used to
 /// create the allocation referenced by `ptr`. The `old_size` parameter may be
 /// any value in range_inclusive(requested_size, usable_size).
+///
+/// # Panics
+///
+/// With the `debug-checks` feature, panics if `ptr` is not a live allocation
+/// or was allocated with a different layout.
 #[inline]
 pub unsafe fn deallocate(ptr: *mut u8, old_size: usize, align: usize) {
-    match align {
-        1 => do_deallocate::<u8>(ptr, old_size),
-        2 => do_deallocate::<u16>(ptr, old_size >> 1),
-        4 => do_deallocate::<u32>(ptr, old_size >> 2),
-        8 => do_deallocate::<u64>(ptr, old_size >> 3),
-        _ => panic!("unsupported alignment {}", align),
+    if old_size == 0 {
+        return;
+    }
+
+    #[cfg(feature = "debug-checks")]
+    {
+        leaks::check_release(ptr, old_size, align);
+        leaks::poison_free(ptr, old_size);
+    }
+
+    on_deallocate(ptr, old_size, align);
+
+    backend::deallocate(ptr, Layout::from_size_align_unchecked(old_size, align))
+}
+
+/// Deallocates the memory referenced by `ptr`.
+///
+/// # Safety
+///
+/// The `layout` parameter must be the layout that was used to create the
+/// allocation referenced by `ptr`. Its size may be any value in
+/// range_inclusive(requested_size, usable_size).
+#[inline]
+pub unsafe fn release(ptr: NonNull<u8>, layout: Layout) {
+    deallocate(ptr.as_ptr(), layout.size(), layout.align())
+}
+
+/// Zeroes and deallocates the memory referenced by `ptr`.
+///
+/// The whole usable size of the block is overwritten before it is freed, in a
+/// way the compiler can't elide. A plain `write_bytes` followed by
+/// `deallocate` may be removed as a dead store.
+///
+/// # Safety
+///
+/// The same requirements as `deallocate` apply.
+pub unsafe fn deallocate_zeroed(ptr: *mut u8, old_size: usize, align: usize) {
+    if old_size == 0 {
+        return;
+    }
+
+    zero_volatile(ptr, usable_size(old_size, align));
+    deallocate(ptr, old_size, align)
+}
+
+/// Resize the allocation referenced by `ptr` to `size` bytes.
+///
+/// On failure, call the handler registered with `set_oom_handler`, if any,
+/// and return a null pointer unless it retries successfully. The original
+/// allocation is left intact.
+///
+/// If the allocation was relocated, the memory at the passed-in pointer is
+/// undefined after the call. The contents of the allocation are preserved up to
+/// the lesser of the new and old sizes.
+///
+/// Growing a zero-size block allocates a new one, and resizing a block to 0
+/// bytes deallocates it, returning `dangling(align)`.
+///
+/// # Safety
+///
+/// Behavior is undefined if the alignment is not a power of 2. The alignment
+/// must be no larger than `MAX_ALIGN`.
+///
+/// The `old_size` and `align` parameters are the parameters that were used to
+/// create the allocation referenced by `ptr`. The `old_size` parameter may be
+/// any value in range_inclusive(requested_size, usable_size).
+#[inline]
+pub unsafe fn reallocate(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
+    if old_size == 0 {
+        return allocate(size, align);
+    }
+
+    if size == 0 {
+        deallocate(ptr, old_size, align);
+        return zero_size(align);
+    }
+
+    let layout = Layout::from_size_align_unchecked(old_size, align);
+    let ptr = NonNull::new_unchecked(ptr);
+    let res = Layout::from_size_align(size, align)
+        .and_then(|new_layout| oom::retry(new_layout, || try_reallocate(ptr, layout, size)));
+
+    raw(res, size, align)
+}
+
+/// Resize the allocation referenced by `ptr` to `size` bytes.
+///
+/// On failure, return an error and leave the original allocation intact.
+/// Errors are reported the same way as `try_allocate`.
+///
+/// # Safety
+///
+/// The `layout` parameter must be the layout that was used to create the
+/// allocation referenced by `ptr`. Its size may be any value in
+/// range_inclusive(requested_size, usable_size).
+#[inline]
+pub unsafe fn try_reallocate(ptr: NonNull<u8>, layout: Layout, size: usize) -> Result<NonNull<u8>, AllocError> {
+    validate(Layout::from_size_align(size, layout.align())?)?;
+
+    #[cfg(feature = "fail-injection")]
+    {
+        if failure::should_fail() {
+            return Err(AllocError::OutOfMemory);
+        }
+    }
+
+    #[cfg(feature = "debug-checks")]
+    leaks::check_release(ptr.as_ptr(), layout.size(), layout.align());
+
+    let new_ptr = NonNull::new(backend::reallocate(ptr.as_ptr(), layout, size)).ok_or(AllocError::OutOfMemory)?;
+
+    // The rest of the block holds the old contents
+    #[cfg(feature = "debug-checks")]
+    {
+        if size > layout.size() {
+            leaks::poison_alloc(new_ptr.as_ptr().add(layout.size()), size - layout.size());
+        }
+    }
+
+    on_reallocate(ptr.as_ptr(), new_ptr.as_ptr(), layout.size(), size, layout.align());
+
+    Ok(new_ptr)
+}
+
+/// Resize the allocation referenced by `ptr` to `size` bytes without moving it.
+///
+/// If the operation succeeds, it returns `usable_size(size, align)` and if it
+/// fails (or is a no-op) it returns `usable_size(old_size, align)`. The
+/// operation only succeeds when the new size fits in the existing block.
+/// Zero-size blocks have a usable size of 0, so they can't be resized in
+/// place, and blocks can't be shrunk to 0 bytes in place either.
+///
+/// # Safety
+///
+/// Behavior is undefined if the alignment is not a power of 2. The alignment
+/// must be no larger than `MAX_ALIGN`.
+///
+/// The `old_size` and `align` parameters are the parameters that were used to
+/// create the allocation referenced by `ptr`. The `old_size` parameter may be
+/// any value in range_inclusive(requested_size, usable_size).
+#[inline]
+pub unsafe fn reallocate_inplace(_ptr: *mut u8, old_size: usize, size: usize, align: usize) -> usize {
+    if old_size == 0 {
+        return 0;
+    }
+
+    if size == 0 {
+        return usable_size(old_size, align);
+    }
+
+    let old_usable = usable_size(old_size, align);
+    let new_usable = usable_size(size, align);
+
+    // None of the backends can resize a block in place. Only sizes that map
+    // to the same block can be served.
+    if new_usable == old_usable {
+        new_usable
+    } else {
+        old_usable
+    }
+}
+
+/// Return a pointer to at least `size` bytes of memory aligned to `align`,
+/// along with the usable size of the block.
+///
+/// Zero-size requests return `dangling(align)` and a size of 0. On failure,
+/// return a null pointer and a size of 0.
+///
+/// # Safety
+///
+/// Behavior is undefined if the alignment is not a power of 2. The alignment
+/// must be no larger than `MAX_ALIGN`.
+#[inline]
+pub unsafe fn allocate_excess(size: usize, align: usize) -> (*mut u8, usize) {
+    excess(allocate(size, align), size, align)
+}
+
+/// Resize the allocation referenced by `ptr` to at least `size` bytes,
+/// returning the new pointer along with the usable size of the block.
+///
+/// On failure, return a null pointer and a size of 0, and leave the original
+/// allocation intact.
+///
+/// # Safety
+///
+/// The same requirements as `reallocate` apply.
+#[inline]
+pub unsafe fn reallocate_excess(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> (*mut u8, usize) {
+    excess(reallocate(ptr, old_size, size, align), size, align)
+}
+
+fn excess(ptr: *mut u8, size: usize, align: usize) -> (*mut u8, usize) {
+    if ptr.is_null() || size == 0 {
+        (ptr, 0)
+    } else {
+        (ptr, usable_size(size, align))
+    }
+}
+
+/// Returns the layout of a block of `size` bytes aligned to `align`, if the
+/// allocation functions can serve it.
+///
+/// Returns `AllocError::InvalidLayout` if `align` is not a power of 2 or is
+/// larger than `MAX_ALIGN`, or if rounding `size` up to `align` overflows
+/// `isize::MAX`. A size of 0 is accepted.
+#[inline]
+pub fn check_layout(size: usize, align: usize) -> Result<Layout, AllocError> {
+    let layout = Layout::from_size_align(size, align)?;
+
+    if align > MAX_ALIGN {
+        return Err(AllocError::InvalidLayout);
+    }
+
+    Ok(layout)
+}
+
+/// Return a pointer to `size` bytes of memory aligned to `align`, without
+/// panicking.
+///
+/// This behaves like `allocate`, including for zero-size requests and the
+/// handler registered with `set_oom_handler`, but arguments that `allocate`
+/// panics on are reported as `AllocError::InvalidLayout`, as checked by
+/// `check_layout`, and exhaustion as `AllocError::OutOfMemory`. This makes it
+/// suitable for callers that must not unwind, such as FFI entry points.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate` using the same `size` and
+/// `align`.
+#[inline]
+pub unsafe fn allocate_checked(size: usize, align: usize) -> Result<NonNull<u8>, AllocError> {
+    let layout = check_layout(size, align)?;
+
+    if size == 0 {
+        return Ok(dangling(align));
+    }
+
+    oom::retry(layout, || try_allocate(layout))
+}
+
+/// Return a pointer to `size` bytes of zeroed memory aligned to `align`,
+/// without panicking.
+///
+/// Errors are reported the same way as `allocate_checked`.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate` using the same `size` and
+/// `align`.
+#[inline]
+pub unsafe fn allocate_zeroed_checked(size: usize, align: usize) -> Result<NonNull<u8>, AllocError> {
+    let layout = check_layout(size, align)?;
+
+    if size == 0 {
+        return Ok(dangling(align));
     }
+
+    oom::retry(layout, || try_allocate_zeroed(layout))
+}
+
+/// Resize the allocation referenced by `ptr` to `size` bytes, without
+/// panicking.
+///
+/// This behaves like `reallocate`, but errors are reported the same way as
+/// `allocate_checked`. On failure, the original allocation is left intact.
+///
+/// # Safety
+///
+/// The `old_size` and `align` parameters are the parameters that were used to
+/// create the allocation referenced by `ptr`. The `old_size` parameter may be
+/// any value in range_inclusive(requested_size, usable_size).
+#[inline]
+pub unsafe fn reallocate_checked(ptr: NonNull<u8>, old_size: usize, size: usize, align: usize) -> Result<NonNull<u8>, AllocError> {
+    let new_layout = check_layout(size, align)?;
+
+    if old_size == 0 {
+        return allocate_checked(size, align);
+    }
+
+    if size == 0 {
+        deallocate(ptr.as_ptr(), old_size, align);
+        return Ok(dangling(align));
+    }
+
+    let layout = Layout::from_size_align_unchecked(old_size, align);
+    oom::retry(new_layout, || try_reallocate(ptr, layout, size))
+}
+
+/// Returns the usable size of an allocation created with the specified
+/// `size` and `align`, without panicking.
+///
+/// Returns `AllocError::InvalidLayout` for the arguments rejected by
+/// `check_layout`.
+#[inline]
+pub fn try_usable_size(size: usize, align: usize) -> Result<usize, AllocError> {
+    check_layout(size, align)?;
+    Ok(usable_size(size, align))
+}
+
+/// Returns the size of a virtual memory page.
+///
+/// The value is queried from the OS once and cached.
+#[cfg(any(unix, windows))]
+#[inline]
+pub fn page_size() -> usize {
+    sys::page_size()
+}
+
+/// Returns the granularity at which the OS places new mappings.
+///
+/// This is the page size on unix, and usually 64 KiB on Windows. The value is
+/// queried from the OS once and cached.
+#[cfg(any(unix, windows))]
+#[inline]
+pub fn allocation_granularity() -> usize {
+    sys::allocation_granularity()
 }
 
-unsafe fn do_deallocate<T>(ptr: *mut u8, capacity: usize) {
-    let _ = Vec::from_raw_parts(ptr as *mut T, 0, capacity);
+/// Returns the usable size of an allocation created with the specified
+/// `size` and `align`.
+///
+/// The block returned by `allocate(size, align)` is valid for writes of this
+/// many bytes, and any size between `size` and the usable size may be passed
+/// back to `deallocate` and `reallocate`.
+///
+/// # Panics
+///
+/// Panics if the alignment is not supported.
+#[inline]
+pub fn usable_size(size: usize, align: usize) -> usize {
+    backend::usable_size(size, align)
 }
 
 #[cfg(test)]
 mod test {
-    use std::mem;
+    use std::{cmp, mem, ptr};
+    use std::vec::Vec;
 
     #[test]
     fn test_align() {
@@ -77,8 +852,299 @@ mod test {
     }
 
     #[test]
+    fn test_zero_size() {
+        unsafe {
+            for &align in &[1, 8, 64] {
+                let ptr = ::allocate(0, align);
+                assert_eq!(::dangling(align).as_ptr(), ptr);
+                assert_eq!(ptr, ::allocate_zeroed(0, align));
+                assert_eq!((ptr, 0), ::allocate_excess(0, align));
+                assert_eq!(0, ::reallocate_inplace(ptr, 0, 16, align));
+
+                // Grow out of the sentinel and shrink back into it
+                let grown = ::reallocate(ptr, 0, 16, align);
+                assert!(!grown.is_null() && grown != ptr);
+                assert_eq!(0, grown as usize & (align - 1));
+                *grown.add(15) = 1;
+
+                assert_eq!(ptr, ::reallocate(grown, 16, 0, align));
+
+                ::deallocate(ptr, 0, align);
+                ::deallocate_zeroed(ptr, 0, align);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported alignment 3")]
+    fn test_zero_size_unsupported_alignment() {
+        unsafe {
+            ::allocate(0, 3);
+        }
+    }
+
+    #[test]
+    fn test_checked() {
+        use {AllocError, Layout};
+
+        assert!(::check_layout(0, 8).is_ok());
+        assert_eq!(Ok(Layout::from_size_align(24, 8).unwrap()), ::check_layout(24, 8));
+        assert_eq!(Err(AllocError::InvalidLayout), ::check_layout(8, 3));
+        assert_eq!(Err(AllocError::InvalidLayout), ::check_layout(usize::MAX, 8));
+
+        if ::MAX_ALIGN < 1 << 62 {
+            assert_eq!(Err(AllocError::InvalidLayout), ::check_layout(8, ::MAX_ALIGN * 2));
+        }
+
+        assert_eq!(Err(AllocError::InvalidLayout), ::try_usable_size(8, 3));
+        assert!(::try_usable_size(10, 8).unwrap() >= 10);
+
+        unsafe {
+            assert_eq!(Err(AllocError::InvalidLayout), ::allocate_checked(8, 3));
+            assert_eq!(Err(AllocError::InvalidLayout), ::allocate_zeroed_checked(isize::MAX as usize, 8));
+            assert_eq!(Ok(::dangling(16)), ::allocate_checked(0, 16));
+
+            let ptr = ::allocate_zeroed_checked(64, 16).unwrap();
+            assert_eq!(0, *ptr.as_ptr().add(63));
+
+            assert_eq!(Err(AllocError::InvalidLayout), ::reallocate_checked(ptr, 64, 128, 3));
+            let ptr = ::reallocate_checked(ptr, 64, 128, 16).unwrap();
+            assert_eq!(0, *ptr.as_ptr());
+
+            assert_eq!(Ok(::dangling(16)), ::reallocate_checked(ptr, 128, 0, 16));
+        }
+    }
+
+    #[test]
+    fn test_size_not_multiple_of_align() {
+        unsafe {
+            let ptr = ::allocate(10, 4);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & 3);
+
+            ptr::write_bytes(ptr, 0xff, 10);
+
+            let ptr = ::reallocate(ptr, 10, 13, 4);
+            ::deallocate(ptr, 13, 4);
+        }
+    }
+
+    #[test]
+    fn test_large_alignments() {
+        let mut align = 16;
+
+        while align <= cmp::min(::MAX_ALIGN, 4096) {
+            unsafe {
+                let ptr = ::allocate(align * 2, align);
+                assert!(!ptr.is_null());
+                assert_eq!(0, ptr as usize & (align - 1));
+                ::deallocate(ptr, align * 2, align);
+
+                let ptr = ::allocate_zeroed(align, align);
+                assert!(!ptr.is_null());
+                assert_eq!(0, ptr as usize & (align - 1));
+
+                for i in 0..align {
+                    assert_eq!(0, *ptr.add(i));
+                }
+
+                ::deallocate(ptr, align, align);
+            }
+
+            align <<= 1;
+        }
+    }
+
+    #[test]
+    fn test_allocate_zeroed() {
+        unsafe {
+            for &align in &[1, 2, 4, 8] {
+                let ptr = ::allocate_zeroed(4096, align);
+                assert!(!ptr.is_null());
+                assert_eq!(0, ptr as usize & (align - 1));
+
+                for i in 0..4096 {
+                    assert_eq!(0, *ptr.offset(i));
+                }
+
+                ::deallocate(ptr, 4096, align);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deallocate_zeroed() {
+        unsafe {
+            let ptr = ::allocate(100, 8);
+            assert!(!ptr.is_null());
+            ptr::write_bytes(ptr, 0xAA, ::usable_size(100, 8));
+            ::deallocate_zeroed(ptr, 100, 8);
+        }
+    }
+
+    #[test]
+    fn test_copy_into_new() {
+        let src = [1u8, 2, 3, 4, 5];
+
+        unsafe {
+            let ptr = ::copy_into_new(src.as_ptr(), 5, 16);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & 15);
+            assert_eq!(&src[..], ::std::slice::from_raw_parts(ptr, 5));
+            ::deallocate(ptr, 5, 16);
+        }
+    }
+
+    #[test]
+    fn test_reallocate_preserves_contents() {
+        unsafe {
+            let ptr = ::allocate(16, 4);
+
+            for i in 0..16 {
+                *ptr.offset(i) = i as u8;
+            }
+
+            let ptr = ::reallocate(ptr, 16, 64, 4);
+            assert!(!ptr.is_null());
+
+            for i in 0..16 {
+                assert_eq!(i as u8, *ptr.offset(i));
+            }
+
+            let ptr = ::reallocate(ptr, 64, 8, 4);
+            assert!(!ptr.is_null());
+
+            for i in 0..8 {
+                assert_eq!(i as u8, *ptr.offset(i));
+            }
+
+            ::deallocate(ptr, 8, 4);
+        }
+    }
+
+    #[test]
+    fn test_usable_size() {
+        assert_eq!(10, ::usable_size(10, 1));
+        assert_eq!(12, ::usable_size(10, 4));
+        assert_eq!(16, ::usable_size(16, 16));
+        assert_eq!(4096, ::usable_size(1, 4096));
+
+        unsafe {
+            let ptr = ::allocate(10, 4);
+            ptr::write_bytes(ptr, 0xff, ::usable_size(10, 4));
+            ::deallocate(ptr, 12, 4);
+        }
+    }
+
+    #[test]
+    fn test_allocate_excess() {
+        unsafe {
+            let (ptr, usable) = ::allocate_excess(10, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(16, usable);
+
+            ptr::write_bytes(ptr, 0xff, usable);
+
+            let (ptr, usable) = ::reallocate_excess(ptr, usable, 100, 8);
+            assert!(!ptr.is_null());
+            assert_eq!(104, usable);
+            assert_eq!(0xff, *ptr.add(15));
+
+            ::deallocate(ptr, usable, 8);
+        }
+    }
+
+    #[test]
+    fn test_reallocate_inplace() {
+        unsafe {
+            let ptr = ::allocate(10, 8);
+
+            // Growing within the block succeeds
+            assert_eq!(16, ::reallocate_inplace(ptr, 10, 16, 8));
+
+            // Growing past the block fails
+            assert_eq!(16, ::reallocate_inplace(ptr, 16, 17, 8));
+
+            // Shrinking within the block succeeds
+            assert_eq!(16, ::reallocate_inplace(ptr, 16, 9, 8));
+
+            // Shrinking to a smaller block fails
+            assert_eq!(16, ::reallocate_inplace(ptr, 9, 8, 8));
+
+            ::deallocate(ptr, 9, 8);
+        }
+    }
+
+    #[test]
+    fn test_try_allocate() {
+        use {AllocError, Layout};
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 16).unwrap();
+            let ptr = ::try_allocate(layout).unwrap();
+            assert_eq!(0, ptr.as_ptr() as usize & 15);
+
+            let ptr = ::try_reallocate(ptr, layout, 128).unwrap();
+            ::release(ptr, Layout::from_size_align(128, 16).unwrap());
+
+            let layout = Layout::new::<[u64; 4]>();
+            let ptr = ::try_allocate_zeroed(layout).unwrap();
+            assert_eq!(0, *ptr.as_ptr());
+            ::release(ptr, layout);
+
+            let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+
+            assert_eq!(Err(AllocError::InvalidLayout), ::try_allocate(layout(0, 8)));
+
+            if let Some(align) = ::MAX_ALIGN.checked_mul(2) {
+                assert_eq!(Err(AllocError::InvalidLayout), ::try_allocate(layout(8, align)));
+            }
+
+            assert_eq!(Err(AllocError::OutOfMemory), ::try_allocate(layout(isize::MAX as usize - 7, 8)));
+            assert_eq!(Err(AllocError::OutOfMemory), ::try_allocate_zeroed(layout(isize::MAX as usize - 7, 8)));
+            assert_eq!(Err(AllocError::OutOfMemory), ::try_allocate_zeroed(layout(isize::MAX as usize / 2 + 1, 1)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported alignment 3")]
+    fn test_allocate_unsupported_alignment() {
+        unsafe {
+            ::allocate(8, 3);
+        }
+    }
+
+    #[test]
+    fn test_page_size() {
+        let page = ::page_size();
+        assert!(page.is_power_of_two());
+        assert_eq!(page, ::page_size());
+
+        let granularity = ::allocation_granularity();
+        assert!(granularity.is_power_of_two());
+        assert_eq!(0, granularity % page);
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn test_empty_constant() {
         let mut v = Vec::<()>::with_capacity(0);
         assert_eq!(::EMPTY, v.as_mut_ptr());
     }
+
+    #[test]
+    fn test_dangling() {
+        for &align in &[1, 2, 8, 64, 4096] {
+            assert_eq!(0, ::dangling(align).as_ptr() as usize % align);
+        }
+
+        assert_eq!(::std::ptr::NonNull::<u64>::dangling(), ::dangling_for::<u64>());
+        assert_eq!(0, ::dangling_for::<u128>().as_ptr() as usize % ::core::mem::align_of::<u128>());
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported alignment 3")]
+    fn test_dangling_unsupported_alignment() {
+        ::dangling(3);
+    }
 }
@@ -0,0 +1,212 @@
+use {Alloc, AllocError, Layout};
+use size_class::{class_align, class_for, class_size, NUM_CLASSES};
+
+use core::cell::RefCell;
+use core::ptr::NonNull;
+use std::vec::Vec;
+
+// Blocks cached per size class and thread
+const MAGAZINE_SIZE: usize = 32;
+
+// Blocks moved to or from the heap at once
+const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+/// An allocator that caches freed blocks per thread.
+///
+/// Requests of up to 16 KiB are rounded up to a size class, and each thread
+/// keeps a magazine of recently freed blocks for every class. Allocating
+/// takes a block from the current thread's magazine, refilling it from the
+/// heap in batches when it runs empty, and freeing a block puts it back,
+/// flushing half of the magazine to the heap when it is full. Most
+/// allocations therefore don't reach the heap, or any lock it takes.
+///
+/// Blocks may be freed on a different thread than the one that allocated
+/// them. Larger requests go straight to the heap. A thread's magazines are
+/// flushed when it exits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadCache;
+
+struct Magazines {
+    // Indexed by size class
+    classes: Vec<Vec<NonNull<u8>>>,
+}
+
+::std::thread_local! {
+    static MAGAZINES: RefCell<Magazines> = RefCell::new(Magazines::new());
+}
+
+impl ThreadCache {
+    /// Returns every block cached by the current thread to the heap.
+    pub fn flush() {
+        let _ = MAGAZINES.try_with(|m| m.borrow_mut().flush());
+    }
+
+    /// Returns the number of blocks cached by the current thread.
+    pub fn cached() -> usize {
+        MAGAZINES.try_with(|m| m.borrow().classes.iter().map(Vec::len).sum())
+            .unwrap_or(0)
+    }
+}
+
+impl Magazines {
+    fn new() -> Magazines {
+        Magazines {
+            classes: (0..NUM_CLASSES).map(|_| Vec::new()).collect(),
+        }
+    }
+
+    fn flush(&mut self) {
+        for (class, magazine) in self.classes.iter_mut().enumerate() {
+            for ptr in magazine.drain(..) {
+                unsafe { ::release(ptr, class_layout(class)) }
+            }
+        }
+    }
+}
+
+impl Drop for Magazines {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+unsafe impl Alloc for ThreadCache {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let class = match class_for(layout) {
+            Some(class) => class,
+            None => return ::try_allocate(layout),
+        };
+
+        let cached = MAGAZINES.try_with(|m| {
+            let magazine = &mut m.borrow_mut().classes[class];
+
+            #[cfg(feature = "stats")]
+            ::stats::record_cache(!magazine.is_empty());
+
+            if magazine.is_empty() {
+                // Take a batch, keeping what was allocated if the heap runs
+                // out midway
+                for _ in 0..BATCH_SIZE {
+                    match ::try_allocate(class_layout(class)) {
+                        Ok(ptr) => magazine.push(ptr),
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            magazine.pop()
+        });
+
+        match cached {
+            Ok(Some(ptr)) => Ok(ptr),
+            _ => ::try_allocate(class_layout(class)),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let class = match class_for(layout) {
+            Some(class) => class,
+            None => return ::release(ptr, layout),
+        };
+
+        let cached = MAGAZINES.try_with(|m| {
+            let magazine = &mut m.borrow_mut().classes[class];
+
+            if magazine.len() == MAGAZINE_SIZE {
+                for ptr in magazine.drain(..BATCH_SIZE) {
+                    ::release(ptr, class_layout(class));
+                }
+            }
+
+            magazine.push(ptr);
+        });
+
+        // The thread is exiting
+        if cached.is_err() {
+            ::release(ptr, class_layout(class));
+        }
+    }
+}
+
+fn class_layout(class: usize) -> Layout {
+    unsafe { Layout::from_size_align_unchecked(class_size(class), class_align(class)) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BATCH_SIZE, MAGAZINE_SIZE};
+    use {Alloc, Layout, ThreadCache};
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_reuse() {
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let a = ThreadCache.alloc(layout).unwrap();
+            assert_eq!(BATCH_SIZE - 1, ThreadCache::cached());
+
+            ThreadCache.dealloc(a, layout);
+            assert_eq!(a, ThreadCache.alloc(layout).unwrap());
+
+            // Any layout of the same class shares the magazine
+            ThreadCache.dealloc(a, layout);
+            assert_eq!(a, ThreadCache.alloc(Layout::from_size_align(112, 16).unwrap()).unwrap());
+            ThreadCache.dealloc(a, layout);
+        }
+
+        ThreadCache::flush();
+        assert_eq!(0, ThreadCache::cached());
+    }
+
+    #[test]
+    fn test_flush_when_full() {
+        let layout = Layout::new::<[u64; 4]>();
+
+        unsafe {
+            let ptrs: Vec<_> = (0..MAGAZINE_SIZE * 2).map(|_| ThreadCache.alloc(layout).unwrap()).collect();
+
+            for ptr in ptrs {
+                ThreadCache.dealloc(ptr, layout);
+                assert!(ThreadCache::cached() <= MAGAZINE_SIZE);
+            }
+        }
+
+        ThreadCache::flush();
+    }
+
+    #[test]
+    fn test_alignment_and_large() {
+        unsafe {
+            for &(size, align) in &[(1, 1), (48, 16), (40, 64), (300, 512), (100_000, 8)] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = ThreadCache.alloc(layout).unwrap();
+                assert_eq!(0, ptr.as_ptr() as usize % align);
+                *ptr.as_ptr().add(size - 1) = 1;
+                ThreadCache.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cross_thread() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        let ptrs: Vec<usize> = thread::spawn(move || unsafe {
+            (0..100).map(|_| ThreadCache.alloc(layout).unwrap().as_ptr() as usize).collect()
+        }).join().unwrap();
+
+        unsafe {
+            for ptr in ptrs {
+                ThreadCache.dealloc(::std::ptr::NonNull::new_unchecked(ptr as *mut u8), layout);
+            }
+        }
+
+        ThreadCache::flush();
+    }
+}
@@ -0,0 +1,278 @@
+use sys;
+
+use core::ptr;
+
+use std::fs::File;
+use std::io;
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+
+/// How a file is mapped into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapMode {
+    /// The mapping can only be read. The file must be opened for reading.
+    ReadOnly,
+
+    /// The mapping can be read and written, but writes are private to the
+    /// mapping and never reach the file. The file must be opened for reading.
+    CopyOnWrite,
+
+    /// Writes to the mapping are written back to the file and are visible to
+    /// other mappings of it. The file must be opened for reading and writing.
+    Shared,
+}
+
+/// A file mapped into memory.
+///
+/// The contents are exposed as a raw pointer and length, like the other
+/// allocations of this crate. The mapping is released when the value is
+/// dropped, or can be taken over with `into_raw` and released with
+/// `deallocate_mapped`.
+///
+/// The file may be changed by other processes while it is mapped, so the
+/// memory must not be assumed to stay the same between reads.
+#[derive(Debug)]
+pub struct MappedFile {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps the whole of `file` into memory.
+    ///
+    /// The length of the mapping is the length of the file at the time of the
+    /// call. The mapping stays valid after `file` is closed.
+    pub fn open(file: &File, mode: MapMode) -> io::Result<MappedFile> {
+        let len = file.metadata()?.len();
+
+        if len > isize::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"));
+        }
+
+        let len = len as usize;
+
+        if len == 0 {
+            return Ok(MappedFile { ptr: ::dangling(1).as_ptr(), len: 0 });
+        }
+
+        let ptr = unsafe { map_file(file, len, mode)? };
+
+        Ok(MappedFile { ptr, len })
+    }
+
+    /// Returns a pointer to the start of the mapping.
+    ///
+    /// The memory may only be written if the file was mapped with
+    /// `CopyOnWrite` or `Shared`.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the length of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the mapping is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writes modified pages of a `Shared` mapping back to the file, blocking
+    /// until done.
+    pub fn flush(&self) -> io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        unsafe { flush(self.ptr, self.len) }
+    }
+
+    /// Consumes the `MappedFile`, returning the pointer and length of the
+    /// mapping.
+    ///
+    /// The mapping must be released with `deallocate_mapped`.
+    pub fn into_raw(self) -> (*mut u8, usize) {
+        let raw = (self.ptr, self.len);
+        ::core::mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe { deallocate_mapped(self.ptr, self.len) }
+    }
+}
+
+/// Releases a mapping obtained from `MappedFile::into_raw`.
+///
+/// # Safety
+///
+/// `ptr` and `len` must have been returned by `MappedFile::into_raw`.
+pub unsafe fn deallocate_mapped(ptr: *mut u8, len: usize) {
+    if len != 0 {
+        unmap_file(ptr, len);
+    }
+}
+
+#[cfg(unix)]
+unsafe fn map_file(file: &File, len: usize, mode: MapMode) -> io::Result<*mut u8> {
+    let (prot, flags) = match mode {
+        MapMode::ReadOnly => (sys::PROT_READ, sys::MAP_SHARED),
+        MapMode::CopyOnWrite => (sys::PROT_READ | sys::PROT_WRITE, sys::MAP_PRIVATE),
+        MapMode::Shared => (sys::PROT_READ | sys::PROT_WRITE, sys::MAP_SHARED),
+    };
+
+    let ptr = sys::mmap(ptr::null_mut(), len, prot, flags, file.as_raw_fd(), 0);
+
+    if ptr == sys::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(unix)]
+unsafe fn unmap_file(ptr: *mut u8, len: usize) {
+    sys::unmap(ptr, len)
+}
+
+#[cfg(unix)]
+unsafe fn flush(ptr: *mut u8, len: usize) -> io::Result<()> {
+    if sys::msync(ptr as *mut sys::c_void, len, sys::MS_SYNC) != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+unsafe fn map_file(file: &File, len: usize, mode: MapMode) -> io::Result<*mut u8> {
+    let (protect, access) = match mode {
+        MapMode::ReadOnly => (sys::PAGE_READONLY, sys::FILE_MAP_READ),
+        MapMode::CopyOnWrite => (sys::PAGE_WRITECOPY, sys::FILE_MAP_COPY),
+        MapMode::Shared => (sys::PAGE_READWRITE, sys::FILE_MAP_WRITE),
+    };
+
+    let mapping = sys::CreateFileMappingW(
+        file.as_raw_handle() as sys::HANDLE,
+        ptr::null_mut(),
+        protect,
+        0,
+        0,
+        ptr::null());
+
+    if mapping.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ptr = sys::MapViewOfFile(mapping, access, 0, 0, len);
+    let err = io::Error::last_os_error();
+
+    // The view keeps the mapping object alive
+    sys::CloseHandle(mapping);
+
+    if ptr.is_null() {
+        return Err(err);
+    }
+
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(windows)]
+unsafe fn unmap_file(ptr: *mut u8, _len: usize) {
+    sys::UnmapViewOfFile(ptr as sys::LPVOID);
+}
+
+#[cfg(windows)]
+unsafe fn flush(ptr: *mut u8, len: usize) -> io::Result<()> {
+    if sys::FlushViewOfFile(ptr as sys::LPVOID, len) == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MapMode, MappedFile};
+
+    use std::{env, fs, process, slice};
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::vec::Vec;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(::std::format!("stable-heap-{}-{}", process::id(), name));
+        File::create(&path).unwrap().write_all(contents).unwrap();
+        path
+    }
+
+    fn read(path: &PathBuf) -> Vec<u8> {
+        let mut buf = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_map_read_only() {
+        let path = temp_file("read-only", b"hello world");
+        let map = MappedFile::open(&File::open(&path).unwrap(), MapMode::ReadOnly).unwrap();
+
+        assert_eq!(11, map.len());
+        assert_eq!(b"hello world", unsafe { slice::from_raw_parts(map.as_ptr(), map.len()) });
+
+        drop(map);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_map_copy_on_write() {
+        let path = temp_file("copy-on-write", b"hello world");
+        let map = MappedFile::open(&File::open(&path).unwrap(), MapMode::CopyOnWrite).unwrap();
+
+        unsafe { *map.as_ptr() = b'j' };
+        assert_eq!(b"jello world", unsafe { slice::from_raw_parts(map.as_ptr(), map.len()) });
+
+        drop(map);
+        assert_eq!(b"hello world", &read(&path)[..]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_map_shared() {
+        let path = temp_file("shared", b"hello world");
+        let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let map = MappedFile::open(&file, MapMode::Shared).unwrap();
+
+        unsafe { *map.as_ptr() = b'j' };
+        map.flush().unwrap();
+
+        assert_eq!(b"jello world", &read(&path)[..]);
+
+        let (ptr, len) = map.into_raw();
+        unsafe { super::deallocate_mapped(ptr, len) };
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_map_empty() {
+        let path = temp_file("empty", b"");
+        let map = MappedFile::open(&File::open(&path).unwrap(), MapMode::ReadOnly).unwrap();
+
+        assert!(map.is_empty());
+        map.flush().unwrap();
+
+        drop(map);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+//! Metrics export.
+//!
+//! With the `metrics` feature, the counters of the `stats` module can be
+//! handed to a monitoring system through an `Exporter`. The crate reports
+//! each metric by name, and the exporter forwards it to whatever client
+//! library the application uses, or renders it with `TextExporter` in the
+//! Prometheus text format for a scrape endpoint.
+//!
+//! Metric names are prefixed with `stable_heap_`. Counters only ever grow,
+//! so rates are best computed by the monitoring system; `Metrics` also
+//! reports the allocation rate since its previous export, for systems that
+//! can't.
+
+use stats::{self, Stats};
+
+use std::fmt::{self, Write};
+use std::string::String;
+use std::time::Instant;
+
+/// Receives the allocator metrics reported by `Metrics::export`.
+pub trait Exporter {
+    /// Reports a counter, a value that only grows over the life of the
+    /// process.
+    fn counter(&mut self, name: &str, help: &str, value: u64);
+
+    /// Reports a gauge, a value that can go up and down.
+    fn gauge(&mut self, name: &str, help: &str, value: f64);
+}
+
+/// Reports the allocator metrics to an `Exporter`.
+///
+/// The reported metrics are:
+///
+/// - `stable_heap_live_bytes` and `stable_heap_peak_bytes`, gauges;
+/// - `stable_heap_allocated_bytes_total`, `stable_heap_allocations_total`,
+///   `stable_heap_deallocations_total` and `stable_heap_reallocations_total`,
+///   counters;
+/// - `stable_heap_allocation_rate`, a gauge of allocations per second since
+///   the previous export, omitted the first time;
+/// - `stable_heap_cache_hits_total` and `stable_heap_cache_misses_total`,
+///   counters, and `stable_heap_cache_hit_rate`, a gauge omitted until
+///   `ThreadCache` has been used.
+#[derive(Debug)]
+pub struct Metrics {
+    // The time and counters of the previous export
+    last: Option<(Instant, Stats)>,
+}
+
+impl Metrics {
+    /// Creates a reporter.
+    pub fn new() -> Metrics {
+        Metrics { last: None }
+    }
+
+    /// Reports the current value of every metric to `exporter`.
+    pub fn export<E: Exporter + ?Sized>(&mut self, exporter: &mut E) {
+        let now = Instant::now();
+        let stats = stats::snapshot();
+
+        exporter.gauge("stable_heap_live_bytes", "Bytes in blocks that have not been released", stats.live_bytes as f64);
+        exporter.gauge("stable_heap_peak_bytes", "Largest number of live bytes", stats.peak_bytes as f64);
+        exporter.counter("stable_heap_allocated_bytes_total", "Bytes allocated, including growth by reallocation", stats.allocated_bytes as u64);
+        exporter.counter("stable_heap_allocations_total", "Blocks allocated", stats.allocations as u64);
+        exporter.counter("stable_heap_deallocations_total", "Blocks released", stats.deallocations as u64);
+        exporter.counter("stable_heap_reallocations_total", "Blocks resized", stats.reallocations as u64);
+
+        if let Some((then, ref last)) = self.last {
+            let secs = now.duration_since(then).as_secs_f64();
+
+            if secs > 0.0 {
+                let rate = stats.allocations.saturating_sub(last.allocations) as f64 / secs;
+                exporter.gauge("stable_heap_allocation_rate", "Blocks allocated per second since the previous export", rate);
+            }
+        }
+
+        exporter.counter("stable_heap_cache_hits_total", "Thread cache allocations served from the cache", stats.cache_hits as u64);
+        exporter.counter("stable_heap_cache_misses_total", "Thread cache allocations served by the heap", stats.cache_misses as u64);
+
+        if let Some(rate) = stats.cache_hit_rate() {
+            exporter.gauge("stable_heap_cache_hit_rate", "Share of thread cache allocations served from the cache", rate);
+        }
+
+        self.last = Some((now, stats));
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+/// An `Exporter` rendering the metrics in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct TextExporter {
+    out: String,
+}
+
+impl TextExporter {
+    /// Creates an exporter with no metrics.
+    pub fn new() -> TextExporter {
+        TextExporter::default()
+    }
+
+    /// Returns the rendered metrics, to be served to the scraper.
+    pub fn into_string(self) -> String {
+        self.out
+    }
+
+    fn metric(&mut self, name: &str, help: &str, kind: &str, value: &dyn fmt::Display) {
+        // Writing to a `String` can't fail
+        let _ = write!(self.out, "# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+    }
+}
+
+impl Exporter for TextExporter {
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.metric(name, help, "counter", &value);
+    }
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.metric(name, help, "gauge", &value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Exporter, Metrics, TextExporter};
+    use {Alloc, Layout, ThreadCache};
+    use std::collections::HashMap;
+    use std::string::{String, ToString};
+
+    #[derive(Default)]
+    struct Collect(HashMap<String, f64>);
+
+    impl Exporter for Collect {
+        fn counter(&mut self, name: &str, _help: &str, value: u64) {
+            self.0.insert(name.to_string(), value as f64);
+        }
+
+        fn gauge(&mut self, name: &str, _help: &str, value: f64) {
+            self.0.insert(name.to_string(), value);
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let mut metrics = Metrics::new();
+        let mut first = Collect::default();
+        metrics.export(&mut first);
+        assert!(!first.0.contains_key("stable_heap_allocation_rate"));
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        unsafe {
+            for _ in 0..10 {
+                let ptr = ThreadCache.alloc(layout).unwrap();
+                ThreadCache.dealloc(ptr, layout);
+            }
+        }
+
+        let mut second = Collect::default();
+        metrics.export(&mut second);
+
+        assert!(second.0["stable_heap_allocations_total"] > first.0["stable_heap_allocations_total"]);
+        assert!(second.0["stable_heap_cache_hits_total"] >= 9.0);
+        assert!(second.0["stable_heap_allocation_rate"] >= 0.0);
+
+        let rate = second.0["stable_heap_cache_hit_rate"];
+        assert!(rate > 0.0 && rate <= 1.0);
+    }
+
+    #[test]
+    fn test_text_exporter() {
+        let mut text = TextExporter::new();
+        text.counter("requests_total", "Requests served", 3);
+        text.gauge("load", "Current load", 0.5);
+
+        let expected = "# HELP requests_total Requests served\n# TYPE requests_total counter\nrequests_total 3\n\
+                        # HELP load Current load\n# TYPE load gauge\nload 0.5\n";
+        assert_eq!(expected, text.into_string());
+
+        let mut text = TextExporter::new();
+        Metrics::new().export(&mut text);
+        assert!(text.into_string().contains("\n# TYPE stable_heap_live_bytes gauge\nstable_heap_live_bytes "));
+    }
+}
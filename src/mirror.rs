@@ -0,0 +1,189 @@
+use {round_up, shm, sys};
+
+use core::ptr;
+
+use std::io;
+
+/// A region of memory mapped twice, back to back.
+///
+/// The bytes at `as_ptr() + i` and `as_ptr() + len() + i` are the same
+/// memory, so a ring buffer built on the region can read and write across
+/// its end with a single contiguous access, without splitting it at the
+/// wraparound point.
+///
+/// Both mappings are released when the value is dropped.
+#[derive(Debug)]
+pub struct MirroredMemory {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for MirroredMemory {}
+unsafe impl Sync for MirroredMemory {}
+
+/// Return `size` bytes of zeroed memory, followed by a second mapping of the
+/// same bytes.
+///
+/// The size is rounded up to the allocation granularity, the page size on
+/// Unix and usually 64 KiB on Windows; `MirroredMemory::len` returns the
+/// rounded size.
+pub fn allocate_mirrored(size: usize) -> io::Result<MirroredMemory> {
+    let granularity = sys::allocation_granularity();
+
+    if size == 0 || size > (isize::MAX as usize) / 2 - (granularity - 1) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid mirrored memory size"));
+    }
+
+    let len = round_up(size, granularity);
+
+    unsafe {
+        let handle = shm::create(len)?;
+        let ptr = map_twice(&handle, len)?;
+
+        // The mappings keep the memory object alive
+        Ok(MirroredMemory { ptr, len })
+    }
+}
+
+impl MirroredMemory {
+    /// Returns a pointer to the start of the region.
+    ///
+    /// `2 * len()` bytes are accessible from it.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the length of the region in bytes, counting one mapping.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the buffer has a length of 0.
+    ///
+    /// Always `false`, as `allocate_mirrored` rejects a size of 0.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for MirroredMemory {
+    fn drop(&mut self) {
+        unsafe { unmap_twice(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+unsafe fn map_twice(handle: &shm::Handle, len: usize) -> io::Result<*mut u8> {
+    use std::os::unix::io::AsRawFd;
+
+    // Reserve the address range, then replace both halves with the object
+    let base = sys::mmap(
+        ptr::null_mut(),
+        2 * len,
+        sys::PROT_NONE,
+        sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+        -1,
+        0);
+
+    if base == sys::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    let base = base as *mut u8;
+
+    for half in 0..2 {
+        let ptr = sys::mmap(
+            base.add(half * len) as *mut sys::c_void,
+            len,
+            sys::PROT_READ | sys::PROT_WRITE,
+            sys::MAP_SHARED | sys::MAP_FIXED,
+            handle.as_raw_fd(),
+            0);
+
+        if ptr == sys::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            sys::unmap(base, 2 * len);
+            return Err(err);
+        }
+    }
+
+    Ok(base)
+}
+
+#[cfg(unix)]
+unsafe fn unmap_twice(ptr: *mut u8, len: usize) {
+    sys::unmap(ptr, 2 * len)
+}
+
+#[cfg(windows)]
+unsafe fn map_twice(handle: &shm::Handle, len: usize) -> io::Result<*mut u8> {
+    // Another thread may map into the range between releasing the
+    // reservation and mapping the views, in which case this starts over
+    const ATTEMPTS: usize = 16;
+
+    for _ in 0..ATTEMPTS {
+        let base = sys::VirtualAlloc(ptr::null_mut(), 2 * len, sys::MEM_RESERVE, sys::PAGE_NOACCESS);
+
+        if base.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        sys::VirtualFree(base, 0, sys::MEM_RELEASE);
+
+        let base = base as *mut u8;
+        let first = sys::MapViewOfFileEx(handle.0, sys::FILE_MAP_WRITE, 0, 0, len, base as sys::LPVOID);
+
+        if first.is_null() {
+            continue;
+        }
+
+        let second = sys::MapViewOfFileEx(handle.0, sys::FILE_MAP_WRITE, 0, 0, len, base.add(len) as sys::LPVOID);
+
+        if second.is_null() {
+            sys::UnmapViewOfFile(first);
+            continue;
+        }
+
+        return Ok(base);
+    }
+
+    Err(io::Error::other("failed to map mirrored memory"))
+}
+
+#[cfg(windows)]
+unsafe fn unmap_twice(ptr: *mut u8, len: usize) {
+    sys::UnmapViewOfFile(ptr as sys::LPVOID);
+    sys::UnmapViewOfFile(ptr.add(len) as sys::LPVOID);
+}
+
+#[cfg(test)]
+mod test {
+    use allocate_mirrored;
+
+    #[test]
+    fn test_allocate_mirrored() {
+        let mem = allocate_mirrored(100).unwrap();
+        let len = mem.len();
+        assert!(len >= 100);
+
+        unsafe {
+            let ptr = mem.as_ptr();
+            assert_eq!(0, *ptr.add(2 * len - 1));
+
+            *ptr = 1;
+            assert_eq!(1, *ptr.add(len));
+
+            // Write across the end of the first mapping
+            let bytes = [2u8, 3, 4, 5];
+            ::std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.add(len - 2), 4);
+            assert_eq!(4, *ptr);
+            assert_eq!(5, *ptr.add(1));
+            assert_eq!(2, *ptr.add(2 * len - 2));
+        }
+    }
+
+    #[test]
+    fn test_allocate_mirrored_zero_size() {
+        assert!(allocate_mirrored(0).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use {round_up, sys};
+
+use core::ptr;
+
+/// Return a pointer to `size` bytes of zeroed memory aligned to `align`,
+/// placed on NUMA node `node`.
+///
+/// The allocation is rounded up to a multiple of the page size. Placement is
+/// a hint: if the node doesn't exist or the system doesn't support NUMA
+/// policies, the memory is allocated without one. Linux and Windows are
+/// supported; elsewhere this is a plain page allocation.
+///
+/// On failure, or if `align` is larger than the page size, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_numa` using the same `size`.
+pub unsafe fn allocate_on_node(size: usize, align: usize, node: usize) -> *mut u8 {
+    let len = match map_len(size, align) {
+        Some(len) => len,
+        None => return ptr::null_mut(),
+    };
+
+    map_on_node(len, node)
+}
+
+/// Return a pointer to `size` bytes of zeroed memory aligned to `align`,
+/// with its pages interleaved across the NUMA nodes in `nodes`.
+///
+/// The allocation is rounded up to a multiple of the page size. Placement is
+/// a hint, as with `allocate_on_node`. Interleaving is only supported on
+/// Linux.
+///
+/// On failure, or if `align` is larger than the page size, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_numa` using the same `size`.
+pub unsafe fn interleave(size: usize, align: usize, nodes: &[usize]) -> *mut u8 {
+    let len = match map_len(size, align) {
+        Some(len) => len,
+        None => return ptr::null_mut(),
+    };
+
+    let ptr = sys::map_anonymous(len, 0);
+
+    if !ptr.is_null() {
+        bind(ptr, len, MPOL_INTERLEAVE, nodes);
+    }
+
+    ptr
+}
+
+/// Deallocates memory obtained from `allocate_on_node` or `interleave`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_on_node` or `interleave` with
+/// the same `size`.
+pub unsafe fn deallocate_numa(ptr: *mut u8, size: usize) {
+    sys::unmap(ptr, round_up(size, sys::page_size()))
+}
+
+fn map_len(size: usize, align: usize) -> Option<usize> {
+    let page = sys::page_size();
+
+    if size == 0 || !align.is_power_of_two() || align > page || size > isize::MAX as usize - (page - 1) {
+        return None;
+    }
+
+    Some(round_up(size, page))
+}
+
+#[cfg(unix)]
+const MPOL_BIND: usize = 2;
+const MPOL_INTERLEAVE: usize = 3;
+
+#[cfg(unix)]
+unsafe fn map_on_node(len: usize, node: usize) -> *mut u8 {
+    let ptr = sys::map_anonymous(len, 0);
+
+    if !ptr.is_null() {
+        bind(ptr, len, MPOL_BIND, &[node]);
+    }
+
+    ptr
+}
+
+#[cfg(windows)]
+unsafe fn map_on_node(len: usize, node: usize) -> *mut u8 {
+    let ptr = sys::VirtualAllocExNuma(
+        sys::GetCurrentProcess(),
+        ptr::null_mut(),
+        len,
+        sys::MEM_COMMIT | sys::MEM_RESERVE,
+        sys::PAGE_READWRITE,
+        node as sys::DWORD);
+
+    if !ptr.is_null() {
+        return ptr as *mut u8;
+    }
+
+    sys::map_anonymous(len, 0)
+}
+
+/// Applies the memory policy to the pages, ignoring failure.
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64", target_arch = "arm")))]
+unsafe fn bind(ptr: *mut u8, len: usize, mode: usize, nodes: &[usize]) {
+    // Nodes past the mask are ignored
+    const MAX_NODES: usize = 1024;
+    const BITS: usize = 8 * ::core::mem::size_of::<usize>();
+
+    let mut mask = [0usize; MAX_NODES / BITS];
+
+    for &node in nodes {
+        if node < MAX_NODES {
+            mask[node / BITS] |= 1 << (node % BITS);
+        }
+    }
+
+    sys::syscall(sys::SYS_mbind, ptr, len, mode, mask.as_ptr(), MAX_NODES + 1, 0usize);
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64", target_arch = "arm"))))]
+unsafe fn bind(_ptr: *mut u8, _len: usize, _mode: usize, _nodes: &[usize]) {
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_on_node, deallocate_numa, interleave};
+    use sys;
+
+    #[test]
+    fn test_allocate_on_node() {
+        unsafe {
+            let ptr = allocate_on_node(10_000, 64, 0);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & 63);
+
+            for i in 0..10_000 {
+                assert_eq!(0, *ptr.add(i));
+                *ptr.add(i) = i as u8;
+            }
+
+            deallocate_numa(ptr, 10_000);
+        }
+    }
+
+    #[test]
+    fn test_interleave() {
+        unsafe {
+            // Nodes that don't exist are ignored
+            let ptr = interleave(3 * sys::page_size(), 8, &[0, 1, 5000]);
+            assert!(!ptr.is_null());
+            *ptr = 1;
+            deallocate_numa(ptr, 3 * sys::page_size());
+        }
+    }
+
+    #[test]
+    fn test_allocate_on_node_unsupported() {
+        unsafe {
+            assert!(allocate_on_node(0, 8, 0).is_null());
+            assert!(allocate_on_node(8, 2 * sys::page_size(), 0).is_null());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use {AllocError, Layout};
+
+use core::{mem, ptr};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// What to do after the out-of-memory handler ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomAction {
+    /// Try the allocation again, typically after memory was freed.
+    Retry,
+
+    /// Give up and return a null pointer, as if no handler was registered.
+    Fail,
+
+    /// Abort the process, reporting the failed layout.
+    Abort,
+}
+
+// The handler as a data pointer, or null if there is none
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers `handler` to be called when `allocate`, `allocate_zeroed` or
+/// `reallocate` runs out of memory, replacing any previous handler.
+///
+/// The handler is passed the layout that could not be served, and decides
+/// whether the allocation is retried, fails with a null pointer or aborts the
+/// process. A handler can log the failure, or release caches and ask for a
+/// retry. It is called again each time a retry fails, so it must not ask for
+/// retries forever.
+///
+/// The `try_` functions report exhaustion as an error and never call the
+/// handler.
+pub fn set_oom_handler(handler: fn(Layout) -> OomAction) {
+    HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Removes the handler registered with `set_oom_handler`, if any.
+pub fn clear_oom_handler() {
+    HANDLER.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Calls `f` until it succeeds or the handler gives up on `layout`.
+#[inline]
+pub fn retry<F>(layout: Layout, mut f: F) -> Result<NonNull<u8>, AllocError>
+    where F: FnMut() -> Result<NonNull<u8>, AllocError>
+{
+    loop {
+        match f() {
+            Err(AllocError::OutOfMemory) => {}
+            res => return res,
+        }
+
+        let handler = HANDLER.load(Ordering::Acquire);
+
+        if handler.is_null() {
+            return Err(AllocError::OutOfMemory);
+        }
+
+        // Only ever stored from a `fn(Layout) -> OomAction`
+        let handler: fn(Layout) -> OomAction = unsafe { mem::transmute(handler) };
+
+        match handler(layout) {
+            OomAction::Retry => {}
+            OomAction::Fail => return Err(AllocError::OutOfMemory),
+            OomAction::Abort => abort(layout),
+        }
+    }
+}
+
+/// Aborts the process, reporting that `layout` could not be allocated.
+#[cold]
+pub fn abort(layout: Layout) -> ! {
+    let layout = unsafe { ::core::alloc::Layout::from_size_align_unchecked(layout.size(), layout.align()) };
+    ::alloc::alloc::handle_alloc_error(layout)
+}
+
+#[cfg(test)]
+mod test {
+    use {clear_oom_handler, set_oom_handler, AllocError, Layout, OomAction};
+    use super::retry;
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+
+    ::std::thread_local! {
+        #[allow(clippy::missing_const_for_thread_local)]
+        static CALLS: Cell<usize> = Cell::new(0);
+    }
+
+    fn handler(layout: Layout) -> OomAction {
+        // The handler is global, so only act on this test's size
+        if layout.size() != 4321 {
+            return OomAction::Fail;
+        }
+
+        CALLS.with(|calls| calls.set(calls.get() + 1));
+
+        if CALLS.with(|calls| calls.get()) < 3 {
+            OomAction::Retry
+        } else {
+            OomAction::Fail
+        }
+    }
+
+    #[test]
+    fn test_oom_handler() {
+        let layout = Layout::from_size_align(4321, 8).unwrap();
+        let attempts = Cell::new(0);
+
+        set_oom_handler(handler);
+
+        // Succeeds on the second retry
+        let res = retry(layout, || {
+            attempts.set(attempts.get() + 1);
+
+            if attempts.get() < 3 {
+                Err(AllocError::OutOfMemory)
+            } else {
+                Ok(NonNull::dangling())
+            }
+        });
+
+        assert!(res.is_ok());
+        assert_eq!(2, CALLS.with(|calls| calls.get()));
+
+        // Gives up once the handler does
+        assert_eq!(Err(AllocError::OutOfMemory), retry(layout, || Err(AllocError::OutOfMemory)));
+        assert_eq!(3, CALLS.with(|calls| calls.get()));
+
+        // Other errors are passed through
+        assert_eq!(Err(AllocError::InvalidLayout), retry(layout, || Err(AllocError::InvalidLayout)));
+
+        clear_oom_handler();
+    }
+}
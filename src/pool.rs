@@ -0,0 +1,259 @@
+use {AllocError, Layout, Slab};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem, ops, ptr};
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+type Reset<T> = Box<dyn Fn(&mut T)>;
+
+/// A pool of reusable objects of type `T`.
+///
+/// Objects are handed out as `PoolBox` pointers, which return their slot to
+/// the pool when dropped, so the memory is reused without going back to the
+/// heap.
+///
+/// By default the object is dropped when its `PoolBox` is. A pool created
+/// with `with_reset` instead resets the object with the provided hook and
+/// keeps it around for `take` to hand out again, which preserves buffers
+/// owned by the object.
+pub struct Pool<T> {
+    slab: RefCell<Slab>,
+
+    // Initialized objects kept by the reset hook
+    idle: RefCell<Vec<NonNull<T>>>,
+
+    reset: Option<Reset<T>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Pool<T> {
+    /// Creates a pool that drops objects when they are returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is too large or over-aligned for a `Slab`.
+    pub fn new() -> Pool<T> {
+        Pool::build(None)
+    }
+
+    /// Creates a pool that calls `reset` on objects when they are returned
+    /// and keeps them for reuse by `take`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is too large or over-aligned for a `Slab`.
+    pub fn with_reset<F>(reset: F) -> Pool<T>
+        where F: Fn(&mut T) + 'static
+    {
+        Pool::build(Some(Box::new(reset)))
+    }
+
+    fn build(reset: Option<Reset<T>>) -> Pool<T> {
+        // Zero-sized types still get distinct slots
+        let layout = Layout::from_size_align(cmp::max(mem::size_of::<T>(), 1), mem::align_of::<T>())
+            .and_then(Slab::new);
+
+        match layout {
+            Ok(slab) => Pool {
+                slab: RefCell::new(slab),
+                idle: RefCell::new(Vec::new()),
+                reset,
+                _marker: PhantomData,
+            },
+            Err(e) => panic!("unsupported pool object type: {}", e),
+        }
+    }
+
+    /// Moves `value` into a free slot of the pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails.
+    pub fn alloc(&self, value: T) -> PoolBox<'_, T> {
+        match self.try_alloc(value) {
+            Ok(b) => b,
+            Err(e) => panic!("pool allocation failed: {}", e),
+        }
+    }
+
+    /// Moves `value` into a free slot of the pool, returning an error on
+    /// failure.
+    pub fn try_alloc(&self, value: T) -> Result<PoolBox<'_, T>, AllocError> {
+        let ptr = self.slab.borrow_mut().alloc()?.cast::<T>();
+        unsafe { ptr::write(ptr.as_ptr(), value) };
+        Ok(PoolBox { pool: self, ptr })
+    }
+
+    /// Returns an object kept by the reset hook, or one created by `init` if
+    /// there is none.
+    pub fn take<F>(&self, init: F) -> PoolBox<'_, T>
+        where F: FnOnce() -> T
+    {
+        let idle = self.idle.borrow_mut().pop();
+
+        match idle {
+            Some(ptr) => PoolBox { pool: self, ptr },
+            None => self.alloc(init()),
+        }
+    }
+
+    /// Returns the number of objects kept for reuse by the reset hook.
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    fn release(&self, ptr: NonNull<T>) {
+        match self.reset {
+            Some(ref reset) => {
+                reset(unsafe { &mut *ptr.as_ptr() });
+                self.idle.borrow_mut().push(ptr);
+            }
+            None => unsafe {
+                ptr::drop_in_place(ptr.as_ptr());
+                self.slab.borrow_mut().free(ptr.cast());
+            },
+        }
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Pool<T> {
+        Pool::new()
+    }
+}
+
+impl<T> fmt::Debug for Pool<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Pool")
+            .field("live", &(self.slab.borrow().len() - self.idle()))
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        for ptr in self.idle.get_mut().drain(..) {
+            unsafe { ptr::drop_in_place(ptr.as_ptr()) };
+        }
+    }
+}
+
+/// An object owned by a `Pool`.
+///
+/// Dropping the `PoolBox` returns the object's slot to the pool.
+pub struct PoolBox<'a, T: 'a> {
+    pool: &'a Pool<T>,
+    ptr: NonNull<T>,
+}
+
+impl<'a, T> PoolBox<'a, T> {
+    /// Moves the object out of the pool, freeing its slot.
+    pub fn into_inner(b: PoolBox<'a, T>) -> T {
+        let pool = b.pool;
+        let ptr = b.ptr;
+        mem::forget(b);
+
+        unsafe {
+            let value = ptr::read(ptr.as_ptr());
+            pool.slab.borrow_mut().free(ptr.cast());
+            value
+        }
+    }
+}
+
+impl<'a, T> ops::Deref for PoolBox<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr.as_ptr() }
+    }
+}
+
+impl<'a, T> ops::DerefMut for PoolBox<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr.as_ptr() }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for PoolBox<'a, T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(fmt)
+    }
+}
+
+impl<'a, T> Drop for PoolBox<'a, T> {
+    fn drop(&mut self) {
+        self.pool.release(self.ptr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Pool, PoolBox};
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_pool_reuses_slots() {
+        let pool = Pool::new();
+
+        let a = pool.alloc(1u32);
+        let addr = &*a as *const u32;
+        drop(a);
+
+        let b = pool.alloc(2u32);
+        assert_eq!(addr, &*b as *const u32);
+        assert_eq!(2, *b);
+        assert_eq!(2, PoolBox::into_inner(b));
+    }
+
+    #[test]
+    fn test_pool_drops() {
+        let rc = Rc::new(());
+        let pool = Pool::new();
+
+        let boxes: Vec<_> = (0..100).map(|_| pool.alloc(rc.clone())).collect();
+        assert_eq!(101, Rc::strong_count(&rc));
+
+        drop(boxes);
+        assert_eq!(1, Rc::strong_count(&rc));
+    }
+
+    #[test]
+    fn test_pool_reset() {
+        let pool = Pool::with_reset(|buf: &mut Vec<u8>| buf.clear());
+
+        let mut buf = pool.take(Vec::new);
+        buf.extend_from_slice(&[0; 1000]);
+        drop(buf);
+
+        assert_eq!(1, pool.idle());
+
+        // The buffer comes back empty, with its capacity
+        let buf = pool.take(|| panic!("no idle object"));
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 1000);
+        drop(buf);
+
+        let rc = Rc::new(());
+        let pool = Pool::with_reset(|_: &mut Rc<()>| {});
+        drop(pool.take(|| rc.clone()));
+        assert_eq!(2, Rc::strong_count(&rc));
+
+        // Idle objects are dropped with the pool
+        drop(pool);
+        assert_eq!(1, Rc::strong_count(&rc));
+    }
+
+    #[test]
+    fn test_pool_zero_sized() {
+        let pool = Pool::new();
+        let a = pool.alloc(());
+        let b = pool.alloc(());
+        assert!(!::std::ptr::eq(&*a, &*b));
+    }
+}
@@ -0,0 +1,211 @@
+use {AllocError, Layout};
+
+use core::{cmp, mem};
+use core::ptr::NonNull;
+
+/// Returns the layout of a block holding a `header` followed by a `payload`,
+/// along with the offset of the payload.
+///
+/// Padding is inserted so the payload is aligned, and the block has the
+/// larger of the two alignments. Returns `AllocError::InvalidLayout` on
+/// overflow.
+pub fn prefixed_layout(header: Layout, payload: Layout) -> Result<(Layout, usize), AllocError> {
+    let (layout, offset) = header.extend(payload)?;
+    Ok((layout.pad_to_align(), offset))
+}
+
+/// Return pointers to the header and payload of a new block laid out by
+/// `prefixed_layout`.
+///
+/// This is the layout of shared buffers keeping a reference count, or of
+/// intrusive nodes, in front of their data. Errors are reported the same way
+/// as `try_allocate`, along with the overflows of `prefixed_layout`.
+///
+/// # Safety
+///
+/// The returned memory is uninitialized and must be released with
+/// `deallocate_prefixed` using the same layouts.
+pub unsafe fn allocate_prefixed(header: Layout, payload: Layout) -> Result<(NonNull<u8>, NonNull<u8>), AllocError> {
+    let (layout, offset) = prefixed_layout(header, payload)?;
+    let ptr = ::try_allocate(layout)?;
+
+    Ok((ptr, NonNull::new_unchecked(ptr.as_ptr().add(offset))))
+}
+
+/// Deallocates a block obtained from `allocate_prefixed`.
+///
+/// # Safety
+///
+/// `header_ptr` must be the header pointer returned by `allocate_prefixed`
+/// with the same `header` and `payload` layouts.
+pub unsafe fn deallocate_prefixed(header_ptr: NonNull<u8>, header: Layout, payload: Layout) {
+    let (layout, _) = layout_unchecked(header, payload);
+    ::release(header_ptr, layout)
+}
+
+/// Returns the header pointer of the block whose payload starts at
+/// `payload_ptr`.
+///
+/// # Safety
+///
+/// `payload_ptr` must be the payload pointer returned by `allocate_prefixed`
+/// with the same `header` and `payload` layouts.
+pub unsafe fn prefixed_header(payload_ptr: NonNull<u8>, header: Layout, payload: Layout) -> NonNull<u8> {
+    let (_, offset) = layout_unchecked(header, payload);
+    NonNull::new_unchecked(payload_ptr.as_ptr().sub(offset))
+}
+
+/// Returns the layout of a block holding an `H` followed by `len` values of
+/// `T`, along with the offset of the first value.
+///
+/// This is the layout of a struct ending in a flexible array member, as used
+/// by FFI and lock-free structures. Returns `AllocError::InvalidLayout` on
+/// overflow.
+pub fn tailed_layout<H, T>(len: usize) -> Result<(Layout, usize), AllocError> {
+    prefixed_layout(Layout::new::<H>(), Layout::array::<T>(len)?)
+}
+
+/// Return pointers to the header and the start of the slice of a new block
+/// laid out by `tailed_layout`.
+///
+/// Both pointers are aligned for their type. Errors are reported the same way
+/// as `try_allocate`, along with the overflows of `tailed_layout`. A zero-sized
+/// block is not allocated, and yields dangling pointers.
+///
+/// # Safety
+///
+/// The returned memory is uninitialized and must be released with
+/// `deallocate_tailed` using the same `len`.
+pub unsafe fn allocate_tailed<H, T>(len: usize) -> Result<(NonNull<H>, NonNull<T>), AllocError> {
+    let (layout, offset) = tailed_layout::<H, T>(len)?;
+
+    let ptr = if layout.size() == 0 {
+        ::dangling(layout.align())
+    } else {
+        ::try_allocate(layout)?
+    };
+
+    Ok((ptr.cast(), NonNull::new_unchecked(ptr.as_ptr().add(offset)).cast()))
+}
+
+/// Deallocates a block obtained from `allocate_tailed`.
+///
+/// The header and values are not dropped.
+///
+/// # Safety
+///
+/// `header_ptr` must be the header pointer returned by `allocate_tailed` with
+/// the same types and `len`.
+pub unsafe fn deallocate_tailed<H, T>(header_ptr: NonNull<H>, len: usize) {
+    let values = Layout::from_size_align_unchecked(len * mem::size_of::<T>(), mem::align_of::<T>());
+    let (layout, _) = layout_unchecked(Layout::new::<H>(), values);
+
+    if layout.size() != 0 {
+        ::release(header_ptr.cast(), layout)
+    }
+}
+
+/// Returns a pointer to the start of the slice following the header at
+/// `header_ptr`.
+///
+/// # Safety
+///
+/// `header_ptr` must be the header pointer returned by `allocate_tailed` with
+/// the same types.
+pub unsafe fn tailed_slice<H, T>(header_ptr: NonNull<H>) -> NonNull<T> {
+    let (_, offset) = layout_unchecked(Layout::new::<H>(), Layout::from_size_align_unchecked(0, mem::align_of::<T>()));
+    NonNull::new_unchecked((header_ptr.as_ptr() as *mut u8).add(offset)).cast()
+}
+
+// `prefixed_layout` for layouts it is known to succeed with, as those of an
+// allocated block
+unsafe fn layout_unchecked(header: Layout, payload: Layout) -> (Layout, usize) {
+    let offset = header.size() + header.padding_needed_for(payload.align());
+    let layout = Layout::from_size_align_unchecked(offset + payload.size(), cmp::max(header.align(), payload.align()));
+
+    (layout.pad_to_align(), offset)
+}
+
+#[cfg(test)]
+mod test {
+    use {allocate_prefixed, deallocate_prefixed, prefixed_header, prefixed_layout, AllocError, Layout};
+    use {allocate_tailed, deallocate_tailed, tailed_layout, tailed_slice};
+    use std::ptr;
+
+    #[test]
+    fn test_prefixed_layout() {
+        let (layout, offset) = prefixed_layout(Layout::new::<u8>(), Layout::new::<u64>()).unwrap();
+        assert_eq!(8, offset);
+        assert_eq!(16, layout.size());
+        assert_eq!(8, layout.align());
+
+        // Padded at the end to the header's alignment
+        let (layout, offset) = prefixed_layout(Layout::new::<u64>(), Layout::from_size_align(3, 1).unwrap()).unwrap();
+        assert_eq!(8, offset);
+        assert_eq!(16, layout.size());
+
+        let huge = Layout::from_size_align(isize::MAX as usize - 8, 1).unwrap();
+        assert_eq!(Err(AllocError::InvalidLayout), prefixed_layout(Layout::new::<u64>(), huge));
+    }
+
+    #[test]
+    fn test_allocate_prefixed() {
+        let header = Layout::new::<usize>();
+        let payload = Layout::from_size_align(100, 32).unwrap();
+
+        unsafe {
+            let (head, data) = allocate_prefixed(header, payload).unwrap();
+            assert_eq!(0, head.as_ptr() as usize % 32);
+            assert_eq!(0, data.as_ptr() as usize % 32);
+            assert!(data.as_ptr() as usize - head.as_ptr() as usize >= header.size());
+
+            ptr::write(head.as_ptr() as *mut usize, 1);
+            ptr::write_bytes(data.as_ptr(), 7, 100);
+
+            assert_eq!(head, prefixed_header(data, header, payload));
+            assert_eq!(1, *(head.as_ptr() as *const usize));
+
+            deallocate_prefixed(head, header, payload);
+        }
+    }
+
+    #[test]
+    fn test_tailed() {
+        #[repr(C)]
+        struct Header {
+            tag: u8,
+            len: usize,
+        }
+
+        let (layout, offset) = tailed_layout::<u8, u32>(3).unwrap();
+        assert_eq!(4, offset);
+        assert_eq!(16, layout.size());
+        assert_eq!(4, layout.align());
+
+        assert_eq!(Err(AllocError::InvalidLayout), tailed_layout::<u64, u64>(usize::MAX / 4));
+
+        unsafe {
+            let (head, values) = allocate_tailed::<Header, u128>(5).unwrap();
+            assert_eq!(0, head.as_ptr() as usize % ::std::mem::align_of::<Header>());
+            assert_eq!(0, values.as_ptr() as usize % ::std::mem::align_of::<u128>());
+            assert_eq!(values, tailed_slice::<Header, u128>(head));
+
+            ptr::write(head.as_ptr(), Header { tag: 1, len: 5 });
+
+            for i in 0..5 {
+                ptr::write(values.as_ptr().add(i), i as u128);
+            }
+
+            let slice = ::std::slice::from_raw_parts(values.as_ptr(), (*head.as_ptr()).len);
+            assert_eq!(&[0, 1, 2, 3, 4], slice);
+            assert_eq!(1, (*head.as_ptr()).tag);
+
+            deallocate_tailed::<Header, u128>(head, 5);
+
+            // Nothing to allocate
+            let (head, values) = allocate_tailed::<(), ()>(10).unwrap();
+            assert_eq!(values.as_ptr() as *mut u8, head.as_ptr() as *mut u8);
+            deallocate_tailed::<(), ()>(head, 10);
+        }
+    }
+}
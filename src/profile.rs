@@ -0,0 +1,536 @@
+//! Sampling heap profiler.
+//!
+//! With the `heap-profile` feature, the heap allocation functions sample one
+//! allocation every `sampling_interval` bytes on average, per thread, and
+//! capture its call stack. Sampled blocks are tracked until they are released,
+//! so the profile shows where the live memory was allocated from.
+//!
+//! `write_pprof` dumps the profile in the protobuf format read by `pprof`,
+//! and `write_collapsed` in the collapsed stack format read by flame graph
+//! tools. The number of bytes between samples is drawn from an exponential
+//! distribution, as tcmalloc does, so that periodic allocation patterns don't
+//! skew the profile. An allocation of `size` bytes is then sampled with a
+//! probability of `1 - exp(-size / interval)`, and each sample is weighted by
+//! the inverse to estimate the totals.
+//!
+//! Stacks are captured with `std::backtrace`, which resolves symbols as it
+//! goes. This is slow, so the interval should stay well above the typical
+//! allocation size; the default of 512 KiB keeps the overhead low.
+
+use reentrancy;
+
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::string::{String, ToString};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+
+const DEFAULT_INTERVAL: usize = 512 * 1024;
+
+// Average number of bytes between samples, zero disables sampling
+static INTERVAL: AtomicUsize = AtomicUsize::new(DEFAULT_INTERVAL);
+
+// Sampled blocks still live, by address
+static SAMPLES: Mutex<Option<HashMap<usize, Sample>>> = Mutex::new(None);
+
+const BUCKETS: usize = 8 * 1024;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicUsize = AtomicUsize::new(0);
+
+// Number of live samples whose address hashes to each bucket. Nearly all
+// released blocks weren't sampled, and their bucket tells so without taking
+// the lock.
+static SAMPLED: [AtomicUsize; BUCKETS] = [EMPTY; BUCKETS];
+
+::std::thread_local! {
+    // State of the generator drawing the sampling intervals
+    #[allow(clippy::missing_const_for_thread_local)]
+    static RNG: Cell<u64> = Cell::new(seed());
+
+    // Bytes left to allocate before the next sample
+    #[allow(clippy::missing_const_for_thread_local)]
+    static UNTIL_SAMPLE: Cell<usize> = Cell::new(next_interval(INTERVAL.load(Ordering::Relaxed)));
+
+    // Set while the thread captures a stack or updates the samples
+    #[allow(clippy::missing_const_for_thread_local)]
+    static BUSY: Cell<bool> = Cell::new(false);
+}
+
+struct Sample {
+    size: usize,
+    stack: Arc<Vec<Frame>>,
+}
+
+// A resolved stack frame
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Frame {
+    function: String,
+    file: String,
+    line: u64,
+}
+
+/// Samples one allocation out of every `bytes` bytes allocated, on average.
+///
+/// The default is 512 KiB. Passing `0` stops sampling, the blocks already
+/// sampled stay in the profile until they are released. Threads pick up the
+/// new interval after their next sample.
+pub fn set_sampling_interval(bytes: usize) {
+    INTERVAL.store(bytes, Ordering::Relaxed);
+}
+
+/// Returns the number of bytes allocated between samples.
+pub fn sampling_interval() -> usize {
+    INTERVAL.load(Ordering::Relaxed)
+}
+
+/// Writes the live sampled blocks to `out` in the collapsed stack format.
+///
+/// Each line holds a call stack, outermost frame first with frames separated
+/// by `;`, followed by the estimated number of live bytes allocated from it.
+pub fn write_collapsed<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut stacks: HashMap<Arc<Vec<Frame>>, u64> = HashMap::new();
+
+    for (stack, _, bytes) in snapshot() {
+        *stacks.entry(stack).or_insert(0) += bytes;
+    }
+
+    let mut lines: Vec<(String, u64)> = stacks.into_iter()
+        .map(|(stack, bytes)| {
+            let names: Vec<&str> = stack.iter().rev().map(|frame| &frame.function[..]).collect();
+            (names.join(";"), bytes)
+        })
+        .collect();
+
+    lines.sort();
+
+    for (stack, bytes) in lines {
+        writeln!(out, "{} {}", stack, bytes)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the live sampled blocks to `out` as an uncompressed `pprof`
+/// profile.
+///
+/// The profile has two sample types, `inuse_objects` and `inuse_space`,
+/// holding the estimated number of live blocks and bytes allocated from each
+/// stack.
+pub fn write_pprof<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut profile = Profile::new();
+    let mut buf = Vec::new();
+
+    for &(kind, unit) in [("inuse_objects", "count"), ("inuse_space", "bytes")].iter() {
+        let value_type = profile.value_type(kind, unit);
+        message(&mut buf, 1, &value_type);
+    }
+
+    for (stack, objects, bytes) in snapshot() {
+        let locations: Vec<u64> = stack.iter().map(|frame| profile.location(frame)).collect();
+
+        let mut sample = Vec::new();
+        packed(&mut sample, 1, &locations);
+        packed(&mut sample, 2, &[objects, bytes]);
+        message(&mut buf, 2, &sample);
+    }
+
+    let period_type = profile.value_type("space", "bytes");
+
+    for location in &profile.locations {
+        message(&mut buf, 4, location);
+    }
+
+    for function in &profile.functions {
+        message(&mut buf, 5, function);
+    }
+
+    for string in &profile.strings {
+        message(&mut buf, 6, string.as_bytes());
+    }
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    field(&mut buf, 9, time);
+    message(&mut buf, 11, &period_type);
+    field(&mut buf, 12, sampling_interval() as u64);
+
+    out.write_all(&buf)
+}
+
+// The tables of a `pprof` profile being encoded. Ids start at 1, as 0 means
+// unset.
+struct Profile {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u64>,
+
+    functions: Vec<Vec<u8>>,
+    function_ids: HashMap<(String, String), u64>,
+
+    locations: Vec<Vec<u8>>,
+    location_ids: HashMap<Frame, u64>,
+}
+
+impl Profile {
+    fn new() -> Profile {
+        let mut profile = Profile {
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+        };
+
+        // The string table starts with the empty string
+        profile.string("");
+        profile
+    }
+
+    fn string(&mut self, s: &str) -> u64 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+
+        let id = self.strings.len() as u64;
+        self.strings.push(s.into());
+        self.string_ids.insert(s.into(), id);
+        id
+    }
+
+    fn value_type(&mut self, kind: &str, unit: &str) -> Vec<u8> {
+        let mut value_type = Vec::new();
+        field(&mut value_type, 1, self.string(kind));
+        field(&mut value_type, 2, self.string(unit));
+        value_type
+    }
+
+    fn function(&mut self, frame: &Frame) -> u64 {
+        let key = (frame.function.clone(), frame.file.clone());
+
+        if let Some(&id) = self.function_ids.get(&key) {
+            return id;
+        }
+
+        let id = self.functions.len() as u64 + 1;
+        let name = self.string(&frame.function);
+        let file = self.string(&frame.file);
+
+        let mut function = Vec::new();
+        field(&mut function, 1, id);
+        field(&mut function, 2, name);
+        field(&mut function, 3, name);
+        field(&mut function, 4, file);
+
+        self.functions.push(function);
+        self.function_ids.insert(key, id);
+        id
+    }
+
+    fn location(&mut self, frame: &Frame) -> u64 {
+        if let Some(&id) = self.location_ids.get(frame) {
+            return id;
+        }
+
+        let id = self.locations.len() as u64 + 1;
+
+        let mut line = Vec::new();
+        field(&mut line, 1, self.function(frame));
+        field(&mut line, 2, frame.line);
+
+        let mut location = Vec::new();
+        field(&mut location, 1, id);
+        message(&mut location, 4, &line);
+
+        self.locations.push(location);
+        self.location_ids.insert(frame.clone(), id);
+        id
+    }
+}
+
+// Protobuf encoding, for the wire types `pprof` uses
+
+fn varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    buf.push(n as u8);
+}
+
+fn field(buf: &mut Vec<u8>, number: u64, n: u64) {
+    varint(buf, number << 3);
+    varint(buf, n);
+}
+
+fn message(buf: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    varint(buf, number << 3 | 2);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn packed(buf: &mut Vec<u8>, number: u64, values: &[u64]) {
+    let mut bytes = Vec::new();
+
+    for &n in values {
+        varint(&mut bytes, n);
+    }
+
+    message(buf, number, &bytes);
+}
+
+pub(crate) fn record_alloc(ptr: *mut u8, size: usize) {
+    if should_sample(size) {
+        reentrancy::guarded(&BUSY, || {
+            let sample = Sample { size, stack: Arc::new(capture()) };
+            samples(|samples| insert(samples, ptr, sample));
+        });
+    }
+}
+
+pub(crate) fn record_dealloc(ptr: *mut u8) {
+    if may_be_sampled(ptr) {
+        reentrancy::guarded(&BUSY, || samples(|samples| remove(samples, ptr)));
+    }
+}
+
+pub(crate) fn record_realloc(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize) {
+    if may_be_sampled(old_ptr) {
+        let moved = reentrancy::guarded(&BUSY, || {
+            samples(|samples| {
+                // A sampled block stays sampled, with its original stack
+                remove(samples, old_ptr).map(|mut sample| {
+                    sample.size = size;
+                    insert(samples, ptr, sample);
+                })
+            })
+        });
+
+        if let Some(Some(())) = moved {
+            return;
+        }
+    }
+
+    // Growth counts towards the next sample like a new allocation
+    if size > old_size {
+        record_alloc(ptr, size - old_size);
+
+        reentrancy::guarded(&BUSY, || samples(|samples| {
+            if let Some(sample) = samples.get_mut(&(ptr as usize)) {
+                sample.size = size;
+            }
+        }));
+    }
+}
+
+// Counts `size` bytes towards the next sample, returning `true` if this
+// allocation is the one to sample
+fn should_sample(size: usize) -> bool {
+    let interval = INTERVAL.load(Ordering::Relaxed);
+
+    if interval == 0 {
+        return false;
+    }
+
+    UNTIL_SAMPLE.try_with(|until| {
+        let left = until.get();
+
+        if size < left {
+            until.set(left - size);
+            false
+        } else {
+            until.set(next_interval(interval));
+            true
+        }
+    }).unwrap_or(false)
+}
+
+// Draws the number of bytes until the next sample from an exponential
+// distribution with a mean of `interval`
+fn next_interval(interval: usize) -> usize {
+    let x = RNG.try_with(|rng| {
+        // xorshift64*
+        let mut x = rng.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        rng.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }).unwrap_or(1 << 63);
+
+    // Uniform in (0, 1], so the logarithm stays finite
+    let uniform = ((x >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    (-uniform.ln() * interval as f64) as usize + 1
+}
+
+fn seed() -> u64 {
+    // `RandomState` is keyed randomly for each thread, without allocating.
+    // xorshift gets stuck at 0.
+    RandomState::new().build_hasher().finish() | 1
+}
+
+// Returns `false` if the block at `ptr` is certainly not sampled
+fn may_be_sampled(ptr: *mut u8) -> bool {
+    SAMPLED[bucket(ptr)].load(Ordering::Relaxed) != 0
+}
+
+fn bucket(ptr: *mut u8) -> usize {
+    // Fibonacci hashing, blocks are at least a few bytes apart
+    let hash = (ptr as usize >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15u64 as usize);
+    hash >> (usize::MAX.count_ones() - BUCKETS.trailing_zeros())
+}
+
+fn insert(samples: &mut HashMap<usize, Sample>, ptr: *mut u8, sample: Sample) {
+    // The block may still be there if its release happened inside the
+    // profiler
+    if samples.insert(ptr as usize, sample).is_none() {
+        SAMPLED[bucket(ptr)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn remove(samples: &mut HashMap<usize, Sample>, ptr: *mut u8) -> Option<Sample> {
+    let sample = samples.remove(&(ptr as usize));
+
+    if sample.is_some() {
+        SAMPLED[bucket(ptr)].fetch_sub(1, Ordering::Relaxed);
+    }
+
+    sample
+}
+
+// Returns every live sample with its estimated number of blocks and bytes
+fn snapshot() -> Vec<(Arc<Vec<Frame>>, u64, u64)> {
+    let interval = sampling_interval() as f64;
+
+    reentrancy::guarded(&BUSY, || samples(|samples| {
+        samples.values()
+            .map(|sample| {
+                // A block of `size` bytes was sampled with a probability of
+                // `1 - exp(-size / interval)`
+                let size = sample.size.max(1) as f64;
+                let weight = 1.0 / (1.0 - (-size / interval).exp());
+                (sample.stack.clone(), weight.round() as u64, (size * weight).round() as u64)
+            })
+            .collect()
+    })).unwrap_or_default()
+}
+
+// Captures the stack of the current allocation, innermost frame first
+fn capture() -> Vec<Frame> {
+    let backtrace = Backtrace::force_capture().to_string();
+    let mut frames: Vec<Frame> = Vec::new();
+
+    for line in backtrace.lines() {
+        let line = line.trim();
+
+        if let Some(location) = line.strip_prefix("at ") {
+            // Paths may contain colons themselves, on Windows
+            let mut parts = location.rsplitn(3, ':');
+            let _column = parts.next();
+            let line = parts.next().and_then(|line| line.parse().ok()).unwrap_or(0);
+
+            if let (Some(frame), Some(file)) = (frames.last_mut(), parts.next()) {
+                frame.file = file.into();
+                frame.line = line;
+            }
+        } else if let Some((index, function)) = line.split_once(": ") {
+            if index.bytes().all(|b| b.is_ascii_digit()) {
+                frames.push(Frame { function: function.into(), file: String::new(), line: 0 });
+            }
+        }
+    }
+
+    // Drop the frames of the backtrace machinery and the profiler
+    let internal = frames.iter()
+        .rposition(|frame| frame.function.starts_with("stable_heap::profile::record_"))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    frames.drain(..internal);
+    frames
+}
+
+fn samples<F, R>(f: F) -> R
+    where F: FnOnce(&mut HashMap<usize, Sample>) -> R
+{
+    let mut samples = SAMPLES.lock().unwrap_or_else(|e| e.into_inner());
+    f(samples.get_or_insert_with(HashMap::new))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{next_interval, write_collapsed, write_pprof};
+    use std::string::String;
+    use std::vec::Vec;
+
+    fn collapsed() -> String {
+        let mut out = Vec::new();
+        write_collapsed(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const SIZE: usize = 64 << 20;
+
+    fn allocate_sampled() -> *mut u8 {
+        // So much larger than the interval that it is always sampled
+        unsafe { ::allocate(SIZE, 8) }
+    }
+
+    #[test]
+    fn test_collapsed() {
+        let ptr = allocate_sampled();
+
+        let find = || {
+            collapsed().lines()
+                .find(|line| line.contains("test_collapsed") && line.contains("allocate_sampled"))
+                .map(String::from)
+        };
+
+        // Outermost frame first, then the estimated bytes
+        let line = find().unwrap();
+        let stack = line.rsplit_once(' ').unwrap().0;
+        assert!(stack.find("test_collapsed").unwrap() < stack.find("allocate_sampled").unwrap());
+        assert!(line.ends_with(&::std::format!(" {}", SIZE)));
+
+        unsafe {
+            // Resizing keeps the original stack
+            let ptr = ::reallocate(ptr, SIZE, SIZE * 2, 8);
+            assert!(find().unwrap().ends_with(&::std::format!(" {}", SIZE * 2)));
+
+            ::deallocate(ptr, SIZE * 2, 8);
+        }
+
+        assert_eq!(None, find());
+    }
+
+    #[test]
+    fn test_sampling_intervals() {
+        let n = 100_000;
+        let mean = (0..n).map(|_| next_interval(1000) as f64).sum::<f64>() / n as f64;
+        assert!((mean - 1000.0).abs() < 20.0, "{}", mean);
+    }
+
+    #[test]
+    fn test_pprof() {
+        let ptr = allocate_sampled();
+
+        let mut out = Vec::new();
+        write_pprof(&mut out).unwrap();
+
+        // Starts with the first sample type
+        assert_eq!(0x0a, out[0]);
+
+        let contains = |s: &str| out.windows(s.len()).any(|w| w == s.as_bytes());
+        assert!(contains("inuse_space"));
+        assert!(contains("allocate_sampled"));
+        assert!(contains("test_pprof"));
+
+        unsafe { ::deallocate(ptr, SIZE, 8) };
+    }
+}
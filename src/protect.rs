@@ -0,0 +1,77 @@
+#[cfg(feature = "std")]
+use sys;
+
+#[cfg(feature = "std")]
+use std::io;
+
+/// The access allowed to a range of pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protection {
+    /// Any access faults.
+    NoAccess,
+
+    /// The pages can be read.
+    ReadOnly,
+
+    /// The pages can be read and written.
+    ReadWrite,
+
+    /// The pages can be read and executed.
+    ReadExecute,
+}
+
+/// Changes the protection of the pages in `ptr..ptr + len`.
+///
+/// Protection applies to whole pages, so `ptr` must be aligned to the page
+/// size and every page overlapping the range is affected. This is meant for
+/// memory obtained from the page based allocation functions, such as
+/// `allocate_huge` or the `mmap` backend; changing the protection of memory
+/// shared with other heap blocks will fault unrelated code.
+///
+/// # Safety
+///
+/// The range must be mapped, and no live references into it may be used in
+/// a way the new protection forbids.
+#[cfg(feature = "std")]
+pub unsafe fn protect(ptr: *mut u8, len: usize, prot: Protection) -> io::Result<()> {
+    if sys::protect(ptr, len, prot) {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use {protect, sys, Protection};
+
+    #[test]
+    fn test_protect() {
+        let page = sys::page_size();
+
+        unsafe {
+            let ptr = sys::map_anonymous(page, 0);
+            assert!(!ptr.is_null());
+            *ptr = 1;
+
+            protect(ptr, page, Protection::ReadOnly).unwrap();
+            assert_eq!(1, *ptr);
+
+            protect(ptr, page, Protection::ReadWrite).unwrap();
+            *ptr = 2;
+
+            sys::unmap(ptr, page);
+        }
+    }
+
+    #[test]
+    fn test_protect_unaligned() {
+        let page = sys::page_size();
+
+        unsafe {
+            let ptr = sys::map_anonymous(page, 0);
+            assert!(protect(ptr.add(1), 1, Protection::ReadOnly).is_err());
+            sys::unmap(ptr, page);
+        }
+    }
+}
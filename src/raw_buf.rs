@@ -0,0 +1,210 @@
+use {AllocError, Layout};
+
+use core::mem;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// A buffer of uninitialized memory for values of type `T`.
+///
+/// `RawBuf` handles the allocation side of a collection: growing the
+/// capacity, checking the size arithmetic for overflow, reallocating, and
+/// releasing the memory on drop. It doesn't track which slots are
+/// initialized, so dropping it never drops any values; that is left to the
+/// collection built on top.
+///
+/// Zero-sized types never allocate and have a capacity of `usize::MAX`.
+pub struct RawBuf<T> {
+    ptr: NonNull<T>,
+    cap: usize,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for RawBuf<T> {}
+unsafe impl<T: Sync> Sync for RawBuf<T> {}
+
+impl<T> RawBuf<T> {
+    /// Creates a buffer without allocating.
+    pub fn new() -> RawBuf<T> {
+        let cap = if mem::size_of::<T>() == 0 { usize::MAX } else { 0 };
+
+        RawBuf {
+            ptr: NonNull::dangling(),
+            cap,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a buffer with room for exactly `cap` values.
+    pub fn with_capacity(cap: usize) -> Result<RawBuf<T>, AllocError> {
+        let mut buf = RawBuf::new();
+        buf.grow_to(cap)?;
+        Ok(buf)
+    }
+
+    /// Returns a pointer to the start of the buffer.
+    ///
+    /// The pointer is dangling if the capacity is 0 or `T` is zero-sized.
+    pub fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the number of values the buffer has room for.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Ensures the buffer has room for `used + additional` values, growing
+    /// the capacity geometrically to amortize reallocation.
+    ///
+    /// The first `used` values are preserved when the buffer moves. Returns
+    /// `AllocError::InvalidLayout` if the capacity overflows. On failure the
+    /// buffer is left intact.
+    pub fn reserve(&mut self, used: usize, additional: usize) -> Result<(), AllocError> {
+        let required = used.checked_add(additional).ok_or(AllocError::InvalidLayout)?;
+
+        if required <= self.cap {
+            return Ok(());
+        }
+
+        let cap = ::growth::amortized_grow(self.cap, required - self.cap, mem::size_of::<T>())?;
+        self.grow_to(cap)
+    }
+
+    /// Ensures the buffer has room for exactly `used + additional` values.
+    ///
+    /// Like `reserve`, but doesn't allocate more than requested.
+    pub fn reserve_exact(&mut self, used: usize, additional: usize) -> Result<(), AllocError> {
+        let required = used.checked_add(additional).ok_or(AllocError::InvalidLayout)?;
+
+        if required <= self.cap {
+            return Ok(());
+        }
+
+        self.grow_to(required)
+    }
+
+    /// Shrinks the capacity to `cap` values, releasing the memory if `cap` is
+    /// 0.
+    ///
+    /// Has no effect if the capacity is already no larger than `cap`.
+    pub fn shrink_to(&mut self, cap: usize) -> Result<(), AllocError> {
+        if cap >= self.cap || mem::size_of::<T>() == 0 {
+            return Ok(());
+        }
+
+        if cap == 0 {
+            unsafe { ::release(self.ptr.cast(), self.layout()) };
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+            return Ok(());
+        }
+
+        let new_size = cap * mem::size_of::<T>();
+        self.ptr = unsafe { ::try_reallocate(self.ptr.cast(), self.layout(), new_size)?.cast() };
+        self.cap = cap;
+
+        Ok(())
+    }
+
+    fn grow_to(&mut self, cap: usize) -> Result<(), AllocError> {
+        if cap <= self.cap {
+            return Ok(());
+        }
+
+        let new_layout = Layout::array::<T>(cap)?;
+
+        let ptr = unsafe {
+            if self.cap == 0 {
+                ::try_allocate(new_layout)?
+            } else {
+                ::try_reallocate(self.ptr.cast(), self.layout(), new_layout.size())?
+            }
+        };
+
+        self.ptr = ptr.cast();
+        self.cap = cap;
+
+        Ok(())
+    }
+
+    // Only called with an allocated, non zero-sized buffer
+    fn layout(&self) -> Layout {
+        unsafe { Layout::from_size_align_unchecked(self.cap * mem::size_of::<T>(), mem::align_of::<T>()) }
+    }
+}
+
+impl<T> Default for RawBuf<T> {
+    fn default() -> RawBuf<T> {
+        RawBuf::new()
+    }
+}
+
+impl<T> ::core::fmt::Debug for RawBuf<T> {
+    fn fmt(&self, fmt: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        fmt.debug_struct("RawBuf")
+            .field("ptr", &self.ptr)
+            .field("cap", &self.cap)
+            .finish()
+    }
+}
+
+impl<T> Drop for RawBuf<T> {
+    fn drop(&mut self) {
+        if self.cap != 0 && mem::size_of::<T>() != 0 {
+            unsafe { ::release(self.ptr.cast(), self.layout()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AllocError, RawBuf};
+    use std::ptr;
+
+    #[test]
+    fn test_raw_buf_grow() {
+        let mut buf = RawBuf::<u32>::new();
+        assert_eq!(0, buf.capacity());
+
+        buf.reserve(0, 1).unwrap();
+        assert_eq!(4, buf.capacity());
+
+        for i in 0..4 {
+            unsafe { ptr::write(buf.ptr().add(i), i as u32) };
+        }
+
+        buf.reserve(4, 1).unwrap();
+        assert_eq!(8, buf.capacity());
+
+        buf.reserve_exact(4, 100).unwrap();
+        assert_eq!(104, buf.capacity());
+
+        for i in 0..4 {
+            assert_eq!(i as u32, unsafe { *buf.ptr().add(i) });
+        }
+
+        buf.shrink_to(4).unwrap();
+        assert_eq!(4, buf.capacity());
+        assert_eq!(3, unsafe { *buf.ptr().add(3) });
+
+        buf.shrink_to(0).unwrap();
+        assert_eq!(0, buf.capacity());
+    }
+
+    #[test]
+    fn test_raw_buf_overflow() {
+        let mut buf = RawBuf::<u64>::with_capacity(1).unwrap();
+        assert_eq!(Err(AllocError::InvalidLayout), buf.reserve(1, usize::MAX));
+        assert_eq!(Err(AllocError::InvalidLayout), buf.reserve_exact(0, usize::MAX / 2));
+        assert_eq!(1, buf.capacity());
+    }
+
+    #[test]
+    fn test_raw_buf_zero_sized() {
+        let mut buf = RawBuf::<()>::new();
+        assert_eq!(usize::MAX, buf.capacity());
+        buf.reserve(100, 100).unwrap();
+        buf.shrink_to(0).unwrap();
+        assert_eq!(usize::MAX, buf.capacity());
+    }
+}
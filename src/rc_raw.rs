@@ -0,0 +1,247 @@
+//! Reference-counted buffers.
+//!
+//! A buffer is a block of memory prefixed with a header holding its
+//! reference count and layout, the way `Rc` and `Arc` lay out their values.
+//! Buffers are referred to by a pointer to their payload, which is what
+//! shared byte buffers hand around; the header is found right in front of it.
+//!
+//! The count is a `Cell<usize>` for buffers used by one thread, or an
+//! `AtomicUsize` for buffers shared between threads. The functions take it as
+//! a type parameter, which must be the same for every call on a buffer.
+
+use {AllocError, Layout};
+
+use core::{mem, ptr, slice};
+use core::cell::Cell;
+use core::ptr::NonNull;
+use core::sync::atomic::{self, AtomicUsize, Ordering};
+
+/// A reference count stored in a buffer's header.
+///
+/// # Safety
+///
+/// `decref` must return `true` exactly once, when the last reference is
+/// dropped, and only after every access made through the other references.
+pub unsafe trait RefCount {
+    /// Returns a count of 1.
+    fn one() -> Self;
+
+    /// Adds a reference.
+    fn incref(&self);
+
+    /// Drops a reference, returning `true` if it was the last one.
+    fn decref(&self) -> bool;
+
+    /// Returns the number of references.
+    fn get(&self) -> usize;
+}
+
+unsafe impl RefCount for Cell<usize> {
+    fn one() -> Cell<usize> {
+        Cell::new(1)
+    }
+
+    fn incref(&self) {
+        self.set(self.get().checked_add(1).expect("reference count overflow"));
+    }
+
+    fn decref(&self) -> bool {
+        self.set(self.get() - 1);
+        self.get() == 0
+    }
+
+    fn get(&self) -> usize {
+        Cell::get(self)
+    }
+}
+
+unsafe impl RefCount for AtomicUsize {
+    fn one() -> AtomicUsize {
+        AtomicUsize::new(1)
+    }
+
+    fn incref(&self) {
+        // New references are made from existing ones, so no ordering is
+        // needed. Leave enough room for concurrent increments to be undone.
+        if self.fetch_add(1, Ordering::Relaxed) > isize::MAX as usize {
+            self.fetch_sub(1, Ordering::Relaxed);
+            panic!("reference count overflow");
+        }
+    }
+
+    fn decref(&self) -> bool {
+        if self.fetch_sub(1, Ordering::Release) != 1 {
+            return false;
+        }
+
+        // Synchronize with the accesses made before the other decrements
+        atomic::fence(Ordering::Acquire);
+        true
+    }
+
+    fn get(&self) -> usize {
+        self.load(Ordering::Acquire)
+    }
+}
+
+// Placed right before the payload
+struct Header<C> {
+    count: C,
+    size: usize,
+    align: usize,
+}
+
+/// Return a pointer to the payload of a new buffer fitting `payload`, with a
+/// reference count of 1.
+///
+/// The payload is uninitialized. Errors are reported the same way as
+/// `allocate_prefixed`.
+///
+/// # Safety
+///
+/// The buffer is released by the `decref` dropping its count to 0.
+pub unsafe fn allocate<C: RefCount>(payload: Layout) -> Result<NonNull<u8>, AllocError> {
+    let (_, data) = ::allocate_prefixed(Layout::new::<Header<C>>(), payload)?;
+
+    ptr::write(header::<C>(data), Header {
+        count: C::one(),
+        size: payload.size(),
+        align: payload.align(),
+    });
+
+    Ok(data)
+}
+
+/// Adds a reference to the buffer whose payload starts at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be the payload pointer of a live buffer allocated with the
+/// same `C`.
+///
+/// # Panics
+///
+/// Panics if the count overflows.
+pub unsafe fn incref<C: RefCount>(ptr: NonNull<u8>) {
+    (*header::<C>(ptr)).count.incref()
+}
+
+/// Drops a reference to the buffer whose payload starts at `ptr`, releasing
+/// the buffer if it was the last one.
+///
+/// Returns `true` if the buffer was released. The payload isn't dropped; it
+/// is plain memory as far as the buffer is concerned.
+///
+/// # Safety
+///
+/// `ptr` must be the payload pointer of a live buffer allocated with the
+/// same `C`, and the reference dropped must not be used afterwards.
+pub unsafe fn decref<C: RefCount>(ptr: NonNull<u8>) -> bool {
+    let header = header::<C>(ptr);
+
+    if !(*header).count.decref() {
+        return false;
+    }
+
+    let payload = Layout::from_size_align_unchecked((*header).size, (*header).align);
+    ptr::drop_in_place(header);
+
+    let block = ::prefixed_header(ptr, Layout::new::<Header<C>>(), payload);
+    ::deallocate_prefixed(block, Layout::new::<Header<C>>(), payload);
+
+    true
+}
+
+/// Returns the number of references to the buffer whose payload starts at
+/// `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be the payload pointer of a live buffer allocated with the
+/// same `C`.
+pub unsafe fn count<C: RefCount>(ptr: NonNull<u8>) -> usize {
+    (*header::<C>(ptr)).count.get()
+}
+
+/// Returns the size of the payload of the buffer starting at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be the payload pointer of a live buffer allocated with the
+/// same `C`.
+pub unsafe fn len<C: RefCount>(ptr: NonNull<u8>) -> usize {
+    (*header::<C>(ptr)).size
+}
+
+/// Returns the payload of the buffer starting at `ptr` as a slice.
+///
+/// # Safety
+///
+/// `ptr` must be the payload pointer of a live buffer allocated with the
+/// same `C`, its payload must be initialized, and the buffer must outlive
+/// `'a` without the payload being written to.
+pub unsafe fn from_raw_parts<'a, C: RefCount>(ptr: NonNull<u8>) -> &'a [u8] {
+    slice::from_raw_parts(ptr.as_ptr(), len::<C>(ptr))
+}
+
+// The header is in front of the payload whatever its alignment, see
+// `allocate_prefixed`
+fn header<C>(ptr: NonNull<u8>) -> *mut Header<C> {
+    ptr.as_ptr().wrapping_sub(mem::size_of::<Header<C>>()) as *mut Header<C>
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate, count, decref, from_raw_parts, incref, len};
+    use Layout;
+    use std::cell::Cell;
+    use std::ptr;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn test_local_count() {
+        unsafe {
+            let ptr = allocate::<Cell<usize>>(Layout::from_size_align(5, 1).unwrap()).unwrap();
+            ptr::copy_nonoverlapping(b"hello".as_ptr(), ptr.as_ptr(), 5);
+
+            assert_eq!(1, count::<Cell<usize>>(ptr));
+            assert_eq!(5, len::<Cell<usize>>(ptr));
+            assert_eq!(b"hello", from_raw_parts::<Cell<usize>>(ptr));
+
+            incref::<Cell<usize>>(ptr);
+            assert_eq!(2, count::<Cell<usize>>(ptr));
+
+            assert!(!decref::<Cell<usize>>(ptr));
+            assert!(decref::<Cell<usize>>(ptr));
+        }
+    }
+
+    #[test]
+    fn test_atomic_count_aligned_payload() {
+        unsafe {
+            let ptr = allocate::<AtomicUsize>(Layout::from_size_align(64, 64).unwrap()).unwrap();
+            assert_eq!(0, ptr.as_ptr() as usize % 64);
+            ptr::write_bytes(ptr.as_ptr(), 3, 64);
+
+            for _ in 0..8 {
+                incref::<AtomicUsize>(ptr);
+            }
+
+            let addr = ptr.as_ptr() as usize;
+
+            let threads: ::std::vec::Vec<_> = (0..8).map(|_| thread::spawn(move || {
+                let ptr = ::std::ptr::NonNull::new(addr as *mut u8).unwrap();
+                assert_eq!(3, from_raw_parts::<AtomicUsize>(ptr)[63]);
+                decref::<AtomicUsize>(ptr)
+            })).collect();
+
+            for thread in threads {
+                assert!(!thread.join().unwrap());
+            }
+
+            assert_eq!(1, count::<AtomicUsize>(ptr));
+            assert!(decref::<AtomicUsize>(ptr));
+        }
+    }
+}
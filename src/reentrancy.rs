@@ -0,0 +1,26 @@
+//! Reentrancy guard for the instrumentation of the heap allocation functions.
+
+use std::cell::Cell;
+use std::thread::LocalKey;
+
+/// Runs `f` with the thread's `busy` flag set, so that the allocations `f`
+/// makes are skipped by the instrumentation it belongs to.
+///
+/// Returns `None` without calling `f` if the flag is already set, or if the
+/// thread is exiting and its locals are gone. Each instrumentation module has
+/// its own flag, so that blocks allocated by one are still seen by the
+/// others.
+pub(crate) fn guarded<F, R>(busy: &'static LocalKey<Cell<bool>>, f: F) -> Option<R>
+    where F: FnOnce() -> R
+{
+    let entered = busy.try_with(|busy| !busy.replace(true)).unwrap_or(false);
+
+    if !entered {
+        return None;
+    }
+
+    let ret = f();
+
+    let _ = busy.try_with(|busy| busy.set(false));
+    Some(ret)
+}
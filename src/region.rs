@@ -0,0 +1,228 @@
+use {round_up, sys, AllocError};
+
+use core::ops::Range;
+use core::ptr;
+
+/// A reserved range of address space whose pages are committed on demand.
+///
+/// Reserving address space doesn't consume memory. Pages become usable once
+/// committed and are returned to the system when decommitted, but the
+/// addresses stay reserved, so memory in the region never moves. This
+/// allows reserving far more than will ever be used, e.g. 1 GiB, and
+/// committing it as a buffer grows.
+///
+/// Committed pages are zeroed, including pages that are committed again
+/// after being decommitted. Accessing memory that is not committed faults.
+#[derive(Debug)]
+pub struct VirtualRegion {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for VirtualRegion {}
+unsafe impl Sync for VirtualRegion {}
+
+impl VirtualRegion {
+    /// Reserves `max_size` bytes of address space, rounded up to the page
+    /// size. No memory is committed.
+    pub fn reserve(max_size: usize) -> Result<VirtualRegion, AllocError> {
+        let page = sys::page_size();
+
+        if max_size == 0 || max_size > isize::MAX as usize - (page - 1) {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let len = round_up(max_size, page);
+        let ptr = unsafe { reserve(len) };
+
+        if ptr.is_null() {
+            return Err(AllocError::OutOfMemory);
+        }
+
+        Ok(VirtualRegion { ptr, len })
+    }
+
+    /// Returns a pointer to the start of the region.
+    ///
+    /// The pointer is aligned to the page size.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the number of bytes reserved.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes are reserved.
+    ///
+    /// Always `false`, as `reserve` rejects a `max_size` of 0.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Commits the pages overlapping `range`, given as byte offsets from the
+    /// start of the region, making them readable and writable.
+    ///
+    /// Committing pages that already are is a no-op for those pages and
+    /// leaves their contents intact.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds of the region.
+    pub fn commit(&mut self, range: Range<usize>) -> Result<(), AllocError> {
+        self.check(&range);
+
+        let page = sys::page_size();
+        let start = range.start & !(page - 1);
+        let end = round_up(range.end, page);
+
+        if start >= end {
+            return Ok(());
+        }
+
+        if unsafe { commit(self.ptr.add(start), end - start) } {
+            Ok(())
+        } else {
+            Err(AllocError::OutOfMemory)
+        }
+    }
+
+    /// Decommits the pages fully contained in `range`, given as byte offsets
+    /// from the start of the region, returning their memory to the system.
+    ///
+    /// Pages only partially covered by `range` are left committed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds of the region.
+    pub fn decommit(&mut self, range: Range<usize>) {
+        self.check(&range);
+
+        let page = sys::page_size();
+        let start = round_up(range.start, page);
+        let end = range.end & !(page - 1);
+
+        if start >= end {
+            return;
+        }
+
+        unsafe { decommit(self.ptr.add(start), end - start) }
+    }
+
+    fn check(&self, range: &Range<usize>) {
+        if range.start > range.end || range.end > self.len {
+            panic!("range {:?} out of bounds of region of {} bytes", range, self.len);
+        }
+    }
+}
+
+impl Drop for VirtualRegion {
+    fn drop(&mut self) {
+        unsafe { release(self.ptr, self.len) }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RESERVE_FLAGS: sys::c_int = sys::MAP_PRIVATE | sys::MAP_ANONYMOUS | sys::MAP_NORESERVE;
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const RESERVE_FLAGS: sys::c_int = sys::MAP_PRIVATE | sys::MAP_ANONYMOUS;
+
+#[cfg(unix)]
+unsafe fn reserve(len: usize) -> *mut u8 {
+    let ptr = sys::mmap(ptr::null_mut(), len, sys::PROT_NONE, RESERVE_FLAGS, -1, 0);
+
+    if ptr == sys::MAP_FAILED {
+        return ptr::null_mut();
+    }
+
+    ptr as *mut u8
+}
+
+#[cfg(unix)]
+unsafe fn commit(ptr: *mut u8, len: usize) -> bool {
+    sys::mprotect(ptr as *mut sys::c_void, len, sys::PROT_READ | sys::PROT_WRITE) == 0
+}
+
+#[cfg(unix)]
+unsafe fn decommit(ptr: *mut u8, len: usize) {
+    // Mapping fresh inaccessible pages over the range drops the old ones
+    sys::mmap(ptr as *mut sys::c_void, len, sys::PROT_NONE, RESERVE_FLAGS | sys::MAP_FIXED, -1, 0);
+}
+
+#[cfg(unix)]
+unsafe fn release(ptr: *mut u8, len: usize) {
+    sys::unmap(ptr, len)
+}
+
+#[cfg(windows)]
+unsafe fn reserve(len: usize) -> *mut u8 {
+    sys::VirtualAlloc(ptr::null_mut(), len, sys::MEM_RESERVE, sys::PAGE_NOACCESS) as *mut u8
+}
+
+#[cfg(windows)]
+unsafe fn commit(ptr: *mut u8, len: usize) -> bool {
+    !sys::VirtualAlloc(ptr as sys::LPVOID, len, sys::MEM_COMMIT, sys::PAGE_READWRITE).is_null()
+}
+
+#[cfg(windows)]
+unsafe fn decommit(ptr: *mut u8, len: usize) {
+    sys::VirtualFree(ptr as sys::LPVOID, len, sys::MEM_DECOMMIT);
+}
+
+#[cfg(windows)]
+unsafe fn release(ptr: *mut u8, _len: usize) {
+    sys::unmap(ptr, 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::VirtualRegion;
+    use sys;
+
+    #[test]
+    fn test_reserve_commit() {
+        let page = sys::page_size();
+        let mut region = VirtualRegion::reserve(1 << 30).unwrap();
+
+        assert_eq!(1 << 30, region.len());
+        assert_eq!(0, region.as_ptr() as usize & (page - 1));
+
+        // Commits the first two pages
+        region.commit(10..page + 1).unwrap();
+
+        unsafe {
+            let ptr = region.as_ptr();
+
+            for i in 0..2 * page {
+                assert_eq!(0, *ptr.add(i));
+                *ptr.add(i) = 1;
+            }
+
+            // Committing again keeps the contents
+            region.commit(0..page).unwrap();
+            assert_eq!(1, *ptr);
+
+            // Only the second page is fully covered
+            region.decommit(1..2 * page);
+            assert_eq!(1, *ptr.add(page - 1));
+
+            region.commit(page..2 * page).unwrap();
+            assert_eq!(0, *ptr.add(page));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_commit_out_of_bounds() {
+        let mut region = VirtualRegion::reserve(1).unwrap();
+        let len = region.len();
+        let _ = region.commit(0..len + 1);
+    }
+
+    #[test]
+    fn test_reserve_zero() {
+        assert!(VirtualRegion::reserve(0).is_err());
+    }
+}
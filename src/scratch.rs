@@ -0,0 +1,80 @@
+use {Allocation, Layout};
+
+use core::{ptr, slice};
+use core::mem::MaybeUninit;
+
+// Buffers up to this size live on the stack
+const INLINE_SIZE: usize = 1024;
+
+const ALIGN: usize = 16;
+
+#[repr(C, align(16))]
+struct Inline([MaybeUninit<u8>; INLINE_SIZE]);
+
+/// Calls `f` with a zeroed scratch buffer of `size` bytes.
+///
+/// Buffers of up to 1 KiB are placed on the stack, so small temporaries
+/// don't go through the allocator. Larger buffers are allocated from the heap
+/// and released when `f` returns. Either way the buffer is aligned to 16
+/// bytes.
+///
+/// # Panics
+///
+/// Panics if a heap buffer is needed and can't be allocated.
+pub fn with_scratch<F, R>(size: usize, f: F) -> R
+    where F: FnOnce(&mut [u8]) -> R
+{
+    if size <= INLINE_SIZE {
+        let mut inline = Inline([MaybeUninit::uninit(); INLINE_SIZE]);
+
+        let buf = unsafe {
+            let ptr = inline.0.as_mut_ptr() as *mut u8;
+            ptr::write_bytes(ptr, 0, size);
+            slice::from_raw_parts_mut(ptr, size)
+        };
+
+        return f(buf);
+    }
+
+    let heap = Layout::from_size_align(size, ALIGN)
+        .and_then(Allocation::zeroed);
+
+    match heap {
+        Ok(heap) => {
+            let buf = unsafe { slice::from_raw_parts_mut(heap.as_ptr(), size) };
+            f(buf)
+        }
+        Err(e) => panic!("failed to allocate scratch buffer: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use with_scratch;
+
+    #[test]
+    fn test_inline() {
+        let sum = with_scratch(100, |buf| {
+            assert_eq!(100, buf.len());
+            assert_eq!(0, buf.as_ptr() as usize % 16);
+            assert!(buf.iter().all(|&b| b == 0));
+
+            buf[99] = 5;
+            buf.iter().map(|&b| b as u32).sum::<u32>()
+        });
+
+        assert_eq!(5, sum);
+
+        assert!(with_scratch(0, |buf| buf.is_empty()));
+    }
+
+    #[test]
+    fn test_heap() {
+        with_scratch(1 << 20, |buf| {
+            assert_eq!(1 << 20, buf.len());
+            assert_eq!(0, buf.as_ptr() as usize % 16);
+            assert!(buf.iter().all(|&b| b == 0));
+            buf[(1 << 20) - 1] = 1;
+        });
+    }
+}
@@ -0,0 +1,131 @@
+//! Memory for holding secrets.
+//!
+//! Blocks returned by this module are locked into physical memory so they are
+//! never written to swap, are excluded from core dumps where the platform
+//! supports it, and are zeroed before being returned to the system.
+//!
+//! Locking works at page granularity and is limited by the process' locked
+//! memory limit, so this is meant for small amounts of key material rather
+//! than general purpose storage.
+
+use {round_up, sys};
+
+use core::ptr;
+
+/// Return a pointer to `size` bytes of zeroed memory that is locked into
+/// physical memory.
+///
+/// The memory is aligned to the page size and rounded up to a multiple of it.
+/// On Linux, the pages are also excluded from core dumps.
+///
+/// On failure, including when the pages can't be locked, return a null
+/// pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_locked` using the same
+/// `size`.
+pub unsafe fn allocate_locked(size: usize) -> *mut u8 {
+    let page = sys::page_size();
+
+    if size == 0 || size > isize::MAX as usize - (page - 1) {
+        return ptr::null_mut();
+    }
+
+    let len = round_up(size, page);
+    let ptr = sys::map_anonymous(len, 0);
+
+    if ptr.is_null() {
+        return ptr;
+    }
+
+    if !lock(ptr, len) {
+        sys::unmap(ptr, len);
+        return ptr::null_mut();
+    }
+
+    exclude_from_dump(ptr, len);
+
+    ptr
+}
+
+/// Zeroes and deallocates memory obtained from `allocate_locked`.
+///
+/// The whole mapping is overwritten, including the rounding past `size`, in
+/// a way the compiler can't elide.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_locked` with the same `size`.
+pub unsafe fn deallocate_locked(ptr: *mut u8, size: usize) {
+    let len = round_up(size, sys::page_size());
+
+    ::zero_volatile(ptr, len);
+    unlock(ptr, len);
+    sys::unmap(ptr, len)
+}
+
+#[cfg(unix)]
+unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+    sys::mlock(ptr as *const sys::c_void, len) == 0
+}
+
+#[cfg(unix)]
+unsafe fn unlock(ptr: *mut u8, len: usize) {
+    sys::munlock(ptr as *const sys::c_void, len);
+}
+
+#[cfg(windows)]
+unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+    sys::VirtualLock(ptr as sys::LPVOID, len) != 0
+}
+
+#[cfg(windows)]
+unsafe fn unlock(ptr: *mut u8, len: usize) {
+    sys::VirtualUnlock(ptr as sys::LPVOID, len);
+}
+
+// Best effort: a block that still ends up in a dump is zeroed on free
+// either way.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn exclude_from_dump(ptr: *mut u8, len: usize) {
+    sys::madvise(ptr as *mut sys::c_void, len, sys::MADV_DONTDUMP);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn exclude_from_dump(_ptr: *mut u8, _len: usize) {
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_locked, deallocate_locked};
+    use sys;
+
+    #[test]
+    fn test_allocate_locked() {
+        unsafe {
+            let ptr = allocate_locked(32);
+
+            // Locking may be denied by the memory limit
+            if ptr.is_null() {
+                return;
+            }
+
+            assert_eq!(0, ptr as usize & (sys::page_size() - 1));
+
+            for i in 0..32 {
+                assert_eq!(0, *ptr.add(i));
+                *ptr.add(i) = 0xAA;
+            }
+
+            deallocate_locked(ptr, 32);
+        }
+    }
+
+    #[test]
+    fn test_allocate_locked_zero_size() {
+        unsafe {
+            assert!(allocate_locked(0).is_null());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use {rc_raw, AllocError, Layout};
+
+use alloc::vec::Vec;
+use core::{fmt, mem, ops, ptr, slice};
+use core::ops::{Bound, RangeBounds};
+use core::ptr::NonNull;
+use core::sync::atomic::AtomicUsize;
+
+/// A cheaply cloneable and sliceable buffer of immutable bytes.
+///
+/// Clones and slices share the same memory, kept in a reference-counted
+/// buffer, which is released when the last of them is dropped. Slicing
+/// narrows the view without copying, so a buffer read from a socket can be
+/// split into frames that are handed to different threads.
+///
+/// Bytes copied in live in the buffer itself. A `Vec<u8>` converted with
+/// `From` is taken over without copying its contents, and its memory is
+/// released through `Vec` once unused.
+pub struct SharedBytes {
+    ptr: *const u8,
+    len: usize,
+
+    // The payload of the shared buffer, or `None` if empty
+    shared: Option<NonNull<u8>>,
+}
+
+unsafe impl Send for SharedBytes {}
+unsafe impl Sync for SharedBytes {}
+
+// The start of the shared buffer's payload
+struct Storage {
+    // The vector holding the bytes, or null if they follow the storage
+    vec_ptr: *mut u8,
+    vec_cap: usize,
+}
+
+impl SharedBytes {
+    /// Creates an empty buffer without allocating.
+    pub fn new() -> SharedBytes {
+        SharedBytes {
+            ptr: ::dangling(1).as_ptr(),
+            len: 0,
+            shared: None,
+        }
+    }
+
+    /// Creates a buffer holding a copy of `src`.
+    ///
+    /// Errors are reported the same way as `try_allocate`.
+    pub fn try_copy_from_slice(src: &[u8]) -> Result<SharedBytes, AllocError> {
+        if src.is_empty() {
+            return Ok(SharedBytes::new());
+        }
+
+        let payload = Layout::new::<Storage>().extend(Layout::for_value(src))?;
+
+        unsafe {
+            let shared = allocate(payload.0)?;
+
+            ptr::write(shared.as_ptr() as *mut Storage, Storage { vec_ptr: ptr::null_mut(), vec_cap: 0 });
+
+            let data = shared.as_ptr().add(payload.1);
+            ptr::copy_nonoverlapping(src.as_ptr(), data, src.len());
+
+            Ok(SharedBytes { ptr: data, len: src.len(), shared: Some(shared) })
+        }
+    }
+
+    /// Creates a buffer holding a copy of `src`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer can't be allocated.
+    pub fn copy_from_slice(src: &[u8]) -> SharedBytes {
+        SharedBytes::try_copy_from_slice(src).expect("failed to allocate shared bytes")
+    }
+
+    /// Returns the number of bytes in the view.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the view is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a view of `range` of these bytes, sharing their memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> SharedBytes {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1).expect("range start overflows"),
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1).expect("range end overflows"),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+
+        if start > end || end > self.len {
+            panic!("range {}..{} out of bounds of {} bytes", start, end, self.len);
+        }
+
+        let mut view = self.clone();
+        view.ptr = unsafe { self.ptr.add(start) };
+        view.len = end - start;
+        view
+    }
+
+    /// Splits the view in two at `at`, keeping `[0, at)` and returning
+    /// `[at, len)`.
+    ///
+    /// Both halves share the same memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> SharedBytes {
+        let tail = self.slice(at..);
+        self.len = at;
+        tail
+    }
+
+    /// Splits the view in two at `at`, keeping `[at, len)` and returning
+    /// `[0, at)`.
+    ///
+    /// Both halves share the same memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_to(&mut self, at: usize) -> SharedBytes {
+        let head = self.slice(..at);
+        self.ptr = unsafe { self.ptr.add(at) };
+        self.len -= at;
+        head
+    }
+}
+
+unsafe fn allocate(payload: Layout) -> Result<NonNull<u8>, AllocError> {
+    rc_raw::allocate::<AtomicUsize>(payload)
+}
+
+impl Default for SharedBytes {
+    fn default() -> SharedBytes {
+        SharedBytes::new()
+    }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+    fn from(vec: Vec<u8>) -> SharedBytes {
+        if vec.is_empty() {
+            return SharedBytes::new();
+        }
+
+        let mut vec = mem::ManuallyDrop::new(vec);
+
+        unsafe {
+            let shared = allocate(Layout::new::<Storage>()).expect("failed to allocate shared bytes");
+
+            ptr::write(shared.as_ptr() as *mut Storage, Storage {
+                vec_ptr: vec.as_mut_ptr(),
+                vec_cap: vec.capacity(),
+            });
+
+            SharedBytes { ptr: vec.as_ptr(), len: vec.len(), shared: Some(shared) }
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for SharedBytes {
+    fn from(src: &'a [u8]) -> SharedBytes {
+        SharedBytes::copy_from_slice(src)
+    }
+}
+
+impl ops::Deref for SharedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Clone for SharedBytes {
+    fn clone(&self) -> SharedBytes {
+        if let Some(shared) = self.shared {
+            unsafe { rc_raw::incref::<AtomicUsize>(shared) }
+        }
+
+        SharedBytes { ptr: self.ptr, len: self.len, shared: self.shared }
+    }
+}
+
+impl PartialEq for SharedBytes {
+    fn eq(&self, other: &SharedBytes) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for SharedBytes {}
+
+impl fmt::Debug for SharedBytes {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SharedBytes")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for SharedBytes {
+    fn drop(&mut self) {
+        let shared = match self.shared {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        unsafe {
+            // The storage can't be read once the buffer is released
+            let storage = ptr::read(shared.as_ptr() as *const Storage);
+
+            if rc_raw::decref::<AtomicUsize>(shared) && !storage.vec_ptr.is_null() {
+                drop(Vec::from_raw_parts(storage.vec_ptr, 0, storage.vec_cap));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use SharedBytes;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_shared_bytes() {
+        let bytes = SharedBytes::copy_from_slice(b"hello world");
+        assert_eq!(b"hello world", &bytes[..]);
+
+        let world = bytes.slice(6..);
+        assert_eq!(b"world", &world[..]);
+        assert_eq!(b"lo w", &bytes.slice(3..=6)[..]);
+        assert!(bytes.slice(4..4).is_empty());
+
+        drop(bytes);
+        assert_eq!(b"world", &world[..]);
+
+        let mut head = world.clone();
+        let tail = head.split_off(2);
+        assert_eq!(b"wo", &head[..]);
+        assert_eq!(b"rld", &tail[..]);
+
+        let mut rest = tail.clone();
+        assert_eq!(b"r", &rest.split_to(1)[..]);
+        assert_eq!(b"ld", &rest[..]);
+        assert_eq!(tail, SharedBytes::from(&b"rld"[..]));
+    }
+
+    #[test]
+    fn test_shared_bytes_from_vec() {
+        let mut vec = Vec::with_capacity(64);
+        vec.extend_from_slice(b"frame one|frame two");
+        let ptr = vec.as_ptr();
+
+        let mut bytes = SharedBytes::from(vec);
+        assert_eq!(ptr, bytes.as_ptr());
+
+        let second = bytes.split_off(10);
+        bytes.split_off(9);
+
+        let handle = thread::spawn(move || second.to_vec());
+        assert_eq!(b"frame two", &handle.join().unwrap()[..]);
+        assert_eq!(b"frame one", &bytes[..]);
+
+        assert!(SharedBytes::from(Vec::new()).is_empty());
+        assert_eq!(SharedBytes::new(), SharedBytes::default());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_shared_bytes_out_of_bounds() {
+        SharedBytes::copy_from_slice(b"abc").slice(2..4);
+    }
+}
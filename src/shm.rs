@@ -0,0 +1,293 @@
+use sys;
+
+use core::ptr;
+
+use std::io;
+
+#[cfg(unix)]
+use std::fs::File;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+#[cfg(windows)]
+use std::os::windows::io::RawHandle;
+
+/// The OS handle backing a `SharedMemory` region.
+///
+/// This is a file descriptor on Unix and a file mapping `HANDLE` on Windows.
+#[cfg(unix)]
+pub type RawSharedHandle = RawFd;
+
+/// The OS handle backing a `SharedMemory` region.
+///
+/// This is a file descriptor on Unix and a file mapping `HANDLE` on Windows.
+#[cfg(windows)]
+pub type RawSharedHandle = RawHandle;
+
+/// A region of memory that can be mapped by multiple processes.
+///
+/// The region is backed by an anonymous shared memory object. Another
+/// process maps the same memory by receiving the handle, over a Unix socket
+/// or with `DuplicateHandle` on Windows, and passing it to
+/// `SharedMemory::from_raw_handle`.
+///
+/// Both the mapping and the handle are released when the value is dropped.
+#[derive(Debug)]
+pub struct SharedMemory {
+    ptr: *mut u8,
+    len: usize,
+    handle: Handle,
+}
+
+unsafe impl Send for SharedMemory {}
+unsafe impl Sync for SharedMemory {}
+
+/// Return `size` bytes of zeroed memory that can be shared with other
+/// processes.
+pub fn allocate_shared(size: usize) -> io::Result<SharedMemory> {
+    if size == 0 || size > isize::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid shared memory size"));
+    }
+
+    unsafe {
+        let handle = create(size)?;
+        let ptr = map(&handle, size)?;
+
+        Ok(SharedMemory { ptr, len: size, handle })
+    }
+}
+
+impl SharedMemory {
+    /// Maps the shared memory object referenced by `handle`.
+    ///
+    /// The returned value takes ownership of the handle.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be an open handle to a region created by
+    /// `allocate_shared`, and `len` must not exceed the size it was created
+    /// with.
+    pub unsafe fn from_raw_handle(handle: RawSharedHandle, len: usize) -> io::Result<SharedMemory> {
+        let handle = from_raw(handle);
+
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid shared memory size"));
+        }
+
+        let ptr = map(&handle, len)?;
+
+        Ok(SharedMemory { ptr, len, handle })
+    }
+
+    /// Returns a pointer to the start of the region.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Returns the length of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the mapping has a length of 0.
+    ///
+    /// Always `false`: both `allocate_shared` and `from_raw_handle` reject
+    /// empty mappings.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the handle to send to another process.
+    ///
+    /// The handle remains owned by `self`.
+    pub fn raw_handle(&self) -> RawSharedHandle {
+        raw(&self.handle)
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe { unmap(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) type Handle = File;
+
+#[cfg(unix)]
+fn raw(handle: &Handle) -> RawSharedHandle {
+    handle.as_raw_fd()
+}
+
+#[cfg(unix)]
+unsafe fn from_raw(handle: RawSharedHandle) -> Handle {
+    File::from_raw_fd(handle)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn create_fd() -> io::Result<File> {
+    let name = b"stable-heap\0";
+    let fd = sys::memfd_create(name.as_ptr() as *const sys::c_char, sys::MFD_CLOEXEC);
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(File::from_raw_fd(fd))
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+unsafe fn create_fd() -> io::Result<File> {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::ffi::CString;
+    use std::process;
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let name = ::std::format!("/stable-heap-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    let name = CString::new(name).unwrap();
+
+    let fd = sys::shm_open(name.as_ptr(), sys::O_RDWR | sys::O_CREAT | sys::O_EXCL, 0o600 as sys::c_uint);
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The object lives on through the descriptor
+    sys::shm_unlink(name.as_ptr());
+
+    Ok(File::from_raw_fd(fd))
+}
+
+#[cfg(unix)]
+pub(crate) unsafe fn create(size: usize) -> io::Result<Handle> {
+    let file = create_fd()?;
+    file.set_len(size as u64)?;
+    Ok(file)
+}
+
+#[cfg(unix)]
+unsafe fn map(handle: &Handle, len: usize) -> io::Result<*mut u8> {
+    let ptr = sys::mmap(
+        ptr::null_mut(),
+        len,
+        sys::PROT_READ | sys::PROT_WRITE,
+        sys::MAP_SHARED,
+        handle.as_raw_fd(),
+        0);
+
+    if ptr == sys::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(unix)]
+unsafe fn unmap(ptr: *mut u8, len: usize) {
+    sys::unmap(ptr, len)
+}
+
+#[cfg(windows)]
+#[derive(Debug)]
+pub(crate) struct Handle(pub(crate) sys::HANDLE);
+
+#[cfg(windows)]
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { sys::CloseHandle(self.0); }
+    }
+}
+
+#[cfg(windows)]
+fn raw(handle: &Handle) -> RawSharedHandle {
+    handle.0 as RawSharedHandle
+}
+
+#[cfg(windows)]
+unsafe fn from_raw(handle: RawSharedHandle) -> Handle {
+    Handle(handle as sys::HANDLE)
+}
+
+#[cfg(windows)]
+pub(crate) unsafe fn create(size: usize) -> io::Result<Handle> {
+    let size = size as u64;
+
+    let handle = sys::CreateFileMappingW(
+        sys::INVALID_HANDLE_VALUE,
+        ptr::null_mut(),
+        sys::PAGE_READWRITE,
+        (size >> 32) as sys::DWORD,
+        size as sys::DWORD,
+        ptr::null());
+
+    if handle.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Handle(handle))
+}
+
+#[cfg(windows)]
+unsafe fn map(handle: &Handle, len: usize) -> io::Result<*mut u8> {
+    let ptr = sys::MapViewOfFile(handle.0, sys::FILE_MAP_WRITE, 0, 0, len);
+
+    if ptr.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ptr as *mut u8)
+}
+
+#[cfg(windows)]
+unsafe fn unmap(ptr: *mut u8, _len: usize) {
+    sys::UnmapViewOfFile(ptr as sys::LPVOID);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_shared, SharedMemory};
+
+    #[cfg(unix)]
+    fn duplicate(mem: &SharedMemory) -> super::RawSharedHandle {
+        use std::fs::File;
+        use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+        let file = unsafe { File::from_raw_fd(mem.raw_handle()) };
+        let dup = file.try_clone().unwrap().into_raw_fd();
+        let _ = file.into_raw_fd();
+        dup
+    }
+
+    #[test]
+    fn test_allocate_shared() {
+        let mem = allocate_shared(4096).unwrap();
+        assert_eq!(4096, mem.len());
+
+        unsafe {
+            for i in 0..4096 {
+                assert_eq!(0, *mem.as_ptr().add(i));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_map_shared_handle() {
+        let mem = allocate_shared(100).unwrap();
+        let other = unsafe { SharedMemory::from_raw_handle(duplicate(&mem), 100).unwrap() };
+
+        assert!(mem.as_ptr() != other.as_ptr());
+
+        unsafe {
+            *mem.as_ptr().add(10) = 42;
+            assert_eq!(42, *other.as_ptr().add(10));
+        }
+    }
+
+    #[test]
+    fn test_allocate_shared_zero_size() {
+        assert!(allocate_shared(0).is_err());
+    }
+}
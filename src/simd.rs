@@ -0,0 +1,216 @@
+use round_up;
+
+use core::{mem, ops, ptr, slice};
+
+/// The alignment of a buffer for vector instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimdAlign {
+    /// 16 byte alignment, for SSE and NEON.
+    A16,
+
+    /// 32 byte alignment, for AVX and AVX2.
+    A32,
+
+    /// 64 byte alignment, for AVX-512.
+    A64,
+}
+
+impl SimdAlign {
+    /// Returns the alignment in bytes.
+    pub fn bytes(&self) -> usize {
+        match *self {
+            SimdAlign::A16 => 16,
+            SimdAlign::A32 => 32,
+            SimdAlign::A64 => 64,
+        }
+    }
+}
+
+/// Return a pointer to uninitialized memory for `len` values of `T`, aligned
+/// to `align`.
+///
+/// The allocation is padded to a multiple of the alignment so a full width
+/// vector load or store of the last elements stays in bounds. If the buffer
+/// is empty, a dangling pointer aligned to `align` is returned.
+///
+/// On failure, or if the size overflows, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_simd` using the same `len`
+/// and `align`.
+pub unsafe fn allocate_simd<T>(len: usize, align: SimdAlign) -> *mut T {
+    match simd_size::<T>(len, align) {
+        Some(0) => ::dangling(simd_align::<T>(align)).as_ptr() as *mut T,
+        Some(size) => ::allocate(size, simd_align::<T>(align)) as *mut T,
+        None => ptr::null_mut(),
+    }
+}
+
+/// Deallocates memory obtained from `allocate_simd`.
+///
+/// The values are not dropped.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_simd` with the same `len` and
+/// `align`.
+pub unsafe fn deallocate_simd<T>(ptr: *mut T, len: usize, align: SimdAlign) {
+    match simd_size::<T>(len, align) {
+        Some(0) | None => {}
+        Some(size) => ::deallocate(ptr as *mut u8, size, simd_align::<T>(align)),
+    }
+}
+
+fn simd_align<T>(align: SimdAlign) -> usize {
+    ::core::cmp::max(align.bytes(), mem::align_of::<T>())
+}
+
+fn simd_size<T>(len: usize, align: SimdAlign) -> Option<usize> {
+    let align = simd_align::<T>(align);
+    let size = len.checked_mul(mem::size_of::<T>())?;
+
+    if size > isize::MAX as usize - (align - 1) {
+        return None;
+    }
+
+    Some(round_up(size, align))
+}
+
+/// A fixed length buffer of `T` aligned for vector instructions, released
+/// when dropped.
+pub struct SimdBuf<T: Copy> {
+    ptr: *mut T,
+    len: usize,
+    align: SimdAlign,
+}
+
+unsafe impl<T: Copy + Send> Send for SimdBuf<T> {}
+unsafe impl<T: Copy + Sync> Sync for SimdBuf<T> {}
+
+impl<T: Copy> SimdBuf<T> {
+    /// Creates a buffer of `len` copies of `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails or the size overflows.
+    pub fn filled(len: usize, align: SimdAlign, value: T) -> SimdBuf<T> {
+        let buf: SimdBuf<T> = SimdBuf::alloc(len, align);
+
+        for i in 0..len {
+            unsafe { ptr::write(buf.ptr.add(i), value) };
+        }
+
+        buf
+    }
+
+    /// Creates a buffer holding a copy of `src`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails or the size overflows.
+    pub fn from_slice(src: &[T], align: SimdAlign) -> SimdBuf<T> {
+        let buf: SimdBuf<T> = SimdBuf::alloc(src.len(), align);
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), buf.ptr, src.len()) };
+        buf
+    }
+
+    fn alloc(len: usize, align: SimdAlign) -> SimdBuf<T> {
+        let ptr = unsafe { allocate_simd::<T>(len, align) };
+
+        if ptr.is_null() {
+            panic!("failed to allocate SIMD buffer of {} elements", len);
+        }
+
+        SimdBuf { ptr, len, align }
+    }
+
+    /// Returns the alignment of the buffer.
+    pub fn align(&self) -> SimdAlign {
+        self.align
+    }
+}
+
+impl<T: Copy> ops::Deref for SimdBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> ops::DerefMut for SimdBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> Clone for SimdBuf<T> {
+    fn clone(&self) -> SimdBuf<T> {
+        SimdBuf::from_slice(self, self.align)
+    }
+}
+
+impl<T: Copy + ::core::fmt::Debug> ::core::fmt::Debug for SimdBuf<T> {
+    fn fmt(&self, fmt: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        (**self).fmt(fmt)
+    }
+}
+
+impl<T: Copy> Drop for SimdBuf<T> {
+    fn drop(&mut self) {
+        unsafe { deallocate_simd(self.ptr, self.len, self.align) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate_simd, deallocate_simd, SimdAlign, SimdBuf};
+
+    #[test]
+    fn test_allocate_simd() {
+        for &align in &[SimdAlign::A16, SimdAlign::A32, SimdAlign::A64] {
+            unsafe {
+                let ptr = allocate_simd::<f32>(13, align);
+                assert!(!ptr.is_null());
+                assert_eq!(0, ptr as usize & (align.bytes() - 1));
+
+                // The tail is padded out to a full vector
+                assert!(::usable_size(13 * 4, align.bytes()) >= 64);
+
+                deallocate_simd(ptr, 13, align);
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocate_simd_empty() {
+        unsafe {
+            let ptr = allocate_simd::<f64>(0, SimdAlign::A32);
+            assert_eq!(32, ptr as usize);
+            deallocate_simd(ptr, 0, SimdAlign::A32);
+        }
+    }
+
+    #[test]
+    fn test_allocate_simd_overflow() {
+        unsafe {
+            assert!(allocate_simd::<u64>(usize::MAX / 4, SimdAlign::A64).is_null());
+        }
+    }
+
+    #[test]
+    fn test_simd_buf() {
+        let mut buf = SimdBuf::filled(100, SimdAlign::A64, 1.5f32);
+        assert_eq!(100, buf.len());
+        assert_eq!(0, buf.as_ptr() as usize & 63);
+
+        buf[99] = 2.0;
+
+        let copy = buf.clone();
+        assert_eq!(&buf[..], &copy[..]);
+
+        let buf = SimdBuf::from_slice(&[1u8, 2, 3], SimdAlign::A16);
+        assert_eq!(&[1, 2, 3], &buf[..]);
+    }
+}
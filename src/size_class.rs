@@ -0,0 +1,530 @@
+use {valgrind, Alloc, AllocError, Layout};
+
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem, ptr};
+use core::ptr::NonNull;
+
+// Classes are multiples of 16 bytes up to 64, then four per doubling up to
+// `MAX_CLASS`
+pub const NUM_CLASSES: usize = 36;
+const MAX_CLASS: usize = 16 * 1024;
+
+// Small blocks are carved out of chunks of this size and alignment
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNK_ALIGN: usize = 4096;
+
+/// A general-purpose allocator built from size classes.
+///
+/// Requests of up to 16 KiB are rounded up to one of 36 size classes, each
+/// with its own free list threaded through the freed blocks. Class lists are
+/// refilled by carving 64 KiB chunks from the heap, which are kept until the
+/// allocator is dropped, though `purge` can hand the pages of free blocks back
+/// to the OS. Larger requests are mapped directly from the OS
+/// where there is one, and from the heap otherwise.
+///
+/// Blocks handed out by the allocator must be released before it is
+/// dropped, except for large blocks, which stay valid until released.
+pub struct SizeClassAlloc {
+    free: [*mut FreeNode; NUM_CLASSES],
+    chunks: Vec<NonNull<u8>>,
+    allocated: usize,
+}
+
+struct FreeNode {
+    next: *mut FreeNode,
+
+    // Set once `purge` returned the pages past the node to the OS
+    purged: bool,
+}
+
+unsafe impl Send for SizeClassAlloc {}
+
+impl SizeClassAlloc {
+    /// Creates an allocator without any chunks.
+    pub fn new() -> SizeClassAlloc {
+        SizeClassAlloc {
+            free: [ptr::null_mut(); NUM_CLASSES],
+            chunks: Vec::new(),
+            allocated: 0,
+        }
+    }
+
+    /// Returns the number of bytes currently allocated, rounded up to the
+    /// size classes.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the number of bytes held in chunks for the size classes.
+    pub fn reserved(&self) -> usize {
+        self.chunks.len() * CHUNK_SIZE
+    }
+
+    /// Returns the pages of the free blocks to the OS, and returns the number
+    /// of bytes released.
+    ///
+    /// Only whole pages inside a free block can be released, so this mostly
+    /// helps with the larger size classes. The blocks stay on their free
+    /// lists, and their pages are committed again when they are handed out.
+    #[cfg(any(unix, windows))]
+    pub fn purge(&mut self) -> usize {
+        let page = ::sys::page_size();
+        let mut released = 0;
+
+        for class in 0..NUM_CLASSES {
+            let size = class_size(class);
+
+            if size < page + mem::size_of::<FreeNode>() {
+                continue;
+            }
+
+            let mut node = self.free[class];
+
+            while !node.is_null() {
+                unsafe {
+                    valgrind::make_defined(node as *const u8, mem::size_of::<FreeNode>());
+
+                    if !(*node).purged {
+                        let (start, len) = purgeable(node, size, page);
+
+                        if len > 0 && ::sys::decommit(start, len) {
+                            (*node).purged = true;
+                            released += len;
+                        }
+                    }
+
+                    let next = (*node).next;
+                    valgrind::make_noaccess(node as *const u8, mem::size_of::<FreeNode>());
+                    node = next;
+                }
+            }
+        }
+
+        released
+    }
+
+    fn refill(&mut self, class: usize) -> Result<(), AllocError> {
+        let chunk = unsafe { ::try_allocate(Layout::from_size_align_unchecked(CHUNK_SIZE, CHUNK_ALIGN))? };
+        self.chunks.push(chunk);
+
+        // Thread every block of the chunk onto the class list, keeping them
+        // in address order
+        let size = class_size(class);
+        let count = CHUNK_SIZE / size;
+
+        unsafe {
+            for i in (0..count).rev() {
+                let node = chunk.as_ptr().add(i * size) as *mut FreeNode;
+                ptr::write(node, FreeNode { next: self.free[class], purged: false });
+                self.free[class] = node;
+            }
+
+            // Blocks are only accessible once handed out
+            valgrind::make_noaccess(chunk.as_ptr(), CHUNK_SIZE);
+        }
+
+        Ok(())
+    }
+}
+
+unsafe impl Alloc for SizeClassAlloc {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let class = match class_for(layout) {
+            Some(class) => class,
+            None => return alloc_large(layout),
+        };
+
+        if self.free[class].is_null() {
+            self.refill(class)?;
+        }
+
+        let node = self.free[class];
+        valgrind::make_defined(node as *const u8, mem::size_of::<FreeNode>());
+
+        if (*node).purged && !recommit(node, class_size(class)) {
+            valgrind::make_noaccess(node as *const u8, mem::size_of::<FreeNode>());
+            return Err(AllocError::OutOfMemory);
+        }
+
+        self.free[class] = (*node).next;
+        self.allocated += class_size(class);
+
+        debug_assert_eq!(0, node as usize % class_align(class));
+
+        valgrind::malloclike(node as *const u8, layout.size());
+        Ok(NonNull::new_unchecked(node as *mut u8))
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let class = match class_for(layout) {
+            Some(class) => class,
+            None => return dealloc_large(ptr, layout),
+        };
+
+        valgrind::freelike(ptr.as_ptr());
+
+        // The link is hidden from memcheck while the block is free
+        let node = ptr.as_ptr() as *mut FreeNode;
+        valgrind::make_undefined(node as *const u8, mem::size_of::<FreeNode>());
+        ptr::write(node, FreeNode { next: self.free[class], purged: false });
+        valgrind::make_noaccess(node as *const u8, mem::size_of::<FreeNode>());
+
+        self.free[class] = node;
+        self.allocated -= class_size(class);
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+
+        // Staying within the class leaves the block where it is
+        if new_size > 0 && class_for(layout).is_some() && class_for(layout) == class_for(new_layout) {
+            valgrind::resize_inplace(ptr.as_ptr(), layout.size(), new_size);
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+}
+
+impl Default for SizeClassAlloc {
+    fn default() -> SizeClassAlloc {
+        SizeClassAlloc::new()
+    }
+}
+
+impl fmt::Debug for SizeClassAlloc {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SizeClassAlloc")
+            .field("allocated", &self.allocated)
+            .field("reserved", &self.reserved())
+            .finish()
+    }
+}
+
+impl Drop for SizeClassAlloc {
+    fn drop(&mut self) {
+        // The heap may touch the chunks once they are released
+        for class in 0..NUM_CLASSES {
+            let mut node = self.free[class];
+
+            while !node.is_null() {
+                unsafe {
+                    valgrind::make_defined(node as *const u8, mem::size_of::<FreeNode>());
+
+                    if (*node).purged {
+                        recommit(node, class_size(class));
+                    }
+
+                    node = (*node).next;
+                }
+            }
+        }
+
+        for &chunk in &self.chunks {
+            unsafe { ::release(chunk, Layout::from_size_align_unchecked(CHUNK_SIZE, CHUNK_ALIGN)) }
+        }
+    }
+}
+
+/// Returns the size class serving `layout`, or `None` for a large block.
+pub fn class_for(layout: Layout) -> Option<usize> {
+    let size = cmp::max(layout.size(), 16);
+
+    // Only the power-of-two classes are aligned beyond 16 bytes
+    let size = if layout.align() > 16 {
+        if layout.align() > CHUNK_ALIGN {
+            return None;
+        }
+
+        cmp::max(size, layout.align()).checked_next_power_of_two()?
+    } else {
+        size
+    };
+
+    if size > MAX_CLASS {
+        return None;
+    }
+
+    Some(class_index(size))
+}
+
+/// Returns the size of the class that blocks of `size` bytes aligned to
+/// `align` are rounded up to by `SizeClassAlloc` and `ThreadCache`.
+///
+/// Returns `None` if the blocks are too large or too aligned for any class,
+/// or if `align` isn't a power of two. Containers can pick capacities filling
+/// a whole class so no slack is wasted.
+pub fn next_size_class(size: usize, align: usize) -> Option<usize> {
+    let layout = Layout::from_size_align(size, align).ok()?;
+    class_for(layout).map(class_size)
+}
+
+/// Returns `true` if `size` is the size of one of the classes.
+pub fn is_size_class(size: usize) -> bool {
+    (16..=MAX_CLASS).contains(&size) && class_size(class_index(size)) == size
+}
+
+/// Returns the smallest power of two that is at least `size`, or `None` if it
+/// overflows.
+pub fn round_up_pow2(size: usize) -> Option<usize> {
+    size.checked_next_power_of_two()
+}
+
+// `size` must be between 1 and `MAX_CLASS`
+fn class_index(size: usize) -> usize {
+    if size <= 64 {
+        return (size - 1) / 16;
+    }
+
+    // The power of two below `size`, and the spacing of the four classes
+    // above it
+    let shift = (usize::MAX.count_ones() - 1 - (size - 1).leading_zeros()) as usize;
+    let step = 1 << (shift - 2);
+
+    4 + (shift - 6) * 4 + (size - (1 << shift) - 1) / step
+}
+
+pub fn class_size(class: usize) -> usize {
+    if class < 4 {
+        return (class + 1) * 16;
+    }
+
+    let shift = 6 + (class - 4) / 4;
+    let k = (class - 4) % 4 + 1;
+
+    (1 << shift) + k * (1 << (shift - 2))
+}
+
+/// Returns the alignment guaranteed for blocks of `class`.
+pub fn class_align(class: usize) -> usize {
+    let size = class_size(class);
+
+    if size.is_power_of_two() {
+        cmp::min(size, CHUNK_ALIGN)
+    } else {
+        16
+    }
+}
+
+// Returns the range of whole pages in a free block, past its node
+#[cfg(any(unix, windows))]
+fn purgeable(node: *mut FreeNode, size: usize, page: usize) -> (*mut u8, usize) {
+    let start = ::round_up(node as usize + mem::size_of::<FreeNode>(), page);
+    let end = (node as usize + size) & !(page - 1);
+
+    (start as *mut u8, end.saturating_sub(start))
+}
+
+#[cfg(any(unix, windows))]
+unsafe fn recommit(node: *mut FreeNode, size: usize) -> bool {
+    let (start, len) = purgeable(node, size, ::sys::page_size());
+    ::sys::recommit(start, len)
+}
+
+#[cfg(not(any(unix, windows)))]
+unsafe fn recommit(_node: *mut FreeNode, _size: usize) -> bool {
+    true
+}
+
+#[cfg(any(unix, windows))]
+unsafe fn alloc_large(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    let page = ::sys::page_size();
+
+    if layout.align() > page {
+        return ::try_allocate(layout);
+    }
+
+    let ptr = ::sys::map_anonymous(::round_up(layout.size(), page), 0);
+    NonNull::new(ptr).ok_or(AllocError::OutOfMemory)
+}
+
+#[cfg(any(unix, windows))]
+unsafe fn dealloc_large(ptr: NonNull<u8>, layout: Layout) {
+    let page = ::sys::page_size();
+
+    if layout.align() > page {
+        return ::release(ptr, layout);
+    }
+
+    ::sys::unmap(ptr.as_ptr(), ::round_up(layout.size(), page))
+}
+
+#[cfg(not(any(unix, windows)))]
+unsafe fn alloc_large(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    ::try_allocate(layout)
+}
+
+#[cfg(not(any(unix, windows)))]
+unsafe fn dealloc_large(ptr: NonNull<u8>, layout: Layout) {
+    ::release(ptr, layout)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{class_for, class_index, class_size, MAX_CLASS, NUM_CLASSES};
+    use {is_size_class, next_size_class, round_up_pow2, Alloc, Layout, SizeClassAlloc};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(MAX_CLASS, class_size(NUM_CLASSES - 1));
+
+        let mut prev = 0;
+
+        for size in 1..MAX_CLASS + 1 {
+            let class = class_index(size);
+            assert!(class_size(class) >= size);
+            assert!(class == 0 || class_size(class - 1) < size);
+
+            if size.is_power_of_two() && size >= 16 {
+                assert_eq!(size, class_size(class));
+            }
+
+            assert!(class == prev || class == prev + 1);
+            prev = class;
+        }
+
+        assert_eq!(None, class_for(Layout::from_size_align(MAX_CLASS + 1, 8).unwrap()));
+        assert_eq!(None, class_for(Layout::from_size_align(8, 8192).unwrap()));
+        assert_eq!(Some(class_index(256)), class_for(Layout::from_size_align(130, 256).unwrap()));
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(Some(16), next_size_class(0, 1));
+        assert_eq!(Some(48), next_size_class(33, 8));
+        assert_eq!(Some(80), next_size_class(65, 8));
+        assert_eq!(Some(128), next_size_class(65, 128));
+        assert_eq!(Some(MAX_CLASS), next_size_class(MAX_CLASS, 16));
+        assert_eq!(None, next_size_class(MAX_CLASS + 1, 16));
+        assert_eq!(None, next_size_class(16, 3));
+
+        for class in 0..NUM_CLASSES {
+            assert!(is_size_class(class_size(class)));
+            assert!(!is_size_class(class_size(class) + 1));
+        }
+
+        assert!(!is_size_class(0));
+        assert!(!is_size_class(MAX_CLASS * 2));
+
+        assert_eq!(Some(1), round_up_pow2(0));
+        assert_eq!(Some(64), round_up_pow2(33));
+        assert_eq!(Some(64), round_up_pow2(64));
+        assert_eq!(None, round_up_pow2(usize::MAX));
+    }
+
+    #[test]
+    fn test_alloc_reuses_blocks() {
+        let mut a = SizeClassAlloc::new();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let ptrs: Vec<_> = (0..1000).map(|_| a.alloc(layout).unwrap()).collect();
+            assert_eq!(1000 * 112, a.allocated());
+            assert!(a.reserved() >= 1000 * 112);
+
+            for (i, ptr) in ptrs.iter().enumerate() {
+                *(ptr.as_ptr() as *mut usize) = i;
+            }
+
+            for (i, ptr) in ptrs.iter().enumerate() {
+                assert_eq!(i, *(ptr.as_ptr() as *mut usize));
+            }
+
+            let last = *ptrs.last().unwrap();
+
+            for ptr in ptrs {
+                a.dealloc(ptr, layout);
+            }
+
+            // The list is LIFO
+            assert_eq!(last, a.alloc(layout).unwrap());
+            a.dealloc(last, layout);
+        }
+
+        assert_eq!(0, a.allocated());
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut a = SizeClassAlloc::new();
+
+        unsafe {
+            for &align in &[1, 8, 16, 32, 64, 512, 4096, 16384] {
+                if align > ::MAX_ALIGN {
+                    continue;
+                }
+
+                let layout = Layout::from_size_align(24, align).unwrap();
+                let ptr = a.alloc(layout).unwrap();
+                assert_eq!(0, ptr.as_ptr() as usize % align);
+                a.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_purge() {
+        let mut a = SizeClassAlloc::new();
+        let layout = Layout::from_size_align(MAX_CLASS, 8).unwrap();
+
+        unsafe {
+            let ptrs: Vec<_> = (0..8).map(|_| a.alloc(layout).unwrap()).collect();
+
+            for ptr in &ptrs {
+                ::std::ptr::write_bytes(ptr.as_ptr(), 1, MAX_CLASS);
+            }
+
+            for &ptr in &ptrs {
+                a.dealloc(ptr, layout);
+            }
+
+            let released = a.purge();
+            assert!(released >= 8 * (MAX_CLASS - 2 * ::sys::page_size()));
+            assert_eq!(0, a.purge());
+
+            // Purged blocks are usable again
+            let ptrs: Vec<_> = (0..8).map(|_| a.alloc(layout).unwrap()).collect();
+
+            for &ptr in &ptrs {
+                ::std::ptr::write_bytes(ptr.as_ptr(), 2, MAX_CLASS);
+                assert_eq!(2, *ptr.as_ptr().add(MAX_CLASS - 1));
+                a.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_and_realloc() {
+        let mut a = SizeClassAlloc::new();
+
+        unsafe {
+            let layout = Layout::from_size_align(20, 8).unwrap();
+            let ptr = a.alloc(layout).unwrap();
+            *ptr.as_ptr() = 9;
+
+            // Same class
+            assert_eq!(ptr, a.realloc(ptr, layout, 30).unwrap());
+
+            let layout = Layout::from_size_align(30, 8).unwrap();
+            let ptr = a.realloc(ptr, layout, 1 << 20).unwrap();
+            assert_eq!(9, *ptr.as_ptr());
+            *ptr.as_ptr().add((1 << 20) - 1) = 1;
+            assert_eq!(0, a.allocated());
+
+            let layout = Layout::from_size_align(1 << 20, 8).unwrap();
+            let ptr = a.realloc(ptr, layout, 10).unwrap();
+            assert_eq!(9, *ptr.as_ptr());
+            a.dealloc(ptr, Layout::from_size_align(10, 8).unwrap());
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use {asan, round_up, valgrind, AllocError, Layout};
+
+use alloc::vec::Vec;
+use core::{cmp, fmt, mem};
+use core::ptr::NonNull;
+
+const PAGE_SIZE: usize = 4096;
+
+// Pages are made large enough for this many slots when they can be, so the
+// space left over past the last slot is small
+const MIN_SLOTS: usize = 64;
+
+/// An allocator of fixed-size slots.
+///
+/// Slots are carved out of pages allocated from the heap, and each page
+/// tracks its free slots with a bitmap, so allocating and freeing a slot is
+/// O(1) and slots are packed without per-allocation overhead. Pages are kept
+/// until the slab is dropped.
+///
+/// Pages are aligned to their size, which must not exceed `MAX_ALIGN`. With
+/// the default backend pages are at most 4 KiB, which limits slots to a little
+/// under 4 KiB, and slots larger than about 1 KiB leave much of each page
+/// unused. Other backends make pages large enough for 64 slots.
+pub struct Slab {
+    slot: Layout,
+
+    // Layout of a page and offset of its first slot
+    page: Layout,
+    offset: usize,
+    slots_per_page: usize,
+
+    pages: Vec<Page>,
+
+    // Free slot bitmaps of the pages, `words` per page. Set bits are free
+    // slots.
+    free: Vec<u64>,
+    words: usize,
+
+    // Pages with at least one free slot
+    partial: Vec<usize>,
+
+    len: usize,
+}
+
+struct Page {
+    ptr: NonNull<u8>,
+
+    // Number of free slots
+    free: usize,
+}
+
+unsafe impl Send for Slab {}
+
+impl Slab {
+    /// Creates a slab handing out slots fitting `layout`.
+    ///
+    /// Returns `AllocError::InvalidLayout` if `layout` has a size of 0 or the
+    /// pages needed for it can't be aligned to their size.
+    pub fn new(layout: Layout) -> Result<Slab, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let slot = layout.pad_to_align();
+        let offset = round_up(mem::size_of::<usize>(), slot.align());
+
+        let page_size = slot.size().checked_mul(MIN_SLOTS)
+            .and_then(|size| size.checked_add(offset))
+            .and_then(usize::checked_next_power_of_two)
+            .map_or(::MAX_ALIGN, |size| cmp::max(size, PAGE_SIZE));
+        let page_size = cmp::min(page_size, ::MAX_ALIGN);
+
+        if offset >= page_size || page_size - offset < slot.size() {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let slots_per_page = (page_size - offset) / slot.size();
+
+        Ok(Slab {
+            slot,
+            page: Layout::from_size_align(page_size, page_size)?,
+            offset,
+            slots_per_page,
+            pages: Vec::new(),
+            free: Vec::new(),
+            words: round_up(slots_per_page, 64) / 64,
+            partial: Vec::new(),
+            len: 0,
+        })
+    }
+
+    /// Returns the layout of the slots, padded to their alignment.
+    pub fn layout(&self) -> Layout {
+        self.slot
+    }
+
+    /// Returns the number of allocated slots.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no slots are allocated.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of slots the slab can hand out without allocating
+    /// another page.
+    pub fn capacity(&self) -> usize {
+        self.pages.len() * self.slots_per_page
+    }
+
+    /// Returns the number of bytes held in pages.
+    pub fn reserved(&self) -> usize {
+        self.pages.len() * self.page.size()
+    }
+
+    /// Returns a pointer to a free slot.
+    ///
+    /// The memory is uninitialized and stays valid until it is passed to
+    /// `free` or the slab is dropped.
+    pub fn alloc(&mut self) -> Result<NonNull<u8>, AllocError> {
+        let index = match self.partial.last() {
+            Some(&index) => index,
+            None => self.add_page()?,
+        };
+
+        let page = &mut self.pages[index];
+        let words = &mut self.free[index * self.words..(index + 1) * self.words];
+
+        // The page is partial, so one of its words has a free slot
+        let word = words.iter().position(|&word| word != 0).unwrap();
+        let bit = words[word].trailing_zeros() as usize;
+        words[word] &= !(1 << bit);
+
+        let slot = word * 64 + bit;
+        page.free -= 1;
+
+        if page.free == 0 {
+            self.partial.pop();
+        }
+
+        self.len += 1;
+
+        unsafe {
+            let ptr = page.ptr.as_ptr().add(self.offset + slot * self.slot.size());
+            asan::unpoison(ptr, self.slot.size());
+            valgrind::malloclike(ptr, self.slot.size());
+
+            Ok(NonNull::new_unchecked(ptr))
+        }
+    }
+
+    /// Returns a slot to the slab.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` on this slab and not freed
+    /// since.
+    pub unsafe fn free(&mut self, ptr: NonNull<u8>) {
+        let addr = ptr.as_ptr() as usize;
+        let base = addr & !(self.page.size() - 1);
+
+        // Pages start with their index
+        let index = *(base as *const usize);
+        let slot = (addr - base - self.offset) / self.slot.size();
+
+        let page = &mut self.pages[index];
+        let word = &mut self.free[index * self.words + slot / 64];
+        debug_assert!(*word & (1 << (slot % 64)) == 0, "slot freed twice");
+
+        if page.free == 0 {
+            self.partial.push(index);
+        }
+
+        *word |= 1 << (slot % 64);
+        page.free += 1;
+        self.len -= 1;
+
+        asan::poison(ptr.as_ptr(), self.slot.size());
+        valgrind::freelike(ptr.as_ptr());
+    }
+
+    fn add_page(&mut self) -> Result<usize, AllocError> {
+        let ptr = unsafe { ::try_allocate(self.page)? };
+        let index = self.pages.len();
+
+        unsafe {
+            *(ptr.as_ptr() as *mut usize) = index;
+
+            // Slots are only accessible once handed out
+            asan::poison(ptr.as_ptr().add(self.offset), self.page.size() - self.offset);
+            valgrind::make_noaccess(ptr.as_ptr().add(self.offset), self.page.size() - self.offset);
+        }
+
+        for word in 0..self.words {
+            let slots = cmp::min(self.slots_per_page - word * 64, 64);
+            self.free.push(if slots == 64 { !0 } else { (1 << slots) - 1 });
+        }
+
+        self.pages.push(Page { ptr, free: self.slots_per_page });
+        self.partial.push(index);
+
+        Ok(index)
+    }
+}
+
+impl fmt::Debug for Slab {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Slab")
+            .field("layout", &self.slot)
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        for page in &self.pages {
+            // The heap expects to get back memory it can access
+            asan::unpoison(page.ptr.as_ptr(), self.page.size());
+            unsafe { ::release(page.ptr, self.page) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {AllocError, Layout, Slab};
+    use std::ptr::NonNull;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_slab() {
+        let mut slab = Slab::new(Layout::new::<[u64; 3]>()).unwrap();
+        let mut slots: Vec<NonNull<u8>> = Vec::new();
+
+        for i in 0..1000 {
+            let ptr = slab.alloc().unwrap();
+            assert_eq!(0, ptr.as_ptr() as usize & 7);
+            unsafe { *(ptr.as_ptr() as *mut [u64; 3]) = [i; 3] };
+            slots.push(ptr);
+        }
+
+        assert_eq!(1000, slab.len());
+        let capacity = slab.capacity();
+
+        // Free every other slot and allocate them again
+        for ptr in slots.iter().step_by(2) {
+            unsafe { slab.free(*ptr) };
+        }
+
+        assert_eq!(500, slab.len());
+
+        for _ in 0..500 {
+            slab.alloc().unwrap();
+        }
+
+        assert_eq!(capacity, slab.capacity());
+
+        for (i, ptr) in slots.iter().enumerate().skip(1).step_by(2) {
+            assert_eq!([i as u64; 3], unsafe { *(ptr.as_ptr() as *const [u64; 3]) });
+        }
+    }
+
+    #[test]
+    fn test_slab_large_slots() {
+        let mut slab = Slab::new(Layout::from_size_align(3000, 8).unwrap()).unwrap();
+        let a = slab.alloc().unwrap();
+        let b = slab.alloc().unwrap();
+        assert!(a != b);
+
+        unsafe {
+            slab.free(a);
+            slab.free(b);
+        }
+
+        assert!(slab.is_empty());
+    }
+
+    #[test]
+    fn test_slab_packing() {
+        let mut sizes = ::std::vec![8, 24, 1000];
+
+        // Larger slots only get pages for 64 of them past the default backend
+        if ::MAX_ALIGN >= 256 * 1024 {
+            sizes.extend_from_slice(&[2049, 4096]);
+        }
+
+        for size in sizes {
+            let mut slab = Slab::new(Layout::from_size_align(size, 8).unwrap()).unwrap();
+
+            for _ in 0..2000 {
+                slab.alloc().unwrap();
+            }
+
+            let used = (slab.len() * slab.layout().size()) as f64;
+            assert!((slab.reserved() as f64) / used < 1.05, "{} {}", size, slab.reserved());
+        }
+    }
+
+    #[test]
+    fn test_slab_invalid() {
+        assert_eq!(AllocError::InvalidLayout, Slab::new(Layout::from_size_align(0, 1).unwrap()).unwrap_err());
+
+        if let Some(align) = ::MAX_ALIGN.checked_mul(2) {
+            assert_eq!(AllocError::InvalidLayout, Slab::new(Layout::from_size_align(8, align).unwrap()).unwrap_err());
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use {round_up, Alloc, AllocError, Allocation, Layout};
+
+use alloc::vec::Vec;
+use core::{cmp, fmt, ptr};
+use core::ptr::NonNull;
+
+/// A stack allocator with LIFO frames.
+///
+/// Blocks are bumped off a single buffer allocated up front. `push_frame`
+/// marks the top of the stack and `pop_frame` releases every block allocated
+/// since, so scratch space can be taken in a loop or a recursion without
+/// touching the heap on each iteration.
+///
+/// Releasing the most recently allocated block with `dealloc` also returns
+/// its memory to the stack. Other blocks stay allocated until their frame is
+/// popped.
+pub struct StackAlloc {
+    buf: Allocation,
+
+    // Offset of the first free byte
+    top: usize,
+
+    // Value of `top` when each frame was pushed
+    frames: Vec<usize>,
+}
+
+impl StackAlloc {
+    /// Creates a stack allocator with a buffer of `capacity` bytes.
+    ///
+    /// Errors are reported the same way as `try_allocate`.
+    pub fn with_capacity(capacity: usize) -> Result<StackAlloc, AllocError> {
+        let layout = Layout::from_size_align(capacity, 16)?;
+
+        Ok(StackAlloc {
+            buf: Allocation::new(layout)?,
+            top: 0,
+            frames: Vec::new(),
+        })
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        self.buf.layout().size()
+    }
+
+    /// Returns the number of bytes in use, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.top
+    }
+
+    /// Returns the number of frames pushed and not yet popped.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if `ptr` points into the buffer.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        let base = self.buf.as_ptr() as usize;
+        addr >= base && addr < base + self.capacity()
+    }
+
+    /// Starts a new frame.
+    pub fn push_frame(&mut self) {
+        self.frames.push(self.top);
+    }
+
+    /// Releases every block allocated since the matching `push_frame`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no frame has been pushed.
+    pub fn pop_frame(&mut self) {
+        match self.frames.pop() {
+            Some(top) => self.top = top,
+            None => panic!("pop_frame called without a matching push_frame"),
+        }
+    }
+
+    /// Calls `f` inside a new frame, popping it once `f` returns.
+    ///
+    /// The frame is not popped if `f` panics.
+    pub fn with_frame<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut StackAlloc) -> R
+    {
+        self.push_frame();
+        let ret = f(self);
+        self.pop_frame();
+        ret
+    }
+
+    // Returns true if the block is the last one allocated
+    fn is_top(&self, ptr: NonNull<u8>, size: usize) -> bool {
+        ptr.as_ptr() as usize + size == self.buf.as_ptr() as usize + self.top
+    }
+
+    fn frame_base(&self) -> usize {
+        self.frames.last().cloned().unwrap_or(0)
+    }
+}
+
+unsafe impl Alloc for StackAlloc {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        let base = self.buf.as_ptr() as usize;
+        let start = round_up(base + self.top, layout.align()) - base;
+
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= self.capacity() => {
+                self.top = end;
+                Ok(NonNull::new_unchecked(self.buf.as_ptr().add(start)))
+            }
+            _ => Err(AllocError::OutOfMemory),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        // Only the last block can be given back, and not past the current
+        // frame
+        let start = ptr.as_ptr() as usize - self.buf.as_ptr() as usize;
+
+        if self.is_top(ptr, layout.size()) && start >= self.frame_base() {
+            self.top = start;
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let start = ptr.as_ptr() as usize - self.buf.as_ptr() as usize;
+
+        // The last block can be resized in place
+        if self.is_top(ptr, layout.size()) && start >= self.frame_base() && new_size > 0 {
+            if new_size > self.capacity() - start {
+                return Err(AllocError::OutOfMemory);
+            }
+
+            self.top = start + new_size;
+            return Ok(ptr);
+        }
+
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+        let new_ptr = self.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+}
+
+impl fmt::Debug for StackAlloc {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("StackAlloc")
+            .field("capacity", &self.capacity())
+            .field("used", &self.top)
+            .field("depth", &self.frames.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, AllocError, Layout, StackAlloc};
+
+    #[test]
+    fn test_frames() {
+        let mut stack = StackAlloc::with_capacity(1024).unwrap();
+        let layout = Layout::from_size_align(96, 8).unwrap();
+
+        unsafe {
+            let a = stack.alloc(layout).unwrap();
+
+            stack.push_frame();
+            let b = stack.alloc(layout).unwrap();
+            let _c = stack.alloc(layout).unwrap();
+            assert_eq!(1, stack.depth());
+            assert_eq!(288, stack.used());
+
+            stack.pop_frame();
+            assert_eq!(96, stack.used());
+
+            // The frame's memory is reused
+            assert_eq!(b, stack.alloc(layout).unwrap());
+
+            stack.dealloc(b, layout);
+            stack.dealloc(a, layout);
+            assert_eq!(0, stack.used());
+        }
+    }
+
+    #[test]
+    fn test_dealloc_respects_frame() {
+        let mut stack = StackAlloc::with_capacity(1024).unwrap();
+        let layout = Layout::new::<u64>();
+
+        unsafe {
+            let a = stack.alloc(layout).unwrap();
+
+            stack.push_frame();
+            stack.dealloc(a, layout);
+            assert_eq!(8, stack.used());
+            stack.pop_frame();
+        }
+    }
+
+    #[test]
+    fn test_with_frame() {
+        fn recurse(stack: &mut StackAlloc, n: usize) -> usize {
+            if n == 0 {
+                return stack.used();
+            }
+
+            stack.with_frame(|stack| unsafe {
+                let ptr = stack.alloc_array::<u32>(4).unwrap();
+                *ptr.as_ptr() = n as u32;
+                recurse(stack, n - 1)
+            })
+        }
+
+        let mut stack = StackAlloc::with_capacity(1024).unwrap();
+        assert_eq!(160, recurse(&mut stack, 10));
+        assert_eq!(0, stack.used());
+        assert_eq!(0, stack.depth());
+    }
+
+    #[test]
+    fn test_realloc_and_exhaustion() {
+        let mut stack = StackAlloc::with_capacity(256).unwrap();
+
+        unsafe {
+            let layout = Layout::from_size_align(16, 8).unwrap();
+            let a = stack.alloc(layout).unwrap();
+            *a.as_ptr() = 7;
+
+            let a = stack.realloc(a, layout, 200).unwrap();
+            assert_eq!(200, stack.used());
+            assert_eq!(7, *a.as_ptr());
+
+            let big = Layout::from_size_align(100, 1).unwrap();
+            assert_eq!(Err(AllocError::OutOfMemory), stack.alloc(big));
+            assert_eq!(Err(AllocError::OutOfMemory), stack.realloc(a, Layout::from_size_align(200, 8).unwrap(), 300));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pop_without_push() {
+        StackAlloc::with_capacity(16).unwrap().pop_frame();
+    }
+}
@@ -0,0 +1,575 @@
+//! Allocation statistics.
+//!
+//! With the `stats` feature, the heap allocation functions (`allocate`,
+//! `reallocate`, `deallocate` and the functions built on them) count the
+//! blocks and bytes that go through them. The counters are split in shards,
+//! each thread updating its own with relaxed atomic adds so that threads
+//! allocating at once don't contend on the same cache line, and `snapshot`
+//! sums them up.
+//!
+//! Sizes are recorded as passed to the functions, so a block released with
+//! its usable size rather than its requested size skews `live_bytes`.
+//!
+//! Allocations can also be attributed to tags, such as the name of the
+//! subsystem making them, and `tag_report` groups the counters by tag. A
+//! block is tagged either explicitly, with `allocate_tagged` and
+//! `deallocate_tagged`, or by allocating it within `with_tag`. Up to 64
+//! distinct tags are tracked; allocations under further tags are only
+//! counted globally.
+
+use alloc::vec::Vec;
+use core::cmp;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+
+const SHARDS: usize = 16;
+
+// Live bytes are added to a shard's `pending` count, and moved to `LIVE_BYTES`
+// once it exceeds this many bytes either way
+const FLUSH_BYTES: isize = 64 * 1024;
+
+// Each thread updates the counters of one shard, so threads rarely contend on
+// a cache line. Counters are summed when read.
+#[repr(align(128))]
+struct Shard {
+    allocated_bytes: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    reallocations: AtomicUsize,
+    cache_hits: AtomicUsize,
+    cache_misses: AtomicUsize,
+
+    // Live bytes not yet moved to `LIVE_BYTES`
+    pending: AtomicIsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SHARD: Shard = Shard {
+    allocated_bytes: AtomicUsize::new(0),
+    allocations: AtomicUsize::new(0),
+    deallocations: AtomicUsize::new(0),
+    reallocations: AtomicUsize::new(0),
+    cache_hits: AtomicUsize::new(0),
+    cache_misses: AtomicUsize::new(0),
+    pending: AtomicIsize::new(0),
+};
+
+static SHARD_COUNTERS: [Shard; SHARDS] = [EMPTY_SHARD; SHARDS];
+
+// Signed, so that releasing blocks with a larger size than they were
+// allocated with can't wrap it around
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The values of the allocation counters at one point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Total number of bytes allocated, including growth by reallocation.
+    pub allocated_bytes: usize,
+
+    /// Number of bytes in blocks that have not been released.
+    pub live_bytes: usize,
+
+    /// The largest value `live_bytes` reached. Spikes shorter than 64 KiB
+    /// per shard may be missed.
+    pub peak_bytes: usize,
+
+    /// Number of blocks allocated.
+    pub allocations: usize,
+
+    /// Number of blocks released.
+    pub deallocations: usize,
+
+    /// Number of blocks resized.
+    pub reallocations: usize,
+
+    /// Number of `ThreadCache` allocations served from the thread's cache.
+    pub cache_hits: usize,
+
+    /// Number of `ThreadCache` allocations of a size class that had to go to
+    /// the heap.
+    pub cache_misses: usize,
+}
+
+impl Stats {
+    /// Returns the number of blocks that have not been released.
+    pub fn live_blocks(&self) -> usize {
+        self.allocations.saturating_sub(self.deallocations)
+    }
+
+    /// Returns the share of `ThreadCache` allocations served from the cache,
+    /// between 0 and 1, or `None` if there were none.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let total = self.cache_hits + self.cache_misses;
+
+        if total == 0 {
+            None
+        } else {
+            Some(self.cache_hits as f64 / total as f64)
+        }
+    }
+}
+
+/// Returns the current values of the counters.
+///
+/// The counters are read one after the other, so a snapshot taken while
+/// other threads allocate may not be consistent across fields.
+pub fn snapshot() -> Stats {
+    let mut stats = Stats::default();
+
+    for shard in SHARD_COUNTERS.iter() {
+        stats.allocated_bytes += shard.allocated_bytes.load(Ordering::Relaxed);
+        stats.allocations += shard.allocations.load(Ordering::Relaxed);
+        stats.deallocations += shard.deallocations.load(Ordering::Relaxed);
+        stats.reallocations += shard.reallocations.load(Ordering::Relaxed);
+        stats.cache_hits += shard.cache_hits.load(Ordering::Relaxed);
+        stats.cache_misses += shard.cache_misses.load(Ordering::Relaxed);
+    }
+
+    stats.live_bytes = live_bytes();
+    stats.peak_bytes = raise_peak(stats.live_bytes);
+    stats
+}
+
+/// Lowers the peak to the current number of live bytes.
+pub fn reset_peak() {
+    PEAK_BYTES.store(live_bytes(), Ordering::Relaxed);
+}
+
+pub(crate) fn record_alloc(size: usize) {
+    let shard = shard();
+    shard.allocations.fetch_add(1, Ordering::Relaxed);
+    shard.allocated_bytes.fetch_add(size, Ordering::Relaxed);
+    add_live(shard, size as isize);
+
+    if let Some(tag) = current_tag() {
+        TAGS.slots[tag].record_alloc(size);
+    }
+}
+
+pub(crate) fn record_dealloc(size: usize) {
+    let shard = shard();
+    shard.deallocations.fetch_add(1, Ordering::Relaxed);
+    add_live(shard, (size as isize).wrapping_neg());
+
+    if let Some(tag) = current_tag() {
+        TAGS.slots[tag].record_dealloc(size);
+    }
+}
+
+pub(crate) fn record_realloc(old_size: usize, size: usize) {
+    let shard = shard();
+    shard.reallocations.fetch_add(1, Ordering::Relaxed);
+
+    if size > old_size {
+        shard.allocated_bytes.fetch_add(size - old_size, Ordering::Relaxed);
+    }
+
+    add_live(shard, (size as isize).wrapping_sub(old_size as isize));
+
+    if let Some(tag) = current_tag() {
+        TAGS.slots[tag].record_realloc(old_size, size);
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn record_cache(hit: bool) {
+    let shard = shard();
+
+    if hit {
+        shard.cache_hits.fetch_add(1, Ordering::Relaxed);
+    } else {
+        shard.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "std")]
+fn shard() -> &'static Shard {
+    static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+    ::std::thread_local! {
+        // Threads are spread over the shards in the order they first allocate
+        #[allow(clippy::missing_const_for_thread_local)]
+        static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+
+    // Thread locals are gone while the thread shuts down
+    &SHARD_COUNTERS[SHARD.try_with(|&shard| shard).unwrap_or(0)]
+}
+
+// Without thread locals, every thread shares the first shard
+#[cfg(not(feature = "std"))]
+fn shard() -> &'static Shard {
+    &SHARD_COUNTERS[0]
+}
+
+// The peak is only raised when pending bytes are flushed or the counters
+// are read, so it can miss short spikes of less than `FLUSH_BYTES` per shard
+fn add_live(shard: &Shard, delta: isize) {
+    let pending = shard.pending.fetch_add(delta, Ordering::Relaxed).wrapping_add(delta);
+
+    if pending.wrapping_abs() < FLUSH_BYTES {
+        return;
+    }
+
+    let pending = shard.pending.swap(0, Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(pending, Ordering::Relaxed).wrapping_add(pending);
+
+    raise_peak(cmp::max(live, 0) as usize);
+}
+
+fn live_bytes() -> usize {
+    let pending = SHARD_COUNTERS.iter()
+        .fold(0isize, |sum, shard| sum.wrapping_add(shard.pending.load(Ordering::Relaxed)));
+
+    cmp::max(LIVE_BYTES.load(Ordering::Relaxed).wrapping_add(pending), 0) as usize
+}
+
+// Raises the peak to `live`, returning the new peak
+fn raise_peak(live: usize) -> usize {
+    let mut peak = PEAK_BYTES.load(Ordering::Relaxed);
+
+    while live > peak {
+        match PEAK_BYTES.compare_exchange_weak(peak, live, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return live,
+            Err(actual) => peak = actual,
+        }
+    }
+
+    peak
+}
+
+const MAX_TAGS: usize = 64;
+
+/// The allocation counters of one tag, as returned by `tag_report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagStats {
+    /// The tag the counters belong to.
+    pub tag: &'static str,
+
+    /// Total number of bytes allocated under the tag.
+    pub allocated_bytes: usize,
+
+    /// Number of bytes allocated under the tag, less those released under
+    /// it.
+    pub live_bytes: usize,
+
+    /// Number of blocks allocated under the tag.
+    pub allocations: usize,
+
+    /// Number of blocks released under the tag.
+    pub deallocations: usize,
+}
+
+struct Tags {
+    slots: [TagSlot; MAX_TAGS],
+
+    // Slots below `len` are in use; `lock` serializes claiming a new one
+    len: AtomicUsize,
+    lock: AtomicBool,
+}
+
+struct TagSlot {
+    // Written once, before the slot is published through `Tags::len`
+    name: UnsafeCell<&'static str>,
+
+    allocated_bytes: AtomicUsize,
+    live_bytes: AtomicIsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+}
+
+unsafe impl Sync for Tags {}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: TagSlot = TagSlot {
+    name: UnsafeCell::new(""),
+    allocated_bytes: AtomicUsize::new(0),
+    live_bytes: AtomicIsize::new(0),
+    allocations: AtomicUsize::new(0),
+    deallocations: AtomicUsize::new(0),
+};
+
+static TAGS: Tags = Tags {
+    slots: [EMPTY_SLOT; MAX_TAGS],
+    len: AtomicUsize::new(0),
+    lock: AtomicBool::new(false),
+};
+
+impl Tags {
+    fn name(&self, i: usize) -> &'static str {
+        unsafe { *self.slots[i].name.get() }
+    }
+
+    fn find(&self, tag: &str, from: usize, to: usize) -> Option<usize> {
+        (from..to).find(|&i| self.name(i) == tag)
+    }
+
+    // Returns the slot of `tag`, claiming one if needed, or `None` if all
+    // slots are taken
+    fn register(&self, tag: &'static str) -> Option<usize> {
+        let len = self.len.load(Ordering::Acquire);
+
+        if let Some(i) = self.find(tag, 0, len) {
+            return Some(i);
+        }
+
+        while self.lock.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            ::core::hint::spin_loop();
+        }
+
+        // Another thread may have claimed slots in the meantime
+        let new_len = self.len.load(Ordering::Relaxed);
+
+        let ret = match self.find(tag, len, new_len) {
+            Some(i) => Some(i),
+            None if new_len == MAX_TAGS => None,
+            None => {
+                unsafe { *self.slots[new_len].name.get() = tag };
+                self.len.store(new_len + 1, Ordering::Release);
+                Some(new_len)
+            }
+        };
+
+        self.lock.store(false, Ordering::Release);
+        ret
+    }
+}
+
+impl TagSlot {
+    fn record_alloc(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.allocated_bytes.fetch_add(size, Ordering::Relaxed);
+        self.live_bytes.fetch_add(size as isize, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(&self, size: usize) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.live_bytes.fetch_sub(size as isize, Ordering::Relaxed);
+    }
+
+    fn record_realloc(&self, old_size: usize, size: usize) {
+        if size > old_size {
+            self.allocated_bytes.fetch_add(size - old_size, Ordering::Relaxed);
+        }
+
+        self.live_bytes.fetch_add((size as isize).wrapping_sub(old_size as isize), Ordering::Relaxed);
+    }
+}
+
+/// Returns the counters of every tag allocated under so far, in the order
+/// the tags were first used.
+pub fn tag_report() -> Vec<TagStats> {
+    let len = TAGS.len.load(Ordering::Acquire);
+
+    (0..len).map(|i| {
+        let slot = &TAGS.slots[i];
+
+        TagStats {
+            tag: TAGS.name(i),
+            allocated_bytes: slot.allocated_bytes.load(Ordering::Relaxed),
+            live_bytes: cmp::max(slot.live_bytes.load(Ordering::Relaxed), 0) as usize,
+            allocations: slot.allocations.load(Ordering::Relaxed),
+            deallocations: slot.deallocations.load(Ordering::Relaxed),
+        }
+    }).collect()
+}
+
+/// Return a pointer to `size` bytes of memory aligned to `align`, counting
+/// it under `tag`.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The same requirements as `allocate` apply. The block should be released
+/// with `deallocate_tagged` using the same tag for the tag's live bytes to
+/// be accurate.
+pub unsafe fn allocate_tagged(size: usize, align: usize, tag: &'static str) -> *mut u8 {
+    let ptr = untagged(|| ::allocate(size, align));
+
+    if !ptr.is_null() {
+        if let Some(tag) = TAGS.register(tag) {
+            TAGS.slots[tag].record_alloc(size);
+        }
+    }
+
+    ptr
+}
+
+/// Deallocates the memory referenced by `ptr`, counting it under `tag`.
+///
+/// # Safety
+///
+/// The same requirements as `deallocate` apply.
+pub unsafe fn deallocate_tagged(ptr: *mut u8, old_size: usize, align: usize, tag: &'static str) {
+    untagged(|| ::deallocate(ptr, old_size, align));
+
+    if let Some(tag) = TAGS.register(tag) {
+        TAGS.slots[tag].record_dealloc(old_size);
+    }
+}
+
+/// Calls `f`, counting the allocations made by the current thread in the
+/// meantime under `tag`.
+///
+/// Blocks released within `f` are counted under `tag` too, wherever they
+/// were allocated, so the live bytes of a tag are only accurate if its
+/// blocks are allocated and released under it. Calls can be nested, in which
+/// case the innermost tag applies.
+#[cfg(feature = "std")]
+pub fn with_tag<F, R>(tag: &'static str, f: F) -> R
+    where F: FnOnce() -> R
+{
+    scoped(TAGS.register(tag), f)
+}
+
+#[cfg(feature = "std")]
+::std::thread_local! {
+    #[allow(clippy::missing_const_for_thread_local)]
+    static CURRENT_TAG: ::core::cell::Cell<Option<usize>> = ::core::cell::Cell::new(None);
+}
+
+#[cfg(feature = "std")]
+fn current_tag() -> Option<usize> {
+    CURRENT_TAG.try_with(|tag| tag.get()).unwrap_or(None)
+}
+
+#[cfg(not(feature = "std"))]
+fn current_tag() -> Option<usize> {
+    None
+}
+
+#[cfg(feature = "std")]
+fn scoped<F, R>(tag: Option<usize>, f: F) -> R
+    where F: FnOnce() -> R
+{
+    // Restores the previous tag, even if `f` panics
+    struct Reset(Option<usize>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            let _ = CURRENT_TAG.try_with(|tag| tag.set(self.0));
+        }
+    }
+
+    let _reset = Reset(CURRENT_TAG.try_with(|current| current.replace(tag)).unwrap_or(None));
+    f()
+}
+
+// Runs `f` without attributing its allocations to the current tag
+#[cfg(feature = "std")]
+fn untagged<F, R>(f: F) -> R
+    where F: FnOnce() -> R
+{
+    scoped(None, f)
+}
+
+#[cfg(not(feature = "std"))]
+fn untagged<F, R>(f: F) -> R
+    where F: FnOnce() -> R
+{
+    f()
+}
+
+#[cfg(test)]
+mod test {
+    use super::snapshot;
+
+    #[test]
+    fn test_counters() {
+        // Other tests allocate concurrently, so only lower bounds hold
+        let before = snapshot();
+
+        unsafe {
+            let ptr = ::allocate(1000, 8);
+            let mid = snapshot();
+            assert!(mid.allocations > before.allocations);
+            assert!(mid.allocated_bytes >= before.allocated_bytes + 1000);
+            assert!(mid.peak_bytes >= 1000);
+
+            let ptr = ::reallocate(ptr, 1000, 2000, 8);
+            assert!(snapshot().reallocations > before.reallocations);
+
+            ::deallocate(ptr, 2000, 8);
+        }
+
+        let after = snapshot();
+        assert!(after.deallocations > before.deallocations);
+        assert!(after.allocated_bytes >= before.allocated_bytes + 2000);
+    }
+
+    #[test]
+    fn test_tagged() {
+        use super::{allocate_tagged, deallocate_tagged, tag_report};
+
+        unsafe {
+            let ptr = allocate_tagged(300, 8, "test_tagged");
+            assert!(!ptr.is_null());
+
+            let report = tag_report();
+            let stats = report.iter().find(|s| s.tag == "test_tagged").unwrap();
+            assert_eq!((300, 300, 1, 0), (stats.allocated_bytes, stats.live_bytes, stats.allocations, stats.deallocations));
+
+            deallocate_tagged(ptr, 300, 8, "test_tagged");
+        }
+
+        let report = tag_report();
+        let stats = report.iter().find(|s| s.tag == "test_tagged").unwrap();
+        assert_eq!((0, 1), (stats.live_bytes, stats.deallocations));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_with_tag() {
+        use super::{tag_report, with_tag};
+
+        let ptr = with_tag("test_with_tag", || unsafe {
+            let inner = with_tag("test_with_tag_inner", || ::allocate(10, 1));
+            ::deallocate(inner, 10, 1);
+
+            ::allocate(100, 1)
+        });
+
+        let report = tag_report();
+        let outer = report.iter().find(|s| s.tag == "test_with_tag").unwrap();
+        let inner = report.iter().find(|s| s.tag == "test_with_tag_inner").unwrap();
+
+        // The inner block was released under the outer tag
+        assert_eq!((100, 1, 1), (outer.allocated_bytes, outer.allocations, outer.deallocations));
+        assert_eq!((10, 1, 0), (inner.allocated_bytes, inner.allocations, inner.deallocations));
+
+        unsafe { ::deallocate(ptr, 100, 1) };
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sharded_counters() {
+        use std::thread;
+        use std::vec::Vec;
+
+        let before = snapshot();
+
+        // The threads land on different shards
+        let threads: Vec<_> = (0..8).map(|_| {
+            thread::spawn(|| unsafe {
+                for _ in 0..100 {
+                    let ptr = ::allocate(100_000, 8);
+                    ::deallocate(ptr, 100_000, 8);
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let after = snapshot();
+        assert!(after.allocations >= before.allocations + 800);
+        assert!(after.deallocations >= before.deallocations + 800);
+        assert!(after.allocated_bytes >= before.allocated_bytes + 80_000_000);
+
+        // Blocks larger than the flush threshold always raise the peak
+        assert!(after.peak_bytes >= 100_000);
+    }
+}
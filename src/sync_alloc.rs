@@ -0,0 +1,297 @@
+use {Alloc, AllocError, Heap, Layout};
+
+use core::{cmp, fmt, hint, ops, ptr};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// An allocator that can be used from several threads at once.
+///
+/// Unlike `Alloc`, the methods take `&self`, so the allocator can be shared
+/// through a plain or `Arc` reference. A shared reference to a `SyncAlloc` is
+/// itself an `Alloc`, which lets `&Heap` or `&SyncArena` be passed wherever an
+/// allocator is expected.
+///
+/// Single-threaded allocators can be shared by wrapping them in `Mutexed`,
+/// which serializes every call with a lock.
+///
+/// # Safety
+///
+/// Implementations must return blocks that fit the requested layout and
+/// remain valid until they are passed back to `dealloc` (or `realloc`), from
+/// whichever thread.
+pub unsafe trait SyncAlloc: Sync {
+    /// Returns a pointer to a block of memory fitting `layout`.
+    ///
+    /// # Safety
+    ///
+    /// The returned memory is uninitialized. It must be released with
+    /// `dealloc` on the same allocator using the same layout.
+    unsafe fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Releases the block referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator for `layout`.
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Returns a pointer to a block of zeroed memory fitting `layout`.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as `alloc` apply.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.alloc(layout)?;
+        ptr::write_bytes(ptr.as_ptr(), 0, layout.size());
+        Ok(ptr)
+    }
+
+    /// Resizes the block referenced by `ptr` to `new_size` bytes.
+    ///
+    /// The contents are preserved up to the lesser of the new and old sizes.
+    /// On failure the original block is left intact.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator for `layout`.
+    unsafe fn realloc(&self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        let new_layout = Layout::from_size_align(new_size, layout.align())?;
+        let new_ptr = self.alloc(new_layout)?;
+
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), cmp::min(layout.size(), new_size));
+        self.dealloc(ptr, layout);
+
+        Ok(new_ptr)
+    }
+}
+
+unsafe impl<A: SyncAlloc + ?Sized> Alloc for &A {
+    #[inline]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        (**self).realloc(ptr, layout, new_size)
+    }
+}
+
+unsafe impl SyncAlloc for Heap {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        ::try_allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        ::release(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        ::try_allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        ::try_reallocate(ptr, layout, new_size)
+    }
+}
+
+/// Shares an allocator between threads by serializing access with a spin
+/// lock.
+///
+/// Every allocation and deallocation takes the lock, so a `Mutexed` allocator
+/// scales poorly when many threads allocate at once. Allocators built for
+/// concurrent use, such as `Heap` or `SyncArena`, avoid the lock altogether.
+pub struct Mutexed<A> {
+    locked: AtomicBool,
+    inner: UnsafeCell<A>,
+}
+
+unsafe impl<A: Send> Sync for Mutexed<A> {}
+
+/// Exclusive access to the allocator of a `Mutexed`, released on drop.
+///
+/// The guard hands out `&A`, so it can only be shared between threads when
+/// the allocator itself is `Sync`:
+///
+/// ```compile_fail
+/// use stable_heap::Mutexed;
+/// use stable_heap::arena::Arena;
+///
+/// fn assert_sync<T: Sync>(_: &T) {}
+///
+/// let arena = Mutexed::new(Arena::new());
+/// assert_sync(&arena.lock());
+/// ```
+pub struct MutexedGuard<'a, A: 'a> {
+    mutexed: &'a Mutexed<A>,
+
+    // Opts out of the `Sync` impl derived from `&Mutexed<A>`
+    _marker: PhantomData<&'a mut A>,
+}
+
+unsafe impl<'a, A: Sync> Sync for MutexedGuard<'a, A> {}
+
+impl<A> Mutexed<A> {
+    /// Wraps `inner`.
+    pub const fn new(inner: A) -> Mutexed<A> {
+        Mutexed {
+            locked: AtomicBool::new(false),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Locks the allocator, spinning until it is available.
+    ///
+    /// The lock isn't reentrant, so the allocator must not call back into the
+    /// same `Mutexed` while it is held.
+    pub fn lock(&self) -> MutexedGuard<'_, A> {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            hint::spin_loop();
+        }
+
+        MutexedGuard { mutexed: self, _marker: PhantomData }
+    }
+
+    /// Locks the allocator if it is available, or returns `None`.
+    pub fn try_lock(&self) -> Option<MutexedGuard<'_, A>> {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
+        Some(MutexedGuard { mutexed: self, _marker: PhantomData })
+    }
+
+    /// Returns a mutable reference to the allocator, without locking.
+    pub fn get_mut(&mut self) -> &mut A {
+        self.inner.get_mut()
+    }
+
+    /// Consumes the wrapper, returning the allocator.
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+}
+
+unsafe impl<A: Alloc + Send> SyncAlloc for Mutexed<A> {
+    unsafe fn alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.lock().dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.lock().alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Result<NonNull<u8>, AllocError> {
+        self.lock().realloc(ptr, layout, new_size)
+    }
+}
+
+impl<A: Default> Default for Mutexed<A> {
+    fn default() -> Mutexed<A> {
+        Mutexed::new(A::default())
+    }
+}
+
+impl<A> fmt::Debug for Mutexed<A> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Mutexed")
+            .field("locked", &self.locked.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl<A> ops::Deref for MutexedGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        // The lock is held, so no mutable reference to the allocator exists
+        unsafe { &*self.mutexed.inner.get() }
+    }
+}
+
+impl<A> ops::DerefMut for MutexedGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        unsafe { &mut *self.mutexed.inner.get() }
+    }
+}
+
+impl<A> Drop for MutexedGuard<'_, A> {
+    fn drop(&mut self) {
+        self.mutexed.locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Alloc, Heap, Layout, Mutexed, StackAlloc, SyncAlloc};
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_mutexed() {
+        let stack = Arc::new(Mutexed::new(StackAlloc::with_capacity(64 * 1024).unwrap()));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let stack = stack.clone();
+
+            thread::spawn(move || unsafe {
+                for i in 0..100 {
+                    let ptr = stack.alloc(layout).unwrap();
+                    *ptr.as_ptr() = (t * 100 + i) as u8;
+                    assert_eq!((t * 100 + i) as u8, *ptr.as_ptr());
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut stack = Arc::try_unwrap(stack).unwrap();
+        assert!(stack.try_lock().is_some());
+        assert!(stack.get_mut().used() >= 400 * 16);
+
+        let guard = stack.lock();
+        assert!(stack.try_lock().is_none());
+        drop(guard);
+    }
+
+    #[test]
+    fn test_shared_ref_alloc() {
+        unsafe fn roundtrip<A: Alloc>(mut alloc: A) {
+            let layout = Layout::new::<[u64; 4]>();
+            let ptr = alloc.alloc_zeroed(layout).unwrap();
+            assert_eq!(0, *ptr.as_ptr().add(31));
+
+            let ptr = alloc.realloc(ptr, layout, 64).unwrap();
+            alloc.dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+        }
+
+        let mutexed = Mutexed::new(Heap);
+
+        unsafe {
+            roundtrip(&Heap);
+            roundtrip(&mutexed);
+            roundtrip(&mutexed as &dyn SyncAlloc);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Bindings to the platform APIs used by the backends.
+//!
+//! Only the subset of functions and constants needed by the crate is
+//! declared here.
+
+#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals, dead_code, unused_imports)]
+#![allow(clippy::upper_case_acronyms)]
+
+#[cfg(unix)]
+mod unix;
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use self::windows::*;
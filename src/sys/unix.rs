@@ -0,0 +1,241 @@
+pub use core::ffi::{c_char, c_long, c_uint, c_void};
+
+use {Advice, Protection};
+
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub type c_int = i32;
+pub type size_t = usize;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub type off_t = isize;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub type off_t = i64;
+
+pub const PROT_NONE: c_int = 0;
+pub const PROT_READ: c_int = 1;
+pub const PROT_WRITE: c_int = 2;
+pub const PROT_EXEC: c_int = 4;
+
+pub const MAP_SHARED: c_int = 0x01;
+pub const MAP_PRIVATE: c_int = 0x02;
+pub const MAP_FIXED: c_int = 0x10;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64"))))]
+pub const MAP_ANONYMOUS: c_int = 0x20;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), any(target_arch = "mips", target_arch = "mips64")))]
+pub const MAP_ANONYMOUS: c_int = 0x800;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub const MAP_ANONYMOUS: c_int = 0x1000;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MAP_FIXED_NOREPLACE: c_int = 0x10_0000;
+
+#[cfg(target_os = "freebsd")]
+pub const MAP_EXCL: c_int = 0x4000;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64"))))]
+pub const MAP_NORESERVE: c_int = 0x4000;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), any(target_arch = "mips", target_arch = "mips64")))]
+pub const MAP_NORESERVE: c_int = 0x0400;
+
+pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MREMAP_MAYMOVE: c_int = 1;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64"))))]
+pub const MAP_HUGETLB: c_int = 0x40000;
+
+#[cfg(all(any(target_os = "linux", target_os = "android"), any(target_arch = "mips", target_arch = "mips64")))]
+pub const MAP_HUGETLB: c_int = 0x80000;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MAP_HUGE_SHIFT: c_int = 26;
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "netbsd"))]
+pub const MS_SYNC: c_int = 4;
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub const MS_SYNC: c_int = 0x10;
+
+#[cfg(target_os = "openbsd")]
+pub const MS_SYNC: c_int = 2;
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "netbsd", target_os = "macos", target_os = "ios", target_os = "openbsd")))]
+pub const MS_SYNC: c_int = 0;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MFD_CLOEXEC: c_uint = 1;
+
+pub const O_RDWR: c_int = 2;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const O_CREAT: c_int = 0o100;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const O_EXCL: c_int = 0o200;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub const O_CREAT: c_int = 0x200;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub const O_EXCL: c_int = 0x800;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub const SYS_mbind: c_long = 237;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub const SYS_mbind: c_long = 274;
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+pub const SYS_mbind: c_long = 235;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub const SYS_mbind: c_long = 319;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub const _SC_LEVEL1_DCACHE_LINESIZE: c_int = 190;
+
+pub const MADV_RANDOM: c_int = 1;
+pub const MADV_SEQUENTIAL: c_int = 2;
+pub const MADV_WILLNEED: c_int = 3;
+pub const MADV_DONTNEED: c_int = 4;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MADV_HUGEPAGE: c_int = 14;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const MADV_DONTDUMP: c_int = 16;
+
+extern "C" {
+    pub fn malloc(size: size_t) -> *mut c_void;
+    pub fn calloc(nobj: size_t, size: size_t) -> *mut c_void;
+    pub fn realloc(p: *mut c_void, size: size_t) -> *mut c_void;
+    pub fn free(p: *mut c_void);
+    pub fn posix_memalign(memptr: *mut *mut c_void, align: size_t, size: size_t) -> c_int;
+
+    pub fn mmap(addr: *mut c_void, len: size_t, prot: c_int, flags: c_int, fd: c_int, offset: off_t) -> *mut c_void;
+    pub fn munmap(addr: *mut c_void, len: size_t) -> c_int;
+    pub fn mprotect(addr: *mut c_void, len: size_t, prot: c_int) -> c_int;
+    pub fn msync(addr: *mut c_void, len: size_t, flags: c_int) -> c_int;
+    pub fn madvise(addr: *mut c_void, len: size_t, advice: c_int) -> c_int;
+    pub fn mlock(addr: *const c_void, len: size_t) -> c_int;
+    pub fn munlock(addr: *const c_void, len: size_t) -> c_int;
+    pub fn getpagesize() -> c_int;
+    pub fn sysconf(name: c_int) -> c_long;
+
+    pub fn atexit(cb: extern "C" fn()) -> c_int;
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+extern "C" {
+    pub fn sysctlbyname(name: *const c_char, oldp: *mut c_void, oldlenp: *mut size_t, newp: *mut c_void, newlen: size_t) -> c_int;
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+extern "C" {
+    pub fn __clear_cache(start: *mut c_void, end: *mut c_void);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+extern "C" {
+    pub fn shm_open(name: *const c_char, oflag: c_int, ...) -> c_int;
+    pub fn shm_unlink(name: *const c_char) -> c_int;
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+extern "C" {
+    pub fn syscall(num: c_long, ...) -> c_long;
+    pub fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+    pub fn mremap(addr: *mut c_void, len: size_t, new_len: size_t, flags: c_int, ...) -> *mut c_void;
+}
+
+/// Maps `len` bytes of private, zeroed, read-write memory, passing `flags` in
+/// addition to `MAP_PRIVATE | MAP_ANONYMOUS`.
+///
+/// Returns null on failure.
+pub unsafe fn map_anonymous(len: usize, flags: c_int) -> *mut u8 {
+    let ptr = mmap(
+        ptr::null_mut(),
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS | flags,
+        -1,
+        0);
+
+    if ptr == MAP_FAILED {
+        return ptr::null_mut();
+    }
+
+    ptr as *mut u8
+}
+
+/// Unmaps memory obtained from `map_anonymous`.
+pub unsafe fn unmap(ptr: *mut u8, len: usize) {
+    munmap(ptr as *mut c_void, len);
+}
+
+/// Lets the OS reclaim the pages in `ptr..ptr + len`, discarding their
+/// contents. The range stays mapped and is backed again on the next access.
+pub unsafe fn decommit(ptr: *mut u8, len: usize) -> bool {
+    madvise(ptr as *mut c_void, len, MADV_DONTNEED) == 0
+}
+
+/// Makes pages passed to `decommit` usable again.
+pub unsafe fn recommit(_ptr: *mut u8, _len: usize) -> bool {
+    // Pages are faulted back in on access
+    true
+}
+
+/// Passes `advice` about the pages in `ptr..ptr + len` to `madvise`.
+///
+/// Returns `false` on failure, leaving the reason in `errno`.
+pub unsafe fn advise(ptr: *mut u8, len: usize, advice: Advice) -> bool {
+    let advice = match advice {
+        Advice::WillNeed => MADV_WILLNEED,
+        Advice::DontNeed => MADV_DONTNEED,
+        Advice::Sequential => MADV_SEQUENTIAL,
+        Advice::Random => MADV_RANDOM,
+    };
+
+    madvise(ptr as *mut c_void, len, advice) == 0
+}
+
+/// Changes the protection of the pages in `ptr..ptr + len`.
+///
+/// Returns `false` on failure, leaving the reason in `errno`.
+pub unsafe fn protect(ptr: *mut u8, len: usize, prot: Protection) -> bool {
+    let prot = match prot {
+        Protection::NoAccess => PROT_NONE,
+        Protection::ReadOnly => PROT_READ,
+        Protection::ReadWrite => PROT_READ | PROT_WRITE,
+        Protection::ReadExecute => PROT_READ | PROT_EXEC,
+    };
+
+    mprotect(ptr as *mut c_void, len, prot) == 0
+}
+
+/// Returns the size of a page, caching it after the first call.
+pub fn page_size() -> usize {
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let size = unsafe { getpagesize() } as usize;
+            PAGE_SIZE.store(size, Ordering::Relaxed);
+            size
+        }
+        size => size,
+    }
+}
+
+/// Mappings are placed at page granularity.
+pub fn allocation_granularity() -> usize {
+    page_size()
+}
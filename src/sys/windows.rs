@@ -0,0 +1,214 @@
+pub use core::ffi::c_void;
+
+use {Advice, Protection};
+
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub type size_t = usize;
+pub type BOOL = i32;
+pub type DWORD = u32;
+pub type HANDLE = *mut c_void;
+pub type LPCWSTR = *const u16;
+pub type LPVOID = *mut c_void;
+pub type SIZE_T = usize;
+
+pub const INVALID_HANDLE_VALUE: HANDLE = !0 as HANDLE;
+
+pub const HEAP_ZERO_MEMORY: DWORD = 0x0000_0008;
+
+pub const MEM_COMMIT: DWORD = 0x0000_1000;
+pub const MEM_RESERVE: DWORD = 0x0000_2000;
+pub const MEM_DECOMMIT: DWORD = 0x0000_4000;
+pub const MEM_RELEASE: DWORD = 0x0000_8000;
+pub const MEM_RESET: DWORD = 0x0008_0000;
+pub const MEM_LARGE_PAGES: DWORD = 0x2000_0000;
+
+pub const PAGE_NOACCESS: DWORD = 0x01;
+pub const PAGE_READONLY: DWORD = 0x02;
+pub const PAGE_READWRITE: DWORD = 0x04;
+pub const PAGE_WRITECOPY: DWORD = 0x08;
+pub const PAGE_EXECUTE_READ: DWORD = 0x20;
+
+pub const FILE_MAP_COPY: DWORD = 0x01;
+pub const FILE_MAP_WRITE: DWORD = 0x02;
+pub const FILE_MAP_READ: DWORD = 0x04;
+
+#[repr(C)]
+pub struct SYSTEM_INFO {
+    pub wProcessorArchitecture: u16,
+    pub wReserved: u16,
+    pub dwPageSize: DWORD,
+    pub lpMinimumApplicationAddress: LPVOID,
+    pub lpMaximumApplicationAddress: LPVOID,
+    pub dwActiveProcessorMask: usize,
+    pub dwNumberOfProcessors: DWORD,
+    pub dwProcessorType: DWORD,
+    pub dwAllocationGranularity: DWORD,
+    pub wProcessorLevel: u16,
+    pub wProcessorRevision: u16,
+}
+
+#[repr(C)]
+pub struct WIN32_MEMORY_RANGE_ENTRY {
+    pub VirtualAddress: LPVOID,
+    pub NumberOfBytes: SIZE_T,
+}
+
+extern "C" {
+    pub fn _aligned_malloc(size: size_t, alignment: size_t) -> *mut c_void;
+    pub fn _aligned_realloc(memblock: *mut c_void, size: size_t, alignment: size_t) -> *mut c_void;
+    pub fn _aligned_free(memblock: *mut c_void);
+
+    pub fn atexit(func: extern "C" fn()) -> i32;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn GetProcessHeap() -> HANDLE;
+    pub fn HeapAlloc(hHeap: HANDLE, dwFlags: DWORD, dwBytes: SIZE_T) -> LPVOID;
+    pub fn HeapReAlloc(hHeap: HANDLE, dwFlags: DWORD, lpMem: LPVOID, dwBytes: SIZE_T) -> LPVOID;
+    pub fn HeapFree(hHeap: HANDLE, dwFlags: DWORD, lpMem: LPVOID) -> BOOL;
+
+    pub fn VirtualAlloc(lpAddress: LPVOID, dwSize: SIZE_T, flAllocationType: DWORD, flProtect: DWORD) -> LPVOID;
+    pub fn VirtualAllocExNuma(
+        hProcess: HANDLE,
+        lpAddress: LPVOID,
+        dwSize: SIZE_T,
+        flAllocationType: DWORD,
+        flProtect: DWORD,
+        nndPreferred: DWORD) -> LPVOID;
+    pub fn VirtualFree(lpAddress: LPVOID, dwSize: SIZE_T, dwFreeType: DWORD) -> BOOL;
+    pub fn VirtualProtect(lpAddress: LPVOID, dwSize: SIZE_T, flNewProtect: DWORD, lpflOldProtect: *mut DWORD) -> BOOL;
+    pub fn VirtualLock(lpAddress: LPVOID, dwSize: SIZE_T) -> BOOL;
+    pub fn VirtualUnlock(lpAddress: LPVOID, dwSize: SIZE_T) -> BOOL;
+    pub fn PrefetchVirtualMemory(
+        hProcess: HANDLE,
+        NumberOfEntries: SIZE_T,
+        VirtualAddresses: *const WIN32_MEMORY_RANGE_ENTRY,
+        Flags: DWORD) -> BOOL;
+
+    pub fn CreateFileMappingW(
+        hFile: HANDLE,
+        lpAttributes: LPVOID,
+        flProtect: DWORD,
+        dwMaximumSizeHigh: DWORD,
+        dwMaximumSizeLow: DWORD,
+        lpName: LPCWSTR) -> HANDLE;
+    pub fn MapViewOfFile(
+        hFileMappingObject: HANDLE,
+        dwDesiredAccess: DWORD,
+        dwFileOffsetHigh: DWORD,
+        dwFileOffsetLow: DWORD,
+        dwNumberOfBytesToMap: SIZE_T) -> LPVOID;
+    pub fn MapViewOfFileEx(
+        hFileMappingObject: HANDLE,
+        dwDesiredAccess: DWORD,
+        dwFileOffsetHigh: DWORD,
+        dwFileOffsetLow: DWORD,
+        dwNumberOfBytesToMap: SIZE_T,
+        lpBaseAddress: LPVOID) -> LPVOID;
+    pub fn UnmapViewOfFile(lpBaseAddress: LPVOID) -> BOOL;
+    pub fn FlushViewOfFile(lpBaseAddress: LPVOID, dwNumberOfBytesToFlush: SIZE_T) -> BOOL;
+    pub fn CloseHandle(hObject: HANDLE) -> BOOL;
+
+    pub fn GetCurrentProcess() -> HANDLE;
+    pub fn FlushInstructionCache(hProcess: HANDLE, lpBaseAddress: LPVOID, dwSize: SIZE_T) -> BOOL;
+
+    pub fn GetSystemInfo(lpSystemInfo: *mut SYSTEM_INFO);
+    pub fn GetLargePageMinimum() -> SIZE_T;
+}
+
+/// Commits `len` bytes of zeroed, read-write memory, passing `flags` in
+/// addition to `MEM_COMMIT | MEM_RESERVE`.
+///
+/// Returns null on failure.
+pub unsafe fn map_anonymous(len: usize, flags: DWORD) -> *mut u8 {
+    VirtualAlloc(ptr::null_mut(), len, MEM_COMMIT | MEM_RESERVE | flags, PAGE_READWRITE) as *mut u8
+}
+
+/// Releases memory obtained from `map_anonymous`.
+pub unsafe fn unmap(ptr: *mut u8, _len: usize) {
+    VirtualFree(ptr as LPVOID, 0, MEM_RELEASE);
+}
+
+/// Decommits the pages in `ptr..ptr + len`, discarding their contents. The
+/// range stays reserved, but faults until it is passed to `recommit`.
+pub unsafe fn decommit(ptr: *mut u8, len: usize) -> bool {
+    VirtualFree(ptr as LPVOID, len, MEM_DECOMMIT) != 0
+}
+
+/// Commits pages passed to `decommit` again, zeroed.
+pub unsafe fn recommit(ptr: *mut u8, len: usize) -> bool {
+    !VirtualAlloc(ptr as LPVOID, len, MEM_COMMIT, PAGE_READWRITE).is_null()
+}
+
+/// Passes `advice` about the pages in `ptr..ptr + len` to the memory manager.
+/// Advice without a Windows equivalent is ignored.
+///
+/// Returns `false` on failure, leaving the reason in `GetLastError`.
+pub unsafe fn advise(ptr: *mut u8, len: usize, advice: Advice) -> bool {
+    match advice {
+        Advice::WillNeed => {
+            let range = WIN32_MEMORY_RANGE_ENTRY {
+                VirtualAddress: ptr as LPVOID,
+                NumberOfBytes: len,
+            };
+
+            PrefetchVirtualMemory(GetCurrentProcess(), 1, &range, 0) != 0
+        }
+        Advice::DontNeed => !VirtualAlloc(ptr as LPVOID, len, MEM_RESET, PAGE_READWRITE).is_null(),
+        Advice::Sequential | Advice::Random => true,
+    }
+}
+
+/// Changes the protection of the pages in `ptr..ptr + len`.
+///
+/// Returns `false` on failure, leaving the reason in `GetLastError`.
+pub unsafe fn protect(ptr: *mut u8, len: usize, prot: Protection) -> bool {
+    let prot = match prot {
+        Protection::NoAccess => PAGE_NOACCESS,
+        Protection::ReadOnly => PAGE_READONLY,
+        Protection::ReadWrite => PAGE_READWRITE,
+        Protection::ReadExecute => PAGE_EXECUTE_READ,
+    };
+
+    let mut old = 0;
+    VirtualProtect(ptr as LPVOID, len, prot, &mut old) != 0
+}
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+static GRANULARITY: AtomicUsize = AtomicUsize::new(0);
+
+fn system_info() {
+    unsafe {
+        let mut info: SYSTEM_INFO = mem::zeroed();
+        GetSystemInfo(&mut info);
+
+        GRANULARITY.store(info.dwAllocationGranularity as usize, Ordering::Relaxed);
+        PAGE_SIZE.store(info.dwPageSize as usize, Ordering::Relaxed);
+    }
+}
+
+/// Returns the size of a page, caching it after the first call.
+pub fn page_size() -> usize {
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            system_info();
+            PAGE_SIZE.load(Ordering::Relaxed)
+        }
+        size => size,
+    }
+}
+
+/// Returns the granularity of `VirtualAlloc` addresses, caching it after the
+/// first call.
+pub fn allocation_granularity() -> usize {
+    match GRANULARITY.load(Ordering::Relaxed) {
+        0 => {
+            system_info();
+            GRANULARITY.load(Ordering::Relaxed)
+        }
+        size => size,
+    }
+}
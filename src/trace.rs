@@ -0,0 +1,465 @@
+//! Allocation tracing.
+//!
+//! With the `trace` feature, the heap allocation functions can record every
+//! allocation, deallocation and reallocation between `start` and `stop`,
+//! along with when and on which thread it happened. The resulting `Trace`
+//! can be saved in a compact binary form and replayed against any `Alloc`,
+//! which makes it possible to compare allocators on the allocation pattern of
+//! a real workload rather than a synthetic benchmark.
+//!
+//! Blocks are identified by the order they were allocated in rather than by
+//! address, so a trace replays the same way wherever the allocator places
+//! the blocks. Blocks allocated before recording started are left out.
+//!
+//! Recording takes a lock on every event, so traces are meant to be
+//! captured from test or staging runs rather than production.
+
+use {reentrancy, Alloc, Layout};
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ptr::NonNull;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+const MAGIC: &[u8; 4] = b"SHT1";
+
+/// An operation recorded in a `Trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// The block was allocated with `size` and `align`.
+    Alloc,
+
+    /// The block was released.
+    Free,
+
+    /// The block was resized from `old_size` to `size`.
+    Realloc {
+        old_size: usize,
+    },
+}
+
+/// One event of a `Trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// What happened to the block.
+    pub op: Op,
+
+    /// The block, numbered in allocation order from 0.
+    pub block: u64,
+
+    /// The size of the block after the event.
+    pub size: usize,
+
+    /// The alignment of the block.
+    pub align: usize,
+
+    /// Nanoseconds since recording started.
+    pub time: u64,
+
+    /// The thread the event happened on, numbered from 1 in the order the
+    /// threads first allocated.
+    pub thread: u64,
+}
+
+/// A recorded sequence of allocation events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    events: Vec<Event>,
+}
+
+/// The outcome of replaying a `Trace`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Replay {
+    /// Number of events replayed.
+    pub operations: usize,
+
+    /// Number of allocations and reallocations the allocator failed.
+    pub failures: usize,
+
+    /// Number of events skipped, as they referred to a block that was never
+    /// allocated or whose allocation failed.
+    pub skipped: usize,
+
+    /// Time spent in the allocator.
+    pub elapsed: Duration,
+
+    /// The largest number of bytes live at once.
+    pub peak_bytes: usize,
+}
+
+struct Recorder {
+    start: Instant,
+    next_block: u64,
+
+    // Blocks allocated since recording started, by address
+    live: HashMap<usize, u64>,
+    events: Vec<Event>,
+}
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+::std::thread_local! {
+    #[allow(clippy::missing_const_for_thread_local)]
+    static THREAD: u64 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+
+    // Set while the thread records an event
+    #[allow(clippy::missing_const_for_thread_local)]
+    static BUSY: Cell<bool> = Cell::new(false);
+}
+
+/// Starts recording, discarding any events recorded so far.
+pub fn start() {
+    let mut recorder = RECORDER.lock().unwrap_or_else(|e| e.into_inner());
+
+    *recorder = Some(Recorder {
+        start: Instant::now(),
+        next_block: 0,
+        live: HashMap::new(),
+        events: Vec::new(),
+    });
+
+    RECORDING.store(true, Ordering::Release);
+}
+
+/// Stops recording and returns the events recorded since `start`.
+pub fn stop() -> Trace {
+    RECORDING.store(false, Ordering::Release);
+
+    let mut recorder = RECORDER.lock().unwrap_or_else(|e| e.into_inner());
+
+    Trace {
+        events: recorder.take().map(|recorder| recorder.events).unwrap_or_default(),
+    }
+}
+
+/// Returns `true` between `start` and `stop`.
+pub fn is_recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+impl Trace {
+    /// Creates a trace of `events`, in the order they happened.
+    pub fn from_events(events: Vec<Event>) -> Trace {
+        Trace { events }
+    }
+
+    /// Returns the events, in the order they happened.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns the number of events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if the trace has no events.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Writes the trace to `out` in a compact binary form.
+    ///
+    /// Integers are variable-length and times are relative to the previous
+    /// event, so most events take a handful of bytes.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(8 + self.events.len() * 8);
+        buf.extend_from_slice(MAGIC);
+        varint(&mut buf, self.events.len() as u64);
+
+        let mut time = 0;
+
+        for event in &self.events {
+            match event.op {
+                Op::Alloc => buf.push(0),
+                Op::Free => buf.push(1),
+                Op::Realloc { old_size } => {
+                    buf.push(2);
+                    varint(&mut buf, old_size as u64);
+                }
+            }
+
+            varint(&mut buf, event.block);
+            varint(&mut buf, event.size as u64);
+            buf.push(event.align.trailing_zeros() as u8);
+            varint(&mut buf, event.time.wrapping_sub(time));
+            varint(&mut buf, event.thread);
+
+            time = event.time;
+        }
+
+        out.write_all(&buf)
+    }
+
+    /// Reads a trace written by `write_to`.
+    ///
+    /// Returns an `InvalidData` error if the input is not a trace.
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Trace> {
+        let mut buf = Vec::new();
+        input.read_to_end(&mut buf)?;
+
+        if !buf.starts_with(MAGIC) {
+            return Err(invalid());
+        }
+
+        let mut input = &buf[MAGIC.len()..];
+        let len = read_varint(&mut input)? as usize;
+
+        // Every event takes at least 6 bytes
+        let mut events = Vec::with_capacity(len.min(input.len() / 6));
+        let mut time = 0u64;
+
+        for _ in 0..len {
+            let op = match read_u8(&mut input)? {
+                0 => Op::Alloc,
+                1 => Op::Free,
+                2 => Op::Realloc { old_size: read_varint(&mut input)? as usize },
+                _ => return Err(invalid()),
+            };
+
+            let block = read_varint(&mut input)?;
+            let size = read_varint(&mut input)? as usize;
+
+            let align = match read_u8(&mut input)? {
+                shift if (shift as u32) < usize::BITS => 1 << shift,
+                _ => return Err(invalid()),
+            };
+
+            time = time.wrapping_add(read_varint(&mut input)?);
+            let thread = read_varint(&mut input)?;
+
+            events.push(Event { op, block, size, align, time, thread });
+        }
+
+        Ok(Trace { events })
+    }
+
+    /// Re-executes the events against `alloc`, one after the other.
+    ///
+    /// The blocks still live at the end of the trace are released afterwards,
+    /// outside of the measured time. Events arrive in the order they were
+    /// recorded in, the threads and timing of the original run are not
+    /// reproduced.
+    pub fn replay<A: Alloc>(&self, mut alloc: A) -> Replay {
+        let mut report = Replay::default();
+        let mut blocks: HashMap<u64, (NonNull<u8>, Layout)> = HashMap::new();
+        let mut live = 0usize;
+
+        for event in &self.events {
+            report.operations += 1;
+
+            let start = Instant::now();
+
+            let ok = unsafe {
+                match event.op {
+                    Op::Alloc => match Layout::from_size_align(event.size, event.align) {
+                        Ok(layout) => alloc.alloc(layout).map(|ptr| {
+                            blocks.insert(event.block, (ptr, layout));
+                            live += layout.size();
+                        }).is_ok(),
+                        Err(_) => false,
+                    },
+                    Op::Free => match blocks.remove(&event.block) {
+                        Some((ptr, layout)) => {
+                            alloc.dealloc(ptr, layout);
+                            live -= layout.size();
+                            true
+                        }
+                        None => {
+                            report.skipped += 1;
+                            true
+                        }
+                    },
+                    Op::Realloc { .. } => match blocks.get_mut(&event.block) {
+                        Some(block) => {
+                            let (ptr, layout) = *block;
+
+                            alloc.realloc(ptr, layout, event.size).map(|ptr| {
+                                *block = (ptr, Layout::from_size_align_unchecked(event.size, layout.align()));
+                                live = live - layout.size() + event.size;
+                            }).is_ok()
+                        }
+                        None => {
+                            report.skipped += 1;
+                            true
+                        }
+                    },
+                }
+            };
+
+            report.elapsed += start.elapsed();
+
+            if !ok {
+                report.failures += 1;
+            }
+
+            report.peak_bytes = report.peak_bytes.max(live);
+        }
+
+        for (_, (ptr, layout)) in blocks {
+            unsafe { alloc.dealloc(ptr, layout) };
+        }
+
+        report
+    }
+}
+
+pub(crate) fn record_alloc(ptr: *mut u8, size: usize, align: usize) {
+    if is_recording() {
+        record(|recorder| {
+            let block = recorder.next_block;
+            recorder.next_block += 1;
+            recorder.live.insert(ptr as usize, block);
+            Some((Op::Alloc, block, size, align))
+        });
+    }
+}
+
+pub(crate) fn record_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    if is_recording() {
+        record(|recorder| {
+            recorder.live.remove(&(ptr as usize)).map(|block| (Op::Free, block, size, align))
+        });
+    }
+}
+
+pub(crate) fn record_realloc(old_ptr: *mut u8, ptr: *mut u8, old_size: usize, size: usize, align: usize) {
+    if is_recording() {
+        record(|recorder| {
+            match recorder.live.remove(&(old_ptr as usize)) {
+                Some(block) => {
+                    recorder.live.insert(ptr as usize, block);
+                    Some((Op::Realloc { old_size }, block, size, align))
+                }
+                // Allocated before recording started, so it is new to the
+                // trace
+                None => {
+                    let block = recorder.next_block;
+                    recorder.next_block += 1;
+                    recorder.live.insert(ptr as usize, block);
+                    Some((Op::Alloc, block, size, align))
+                }
+            }
+        });
+    }
+}
+
+fn record<F>(f: F)
+    where F: FnOnce(&mut Recorder) -> Option<(Op, u64, usize, usize)>
+{
+    reentrancy::guarded(&BUSY, || {
+        let thread = THREAD.try_with(|&thread| thread).unwrap_or(0);
+        let mut recorder = RECORDER.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(ref mut recorder) = *recorder {
+            if let Some((op, block, size, align)) = f(recorder) {
+                let time = recorder.start.elapsed().as_nanos() as u64;
+                recorder.events.push(Event { op, block, size, align, time, thread });
+            }
+        }
+    });
+}
+
+fn varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+
+    buf.push(n as u8);
+}
+
+fn read_u8(input: &mut &[u8]) -> io::Result<u8> {
+    let (&b, rest) = input.split_first().ok_or_else(invalid)?;
+    *input = rest;
+    Ok(b)
+}
+
+fn read_varint(input: &mut &[u8]) -> io::Result<u64> {
+    let mut n = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let b = read_u8(input)?;
+        n |= ((b & 0x7f) as u64) << shift;
+
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+
+    Err(invalid())
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid allocation trace")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{start, stop, Event, Op, Trace};
+    use {Budget, Heap};
+    use std::io;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_record_and_replay() {
+        start();
+
+        unsafe {
+            let a = ::allocate(12347, 16);
+            let b = ::allocate(23459, 8);
+            let b = ::reallocate(b, 23459, 34571, 8);
+            ::deallocate(a, 12347, 16);
+            ::deallocate(b, 34571, 8);
+        }
+
+        let trace = stop();
+
+        // Other tests allocate at the same time, keep this thread's blocks
+        let mine: Vec<Event> = trace.events().iter()
+            .filter(|e| [12347, 23459, 34571].contains(&e.size))
+            .cloned()
+            .collect();
+
+        let ops: Vec<Op> = mine.iter().map(|e| e.op).collect();
+        assert_eq!(::std::vec![Op::Alloc, Op::Alloc, Op::Realloc { old_size: 23459 }, Op::Free, Op::Free], ops);
+        assert_eq!(mine[0].block, mine[3].block);
+        assert_eq!(mine[1].block, mine[2].block);
+        assert!(mine.windows(2).all(|w| w[0].time <= w[1].time && w[0].thread == w[1].thread));
+        assert_eq!(16, mine[0].align);
+
+        let trace = Trace::from_events(mine);
+
+        let mut encoded = Vec::new();
+        trace.write_to(&mut encoded).unwrap();
+        assert_eq!(trace, Trace::read_from(&mut &encoded[..]).unwrap());
+
+        let replay = trace.replay(Heap);
+        assert_eq!((5, 0, 0), (replay.operations, replay.failures, replay.skipped));
+        assert_eq!(12347 + 34571, replay.peak_bytes);
+
+        // The failed reallocation leaves the block as it was
+        let replay = trace.replay(Budget::new(Heap, 40000));
+        assert_eq!((1, 0), (replay.failures, replay.skipped));
+        assert_eq!(12347 + 23459, replay.peak_bytes);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        let err = Trace::read_from(&mut &b"SHT0"[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Truncated in the middle of an event
+        let trace = Trace::from_events(::std::vec![Event { op: Op::Alloc, block: 0, size: 300, align: 8, time: 5, thread: 1 }]);
+        let mut encoded = Vec::new();
+        trace.write_to(&mut encoded).unwrap();
+        encoded.pop();
+        assert!(Trace::read_from(&mut &encoded[..]).is_err());
+    }
+}
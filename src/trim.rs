@@ -0,0 +1,109 @@
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// How much memory `trim` is asked to give back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TrimLevel {
+    /// Release memory that is cheap to get back, such as cached blocks.
+    Moderate,
+
+    /// Release everything that can be released, even at the cost of slower
+    /// allocations afterwards.
+    Critical,
+}
+
+const MAX_CALLBACKS: usize = 32;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+// Registered callbacks as data pointers, filled from the front
+static CALLBACKS: [AtomicPtr<()>; MAX_CALLBACKS] = [EMPTY; MAX_CALLBACKS];
+
+/// Registers `callback` to be called by `trim`.
+///
+/// Callbacks release the memory held by caches and allocators the crate
+/// doesn't know about, for instance by calling `Arena::trim` or dropping pooled
+/// objects. They can't be unregistered.
+///
+/// # Panics
+///
+/// Panics if 32 callbacks are already registered.
+pub fn register_trim_callback(callback: fn(TrimLevel)) {
+    for slot in CALLBACKS.iter() {
+        let res = slot.compare_exchange(ptr::null_mut(), callback as *mut (), Ordering::AcqRel, Ordering::Acquire);
+
+        if res.is_ok() {
+            return;
+        }
+    }
+
+    panic!("too many trim callbacks registered; max={}", MAX_CALLBACKS);
+}
+
+/// Gives memory back under memory pressure.
+///
+/// The blocks cached by the calling thread in `ThreadCache` are returned to
+/// the heap, then every callback registered with `register_trim_callback` is
+/// called with `level`, in registration order. Other threads keep their
+/// caches until they call `trim` or `ThreadCache::flush` themselves.
+pub fn trim(level: TrimLevel) {
+    #[cfg(feature = "std")]
+    ::ThreadCache::flush();
+
+    for slot in CALLBACKS.iter() {
+        let callback = slot.load(Ordering::Acquire);
+
+        if callback.is_null() {
+            break;
+        }
+
+        // Only ever stored from a `fn(TrimLevel)`
+        let callback: fn(TrimLevel) = unsafe { mem::transmute(callback) };
+        callback(level);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {register_trim_callback, trim, TrimLevel};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static MODERATE: AtomicUsize = AtomicUsize::new(0);
+    static CRITICAL: AtomicUsize = AtomicUsize::new(0);
+
+    fn callback(level: TrimLevel) {
+        match level {
+            TrimLevel::Moderate => MODERATE.fetch_add(1, Ordering::SeqCst),
+            TrimLevel::Critical => CRITICAL.fetch_add(1, Ordering::SeqCst),
+        };
+    }
+
+    #[test]
+    fn test_trim() {
+        register_trim_callback(callback);
+
+        trim(TrimLevel::Moderate);
+        assert!(MODERATE.load(Ordering::SeqCst) >= 1);
+
+        trim(TrimLevel::Critical);
+        assert!(CRITICAL.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_trim_flushes_thread_cache() {
+        use {Alloc, Layout, ThreadCache};
+
+        let layout = Layout::from_size_align(48, 8).unwrap();
+
+        unsafe {
+            let ptr = ThreadCache.alloc(layout).unwrap();
+            ThreadCache.dealloc(ptr, layout);
+        }
+
+        assert!(ThreadCache::cached() > 0);
+        trim(TrimLevel::Moderate);
+        assert_eq!(0, ThreadCache::cached());
+    }
+}
@@ -0,0 +1,346 @@
+use {AllocError, Layout};
+
+use core::{mem, ptr, slice};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+/// Return a pointer to uninitialized memory for a value of type `T`.
+///
+/// The size and alignment are taken from the type. For zero-sized types, a
+/// dangling pointer aligned for `T` is returned without allocating.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_one::<T>`.
+///
+/// # Panics
+///
+/// Panics if the alignment of `T` is not supported.
+#[inline]
+pub unsafe fn allocate_one<T>() -> *mut T {
+    let size = mem::size_of::<T>();
+    let align = mem::align_of::<T>();
+
+    if size == 0 {
+        return NonNull::dangling().as_ptr();
+    }
+
+    ::allocate(size, align) as *mut T
+}
+
+/// Deallocates memory obtained from `allocate_one::<T>`, without dropping the
+/// value.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_one::<T>`.
+#[inline]
+pub unsafe fn deallocate_one<T>(ptr: *mut T) {
+    let size = mem::size_of::<T>();
+
+    if size != 0 {
+        ::deallocate(ptr as *mut u8, size, mem::align_of::<T>())
+    }
+}
+
+/// Allocates memory for a value of type `T` and moves `value` into it.
+///
+/// On failure, `value` is dropped and a null pointer is returned.
+///
+/// # Safety
+///
+/// The value must be released with `drop_and_deallocate::<T>`, or moved out
+/// and the memory released with `deallocate_one::<T>`.
+///
+/// # Panics
+///
+/// Panics if the alignment of `T` is not supported.
+#[inline]
+pub unsafe fn allocate_init<T>(value: T) -> *mut T {
+    let ptr = allocate_one::<T>();
+
+    if !ptr.is_null() {
+        ptr::write(ptr, value);
+    }
+
+    ptr
+}
+
+/// Drops the value referenced by `ptr` and deallocates its memory.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `allocate_init::<T>`, or by
+/// `allocate_one::<T>` and initialized since.
+#[inline]
+pub unsafe fn drop_and_deallocate<T>(ptr: *mut T) {
+    ptr::drop_in_place(ptr);
+    deallocate_one(ptr)
+}
+
+/// Return a pointer to uninitialized memory for an array of `n` values of
+/// type `T`.
+///
+/// The size is computed as `n * size_of::<T>()` with overflow checking. If the
+/// array is empty or `T` is zero-sized, a dangling pointer aligned for `T` is
+/// returned without allocating.
+///
+/// On failure, or if the size overflows, return a null pointer.
+///
+/// # Safety
+///
+/// The memory must be released with `deallocate_array::<T>` using the same
+/// `n`.
+///
+/// # Panics
+///
+/// Panics if the alignment of `T` is not supported.
+#[inline]
+pub unsafe fn allocate_array<T>(n: usize) -> *mut T {
+    match Layout::array::<T>(n) {
+        Ok(layout) if layout.size() == 0 => NonNull::dangling().as_ptr(),
+        Ok(layout) => ::allocate(layout.size(), layout.align()) as *mut T,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Deallocates an array obtained from `allocate_array::<T>` or
+/// `reallocate_array::<T>`, without dropping its elements.
+///
+/// # Safety
+///
+/// `ptr` must have been returned for an array of `n` values of type `T`.
+#[inline]
+pub unsafe fn deallocate_array<T>(ptr: *mut T, n: usize) {
+    // The size was checked when the array was allocated
+    let size = n * mem::size_of::<T>();
+
+    if size != 0 {
+        ::deallocate(ptr as *mut u8, size, mem::align_of::<T>())
+    }
+}
+
+/// Resize an array obtained from `allocate_array::<T>` from `old_n` to `n`
+/// values.
+///
+/// The elements are preserved up to the lesser of the old and new lengths.
+/// Resizing to or from an empty array allocates or deallocates as needed.
+///
+/// On failure, or if the size overflows, return a null pointer and leave the
+/// original array intact.
+///
+/// # Safety
+///
+/// `ptr` must have been returned for an array of `old_n` values of type `T`.
+#[inline]
+pub unsafe fn reallocate_array<T>(ptr: *mut T, old_n: usize, n: usize) -> *mut T {
+    let old_size = old_n * mem::size_of::<T>();
+
+    let layout = match Layout::array::<T>(n) {
+        Ok(layout) => layout,
+        Err(_) => return ptr::null_mut(),
+    };
+
+    if old_size == 0 {
+        return allocate_array(n);
+    }
+
+    if layout.size() == 0 {
+        deallocate_array(ptr, old_n);
+        return NonNull::dangling().as_ptr();
+    }
+
+    ::reallocate(ptr as *mut u8, old_size, layout.size(), layout.align()) as *mut T
+}
+
+/// Return a pointer to a new array holding a copy of `src`.
+///
+/// The array has `src.len()` elements and is allocated as by
+/// `allocate_array`, so an empty slice doesn't allocate.
+///
+/// On failure, return a null pointer.
+///
+/// # Safety
+///
+/// The array must be released with `deallocate_array::<T>` using
+/// `src.len()`.
+#[inline]
+pub unsafe fn clone_slice_raw<T: Copy>(src: &[T]) -> *mut T {
+    let ptr = allocate_array::<T>(src.len());
+
+    if !ptr.is_null() {
+        ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+    }
+
+    ptr
+}
+
+/// Return an uninitialized slice of `n` values of type `T`.
+///
+/// The elements can be initialized one by one through the slice. As with
+/// `allocate_array`, empty slices and zero-sized types don't allocate.
+///
+/// Returns `AllocError::InvalidLayout` if the size overflows or the alignment
+/// of `T` is not supported, and `AllocError::OutOfMemory` on exhaustion.
+///
+/// # Safety
+///
+/// The slice must be released with `deallocate_uninit_slice`.
+pub unsafe fn allocate_uninit_slice<T>(n: usize) -> Result<NonNull<[MaybeUninit<T>]>, AllocError> {
+    let layout = Layout::array::<T>(n)?;
+
+    let ptr = if layout.size() == 0 {
+        NonNull::dangling()
+    } else {
+        ::try_allocate(layout)?.cast()
+    };
+
+    Ok(NonNull::from(slice::from_raw_parts_mut(ptr.as_ptr(), n)))
+}
+
+/// Deallocates a slice obtained from `allocate_uninit_slice`, without
+/// dropping its elements.
+///
+/// # Safety
+///
+/// `slice` must have been returned by `allocate_uninit_slice`, with its
+/// length unchanged.
+pub unsafe fn deallocate_uninit_slice<T>(slice: NonNull<[MaybeUninit<T>]>) {
+    let n = slice.as_ref().len();
+    deallocate_array(slice.as_ptr() as *mut T, n)
+}
+
+#[cfg(test)]
+mod test {
+    use {allocate_array, allocate_init, allocate_one, allocate_uninit_slice, clone_slice_raw, deallocate_array, deallocate_one};
+    use {deallocate_uninit_slice, drop_and_deallocate, reallocate_array};
+    use AllocError;
+    use std::{ptr, slice};
+    use std::cell::Cell;
+    use std::mem::MaybeUninit;
+
+    #[test]
+    fn test_allocate_one() {
+        unsafe {
+            let ptr = allocate_one::<u64>();
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize & 7);
+
+            ptr::write(ptr, 123);
+            assert_eq!(123, *ptr);
+
+            deallocate_one(ptr);
+        }
+    }
+
+    #[test]
+    fn test_allocate_init() {
+        struct Counted<'a>(&'a Cell<usize>);
+
+        impl<'a> Drop for Counted<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+
+        unsafe {
+            let ptr = allocate_init(Counted(&drops));
+            assert!(!ptr.is_null());
+            assert_eq!(0, drops.get());
+
+            drop_and_deallocate(ptr);
+            assert_eq!(1, drops.get());
+
+            // Zero-sized values are still dropped
+            let ptr = allocate_init(());
+            drop_and_deallocate(ptr);
+        }
+    }
+
+    #[test]
+    fn test_allocate_array() {
+        unsafe {
+            let ptr = allocate_array::<u32>(10);
+            assert!(!ptr.is_null());
+
+            for i in 0..10 {
+                ptr::write(ptr.add(i), i as u32);
+            }
+
+            let ptr = reallocate_array(ptr, 10, 1000);
+            assert!(!ptr.is_null());
+
+            for i in 0..10 {
+                assert_eq!(i as u32, *ptr.add(i));
+            }
+
+            let ptr = reallocate_array(ptr, 1000, 0);
+            assert_eq!(4, ptr as usize);
+
+            let ptr = reallocate_array(ptr, 0, 5);
+            assert!(!ptr.is_null());
+            deallocate_array(ptr, 5);
+        }
+    }
+
+    #[test]
+    fn test_allocate_array_overflow() {
+        unsafe {
+            assert!(allocate_array::<u64>(usize::MAX / 4).is_null());
+            assert!(allocate_array::<u64>(usize::MAX).is_null());
+
+            let ptr = allocate_array::<u64>(1);
+            assert!(reallocate_array(ptr, 1, usize::MAX).is_null());
+            deallocate_array(ptr, 1);
+        }
+    }
+
+    #[test]
+    fn test_clone_slice_raw() {
+        let src = [1.0f64, 2.0, 3.0];
+
+        unsafe {
+            let ptr = clone_slice_raw(&src);
+            assert!(!ptr.is_null());
+            assert_eq!(&src[..], slice::from_raw_parts(ptr, 3));
+            deallocate_array(ptr, 3);
+        }
+    }
+
+    #[test]
+    fn test_allocate_uninit_slice() {
+        unsafe {
+            let mut slice = allocate_uninit_slice::<u16>(100).unwrap();
+            assert_eq!(100, slice.as_ref().len());
+
+            for (i, elem) in slice.as_mut().iter_mut().enumerate() {
+                *elem = MaybeUninit::new(i as u16);
+            }
+
+            assert_eq!(99, slice.as_ref()[99].assume_init());
+            deallocate_uninit_slice(slice);
+
+            let empty = allocate_uninit_slice::<u16>(0).unwrap();
+            assert_eq!(0, empty.as_ref().len());
+            deallocate_uninit_slice(empty);
+
+            assert_eq!(AllocError::InvalidLayout, allocate_uninit_slice::<u64>(usize::MAX).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn test_allocate_one_zero_sized() {
+        #[repr(align(16))]
+        struct Empty;
+
+        unsafe {
+            let ptr = allocate_one::<Empty>();
+            assert_eq!(16, ptr as usize);
+            deallocate_one(ptr);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// A non-null pointer that owns its referent.
+///
+/// This is a stable version of the standard library's internal `Unique`
+/// type, meant for building collections. Like `NonNull`, it is covariant over
+/// `T` and allows the null pointer optimization. Unlike `NonNull`, it tells
+/// the compiler that the value behind it is owned, so it is `Send` and `Sync`
+/// whenever `T` is, and dropping the owner is treated as possibly dropping a
+/// `T`.
+///
+/// `Unique` doesn't release anything by itself; the owning type is
+/// responsible for dropping the value and deallocating its memory.
+pub struct Unique<T: ?Sized> {
+    ptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send + ?Sized> Send for Unique<T> {}
+unsafe impl<T: Sync + ?Sized> Sync for Unique<T> {}
+
+impl<T> Unique<T> {
+    /// Creates a dangling `Unique` aligned for `T`, for use as a placeholder
+    /// in empty collections.
+    pub fn dangling() -> Unique<T> {
+        Unique::from(NonNull::dangling())
+    }
+}
+
+impl<T: ?Sized> Unique<T> {
+    /// Creates a `Unique` if `ptr` is non-null.
+    pub fn new(ptr: *mut T) -> Option<Unique<T>> {
+        NonNull::new(ptr).map(Unique::from)
+    }
+
+    /// Creates a `Unique` from a pointer known to be non-null.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be non-null.
+    pub unsafe fn new_unchecked(ptr: *mut T) -> Unique<T> {
+        Unique::from(NonNull::new_unchecked(ptr))
+    }
+
+    /// Returns the underlying raw pointer.
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the underlying pointer as a `NonNull`.
+    pub fn as_non_null(&self) -> NonNull<T> {
+        self.ptr
+    }
+
+    /// Dereferences the pointer.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must reference an initialized value, and the value must not
+    /// be mutated for the lifetime of the returned reference.
+    pub unsafe fn as_ref(&self) -> &T {
+        &*self.ptr.as_ptr()
+    }
+
+    /// Mutably dereferences the pointer.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must reference an initialized value, and the value must not
+    /// be accessed through any other pointer for the lifetime of the returned
+    /// reference.
+    pub unsafe fn as_mut(&mut self) -> &mut T {
+        &mut *self.ptr.as_ptr()
+    }
+
+    /// Casts to a pointer of another type.
+    pub fn cast<U>(self) -> Unique<U> {
+        Unique::from(self.ptr.cast())
+    }
+}
+
+impl<T: ?Sized> Clone for Unique<T> {
+    fn clone(&self) -> Unique<T> {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Unique<T> {}
+
+impl<T: ?Sized> fmt::Debug for Unique<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, fmt)
+    }
+}
+
+impl<T: ?Sized> fmt::Pointer for Unique<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Pointer::fmt(&self.ptr, fmt)
+    }
+}
+
+impl<T: ?Sized> From<NonNull<T>> for Unique<T> {
+    fn from(ptr: NonNull<T>) -> Unique<T> {
+        Unique { ptr, _marker: PhantomData }
+    }
+}
+
+impl<'a, T: ?Sized> From<&'a mut T> for Unique<T> {
+    fn from(r: &'a mut T) -> Unique<T> {
+        Unique::from(NonNull::from(r))
+    }
+}
+
+impl<T: ?Sized> From<Unique<T>> for NonNull<T> {
+    fn from(unique: Unique<T>) -> NonNull<T> {
+        unique.ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {allocate_init, drop_and_deallocate, Unique};
+    use std::{mem, ptr};
+
+    #[test]
+    fn test_unique() {
+        unsafe {
+            let mut unique = Unique::new(allocate_init(5u32)).unwrap();
+            *unique.as_mut() += 1;
+            assert_eq!(6, *unique.as_ref());
+            drop_and_deallocate(unique.as_ptr());
+        }
+
+        assert!(Unique::<u8>::new(ptr::null_mut()).is_none());
+        assert_eq!(mem::size_of::<Unique<u8>>(), mem::size_of::<Option<Unique<u8>>>());
+    }
+
+    #[test]
+    fn test_unique_dangling() {
+        let unique = Unique::<u64>::dangling();
+        assert_eq!(0, unique.as_ptr() as usize & 7);
+        assert!(!unique.cast::<u8>().as_ptr().is_null());
+    }
+
+    #[test]
+    fn test_unique_send_sync() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<Unique<u8>>();
+        is_send_sync::<Unique<[u8]>>();
+    }
+}
@@ -0,0 +1,137 @@
+//! Valgrind client requests.
+//!
+//! Allocators carving blocks out of larger chunks describe them to Valgrind
+//! with these requests, so memcheck tracks each block on its own rather than
+//! the chunk as a whole. Reads of uninitialized slots, accesses to released
+//! blocks and leaked blocks are then reported as they are for `malloc`.
+//!
+//! The requests are only emitted with the `valgrind` feature, on x86_64 and
+//! aarch64. Outside of Valgrind the instruction sequence does nothing.
+
+#![cfg_attr(not(feature = "valgrind"), allow(dead_code, unused_variables))]
+
+// Request codes from `valgrind.h` and `memcheck.h`
+const MALLOCLIKE_BLOCK: usize = 0x1301;
+const FREELIKE_BLOCK: usize = 0x1302;
+const RESIZEINPLACE_BLOCK: usize = 0x130b;
+
+const MEMCHECK_BASE: usize = (b'M' as usize) << 24 | (b'C' as usize) << 16;
+const MAKE_MEM_NOACCESS: usize = MEMCHECK_BASE;
+const MAKE_MEM_UNDEFINED: usize = MEMCHECK_BASE + 1;
+const MAKE_MEM_DEFINED: usize = MEMCHECK_BASE + 2;
+
+/// Marks `size` bytes at `ptr` as a newly allocated, uninitialized block.
+#[inline]
+pub fn malloclike(ptr: *const u8, size: usize) {
+    request(MALLOCLIKE_BLOCK, ptr as usize, size, 0, 0);
+}
+
+/// Marks the block at `ptr` as released. Its memory becomes inaccessible.
+#[inline]
+pub fn freelike(ptr: *const u8) {
+    request(FREELIKE_BLOCK, ptr as usize, 0, 0, 0);
+}
+
+/// Records that the block at `ptr` was resized in place.
+#[inline]
+pub fn resize_inplace(ptr: *const u8, old_size: usize, size: usize) {
+    request(RESIZEINPLACE_BLOCK, ptr as usize, old_size, size, 0);
+}
+
+/// Marks `len` bytes at `ptr` as inaccessible.
+#[inline]
+pub fn make_noaccess(ptr: *const u8, len: usize) {
+    request(MAKE_MEM_NOACCESS, ptr as usize, len, 0, 0);
+}
+
+/// Marks `len` bytes at `ptr` as accessible but uninitialized.
+#[inline]
+pub fn make_undefined(ptr: *const u8, len: usize) {
+    request(MAKE_MEM_UNDEFINED, ptr as usize, len, 0, 0);
+}
+
+/// Marks `len` bytes at `ptr` as accessible and initialized.
+#[inline]
+pub fn make_defined(ptr: *const u8, len: usize) {
+    request(MAKE_MEM_DEFINED, ptr as usize, len, 0, 0);
+}
+
+// Issues a client request, returning 0 when not running under Valgrind
+#[cfg(all(feature = "valgrind", target_arch = "x86_64"))]
+#[inline]
+fn request(code: usize, a1: usize, a2: usize, a3: usize, a4: usize) -> usize {
+    use core::arch::asm;
+
+    let args = [code, a1, a2, a3, a4, 0];
+    let mut res = 0usize;
+
+    unsafe {
+        asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") args.as_ptr(),
+            inout("rdx") res,
+            inout("rdi") 0usize => _,
+            options(nostack),
+        );
+    }
+
+    res
+}
+
+#[cfg(all(feature = "valgrind", target_arch = "aarch64"))]
+#[inline]
+fn request(code: usize, a1: usize, a2: usize, a3: usize, a4: usize) -> usize {
+    use core::arch::asm;
+
+    let args = [code, a1, a2, a3, a4, 0];
+    let mut res = 0usize;
+
+    unsafe {
+        asm!(
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            in("x4") args.as_ptr(),
+            inout("x3") res,
+            inout("x12") 0usize => _,
+            out("x10") _,
+            options(nostack),
+        );
+    }
+
+    res
+}
+
+#[cfg(not(all(feature = "valgrind", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+#[inline]
+fn request(_code: usize, _a1: usize, _a2: usize, _a3: usize, _a4: usize) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_requests() {
+        let mut buf = [0u8; 64];
+        let ptr = buf.as_ptr();
+
+        // Natively, the requests leave the memory alone
+        make_noaccess(ptr, 64);
+        make_undefined(ptr, 64);
+        malloclike(ptr, 32);
+        resize_inplace(ptr, 32, 48);
+        freelike(ptr);
+        make_defined(ptr, 64);
+
+        buf[63] = 1;
+        assert_eq!(1, buf.iter().map(|&b| b as u32).sum::<u32>());
+    }
+}
@@ -0,0 +1,23 @@
+//! The mmap threshold freezes on the first allocation of the process, so it
+//! is tested in a binary of its own rather than alongside the unit tests.
+
+#![cfg(all(feature = "mmap", unix))]
+
+extern crate stable_heap;
+
+use stable_heap::{allocate, deallocate, mmap_threshold, set_mmap_threshold};
+
+#[test]
+fn test_set_mmap_threshold() {
+    // Reading the threshold doesn't freeze it
+    let threshold = mmap_threshold();
+    assert!(set_mmap_threshold(threshold * 2));
+    assert_eq!(threshold * 2, mmap_threshold());
+
+    unsafe {
+        deallocate(allocate(16, 8), 16, 8);
+    }
+
+    assert!(!set_mmap_threshold(threshold));
+    assert_eq!(threshold * 2, mmap_threshold());
+}
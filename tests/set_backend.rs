@@ -0,0 +1,63 @@
+//! `set_backend` swaps the backend of the whole process, so it is tested in a
+//! binary of its own rather than alongside the unit tests.
+
+extern crate stable_heap;
+
+use stable_heap::{allocate, deallocate, reallocate, set_backend, Backend, DefaultBackend, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting {
+    allocated: AtomicUsize,
+    deallocated: AtomicUsize,
+}
+
+unsafe impl Backend for Counting {
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 12345 {
+            self.allocated.fetch_add(1, Ordering::SeqCst);
+        }
+
+        DefaultBackend.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 67890 {
+            self.deallocated.fetch_add(1, Ordering::SeqCst);
+        }
+
+        DefaultBackend.deallocate(ptr, layout)
+    }
+
+    fn usable_size(&self, size: usize, align: usize) -> usize {
+        DefaultBackend.usable_size(size, align)
+    }
+}
+
+static COUNTING: Counting = Counting {
+    allocated: AtomicUsize::new(0),
+    deallocated: AtomicUsize::new(0),
+};
+
+#[test]
+fn test_set_backend() {
+    unsafe {
+        // Blocks allocated by either backend can be released by the other
+        let before = allocate(67890, 8);
+        set_backend(&COUNTING);
+
+        let ptr = allocate(12345, 8);
+        assert!(!ptr.is_null());
+        assert_eq!(1, COUNTING.allocated.load(Ordering::SeqCst));
+
+        // Falls back to allocating, copying and deallocating
+        let ptr = reallocate(ptr, 12345, 67890, 8);
+        assert!(!ptr.is_null());
+        deallocate(ptr, 67890, 8);
+        deallocate(before, 67890, 8);
+        assert_eq!(2, COUNTING.deallocated.load(Ordering::SeqCst));
+
+        set_backend(&DefaultBackend);
+        deallocate(allocate(12345, 8), 12345, 8);
+        assert_eq!(1, COUNTING.allocated.load(Ordering::SeqCst));
+    }
+}